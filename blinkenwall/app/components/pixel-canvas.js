@@ -0,0 +1,90 @@
+import Component from '@glimmer/component';
+import { action } from '@ember/object';
+import { tracked } from '@glimmer/tracking';
+import { inject as service } from '@ember/service';
+
+const SCALE = 10;
+
+export default class PixelCanvasComponent extends Component {
+    @service serverConnection;
+
+    @tracked palette = [];
+    @tracked selected = 1;
+    @tracked message = '';
+
+    get colors() {
+        return this.palette.map((color, index) => ({ color, index, active: index === this.selected }));
+    }
+
+    _element = null;
+    _canvas = null;
+    _listener = null;
+
+    @action
+    didInsert(element) {
+        this._element = element.querySelector('canvas');
+        this._listener = this.pixelChanged.bind(this);
+        this.serverConnection.addListener('canvas pixel', this._listener);
+        this.serverConnection.send({ cmd: 'canvas' }, (msg) => {
+            if(msg.status !== 'ok') {
+                return;
+            }
+            this._canvas = msg.canvas;
+            this.palette = msg.palette;
+            this.message = `You can paint a pixel every ${msg.cooldown} seconds.`;
+            this._element.width = this._canvas.width * SCALE;
+            this._element.height = this._canvas.height * SCALE;
+            for(let y = 0; y < this._canvas.height; ++y) {
+                for(let x = 0; x < this._canvas.width; ++x) {
+                    this.drawPixel(x, y, this._canvas.pixels[y * this._canvas.width + x]);
+                }
+            }
+        });
+    }
+
+    @action
+    willDestroy() {
+        if(this._listener !== null) {
+            this.serverConnection.removeListener('canvas pixel', this._listener);
+            this._listener = null;
+        }
+    }
+
+    drawPixel(x, y, color) {
+        const context = this._element.getContext('2d');
+        context.fillStyle = this.palette[color] || this.palette[0];
+        context.fillRect(x * SCALE, y * SCALE, SCALE, SCALE);
+    }
+
+    pixelChanged(msg) {
+        if(this._canvas) {
+            this._canvas.pixels[msg.y * this._canvas.width + msg.x] = msg.color;
+            this.drawPixel(msg.x, msg.y, msg.color);
+        }
+    }
+
+    @action
+    select(index) {
+        this.selected = index;
+    }
+
+    @action
+    paint(event) {
+        if(!this._canvas) {
+            return;
+        }
+        const bounds = this._element.getBoundingClientRect();
+        const x = Math.floor((event.clientX - bounds.left) / bounds.width * this._canvas.width);
+        const y = Math.floor((event.clientY - bounds.top) / bounds.height * this._canvas.height);
+        this.serverConnection.send({
+            cmd: 'canvas paint',
+            x,
+            y,
+            color: this.selected,
+        }, (msg) => {
+            if(msg.status !== 'ok') {
+                this.message = msg.message || 'Could not paint there.';
+            }
+        });
+    }
+}
@@ -31,6 +31,10 @@ uniform vec3 iResolution;
 uniform vec4 iMouse;
 uniform vec4 iDate;
 uniform int iFrame;
+uniform float iLevel;
+uniform vec3 iBands;
+uniform float iBeat;
+uniform float iBPM;
 
 void mainImage(out vec4, in vec2);
 
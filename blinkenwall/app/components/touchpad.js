@@ -0,0 +1,44 @@
+import Component from '@glimmer/component';
+import { action } from '@ember/object';
+import { inject as service } from '@ember/service';
+
+export default class TouchpadComponent extends Component {
+    @service serverConnection;
+
+    down = false;
+
+    @action
+    pointer(down, event) {
+        if(down === null) {
+            // only dragging moves the mouse, like on shadertoy.com
+            if(!this.down) {
+                return;
+            }
+            down = true;
+        } else if(down) {
+            event.currentTarget.setPointerCapture(event.pointerId);
+        }
+        event.preventDefault();
+        this.down = down;
+        const bounds = event.currentTarget.getBoundingClientRect();
+        this.serverConnection.send({
+            cmd: "shader pointer",
+            x: (event.clientX - bounds.left) / bounds.width,
+            y: (event.clientY - bounds.top) / bounds.height,
+            down,
+        });
+    }
+
+    @action
+    key(down, event) {
+        if(event.keyCode >= 256 || (down && event.repeat)) {
+            return;
+        }
+        event.preventDefault();
+        this.serverConnection.send({
+            cmd: "shader key",
+            key: event.keyCode,
+            down,
+        });
+    }
+}
@@ -0,0 +1,19 @@
+import { inject as service } from '@ember/service';
+import Controller from '@ember/controller';
+
+export default Controller.extend({
+  serverConnection: service(),
+
+  actions: {
+    show() {
+      this.serverConnection.send({
+        cmd: 'show canvas',
+      });
+    },
+    timelapse() {
+      this.serverConnection.send({
+        cmd: 'canvas timelapse',
+      });
+    },
+  },
+});
@@ -0,0 +1,40 @@
+import { inject as service } from '@ember/service';
+import Controller from '@ember/controller';
+
+export default Controller.extend({
+  server: '',
+  serverConnection: service(),
+
+  actions: {
+    connect() {
+      let message = { cmd: 'show vnc' };
+      if (this.server) {
+        message.server = this.server;
+      }
+      this.serverConnection.send(message);
+    },
+    pointer(event) {
+      let bounds = event.currentTarget.getBoundingClientRect();
+      // browsers number the middle and right buttons the other way round than VNC
+      let buttons =
+        (event.buttons & 1) | ((event.buttons & 4) >> 1) | ((event.buttons & 2) << 1);
+      this.serverConnection.send({
+        cmd: 'vnc pointer',
+        x: (event.clientX - bounds.left) / bounds.width,
+        y: (event.clientY - bounds.top) / bounds.height,
+        buttons: buttons,
+      });
+    },
+    key(down, event) {
+      event.preventDefault();
+      this.serverConnection.send({
+        cmd: 'vnc key',
+        key: event.key,
+        down: down,
+      });
+    },
+    preventDefault(event) {
+      event.preventDefault();
+    },
+  },
+});
@@ -13,6 +13,32 @@ export default Controller.extend({
         url: this.url,
       });
     },
+    queueVideo() {
+      this.serverConnection.send({
+        cmd: 'video queue add',
+        url: this.url,
+      });
+    },
+    pauseVideo() {
+      this.serverConnection.send({
+        cmd: 'video pause',
+      });
+    },
+    resumeVideo() {
+      this.serverConnection.send({
+        cmd: 'video resume',
+      });
+    },
+    previousVideo() {
+      this.serverConnection.send({
+        cmd: 'video previous',
+      });
+    },
+    nextVideo() {
+      this.serverConnection.send({
+        cmd: 'video next',
+      });
+    },
     stopVideo() {
       this.serverConnection.send({
         cmd: 'turnoff',
@@ -19,4 +19,5 @@ Router.map(function () {
   this.route('retro');
   this.route('tox');
   this.route('poetry');
+  this.route('canvas');
 });
@@ -0,0 +1,3 @@
+import Route from '@ember/routing/route';
+
+export default Route.extend({});
@@ -8,6 +8,7 @@ export default Service.extend({
   index: 1,
   messageQueue: [],
   callbacks: {},
+  listeners: {},
 
   init() {
     this._super(...arguments);
@@ -30,6 +31,19 @@ export default Service.extend({
     this.incrementProperty('index');
   },
 
+  // Calls `cb` with every broadcast `event`, like 'canvas pixel'.
+  addListener(event, cb) {
+    (this.listeners[event] = this.listeners[event] || []).push(cb);
+  },
+
+  removeListener(event, cb) {
+    let listeners = this.listeners[event] || [];
+    let index = listeners.indexOf(cb);
+    if (index >= 0) {
+      listeners.splice(index, 1);
+    }
+  },
+
   onOpen() {
     console.log('Websocket connection opened.');
     let ws = this.ws;
@@ -42,6 +56,9 @@ export default Service.extend({
   onMessage(event) {
     let msg = JSON.parse(event.data);
     console.log('Received websocket message', msg);
+    if (msg.event) {
+      (this.listeners[msg.event] || []).forEach((cb) => cb(msg));
+    }
     let req = msg.req;
     if (req) {
       let cb = this.callbacks[req];
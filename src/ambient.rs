@@ -0,0 +1,283 @@
+use chrono::{Local, Timelike};
+use glium::{backend::glutin::Display, program, uniform, Surface};
+use log::error;
+use palette::Srgba;
+use std::{
+    rc::Rc,
+    sync::mpsc::{Receiver, TryRecvError},
+    time::Duration,
+};
+
+mod calendar;
+mod sources;
+
+use crate::{
+    blit::Blit,
+    config::{self, Align, PoetryStyle, Widget as WidgetConfig},
+    poetry::{parse_color, text_program, Animation, Fonts, Text},
+    shadertoy::ShaderToy,
+};
+
+const CLOCK_VERTEX_SHADER: &str = "#version 140
+
+in vec2 position;
+
+out vec2 vPosition;
+
+// center and radius in pixels
+uniform vec2 center;
+uniform float radius;
+uniform vec2 area;
+
+void main() {
+    gl_Position = vec4((center + position * radius) / area * 2.0 - 1.0, 0.0, 1.0);
+    vPosition = position;
+}
+";
+
+const CLOCK_FRAGMENT_SHADER: &str = "#version 140
+
+in vec2 vPosition;
+out vec4 fragColor;
+
+uniform vec3 color;
+// hour, minute and second hand in turns
+uniform vec3 hands;
+
+float hand(float turns, float len, float width) {
+    vec2 direction = vec2(sin(turns * 6.2831853), cos(turns * 6.2831853));
+    float along = clamp(dot(vPosition, direction), 0.0, len);
+    return step(length(vPosition - direction * along), width);
+}
+
+void main() {
+    float radius = length(vPosition);
+    float face = step(abs(radius - 0.93), 0.04);
+    float position = atan(vPosition.x, vPosition.y) / 6.2831853 * 12.0;
+    float tick = step(abs(fract(position + 0.5) - 0.5), 0.15) * step(0.75, radius) * step(radius, 0.85);
+    float shape = max(max(face, tick), max(hand(hands.x, 0.5, 0.07), max(hand(hands.y, 0.75, 0.05), hand(hands.z, 0.85, 0.02))));
+    fragColor = vec4(color, shape);
+}
+";
+
+enum Content {
+    Clock {
+        analog: bool,
+        format: String,
+    },
+    Date {
+        format: String,
+    },
+    /// Text fetched in the background
+    Fetched(Receiver<Result<String, String>>),
+}
+
+struct Widget {
+    content: Content,
+    /// Anchor from the top left, as fractions of the display
+    x: f32,
+    y: f32,
+    align: Align,
+    scale: f32,
+    color: [u8; 3],
+    /// What is shown right now, and its rendering
+    text: Option<(String, Text)>,
+}
+
+impl Widget {
+    fn new(config: &config::WidgetSpec, refresh: Duration) -> Self {
+        let content = match &config.widget {
+            WidgetConfig::Clock { analog, format } => Content::Clock {
+                analog: *analog,
+                format: format.clone(),
+            },
+            WidgetConfig::Date { format } => Content::Date {
+                format: format.clone(),
+            },
+            WidgetConfig::Events { file, count } => Content::Fetched(sources::poll(
+                Box::new(sources::Events {
+                    file: file.clone(),
+                    count: *count,
+                }),
+                refresh,
+            )),
+            WidgetConfig::Weather { provider } => {
+                Content::Fetched(sources::poll(sources::weather(provider), refresh))
+            }
+            WidgetConfig::Space { url, open, closed } => Content::Fetched(sources::poll(
+                Box::new(sources::SpaceApi {
+                    url: url.clone(),
+                    open: open.clone(),
+                    closed: closed.clone(),
+                }),
+                refresh,
+            )),
+        };
+        let color = parse_color(&config.color).unwrap_or_else(|| {
+            error!("Invalid widget colour {}", config.color);
+            [255, 255, 255]
+        });
+        Self {
+            content,
+            x: config.x,
+            y: config.y,
+            align: config.align,
+            scale: config.scale,
+            color,
+            text: None,
+        }
+    }
+
+    /// The text to show now, `None` if it didn't change.
+    fn next_text(&mut self) -> Option<String> {
+        let current = self.text.as_ref().map(|(text, _)| text.as_str());
+        let next = match &self.content {
+            Content::Clock { analog: true, .. } => return None,
+            Content::Clock { format, .. } | Content::Date { format } => {
+                Local::now().format(format).to_string()
+            }
+            Content::Fetched(updates) => match updates.try_recv() {
+                Ok(Ok(text)) => text,
+                Ok(Err(err)) => {
+                    error!("Failed updating widget: {}", err);
+                    return None;
+                }
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => return None,
+            },
+        };
+        if current == Some(next.as_str()) {
+            None
+        } else {
+            Some(next)
+        }
+    }
+
+    /// Left edge of something `width` wide at the anchor, in pixels.
+    fn left(&self, width: f32, size: (u32, u32)) -> f32 {
+        let x = self.x * size.0 as f32;
+        match self.align {
+            Align::Left => x,
+            Align::Center => x - width / 2.0,
+            Align::Right => x - width,
+        }
+    }
+}
+
+/// Clock, date, events, weather and the like on top of an optional shader.
+pub struct Ambient {
+    background: Option<ShaderToy>,
+    fonts: Rc<Fonts>,
+    text_program: glium::Program,
+    clock_program: glium::Program,
+    widgets: Vec<Widget>,
+}
+
+impl Ambient {
+    /// Creates the configured widgets in front of the shader `background`.
+    pub fn new(
+        display: &Display,
+        fonts: Rc<Fonts>,
+        config: &config::Ambient,
+        background: Option<&str>,
+    ) -> Self {
+        let clock_program = program!(display, 140 => {
+            vertex: CLOCK_VERTEX_SHADER,
+            fragment: CLOCK_FRAGMENT_SHADER
+        })
+        .unwrap();
+
+        let refresh = Duration::from_secs(config.refresh.max(1));
+        Self {
+            background: background.map(|source| ShaderToy::new(display, source)),
+            fonts,
+            text_program: text_program(display),
+            clock_program,
+            widgets: config
+                .widgets
+                .iter()
+                .map(|widget| Widget::new(widget, refresh))
+                .collect(),
+        }
+    }
+
+    /// Renders the text of widgets that changed.
+    pub fn update(&mut self, display: &Display) {
+        for widget in &mut self.widgets {
+            if let Some(next) = widget.next_text() {
+                let text = Text::plain(
+                    display,
+                    &self.fonts,
+                    &next,
+                    widget.scale,
+                    widget.color,
+                    widget.align,
+                    u32::MAX,
+                );
+                widget.text = Some((next, text));
+            }
+        }
+    }
+
+    fn draw_clock<S: Surface>(
+        &self,
+        target: &mut S,
+        size: (u32, u32),
+        blit: &Blit,
+        widget: &Widget,
+    ) {
+        let now = Local::now();
+        let seconds = now.second() as f32 + now.nanosecond() as f32 / 1e9;
+        let minutes = now.minute() as f32 + seconds / 60.0;
+        let hours = (now.hour() % 12) as f32 + minutes / 60.0;
+        let radius = size.0.min(size.1) as f32 * 0.2 * widget.scale;
+        let left = widget.left(radius * 2.0, size);
+        let top = widget.y * size.1 as f32;
+        let uniforms = uniform! {
+            center: [left + radius, size.1 as f32 - top - radius],
+            radius: radius,
+            area: [size.0 as f32, size.1 as f32],
+            color: [
+                widget.color[0] as f32 / 255.0,
+                widget.color[1] as f32 / 255.0,
+                widget.color[2] as f32 / 255.0,
+            ],
+            hands: [hours / 12.0, minutes / 60.0, seconds / 60.0],
+        };
+        blit.draw_quad(
+            target,
+            &self.clock_program,
+            &uniforms,
+            &glium::DrawParameters {
+                blend: glium::draw_parameters::Blend::alpha_blending(),
+                ..Default::default()
+            },
+        );
+    }
+
+    pub fn draw<S: Surface>(&mut self, target: &mut S, size: (u32, u32), blit: &Blit) {
+        if let Some(background) = &mut self.background {
+            background.draw(target, size, None);
+        }
+        let animation = Animation {
+            style: PoetryStyle::Fade,
+            time: 0.0,
+            progress: 1.0,
+            beat: 0.0,
+        };
+        for widget in &self.widgets {
+            if let Content::Clock { analog: true, .. } = widget.content {
+                self.draw_clock(target, size, blit, widget);
+            } else if let Some((_, text)) = &widget.text {
+                let top = size.1 as f32 - widget.y * size.1 as f32;
+                text.draw(
+                    target,
+                    &self.text_program,
+                    [widget.left(text.width as f32, size).round(), top.round()],
+                    size,
+                    Srgba::new(1.0, 1.0, 1.0, 1.0),
+                    animation,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,649 @@
+use chrono::{
+    DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc, Weekday,
+};
+use log::warn;
+use std::convert::TryFrom;
+use std::{iter, mem};
+
+/// Periods a recurrence is followed for before giving up on finding another occurrence.
+const MAX_PERIODS: u32 = 100_000;
+
+pub struct Event {
+    pub start: DateTime<Local>,
+    pub all_day: bool,
+    pub summary: String,
+    rule: Option<Rule>,
+    /// Occurrences that were removed or moved to an event of their own
+    exceptions: Vec<DateTime<Local>>,
+}
+
+impl Event {
+    /// Starts of all occurrences in order, just the first one for events that don't recur.
+    fn occurrences(&self) -> Box<dyn Iterator<Item = DateTime<Local>> + '_> {
+        let rule = match &self.rule {
+            Some(rule) => rule,
+            None => return Box::new(iter::once(self.start)),
+        };
+        let first = self.start.naive_local();
+        Box::new(
+            (0..MAX_PERIODS)
+                .flat_map(move |period| rule.dates(first.date(), period))
+                .filter(move |date| *date >= first.date())
+                .filter_map(move |date| {
+                    Local
+                        .from_local_datetime(&date.and_time(first.time()))
+                        .earliest()
+                })
+                .take(rule.count.unwrap_or(usize::MAX))
+                .take_while(move |start| !matches!(rule.until, Some(until) if *start > until))
+                .filter(move |start| !self.exceptions.contains(start)),
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// The supported subset of an `RRULE`.
+#[derive(Debug, PartialEq)]
+struct Rule {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<usize>,
+    until: Option<DateTime<Local>>,
+    /// Weekdays, for monthly rules optionally the nth one of the month, counted from the end if negative
+    by_day: Vec<(Option<i32>, Weekday)>,
+    /// Days of the month, counted from the end if negative
+    by_month_day: Vec<i32>,
+}
+
+impl Rule {
+    /// Parses an `RRULE` value, or returns the part that isn't supported.
+    fn parse(value: &str) -> Result<Rule, String> {
+        let mut frequency = None;
+        let mut interval = 1;
+        let mut count = None;
+        let mut until = None;
+        let mut by_day = Vec::new();
+        let mut by_month_day = Vec::new();
+        for part in value.split(';') {
+            let unsupported = || part.to_owned();
+            let (name, value) = part.split_once('=').ok_or_else(unsupported)?;
+            match name {
+                "FREQ" => {
+                    frequency = Some(match value {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return Err(unsupported()),
+                    })
+                }
+                "INTERVAL" => {
+                    interval = value
+                        .parse()
+                        .ok()
+                        .filter(|interval| (1..=1000).contains(interval))
+                        .ok_or_else(unsupported)?
+                }
+                "COUNT" => count = Some(value.parse().map_err(|_| unsupported())?),
+                "UNTIL" => until = Some(parse_start(value).ok_or_else(unsupported)?.0),
+                "BYDAY" => {
+                    by_day = value
+                        .split(',')
+                        .map(parse_weekday)
+                        .collect::<Option<_>>()
+                        .ok_or_else(unsupported)?
+                }
+                "BYMONTHDAY" => {
+                    by_month_day = value
+                        .split(',')
+                        .map(|day| {
+                            day.parse()
+                                .ok()
+                                .filter(|day: &i32| (1..=31).contains(&day.abs()))
+                        })
+                        .collect::<Option<_>>()
+                        .ok_or_else(unsupported)?
+                }
+                // weeks start on Monday either way, only the days within a week matter
+                "WKST" => {}
+                _ => return Err(unsupported()),
+            }
+        }
+        let frequency = frequency.ok_or_else(|| value.to_owned())?;
+        let ordinals = by_day.iter().any(|(nth, _)| nth.is_some());
+        let supported = match frequency {
+            Frequency::Daily | Frequency::Yearly => by_day.is_empty() && by_month_day.is_empty(),
+            Frequency::Weekly => !ordinals && by_month_day.is_empty(),
+            Frequency::Monthly => by_day.is_empty() || by_month_day.is_empty(),
+        };
+        if !supported {
+            return Err(value.to_owned());
+        }
+        Ok(Rule {
+            frequency,
+            interval,
+            count,
+            until,
+            by_day,
+            by_month_day,
+        })
+    }
+
+    /// Dates of the period `period` intervals after the one of the `first` date, in order.
+    fn dates(&self, first: NaiveDate, period: u32) -> Vec<NaiveDate> {
+        let step = i64::from(period) * i64::from(self.interval);
+        match self.frequency {
+            Frequency::Daily => first
+                .checked_add_signed(Duration::days(step))
+                .into_iter()
+                .collect(),
+            Frequency::Weekly => {
+                let offset = i64::from(first.weekday().num_days_from_monday());
+                let monday = match first.checked_add_signed(Duration::days(step * 7 - offset)) {
+                    Some(monday) => monday,
+                    None => return Vec::new(),
+                };
+                let mut days: Vec<_> = if self.by_day.is_empty() {
+                    vec![offset]
+                } else {
+                    self.by_day
+                        .iter()
+                        .map(|(_, weekday)| i64::from(weekday.num_days_from_monday()))
+                        .collect()
+                };
+                days.sort_unstable();
+                days.dedup();
+                days.into_iter()
+                    .filter_map(|day| monday.checked_add_signed(Duration::days(day)))
+                    .collect()
+            }
+            Frequency::Monthly => {
+                let month = i64::from(first.year()) * 12 + i64::from(first.month0()) + step;
+                match i32::try_from(month.div_euclid(12)) {
+                    Ok(year) => {
+                        self.month_dates(year, month.rem_euclid(12) as u32 + 1, first.day())
+                    }
+                    Err(_) => Vec::new(),
+                }
+            }
+            Frequency::Yearly => i32::try_from(i64::from(first.year()) + step)
+                .ok()
+                .and_then(|year| NaiveDate::from_ymd_opt(year, first.month(), first.day()))
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// Dates of a month matched by the rule, the given `day` if nothing is chosen explicitly.
+    fn month_dates(&self, year: i32, month: u32, day: u32) -> Vec<NaiveDate> {
+        let first = match NaiveDate::from_ymd_opt(year, month, 1) {
+            Some(first) => first,
+            None => return Vec::new(),
+        };
+        let month: Vec<_> = first
+            .iter_days()
+            .take_while(|date| date.month() == month)
+            .collect();
+        let mut dates = Vec::new();
+        if self.by_day.is_empty() && self.by_month_day.is_empty() {
+            dates.extend(month.get(day as usize - 1));
+        }
+        for &day in &self.by_month_day {
+            let index = if day < 0 {
+                month.len() as i32 + day
+            } else {
+                day - 1
+            };
+            dates.extend(
+                usize::try_from(index)
+                    .ok()
+                    .and_then(|index| month.get(index)),
+            );
+        }
+        for &(nth, weekday) in &self.by_day {
+            let matching: Vec<_> = month
+                .iter()
+                .filter(|date| date.weekday() == weekday)
+                .collect();
+            match nth {
+                None => dates.extend(matching),
+                Some(nth) => {
+                    let index = if nth < 0 {
+                        matching.len() as i32 + nth
+                    } else {
+                        nth - 1
+                    };
+                    dates.extend(
+                        usize::try_from(index)
+                            .ok()
+                            .and_then(|index| matching.get(index).copied()),
+                    );
+                }
+            }
+        }
+        dates.sort_unstable();
+        dates.dedup();
+        dates
+    }
+}
+
+/// Parses a `BYDAY` entry like `TU` or `-1FR`.
+fn parse_weekday(value: &str) -> Option<(Option<i32>, Weekday)> {
+    let split = value.len().checked_sub(2)?;
+    let weekday = match value.get(split..)? {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return None,
+    };
+    let nth = match &value[..split] {
+        "" => None,
+        nth => Some(
+            nth.parse()
+                .ok()
+                .filter(|nth: &i32| (1..=5).contains(&nth.abs()))?,
+        ),
+    };
+    Some((nth, weekday))
+}
+
+/// Joins folded lines, which continue with a space or tab.
+fn unfold(content: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in content.lines() {
+        match (
+            line.strip_prefix(|c| c == ' ' || c == '\t'),
+            lines.last_mut(),
+        ) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => lines.push(line.to_owned()),
+        }
+    }
+    lines
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("\\n", " ")
+        .replace("\\N", " ")
+        .replace("\\,", ",")
+        .replace("\\;", ";")
+        .replace("\\\\", "\\")
+}
+
+/// Parses a `DTSTART` value. Times without `Z` are taken as local time, `TZID` is ignored.
+fn parse_start(value: &str) -> Option<(DateTime<Local>, bool)> {
+    if value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").ok()?;
+        let start = Local
+            .from_local_datetime(&date.and_hms_opt(0, 0, 0)?)
+            .single()?;
+        return Some((start, true));
+    }
+    let (value, utc) = match value.strip_suffix('Z') {
+        Some(value) => (value, true),
+        None => (value, false),
+    };
+    let time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+    let start = if utc {
+        Utc.from_utc_datetime(&time).with_timezone(&Local)
+    } else {
+        Local.from_local_datetime(&time).single()?
+    };
+    Some((start, false))
+}
+
+/// Reads the events of an iCal file. Recurring events follow their `RRULE` and `EXDATE`s, rules
+/// with parts that aren't supported only show up with their first date.
+pub fn parse(content: &str) -> Vec<Event> {
+    let mut events = Vec::new();
+    let mut moved = Vec::new();
+    let mut start = None;
+    let mut summary = None;
+    let mut uid = None;
+    let mut rule = None;
+    let mut exceptions = Vec::new();
+    let mut recurrence = None;
+    for line in unfold(content) {
+        let (name, value) = match line.split_once(':') {
+            Some(property) => property,
+            None => continue,
+        };
+        // parameters like TZID follow the name after a semicolon
+        let name = name.split(';').next().unwrap_or_default();
+        match name {
+            "BEGIN" if value == "VEVENT" => {
+                start = None;
+                summary = None;
+                uid = None;
+                rule = None;
+                exceptions.clear();
+                recurrence = None;
+            }
+            "DTSTART" => start = parse_start(value.trim()),
+            "SUMMARY" => summary = Some(unescape(value.trim())),
+            "UID" => uid = Some(value.trim().to_owned()),
+            "RRULE" => {
+                rule = match Rule::parse(value.trim()) {
+                    Ok(rule) => Some(rule),
+                    Err(part) => {
+                        warn!(
+                            "Unsupported recurrence {}, only showing the first date",
+                            part
+                        );
+                        None
+                    }
+                }
+            }
+            "EXDATE" => exceptions.extend(
+                value
+                    .split(',')
+                    .filter_map(|date| parse_start(date.trim()))
+                    .map(|(date, _)| date),
+            ),
+            "RECURRENCE-ID" => recurrence = parse_start(value.trim()).map(|(date, _)| date),
+            "END" if value == "VEVENT" => {
+                // a changed occurrence replaces the one of the recurring event
+                if let (Some(uid), Some(recurrence)) = (&uid, recurrence.take()) {
+                    moved.push((uid.clone(), recurrence));
+                }
+                if let (Some((start, all_day)), Some(summary)) = (start.take(), summary.take()) {
+                    let event = Event {
+                        start,
+                        all_day,
+                        summary,
+                        rule: rule.take(),
+                        exceptions: mem::take(&mut exceptions),
+                    };
+                    events.push((uid.take(), event));
+                }
+            }
+            _ => {}
+        }
+    }
+    for (uid, recurrence) in moved {
+        for (_, event) in events
+            .iter_mut()
+            .filter(|(other, event)| other.as_ref() == Some(&uid) && event.rule.is_some())
+        {
+            event.exceptions.push(recurrence);
+        }
+    }
+    events.into_iter().map(|(_, event)| event).collect()
+}
+
+/// The next `count` events from today on, a line each.
+pub fn upcoming(content: &str, count: usize) -> String {
+    let today = Local::now().naive_local().date();
+    let events = parse(content);
+    let mut occurrences: Vec<_> = events
+        .iter()
+        .flat_map(|event| {
+            event
+                .occurrences()
+                .skip_while(move |start| start.naive_local().date() < today)
+                .take(count)
+                .map(move |start| (start, event))
+        })
+        .collect();
+    occurrences.sort_by_key(|(start, _)| *start);
+    occurrences
+        .iter()
+        .take(count)
+        .map(|(start, event)| {
+            let format = if event.all_day { "%a %e." } else { "%a %H:%M" };
+            format!("{} {}", start.format(format), event.summary)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        let time = NaiveDate::from_ymd_opt(year, month, day)
+            .and_then(|date| date.and_hms_opt(hour, minute, 0))
+            .unwrap();
+        Local.from_local_datetime(&time).single().unwrap()
+    }
+
+    #[test]
+    fn folded_lines_are_joined() {
+        assert_eq!(
+            unfold("SUMMARY:A long\r\n  summary\r\n\tcontinued\r\nEND:VEVENT\r\n"),
+            ["SUMMARY:A long summarycontinued", "END:VEVENT"]
+        );
+        // nothing to continue
+        assert_eq!(unfold(" orphan\nNEXT:1"), [" orphan", "NEXT:1"]);
+    }
+
+    #[test]
+    fn start_dates_and_times() {
+        let (start, all_day) = parse_start("20240229").unwrap();
+        assert_eq!(start, local(2024, 2, 29, 0, 0));
+        assert!(all_day);
+
+        let (start, all_day) = parse_start("20240301T183000").unwrap();
+        assert_eq!(start, local(2024, 3, 1, 18, 30));
+        assert!(!all_day);
+
+        let (start, _) = parse_start("20240301T183000Z").unwrap();
+        let utc = NaiveDate::from_ymd_opt(2024, 3, 1)
+            .and_then(|date| date.and_hms_opt(18, 30, 0))
+            .unwrap();
+        assert_eq!(start, Utc.from_utc_datetime(&utc));
+
+        assert!(parse_start("20230229").is_none());
+        assert!(parse_start("2024-03-01").is_none());
+        assert!(parse_start("20240301T1830").is_none());
+        assert!(parse_start("").is_none());
+    }
+
+    #[test]
+    fn events_are_parsed() {
+        let events = parse(
+            "BEGIN:VCALENDAR\r\n\
+             SUMMARY:Not an event\r\n\
+             BEGIN:VEVENT\r\n\
+             DTSTART;TZID=Europe/Berlin:20240301T183000\r\n\
+             SUMMARY:Talk\\, then\\nDrinks\r\n\
+             END:VEVENT\r\n\
+             BEGIN:VEVENT\r\n\
+             DTSTART;VALUE=DATE:20240302\r\n\
+             SUMMARY:Hack\r\n\
+             \x20day\r\n\
+             END:VEVENT\r\n\
+             BEGIN:VEVENT\r\n\
+             SUMMARY:No start\r\n\
+             END:VEVENT\r\n\
+             BEGIN:VEVENT\r\n\
+             DTSTART:20240303\r\n\
+             END:VEVENT\r\n\
+             END:VCALENDAR\r\n",
+        );
+        let events: Vec<_> = events
+            .iter()
+            .map(|event| (event.start, event.all_day, event.summary.as_str()))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (local(2024, 3, 1, 18, 30), false, "Talk, then Drinks"),
+                (local(2024, 3, 2, 0, 0), true, "Hackday"),
+            ]
+        );
+    }
+
+    #[test]
+    fn upcoming_events_are_sorted_and_counted() {
+        let calendar = "BEGIN:VEVENT\n\
+                        DTSTART:20990102T090000\n\
+                        SUMMARY:Later\n\
+                        END:VEVENT\n\
+                        BEGIN:VEVENT\n\
+                        DTSTART:20000101\n\
+                        SUMMARY:Past\n\
+                        END:VEVENT\n\
+                        BEGIN:VEVENT\n\
+                        DTSTART:20990101\n\
+                        SUMMARY:Sooner\n\
+                        END:VEVENT\n";
+        assert_eq!(upcoming(calendar, 5), "Thu  1. Sooner\nFri 09:00 Later");
+        assert_eq!(upcoming(calendar, 1), "Thu  1. Sooner");
+        assert_eq!(upcoming("", 5), "");
+    }
+
+    fn starts(event: &str, count: usize) -> Vec<DateTime<Local>> {
+        let events = parse(event);
+        events[0].occurrences().take(count).collect()
+    }
+
+    #[test]
+    fn recurrence_rules() {
+        assert_eq!(
+            Rule::parse("FREQ=MONTHLY;INTERVAL=2;BYDAY=2TH,-1FR;WKST=MO"),
+            Ok(Rule {
+                frequency: Frequency::Monthly,
+                interval: 2,
+                count: None,
+                until: None,
+                by_day: vec![(Some(2), Weekday::Thu), (Some(-1), Weekday::Fri)],
+                by_month_day: Vec::new(),
+            })
+        );
+        let rule = Rule::parse("FREQ=WEEKLY;COUNT=3;UNTIL=20240301").unwrap();
+        assert_eq!(rule.count, Some(3));
+        assert_eq!(rule.until, Some(local(2024, 3, 1, 0, 0)));
+
+        assert_eq!(Rule::parse("FREQ=HOURLY"), Err("FREQ=HOURLY".to_owned()));
+        assert_eq!(
+            Rule::parse("FREQ=YEARLY;BYMONTH=3"),
+            Err("BYMONTH=3".to_owned())
+        );
+        assert_eq!(Rule::parse("INTERVAL=0"), Err("INTERVAL=0".to_owned()));
+        assert_eq!(Rule::parse("BYDAY=6MO"), Err("BYDAY=6MO".to_owned()));
+        assert_eq!(Rule::parse("COUNT=2"), Err("COUNT=2".to_owned()));
+        assert_eq!(
+            Rule::parse("FREQ=WEEKLY;BYDAY=1MO"),
+            Err("FREQ=WEEKLY;BYDAY=1MO".to_owned())
+        );
+    }
+
+    #[test]
+    fn recurring_events_repeat() {
+        // every second week on Tuesday and Thursday, starting on a Thursday
+        assert_eq!(
+            starts(
+                "BEGIN:VEVENT\nDTSTART:20240104T190000\nRRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,TH\nSUMMARY:Meeting\nEND:VEVENT",
+                4
+            ),
+            [
+                local(2024, 1, 4, 19, 0),
+                local(2024, 1, 16, 19, 0),
+                local(2024, 1, 18, 19, 0),
+                local(2024, 1, 30, 19, 0),
+            ]
+        );
+        // second Thursday and last Friday of the month
+        assert_eq!(
+            starts(
+                "BEGIN:VEVENT\nDTSTART:20240201\nRRULE:FREQ=MONTHLY;BYDAY=2TH,-1FR\nSUMMARY:Plenum\nEND:VEVENT",
+                4
+            ),
+            [
+                local(2024, 2, 8, 0, 0),
+                local(2024, 2, 23, 0, 0),
+                local(2024, 3, 14, 0, 0),
+                local(2024, 3, 29, 0, 0),
+            ]
+        );
+        // months without the day are skipped
+        assert_eq!(
+            starts(
+                "BEGIN:VEVENT\nDTSTART:20240131\nRRULE:FREQ=MONTHLY\nSUMMARY:End\nEND:VEVENT",
+                3
+            ),
+            [
+                local(2024, 1, 31, 0, 0),
+                local(2024, 3, 31, 0, 0),
+                local(2024, 5, 31, 0, 0),
+            ]
+        );
+        assert_eq!(
+            starts(
+                "BEGIN:VEVENT\nDTSTART:20240229\nRRULE:FREQ=YEARLY\nSUMMARY:Leap\nEND:VEVENT",
+                2
+            ),
+            [local(2024, 2, 29, 0, 0), local(2028, 2, 29, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn recurrences_end_and_have_exceptions() {
+        let calendar = "BEGIN:VEVENT\n\
+                        UID:daily\n\
+                        DTSTART:20240101T100000\n\
+                        RRULE:FREQ=DAILY;COUNT=4\n\
+                        EXDATE:20240102T100000,20240104T100000\n\
+                        SUMMARY:Count\n\
+                        END:VEVENT\n\
+                        BEGIN:VEVENT\n\
+                        UID:daily\n\
+                        RECURRENCE-ID:20240103T100000\n\
+                        DTSTART:20240103T120000\n\
+                        SUMMARY:Moved\n\
+                        END:VEVENT\n\
+                        BEGIN:VEVENT\n\
+                        DTSTART:20240101\n\
+                        RRULE:FREQ=DAILY;INTERVAL=3;UNTIL=20240107\n\
+                        SUMMARY:Until\n\
+                        END:VEVENT\n\
+                        BEGIN:VEVENT\n\
+                        DTSTART:20240101\n\
+                        RRULE:FREQ=SECONDLY\n\
+                        SUMMARY:Unsupported\n\
+                        END:VEVENT\n";
+        let events = parse(calendar);
+        let starts: Vec<Vec<_>> = events
+            .iter()
+            .map(|event| event.occurrences().take(10).collect())
+            .collect();
+        assert_eq!(
+            starts,
+            [
+                vec![local(2024, 1, 1, 10, 0)],
+                vec![local(2024, 1, 3, 12, 0)],
+                vec![
+                    local(2024, 1, 1, 0, 0),
+                    local(2024, 1, 4, 0, 0),
+                    local(2024, 1, 7, 0, 0)
+                ],
+                vec![local(2024, 1, 1, 0, 0)],
+            ]
+        );
+    }
+
+    #[test]
+    fn upcoming_recurrences() {
+        let calendar = "BEGIN:VEVENT\n\
+                        DTSTART:20000103T190000\n\
+                        RRULE:FREQ=WEEKLY\n\
+                        SUMMARY:Weekly\n\
+                        END:VEVENT\n";
+        let lines: Vec<_> = upcoming(calendar, 3).lines().map(str::to_owned).collect();
+        assert_eq!(lines, ["Mon 19:00 Weekly"; 3]);
+    }
+}
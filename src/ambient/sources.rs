@@ -0,0 +1,214 @@
+use log::error;
+use std::{
+    fs,
+    process::Command,
+    sync::mpsc::{channel, Receiver},
+    thread,
+    time::Duration,
+};
+
+use super::calendar;
+use crate::config::WeatherProvider;
+
+/// Where the text of a slowly changing widget comes from, fetched in a background thread.
+pub trait Source: Send {
+    fn fetch(&self) -> Result<String, String>;
+}
+
+pub struct Events {
+    pub file: String,
+    pub count: usize,
+}
+
+impl Source for Events {
+    fn fetch(&self) -> Result<String, String> {
+        let content = fs::read_to_string(&self.file).map_err(|err| err.to_string())?;
+        Ok(calendar::upcoming(&content, self.count))
+    }
+}
+
+fn get_json(url: &str) -> Result<serde_json::Value, String> {
+    let response = ureq::get(url).call().map_err(|err| err.to_string())?;
+    serde_json::from_reader(response.into_reader()).map_err(|err| err.to_string())
+}
+
+/// Weather from open-meteo.com, which doesn't need an API key.
+pub struct OpenMeteo {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Describes a WMO weather code.
+fn weather_description(code: u64) -> &'static str {
+    match code {
+        0 => "Clear",
+        1 | 2 => "Partly cloudy",
+        3 => "Overcast",
+        45 | 48 => "Fog",
+        51..=57 => "Drizzle",
+        61..=67 => "Rain",
+        71..=77 => "Snow",
+        80..=82 => "Showers",
+        85 | 86 => "Snow showers",
+        95..=99 => "Thunderstorm",
+        _ => "",
+    }
+}
+
+/// Temperature and description of an open-meteo forecast.
+fn current_weather(json: &serde_json::Value) -> Result<String, String> {
+    let current = &json["current_weather"];
+    let temperature = current["temperature"]
+        .as_f64()
+        .ok_or("Missing temperature")?;
+    let description = weather_description(current["weathercode"].as_u64().unwrap_or(u64::MAX));
+    Ok(format!("{:.0}°C {}", temperature, description)
+        .trim_end()
+        .to_owned())
+}
+
+impl Source for OpenMeteo {
+    fn fetch(&self) -> Result<String, String> {
+        current_weather(&get_json(&format!(
+            "https://api.open-meteo.com/v1/forecast?latitude={}&longitude={}&current_weather=true",
+            self.latitude, self.longitude
+        ))?)
+    }
+}
+
+pub struct CommandOutput {
+    pub command: String,
+}
+
+impl Source for CommandOutput {
+    fn fetch(&self) -> Result<String, String> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .output()
+            .map_err(|err| err.to_string())?;
+        if !output.status.success() {
+            return Err(format!("{} exited with {}", self.command, output.status));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_owned())
+    }
+}
+
+pub fn weather(provider: &WeatherProvider) -> Box<dyn Source> {
+    match provider {
+        WeatherProvider::OpenMeteo {
+            latitude,
+            longitude,
+        } => Box::new(OpenMeteo {
+            latitude: *latitude,
+            longitude: *longitude,
+        }),
+        WeatherProvider::Command { command } => Box::new(CommandOutput {
+            command: command.clone(),
+        }),
+    }
+}
+
+/// Open state from a SpaceAPI endpoint.
+pub struct SpaceApi {
+    pub url: String,
+    pub open: String,
+    pub closed: String,
+}
+
+impl SpaceApi {
+    fn state(&self, json: &serde_json::Value) -> Result<String, String> {
+        match json["state"]["open"].as_bool() {
+            Some(true) => Ok(self.open.clone()),
+            Some(false) => Ok(self.closed.clone()),
+            None => Err("Missing state.open".to_owned()),
+        }
+    }
+}
+
+impl Source for SpaceApi {
+    fn fetch(&self) -> Result<String, String> {
+        self.state(&get_json(&self.url)?)
+    }
+}
+
+/// Fetches `source` every `refresh` until the receiver is dropped.
+pub fn poll(source: Box<dyn Source>, refresh: Duration) -> Receiver<Result<String, String>> {
+    let (sender, receiver) = channel();
+    thread::Builder::new()
+        .name("Ambient widget".to_owned())
+        .spawn(move || {
+            while sender.send(source.fetch()).is_ok() {
+                thread::sleep(refresh);
+            }
+        })
+        .map_err(|err| error!("Failed spawning widget thread: {}", err))
+        .ok();
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn weather_is_described() {
+        let weather = |current| current_weather(&json!({ "current_weather": current }));
+        assert_eq!(
+            weather(json!({"temperature": 12.4, "weathercode": 63})),
+            Ok("12°C Rain".to_owned())
+        );
+        assert_eq!(
+            weather(json!({"temperature": -0.6, "weathercode": 2})),
+            Ok("-1°C Partly cloudy".to_owned())
+        );
+        assert_eq!(
+            weather(json!({"temperature": 20, "weathercode": 42})),
+            Ok("20°C".to_owned())
+        );
+        assert_eq!(weather(json!({"temperature": 20})), Ok("20°C".to_owned()));
+        assert!(weather(json!({"weathercode": 0})).is_err());
+    }
+
+    #[test]
+    fn space_state_is_shown() {
+        let space = SpaceApi {
+            url: String::new(),
+            open: "Open".to_owned(),
+            closed: "Closed".to_owned(),
+        };
+        let state = |open| space.state(&json!({ "state": { "open": open } }));
+        assert_eq!(state(json!(true)), Ok("Open".to_owned()));
+        assert_eq!(state(json!(false)), Ok("Closed".to_owned()));
+        assert!(state(json!(null)).is_err());
+    }
+
+    #[test]
+    fn commands_show_their_first_line() {
+        let output = |command: &str| {
+            CommandOutput {
+                command: command.to_owned(),
+            }
+            .fetch()
+        };
+        assert_eq!(output("printf 'one\\ntwo\\n'"), Ok("one".to_owned()));
+        assert_eq!(output("true"), Ok(String::new()));
+        assert!(output("echo broken; exit 3").is_err());
+    }
+
+    #[test]
+    fn sources_are_polled() {
+        let events = Events {
+            file: "/nonexistent/blinkenwall.ics".to_owned(),
+            count: 3,
+        };
+        let results = poll(Box::new(events), Duration::from_millis(1));
+        assert!(results.recv().unwrap().is_err());
+        assert!(results.recv().unwrap().is_err());
+    }
+}
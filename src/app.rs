@@ -0,0 +1,306 @@
+use log::{error, info};
+use std::{
+    io,
+    process::Command,
+    sync::mpsc::{channel, Receiver, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::config;
+
+/// How often to look for the result of a running health check
+const HEALTH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Runs `command` with the shell, failing if it exits unsuccessfully.
+fn run(command: &str) -> io::Result<()> {
+    let output = Command::new("sh").arg("-c").arg(command).output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "{} exited with {}: {}",
+                command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ))
+    }
+}
+
+/// Runs `command` on a thread called `name`, its result arrives on the returned channel.
+fn spawn(name: &str, command: &str) -> io::Result<Receiver<io::Result<()>>> {
+    let (sender, receiver) = channel();
+    let command = command.to_owned();
+    thread::Builder::new()
+        .name(name.to_owned())
+        .spawn(move || sender.send(run(&command)).ok())?;
+    Ok(receiver)
+}
+
+fn switch_vt(vt: u16) -> io::Result<()> {
+    if cfg!(target_os = "linux") {
+        run(&format!("/usr/bin/sudo /bin/chvt {}", vt))
+    } else {
+        Ok(())
+    }
+}
+
+/// A running external application.
+pub struct App {
+    pub name: String,
+    config: config::App,
+    started: Instant,
+    last_check: Instant,
+    /// Result of the start command while it runs in the background
+    starting: Option<Receiver<io::Result<()>>>,
+    /// Result of the health check running in the background
+    health_check: Option<Receiver<io::Result<()>>>,
+    exited: bool,
+}
+
+impl App {
+    /// Switches to the app's terminal and starts it in the background, `check` fails if the start
+    /// command does.
+    pub fn start(name: &str, config: &config::App) -> io::Result<App> {
+        if let Some(vt) = config.vt {
+            switch_vt(vt)?;
+        }
+        let starting = match spawn("App start", &config.start) {
+            Ok(starting) => starting,
+            Err(err) => {
+                if config.vt.is_some() {
+                    switch_vt(config.return_vt).unwrap_or_else(|err| error!("{}", err));
+                }
+                return Err(err);
+            }
+        };
+        Ok(App {
+            name: name.to_owned(),
+            config: config.clone(),
+            started: Instant::now(),
+            last_check: Instant::now(),
+            starting: Some(starting),
+            health_check: None,
+            exited: false,
+        })
+    }
+
+    /// Switches back to the wall's terminal, stopping the app unless `keep_running`. Does
+    /// nothing if it exited before.
+    pub fn exit(&mut self, keep_running: bool) {
+        if self.exited {
+            return;
+        }
+        self.exited = true;
+        if !(keep_running && self.config.messages) {
+            if let Some(stop) = &self.config.stop {
+                match run(stop) {
+                    Ok(()) => info!("Stopped {}", self.name),
+                    Err(err) => error!("Failed stopping {}: {}", self.name, err),
+                }
+            }
+        }
+        if self.config.vt.is_some() {
+            switch_vt(self.config.return_vt)
+                .unwrap_or_else(|err| error!("Failed switching back from {}: {}", self.name, err));
+        }
+    }
+
+    /// Whether the app started, is within its time limit and its health checks pass. Checks run
+    /// in the background, one that takes longer than the interval between them fails.
+    pub fn check(&mut self) -> bool {
+        if let Some(timeout) = self.config.timeout {
+            if self.started.elapsed() >= Duration::from_secs(timeout) {
+                info!("{} timed out", self.name);
+                return false;
+            }
+        }
+        if let Some(receiver) = &self.starting {
+            let result = match receiver.try_recv() {
+                Ok(result) => result,
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "start command did not report back",
+                )),
+            };
+            self.starting = None;
+            if let Err(err) = result {
+                error!("Failed starting {}: {}", self.name, err);
+                // nothing to stop, just give the terminal back
+                if self.config.vt.is_some() {
+                    switch_vt(self.config.return_vt).unwrap_or_else(|err| error!("{}", err));
+                }
+                self.exited = true;
+                return false;
+            }
+            info!("Started {}", self.name);
+            self.last_check = Instant::now();
+        }
+        let health = match &self.config.health {
+            Some(health) => health,
+            None => return true,
+        };
+        let interval = Duration::from_secs(self.config.health_interval);
+        if let Some(receiver) = &self.health_check {
+            match receiver.try_recv() {
+                Ok(Ok(())) | Err(TryRecvError::Disconnected) => self.health_check = None,
+                Ok(Err(err)) => {
+                    error!("Health check of {} failed: {}", self.name, err);
+                    return false;
+                }
+                Err(TryRecvError::Empty) => {
+                    if self.last_check.elapsed() >= interval {
+                        error!("Health check of {} did not finish in time", self.name);
+                        return false;
+                    }
+                }
+            }
+        } else if self.last_check.elapsed() >= interval {
+            self.last_check = Instant::now();
+            match spawn("Health check", health) {
+                Ok(receiver) => self.health_check = Some(receiver),
+                Err(err) => error!("Failed starting health check of {}: {}", self.name, err),
+            }
+        }
+        true
+    }
+
+    /// Time until `check` has something to do, `None` if never.
+    pub fn next_check(&self) -> Option<Duration> {
+        let timeout = self
+            .config
+            .timeout
+            .map(|timeout| Duration::from_secs(timeout).saturating_sub(self.started.elapsed()));
+        if self.starting.is_some() {
+            return Some(HEALTH_POLL_INTERVAL);
+        }
+        let health = self.config.health.as_ref().map(|_| {
+            if self.health_check.is_some() {
+                HEALTH_POLL_INTERVAL
+            } else {
+                Duration::from_secs(self.config.health_interval)
+                    .saturating_sub(self.last_check.elapsed())
+            }
+        });
+        timeout.into_iter().chain(health).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn config(health: Option<&str>, timeout: Option<u64>) -> config::App {
+        config::App {
+            start: "true".to_owned(),
+            stop: None,
+            vt: None,
+            return_vt: 1,
+            health: health.map(str::to_owned),
+            health_interval: 1,
+            timeout,
+            messages: false,
+        }
+    }
+
+    /// Starts `config` and checks until the start command finished, returns the app if it did.
+    fn start(config: &config::App) -> Option<App> {
+        let mut app = App::start("app", config).unwrap();
+        let started = Instant::now();
+        while app.starting.is_some() && started.elapsed() < Duration::from_secs(5) {
+            if !app.check() {
+                return None;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        Some(app)
+    }
+
+    /// Starts a health check and checks until it's done, returns the last result.
+    fn settle(app: &mut App) -> bool {
+        app.last_check -= Duration::from_secs(1);
+        let started = Instant::now();
+        loop {
+            if !app.check() {
+                return false;
+            }
+            if app.health_check.is_none() || started.elapsed() > Duration::from_secs(5) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn failing_start_is_reported() {
+        let mut config = config(None, None);
+        config.start = "false".to_owned();
+        assert!(start(&config).is_none());
+    }
+
+    #[test]
+    fn start_does_not_block() {
+        let mut config = config(Some("false"), None);
+        config.start = "sleep 5".to_owned();
+        let started = Instant::now();
+        let mut app = App::start("app", &config).unwrap();
+        app.last_check -= Duration::from_secs(1);
+        assert!(app.check());
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(app.next_check(), Some(HEALTH_POLL_INTERVAL));
+        // no health checks before the app is up
+        assert!(app.health_check.is_none());
+    }
+
+    #[test]
+    fn passing_health_checks_keep_it_running() {
+        let mut app = start(&config(Some("true"), None)).unwrap();
+        assert!(app.check());
+        assert!(app.health_check.is_none(), "not due yet");
+        assert!(settle(&mut app));
+        assert!(app.health_check.is_none());
+    }
+
+    #[test]
+    fn failing_health_check_stops_it() {
+        let mut app = start(&config(Some("false"), None)).unwrap();
+        assert!(!settle(&mut app));
+    }
+
+    #[test]
+    fn health_checks_do_not_block() {
+        let mut app = start(&config(Some("sleep 5"), None)).unwrap();
+        app.last_check -= Duration::from_secs(1);
+        let started = Instant::now();
+        assert!(app.check());
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(app.next_check(), Some(HEALTH_POLL_INTERVAL));
+        // still running after the interval counts as failed
+        app.last_check -= Duration::from_secs(1);
+        assert!(!app.check());
+    }
+
+    #[test]
+    fn timeout_stops_it() {
+        let mut app = App::start("app", &config(None, Some(0))).unwrap();
+        assert!(!app.check());
+        assert_eq!(start(&config(None, None)).unwrap().next_check(), None);
+    }
+
+    #[test]
+    fn exits_once() {
+        let mut config = config(None, None);
+        let directory = TempDir::new("app");
+        let marker = directory.join("marker");
+        config.stop = Some(format!("echo stopped >> {}", marker.display()));
+        let mut app = App::start("app", &config).unwrap();
+        app.exit(false);
+        app.exit(false);
+        assert_eq!(std::fs::read_to_string(&marker).unwrap(), "stopped\n");
+    }
+}
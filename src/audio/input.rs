@@ -0,0 +1,50 @@
+use cpal::traits::{DeviceTrait, StreamTrait};
+use log::error;
+use std::io;
+
+use super::{audio_error, Audio, Buffers, Listeners};
+
+/// Records the configured channel of the input device.
+pub fn record(audio: &Audio, listeners: Listeners) -> io::Result<cpal::Stream> {
+    let device = audio.input_device()?;
+    let supported = device.default_input_config().map_err(audio_error)?;
+    let channels = supported.channels();
+    if audio.config.channel >= channels {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("The audio input only has {} channels", channels),
+        ));
+    }
+    let config = cpal::StreamConfig {
+        channels,
+        sample_rate: cpal::SampleRate(audio.config.sample_rate),
+        buffer_size: cpal::BufferSize::Default,
+    };
+    let mut buffers = Buffers::new(
+        audio.fft_size(),
+        channels as usize,
+        Some(audio.config.channel as usize),
+        listeners,
+    );
+    let error = |err: cpal::StreamError| error!("Audio input failed: {}", err);
+    let stream = match supported.sample_format() {
+        cpal::SampleFormat::F32 => device.build_input_stream(
+            &config,
+            move |data: &[f32], _: &cpal::InputCallbackInfo| buffers.push(data),
+            error,
+        ),
+        cpal::SampleFormat::I16 => device.build_input_stream(
+            &config,
+            move |data: &[i16], _: &cpal::InputCallbackInfo| buffers.push(data),
+            error,
+        ),
+        cpal::SampleFormat::U16 => device.build_input_stream(
+            &config,
+            move |data: &[u16], _: &cpal::InputCallbackInfo| buffers.push(data),
+            error,
+        ),
+    }
+    .map_err(audio_error)?;
+    stream.play().map_err(audio_error)?;
+    Ok(stream)
+}
@@ -0,0 +1,457 @@
+use atomicring::AtomicRingBuffer;
+use cpal::traits::{DeviceTrait, HostTrait};
+use log::error;
+use serde::Serialize;
+use std::{
+    error::Error,
+    io, mem,
+    rc::{self, Rc},
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+
+use crate::config;
+
+mod input;
+mod playback;
+mod player;
+
+pub use self::player::Player;
+
+/// Smallest FFT that still has a useful spectrum.
+const MIN_FFT_SIZE: usize = 64;
+
+fn audio_error<E: Into<Box<dyn Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err)
+}
+
+/// A device as listed over the API.
+#[derive(Serialize)]
+pub struct Device {
+    pub name: String,
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Whether it is used when no device is configured
+    pub default: bool,
+}
+
+impl Device {
+    fn describe(
+        device: &cpal::Device,
+        config: Result<cpal::SupportedStreamConfig, cpal::DefaultStreamConfigError>,
+        default: Option<&str>,
+    ) -> Option<Device> {
+        let config = config.ok()?;
+        let name = device.name().ok()?;
+        Some(Device {
+            default: default == Some(name.as_str()),
+            name,
+            channels: config.channels(),
+            sample_rate: config.sample_rate().0,
+        })
+    }
+}
+
+/// The device called `name`, or else the first with `name` in its name, the default one if
+/// `name` is not set.
+fn find_device<I: Iterator<Item = cpal::Device>>(
+    devices: I,
+    default: Option<cpal::Device>,
+    name: Option<&str>,
+) -> io::Result<cpal::Device> {
+    let name = match name {
+        Some(name) => name,
+        None => {
+            return default
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No default audio device"))
+        }
+    };
+    let mut partial = None;
+    for device in devices {
+        let device_name = device.name().unwrap_or_default();
+        if device_name == name {
+            return Ok(device);
+        }
+        if partial.is_none() && device_name.contains(name) {
+            partial = Some(device);
+        }
+    }
+    partial.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No audio device called {}", name),
+        )
+    })
+}
+
+/// Master volume applied to everything played, shared with the audio threads.
+struct Mixer {
+    volume: AtomicU8,
+    muted: AtomicBool,
+    ducked: AtomicBool,
+    /// Share of the volume left while ducked
+    duck: f32,
+}
+
+impl Mixer {
+    fn new(config: &config::Audio) -> Self {
+        Self {
+            volume: AtomicU8::new(config.volume.min(100)),
+            muted: AtomicBool::new(false),
+            ducked: AtomicBool::new(false),
+            duck: config.duck.clamp(0.0, 1.0),
+        }
+    }
+
+    fn volume(&self) -> u8 {
+        self.volume.load(Ordering::SeqCst)
+    }
+
+    fn set_volume(&self, volume: u8) {
+        self.volume.store(volume.min(100), Ordering::SeqCst);
+    }
+
+    fn adjust_volume(&self, delta: i16) -> u8 {
+        let volume = (self.volume() as i16 + delta).clamp(0, 100) as u8;
+        self.set_volume(volume);
+        volume
+    }
+
+    /// Factor samples are scaled by.
+    fn gain(&self) -> f32 {
+        if self.muted.load(Ordering::SeqCst) {
+            return 0.0;
+        }
+        let gain = self.volume() as f32 / 100.0;
+        if self.ducked.load(Ordering::SeqCst) {
+            gain * self.duck
+        } else {
+            gain
+        }
+    }
+}
+
+type Listeners = Arc<Mutex<Vec<Weak<AtomicRingBuffer<Vec<f32>>>>>>;
+
+/// Cuts recorded or played samples into mono buffers for everyone listening.
+struct Buffers {
+    frames: usize,
+    channels: usize,
+    /// Channel to take, all of them mixed down if not set
+    channel: Option<usize>,
+    pending: Vec<f32>,
+    listeners: Listeners,
+}
+
+impl Buffers {
+    fn new(frames: usize, channels: usize, channel: Option<usize>, listeners: Listeners) -> Self {
+        Self {
+            frames,
+            channels,
+            channel,
+            pending: Vec::with_capacity(frames),
+            listeners,
+        }
+    }
+
+    /// Takes interleaved `samples`.
+    fn push<T: cpal::Sample>(&mut self, samples: &[T]) {
+        for frame in samples.chunks_exact(self.channels) {
+            let sample = match self.channel {
+                Some(channel) => frame[channel].to_f32(),
+                None => frame.iter().map(cpal::Sample::to_f32).sum::<f32>() / self.channels as f32,
+            };
+            self.pending.push(sample);
+            if self.pending.len() == self.frames {
+                let buffer = mem::replace(&mut self.pending, Vec::with_capacity(self.frames));
+                let mut listeners = self.listeners.lock().unwrap();
+                listeners.retain(|listener| listener.strong_count() > 0);
+                for listener in listeners.iter().filter_map(Weak::upgrade) {
+                    listener.push_overwrite(buffer.clone());
+                }
+            }
+        }
+    }
+}
+
+enum Source {
+    Input(cpal::Stream),
+    Playback(playback::Playback),
+}
+
+/// The open source, closed once nobody listens anymore.
+struct Feed {
+    _source: Source,
+    listeners: Listeners,
+}
+
+/// Buffers of `Audio::fft_size` samples of the configured source.
+pub struct Listener {
+    _feed: Rc<Feed>,
+    buffers: Arc<AtomicRingBuffer<Vec<f32>>>,
+}
+
+impl Listener {
+    /// Next buffer, the oldest are dropped when they aren't taken in time.
+    pub fn next_buffer(&self) -> Option<Vec<f32>> {
+        self.buffers.try_pop()
+    }
+}
+
+/// Owns the audio devices, the source shaders listen to and the master volume of everything
+/// the wall plays.
+pub struct Audio {
+    config: config::Audio,
+    host: cpal::Host,
+    mixer: Arc<Mixer>,
+    feed: rc::Weak<Feed>,
+}
+
+impl Audio {
+    pub fn new(config: &config::Audio) -> Self {
+        Self {
+            config: config.clone(),
+            host: cpal::default_host(),
+            mixer: Arc::new(Mixer::new(config)),
+            feed: rc::Weak::new(),
+        }
+    }
+
+    pub fn config(&self) -> &config::Audio {
+        &self.config
+    }
+
+    /// Samples per buffer handed to listeners.
+    pub fn fft_size(&self) -> usize {
+        self.config.fft_size.max(MIN_FFT_SIZE)
+    }
+
+    fn input_device(&self) -> io::Result<cpal::Device> {
+        find_device(
+            self.host.input_devices().map_err(audio_error)?,
+            self.host.default_input_device(),
+            self.config.device.as_deref(),
+        )
+    }
+
+    fn output_device(&self) -> io::Result<cpal::Device> {
+        find_device(
+            self.host.output_devices().map_err(audio_error)?,
+            self.host.default_output_device(),
+            self.config.output.as_deref(),
+        )
+    }
+
+    /// Devices that can record.
+    pub fn inputs(&self) -> io::Result<Vec<Device>> {
+        let default = self
+            .host
+            .default_input_device()
+            .and_then(|device| device.name().ok());
+        Ok(self
+            .host
+            .input_devices()
+            .map_err(audio_error)?
+            .filter_map(|device| {
+                Device::describe(&device, device.default_input_config(), default.as_deref())
+            })
+            .collect())
+    }
+
+    /// Devices that can play.
+    pub fn outputs(&self) -> io::Result<Vec<Device>> {
+        let default = self
+            .host
+            .default_output_device()
+            .and_then(|device| device.name().ok());
+        Ok(self
+            .host
+            .output_devices()
+            .map_err(audio_error)?
+            .filter_map(|device| {
+                Device::describe(&device, device.default_output_config(), default.as_deref())
+            })
+            .collect())
+    }
+
+    fn open(&self) -> io::Result<Feed> {
+        let listeners = Listeners::default();
+        let source = match &self.config.source {
+            Some(source) => {
+                Source::Playback(playback::Playback::new(self, source, listeners.clone())?)
+            }
+            None => Source::Input(input::record(self, listeners.clone())?),
+        };
+        Ok(Feed {
+            _source: source,
+            listeners,
+        })
+    }
+
+    /// Listens to the configured source, which stays open while anyone listens. `None` if it
+    /// can't be opened.
+    pub fn listen(&mut self) -> Option<Listener> {
+        let feed = match self.feed.upgrade() {
+            Some(feed) => feed,
+            None => {
+                let feed = Rc::new(
+                    self.open()
+                        .map_err(|err| {
+                            error!("Failed opening audio source, using silence: {}", err)
+                        })
+                        .ok()?,
+                );
+                self.feed = Rc::downgrade(&feed);
+                feed
+            }
+        };
+        let buffers = Arc::new(AtomicRingBuffer::with_capacity(8));
+        feed.listeners
+            .lock()
+            .unwrap()
+            .push(Arc::downgrade(&buffers));
+        Some(Listener {
+            _feed: feed,
+            buffers,
+        })
+    }
+
+    /// Plays interleaved stereo samples at `sample_rate`.
+    pub fn player(&self, sample_rate: u32) -> io::Result<Player> {
+        Player::new(&self.output_device()?, sample_rate, self.mixer.clone())
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.mixer.volume()
+    }
+
+    pub fn set_volume(&self, volume: u8) {
+        self.mixer.set_volume(volume);
+    }
+
+    /// Changes the volume relative to the current one, returning the new volume.
+    pub fn adjust_volume(&self, delta: i16) -> u8 {
+        self.mixer.adjust_volume(delta)
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.mixer.muted.store(muted, Ordering::SeqCst);
+    }
+
+    /// Turns everything down to the configured share, like while another program announces
+    /// something.
+    pub fn set_ducked(&self, ducked: bool) {
+        self.mixer.ducked.store(ducked, Ordering::SeqCst);
+    }
+
+    pub fn muted(&self) -> bool {
+        self.mixer.muted.load(Ordering::SeqCst)
+    }
+
+    pub fn ducked(&self) -> bool {
+        self.mixer.ducked.load(Ordering::SeqCst)
+    }
+
+    /// The configured output as an mpv `audio-device`, matched the same way as for cpal. cpal
+    /// plays through ALSA, so its device names are the ones of mpv's ALSA output. `None` keeps
+    /// mpv's default, also when the configured output isn't found.
+    pub fn mpv_device(&self) -> Option<String> {
+        self.config.output.as_ref()?;
+        match self
+            .output_device()
+            .and_then(|device| device.name().map_err(audio_error))
+        {
+            Ok(name) => Some(format!("alsa/{}", name)),
+            Err(error) => {
+                error!("Keeping the default output for mpv: {}", error);
+                None
+            }
+        }
+    }
+
+    /// Volume from 0 to 100 for players mixing themselves, like mpv.
+    pub fn output_volume(&self) -> i64 {
+        (self.mixer.gain() * 100.0).round() as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mixer(volume: u8, duck: f32) -> Mixer {
+        Mixer::new(&config::Audio {
+            volume,
+            duck,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn gain_follows_volume_mute_and_ducking() {
+        let mixer = mixer(80, 0.25);
+        assert_eq!(mixer.gain(), 0.8);
+        mixer.ducked.store(true, Ordering::SeqCst);
+        assert_eq!(mixer.gain(), 0.2);
+        mixer.muted.store(true, Ordering::SeqCst);
+        assert_eq!(mixer.gain(), 0.0);
+        mixer.ducked.store(false, Ordering::SeqCst);
+        assert_eq!(mixer.gain(), 0.0);
+        mixer.muted.store(false, Ordering::SeqCst);
+        assert_eq!(mixer.gain(), 0.8);
+    }
+
+    #[test]
+    fn configured_levels_are_clamped() {
+        let mixer = self::mixer(150, 2.0);
+        assert_eq!(mixer.volume(), 100);
+        assert_eq!(mixer.duck, 1.0);
+        assert_eq!(self::mixer(50, -1.0).duck, 0.0);
+    }
+
+    #[test]
+    fn volume_stays_in_range() {
+        let mixer = mixer(50, 0.5);
+        assert_eq!(mixer.adjust_volume(-20), 30);
+        assert_eq!(mixer.adjust_volume(-40), 0);
+        assert_eq!(mixer.adjust_volume(120), 100);
+        mixer.set_volume(200);
+        assert_eq!(mixer.volume(), 100);
+    }
+
+    #[test]
+    fn buffers_take_one_channel_or_mix_down() {
+        let listeners = Listeners::default();
+        let buffers = Arc::new(AtomicRingBuffer::with_capacity(4));
+        listeners.lock().unwrap().push(Arc::downgrade(&buffers));
+
+        let mut right = Buffers::new(2, 2, Some(1), listeners.clone());
+        right.push(&[0.1f32, 0.5, 0.3, -0.5, 0.7]);
+        assert_eq!(buffers.try_pop(), Some(vec![0.5, -0.5]));
+        // the dangling sample isn't a whole frame
+        assert!(right.pending.is_empty());
+
+        let mut mixed = Buffers::new(3, 2, None, listeners);
+        mixed.push(&[0.5f32, -0.5, 1.0, 0.0]);
+        assert_eq!(buffers.try_pop(), None);
+        mixed.push(&[0.25f32, 0.25]);
+        assert_eq!(buffers.try_pop(), Some(vec![0.0, 0.5, 0.25]));
+    }
+
+    #[test]
+    fn dropped_listeners_are_forgotten() {
+        let listeners = Listeners::default();
+        let kept = Arc::new(AtomicRingBuffer::with_capacity(4));
+        let dropped = Arc::new(AtomicRingBuffer::with_capacity(4));
+        listeners.lock().unwrap().push(Arc::downgrade(&kept));
+        listeners.lock().unwrap().push(Arc::downgrade(&dropped));
+        drop(dropped);
+
+        let mut buffers = Buffers::new(1, 1, None, listeners.clone());
+        buffers.push(&[0.5f32]);
+        assert_eq!(kept.try_pop(), Some(vec![0.5]));
+        assert_eq!(listeners.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,254 @@
+use cpal::traits::{DeviceTrait, StreamTrait};
+use log::{error, info};
+use ringbuf::{Producer, RingBuffer};
+use std::{
+    io::{self, Read},
+    process::{Child, ChildStdout, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use super::{audio_error, Audio, Buffers, Listeners};
+use crate::config::AudioSource;
+
+/// Channels ffmpeg decodes to and the output plays
+const CHANNELS: usize = 2;
+/// Bytes read from ffmpeg at once
+const CHUNK: usize = 4096;
+/// How long to wait before the next entry when one played nothing, so a broken playlist or a
+/// stream that is down isn't retried in a tight loop
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Where decoded samples go.
+enum Sink {
+    /// To the speakers, which pass them on to the shader as they play them
+    Play(Producer<f32>),
+    /// Straight to the shader, for sources already heard elsewhere
+    Analyse(Buffers),
+}
+
+fn ffmpeg(input: &[String], sample_rate: u32) -> io::Result<Child> {
+    Command::new("ffmpeg")
+        .args(["-loglevel", "error", "-nostdin"])
+        .args(input)
+        .args(["-f", "f32le", "-ac", &CHANNELS.to_string()])
+        .args(["-ar", &sample_rate.to_string(), "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+}
+
+/// ffmpeg arguments opening each entry of `source`, and whether it should be played on the
+/// output.
+fn inputs(source: &AudioSource) -> (Vec<Vec<String>>, bool) {
+    match source {
+        AudioSource::Playlist { files } => (
+            files
+                .iter()
+                .map(|file| vec!["-i".to_owned(), file.clone()])
+                .collect(),
+            true,
+        ),
+        AudioSource::Stream { url } => (
+            vec![vec![
+                "-reconnect".to_owned(),
+                "1".to_owned(),
+                "-reconnect_streamed".to_owned(),
+                "1".to_owned(),
+                "-i".to_owned(),
+                url.clone(),
+            ]],
+            true,
+        ),
+        AudioSource::Monitor { source } => (
+            vec![vec![
+                "-f".to_owned(),
+                "pulse".to_owned(),
+                "-i".to_owned(),
+                source.clone(),
+            ]],
+            false,
+        ),
+    }
+}
+
+/// Passes the samples ffmpeg decodes on to `sink` until it ends or playback stops. Returns
+/// whether there were any.
+fn forward(stdout: &mut ChildStdout, sink: &mut Sink, stop: &AtomicBool) -> bool {
+    let mut bytes = [0u8; CHUNK];
+    let mut samples = Vec::with_capacity(CHUNK / 4);
+    let mut decoded = false;
+    while !stop.load(Ordering::SeqCst) && stdout.read_exact(&mut bytes).is_ok() {
+        decoded = true;
+        samples.clear();
+        samples.extend(
+            bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        );
+        match sink {
+            Sink::Play(producer) => {
+                // files decode faster than they play, so wait for the speakers
+                let mut rest = &samples[..];
+                while !rest.is_empty() && !stop.load(Ordering::SeqCst) {
+                    rest = &rest[producer.push_slice(rest)..];
+                    if !rest.is_empty() {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                }
+            }
+            Sink::Analyse(buffers) => buffers.push(&samples),
+        }
+    }
+    decoded
+}
+
+/// Decodes the `inputs` one after another, over and over, until playback stops.
+fn decode(
+    inputs: Vec<Vec<String>>,
+    sample_rate: u32,
+    mut sink: Sink,
+    child: Arc<Mutex<Option<Child>>>,
+    stop: Arc<AtomicBool>,
+) {
+    for input in inputs.iter().cycle() {
+        if stop.load(Ordering::SeqCst) {
+            return;
+        }
+        let name = input.last().map_or("", String::as_str);
+        let mut process = match ffmpeg(input, sample_rate) {
+            Ok(process) => process,
+            Err(err) => {
+                error!("Failed starting ffmpeg for {}: {}", name, err);
+                return;
+            }
+        };
+        info!("Playing {}", name);
+        let mut stdout = process.stdout.take().unwrap();
+        *child.lock().unwrap() = Some(process);
+        let decoded = forward(&mut stdout, &mut sink, &stop);
+        if let Some(mut process) = child.lock().unwrap().take() {
+            process.kill().ok();
+            process.wait().ok();
+        }
+        if !decoded {
+            error!("Nothing to play from {}", name);
+            thread::sleep(RETRY_DELAY);
+        }
+    }
+}
+
+/// Audio decoded by ffmpeg from files, streams or a PulseAudio source, played on the output
+/// unless it's a source that is already heard.
+pub struct Playback {
+    _stream: Option<cpal::Stream>,
+    child: Arc<Mutex<Option<Child>>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Playback {
+    /// Starts playing `source`, passing on what is heard to the `listeners`.
+    pub fn new(audio: &Audio, source: &AudioSource, listeners: Listeners) -> io::Result<Playback> {
+        let sample_rate = audio.config.sample_rate;
+        let (inputs, audible) = inputs(source);
+        if inputs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The playlist is empty",
+            ));
+        }
+
+        let mut buffers = Buffers::new(audio.fft_size(), CHANNELS, None, listeners);
+        let (stream, sink) = if audible {
+            // a second of audio evens out streams arriving in bursts
+            let (producer, mut consumer) = RingBuffer::new(sample_rate as usize * CHANNELS).split();
+            let device = audio.output_device()?;
+            let mixer = audio.mixer.clone();
+            let config = cpal::StreamConfig {
+                channels: CHANNELS as u16,
+                sample_rate: cpal::SampleRate(sample_rate),
+                buffer_size: cpal::BufferSize::Default,
+            };
+            let stream = device
+                .build_output_stream(
+                    &config,
+                    move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                        for sample in data.iter_mut() {
+                            *sample = consumer.pop().unwrap_or(0.0);
+                        }
+                        // the shader follows what is heard, whatever the volume
+                        buffers.push(data);
+                        let gain = mixer.gain();
+                        for sample in data.iter_mut() {
+                            *sample *= gain;
+                        }
+                    },
+                    |err| error!("Audio playback failed: {}", err),
+                )
+                .map_err(audio_error)?;
+            stream.play().map_err(audio_error)?;
+            (Some(stream), Sink::Play(producer))
+        } else {
+            (None, Sink::Analyse(buffers))
+        };
+
+        let child = Arc::new(Mutex::new(None));
+        let stop = Arc::new(AtomicBool::new(false));
+        {
+            let (child, stop) = (child.clone(), stop.clone());
+            thread::Builder::new()
+                .name("Audio decoder".to_owned())
+                .spawn(move || decode(inputs, sample_rate, sink, child, stop))?;
+        }
+        Ok(Playback {
+            _stream: stream,
+            child,
+            stop,
+        })
+    }
+}
+
+impl Drop for Playback {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // unblocks the decoder waiting for a stream
+        if let Some(process) = self.child.lock().unwrap().as_mut() {
+            process.kill().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_become_ffmpeg_inputs() {
+        let (entries, audible) = inputs(&AudioSource::Playlist {
+            files: vec!["a.mp3".to_owned(), "http://radio/b.ogg".to_owned()],
+        });
+        assert_eq!(entries, [["-i", "a.mp3"], ["-i", "http://radio/b.ogg"]]);
+        assert!(audible);
+
+        let (entries, audible) = inputs(&AudioSource::Stream {
+            url: "http://radio/live".to_owned(),
+        });
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0][..2], ["-reconnect", "1"]);
+        assert_eq!(entries[0].last().unwrap(), "http://radio/live");
+        assert!(audible);
+
+        let (entries, audible) = inputs(&AudioSource::Monitor {
+            source: "speakers.monitor".to_owned(),
+        });
+        assert_eq!(entries, [["-f", "pulse", "-i", "speakers.monitor"]]);
+        assert!(!audible);
+
+        let (entries, _) = inputs(&AudioSource::Playlist { files: Vec::new() });
+        assert!(entries.is_empty());
+    }
+}
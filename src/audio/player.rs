@@ -1,18 +1,22 @@
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use log::error;
 use ringbuf::{Producer, RingBuffer};
+use std::{io, sync::Arc};
 
-pub struct AudioPlayer {
+use super::{audio_error, Mixer};
+
+/// Plays samples as they are produced, like the emulator's.
+pub struct Player {
     buffer_producer: Producer<f32>,
-    output_stream: cpal::Stream,
+    _output_stream: cpal::Stream,
 }
 
-impl AudioPlayer {
-    pub fn new(sample_rate: u32) -> Self {
-        let host = cpal::default_host();
-        let output_device = host
-            .default_output_device()
-            .expect("failed to get default output audio device");
-
+impl Player {
+    pub(super) fn new(
+        output_device: &cpal::Device,
+        sample_rate: u32,
+        mixer: Arc<Mixer>,
+    ) -> io::Result<Self> {
         let config = cpal::StreamConfig {
             channels: 2,
             sample_rate: cpal::SampleRate(sample_rate),
@@ -29,40 +33,27 @@ impl AudioPlayer {
         let (buffer_producer, mut buffer_consumer) = buffer.split();
 
         let output_data_fn = move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+            // /5.5 to reduce the volume of the sample
+            let gain = mixer.gain() / 5.5;
             for sample in data {
-                // /5.5 to reduce the volume of the sample
-                *sample = buffer_consumer.pop().unwrap_or(0.) / 5.5;
+                *sample = buffer_consumer.pop().unwrap_or(0.) * gain;
             }
         };
 
         let output_stream = output_device
-            .build_output_stream(&config, output_data_fn, Self::err_fn)
-            .expect("failed to build an output audio stream");
+            .build_output_stream(&config, output_data_fn, |err| {
+                error!("Audio playback failed: {}", err)
+            })
+            .map_err(audio_error)?;
+        output_stream.play().map_err(audio_error)?;
 
-        Self {
+        Ok(Self {
             buffer_producer,
-            output_stream,
-        }
-    }
-
-    pub fn play(&self) {
-        self.output_stream.play().unwrap();
-    }
-
-    /// Pause the player
-    /// > not used for now, but maybe later
-    #[allow(dead_code)]
-    pub fn pause(&self) {
-        self.output_stream.pause().unwrap();
+            _output_stream: output_stream,
+        })
     }
 
     pub fn queue(&mut self, data: &[f32]) {
         self.buffer_producer.push_slice(data);
     }
 }
-
-impl AudioPlayer {
-    fn err_fn(err: cpal::StreamError) {
-        eprintln!("an error occurred on audio stream: {}", err);
-    }
-}
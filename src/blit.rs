@@ -0,0 +1,226 @@
+use glium::{
+    backend::glutin::Display,
+    draw_parameters::{Blend, BlendingFunction, LinearBlendingFactor},
+    index::PrimitiveType,
+    texture::texture2d::Texture2d,
+    uniform,
+    uniforms::{MagnifySamplerFilter, MinifySamplerFilter, Sampler, Uniforms},
+    DrawParameters, Surface, {implement_vertex, program},
+};
+
+/// Vertex shader for programs drawn with `Blit::draw_quad`, covering the whole target.
+pub const QUAD_VERTEX_SHADER: &str = "#version 140
+
+in vec2 position;
+in vec2 texcoords;
+
+out vec2 vTexCoords;
+
+void main() {
+    gl_Position = vec4(position, 0.0, 1.0);
+    vTexCoords = texcoords;
+}
+";
+
+const VERTEX_SHADER: &str = "#version 140
+
+in vec2 position;
+in vec2 texcoords;
+
+out vec2 vTexCoords;
+
+uniform vec4 bounds;
+
+void main() {
+    gl_Position = vec4((bounds.xy + (position * 0.5 + 0.5) * bounds.zw) * 2.0 - 1.0, 0.0, 1.0);
+    vTexCoords = texcoords;
+}
+";
+
+const FRAGMENT_SHADER: &str = "#version 140
+
+in vec2 vTexCoords;
+out vec4 fragColor;
+
+uniform sampler2D tex;
+uniform float opacity;
+uniform bool translucent;
+
+void main() {
+    vec4 color = texture(tex, vTexCoords);
+    if (!translucent) {
+        color.a = 1.0;
+    }
+    // translucent textures are expected to be premultiplied, like alpha blended renderings
+    // into a transparent target
+    fragColor = color * opacity;
+}
+";
+
+/// How a drawn texture is combined with what is already in the target.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendMode {
+    Normal,
+    Add,
+    Multiply,
+}
+
+impl Default for BlendMode {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
+impl BlendMode {
+    fn blend(self) -> Blend {
+        let (source, destination) = match self {
+            Self::Normal => (
+                LinearBlendingFactor::One,
+                LinearBlendingFactor::OneMinusSourceAlpha,
+            ),
+            Self::Add => (LinearBlendingFactor::One, LinearBlendingFactor::One),
+            Self::Multiply => (
+                LinearBlendingFactor::DestinationColor,
+                LinearBlendingFactor::OneMinusSourceAlpha,
+            ),
+        };
+        let function = BlendingFunction::Addition {
+            source,
+            destination,
+        };
+        Blend {
+            color: function,
+            alpha: function,
+            constant_value: (0.0, 0.0, 0.0, 0.0),
+        }
+    }
+}
+
+/// Where and how `Blit::draw_with` draws a texture.
+#[derive(Clone, Copy, Debug)]
+pub struct Placement {
+    /// x, y, width and height as fractions of the target, from the bottom left corner
+    pub bounds: [f32; 4],
+    pub opacity: f32,
+    /// Use the (premultiplied) alpha channel of the texture, otherwise it is drawn opaque
+    pub translucent: bool,
+    pub blend: BlendMode,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Self {
+            bounds: [0.0, 0.0, 1.0, 1.0],
+            opacity: 1.0,
+            translucent: false,
+            blend: BlendMode::Normal,
+        }
+    }
+}
+
+/// Corner of a textured quad, `position` from -1 to 1 and `texcoords` from 0 to 1.
+#[derive(Copy, Clone)]
+pub struct Vertex {
+    pub position: [f32; 2],
+    pub texcoords: [f32; 2],
+}
+
+implement_vertex!(Vertex, position, texcoords);
+
+/// Draws a texture stretched over the whole target, or a part of it.
+pub struct Blit {
+    program: glium::Program,
+    vertex_buffer: glium::VertexBuffer<Vertex>,
+    index_buffer: glium::IndexBuffer<u16>,
+}
+
+impl Blit {
+    pub fn new(display: &Display) -> Self {
+        let vertex_buffer = glium::VertexBuffer::new(
+            display,
+            &[
+                Vertex {
+                    position: [-1.0, -1.0],
+                    texcoords: [0.0, 0.0],
+                },
+                Vertex {
+                    position: [-1.0, 1.0],
+                    texcoords: [0.0, 1.0],
+                },
+                Vertex {
+                    position: [1.0, 1.0],
+                    texcoords: [1.0, 1.0],
+                },
+                Vertex {
+                    position: [1.0, -1.0],
+                    texcoords: [1.0, 0.0],
+                },
+            ],
+        )
+        .unwrap();
+        let index_buffer = glium::IndexBuffer::new(
+            display,
+            PrimitiveType::TrianglesList,
+            &[0u16, 1, 2, 2, 3, 0],
+        )
+        .unwrap();
+        let program =
+            program!(display, 140 => { vertex: VERTEX_SHADER, fragment: FRAGMENT_SHADER }).unwrap();
+
+        Self {
+            program,
+            vertex_buffer,
+            index_buffer,
+        }
+    }
+
+    pub fn draw<S: Surface>(&self, target: &mut S, texture: &Texture2d) {
+        self.draw_with(target, texture, &Placement::default());
+    }
+
+    pub fn draw_with<S: Surface>(
+        &self,
+        target: &mut S,
+        texture: &Texture2d,
+        placement: &Placement,
+    ) {
+        let uniforms = uniform! {
+            tex: Sampler::new(texture)
+                .minify_filter(MinifySamplerFilter::Linear)
+                .magnify_filter(MagnifySamplerFilter::Linear),
+            bounds: placement.bounds,
+            opacity: placement.opacity,
+            translucent: placement.translucent,
+        };
+        self.draw_quad(
+            target,
+            &self.program,
+            &uniforms,
+            &DrawParameters {
+                blend: placement.blend.blend(),
+                ..Default::default()
+            },
+        );
+    }
+
+    /// Draws the quad covering the whole target with `program`, which gets `position` and
+    /// `texcoords` of the corners.
+    pub fn draw_quad<S: Surface, U: Uniforms>(
+        &self,
+        target: &mut S,
+        program: &glium::Program,
+        uniforms: &U,
+        parameters: &DrawParameters,
+    ) {
+        target
+            .draw(
+                &self.vertex_buffer,
+                &self.index_buffer,
+                program,
+                uniforms,
+                parameters,
+            )
+            .unwrap();
+    }
+}
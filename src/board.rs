@@ -0,0 +1,328 @@
+use std::{
+    convert::TryFrom,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+use chrono::Utc;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Message {
+    pub id: u64,
+    pub text: String,
+    /// Pinned messages don't expire and come up between each of the others
+    #[serde(default)]
+    pub pinned: bool,
+    /// Unix time in seconds
+    pub expires: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Stored {
+    next_id: u64,
+    messages: Vec<Message>,
+}
+
+/// How long fetching a feed may take before it's given up
+const FEED_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Reads the headlines of `url`.
+fn fetch_feed(url: &str, limit: usize) -> Result<Vec<String>, String> {
+    let agent = ureq::AgentBuilder::new().timeout(FEED_TIMEOUT).build();
+    let response = agent.get(url).call().map_err(|err| err.to_string())?;
+    let feed = feed_rs::parser::parse(response.into_reader()).map_err(|err| err.to_string())?;
+    Ok(feed
+        .entries
+        .into_iter()
+        .filter_map(|entry| entry.title)
+        .map(|title| title.content.trim().to_owned())
+        .filter(|title| !title.is_empty())
+        .take(limit)
+        .collect())
+}
+
+fn fetch(config: &config::Board) -> Vec<String> {
+    let mut items = Vec::new();
+    if let Some(file) = &config.file {
+        match fs::read_to_string(file) {
+            Ok(content) => items.extend(
+                content
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_owned),
+            ),
+            Err(err) => error!("Failed reading message file {}: {}", file, err),
+        }
+    }
+    for url in &config.feeds {
+        match fetch_feed(url, config.feed_items) {
+            Ok(headlines) => items.extend(headlines),
+            Err(err) => error!("Failed fetching feed {}: {}", url, err),
+        }
+    }
+    items
+}
+
+/// Messages for the ticker: posted ones that are stored on disk after every change, and
+/// headlines of feeds and lines of a file which are reloaded periodically.
+pub struct Board {
+    stored: Stored,
+    path: PathBuf,
+    feed: Vec<String>,
+    updates: Option<Receiver<Vec<String>>>,
+    /// Position in the rotation of unpinned messages and feed items
+    next: usize,
+    next_pinned: usize,
+    last_pinned: bool,
+}
+
+impl Board {
+    pub fn new(config: &config::Board) -> Self {
+        let path = Path::new(&config.messages);
+        let stored = match File::open(path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|err| {
+                error!("Failed reading messages {}: {}", path.display(), err);
+                Stored::default()
+            }),
+            Err(_) => Stored::default(),
+        };
+        let updates = if config.feeds.is_empty() && config.file.is_none() {
+            None
+        } else {
+            let (sender, receiver) = channel();
+            let config = config.clone();
+            thread::Builder::new()
+                .name("Board feeds".to_owned())
+                .spawn(move || loop {
+                    let items = fetch(&config);
+                    info!("Fetched {} feed items for the board", items.len());
+                    if sender.send(items).is_err() {
+                        break;
+                    }
+                    thread::sleep(Duration::from_secs(config.refresh.max(1)));
+                })
+                .map_err(|err| error!("Failed spawning feed thread: {}", err))
+                .ok()
+                .map(|_| receiver)
+        };
+        Self {
+            stored,
+            path: path.to_owned(),
+            feed: Vec::new(),
+            updates,
+            next: 0,
+            next_pinned: 0,
+            last_pinned: false,
+        }
+    }
+
+    fn save(&self) {
+        let result = File::create(&self.path).and_then(|file| {
+            serde_json::to_writer_pretty(BufWriter::new(file), &self.stored)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+        });
+        if let Err(err) = result {
+            error!("Failed writing messages {}: {}", self.path.display(), err);
+        }
+    }
+
+    pub fn messages(&self) -> &[Message] {
+        &self.stored.messages
+    }
+
+    /// Adds a message that expires after `ttl`, returns its id.
+    pub fn add(&mut self, text: &str, pinned: bool, ttl: Option<Duration>) -> u64 {
+        let id = self.stored.next_id;
+        self.stored.next_id += 1;
+        let expires = ttl.map(|ttl| {
+            let ttl = i64::try_from(ttl.as_secs()).unwrap_or(i64::MAX);
+            Utc::now().timestamp().saturating_add(ttl)
+        });
+        self.stored.messages.push(Message {
+            id,
+            text: text.to_owned(),
+            pinned,
+            expires,
+        });
+        self.save();
+        id
+    }
+
+    pub fn remove(&mut self, id: u64) -> bool {
+        let count = self.stored.messages.len();
+        self.stored.messages.retain(|message| message.id != id);
+        let removed = self.stored.messages.len() != count;
+        if removed {
+            self.save();
+        }
+        removed
+    }
+
+    pub fn pin(&mut self, id: u64, pinned: bool) -> bool {
+        match self
+            .stored
+            .messages
+            .iter_mut()
+            .find(|message| message.id == id)
+        {
+            Some(message) => {
+                message.pinned = pinned;
+                self.save();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drops expired messages and takes the latest feed items.
+    pub fn update(&mut self) {
+        if let Some(updates) = &self.updates {
+            match updates.try_recv() {
+                Ok(items) => self.feed = items,
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => self.updates = None,
+            }
+        }
+        let now = Utc::now().timestamp();
+        let count = self.stored.messages.len();
+        self.stored.messages.retain(|message| {
+            message.pinned || message.expires.map_or(true, |expires| expires > now)
+        });
+        if self.stored.messages.len() != count {
+            self.save();
+        }
+    }
+
+    /// Next text to show on the ticker, pinned messages alternate with the others.
+    pub fn next_message(&mut self) -> Option<String> {
+        let pinned: Vec<&Message> = self
+            .stored
+            .messages
+            .iter()
+            .filter(|message| message.pinned)
+            .collect();
+        let others: Vec<&str> = self
+            .stored
+            .messages
+            .iter()
+            .filter(|message| !message.pinned)
+            .map(|message| message.text.as_str())
+            .chain(self.feed.iter().map(String::as_str))
+            .collect();
+        let text = if !pinned.is_empty() && (!self.last_pinned || others.is_empty()) {
+            self.next_pinned %= pinned.len();
+            let text = pinned[self.next_pinned].text.clone();
+            self.next_pinned += 1;
+            self.last_pinned = true;
+            text
+        } else if !others.is_empty() {
+            self.next %= others.len();
+            let text = others[self.next].to_owned();
+            self.next += 1;
+            self.last_pinned = false;
+            text
+        } else {
+            return None;
+        };
+        Some(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn board(directory: &TempDir) -> Board {
+        Board::new(&config::Board {
+            messages: directory.join("board.json").to_string_lossy().into_owned(),
+            ..Default::default()
+        })
+    }
+
+    fn rotation(board: &mut Board, count: usize) -> Vec<String> {
+        (0..count).filter_map(|_| board.next_message()).collect()
+    }
+
+    #[test]
+    fn unpinned_messages_and_feed_items_take_turns() {
+        let directory = TempDir::new("board");
+        let mut board = board(&directory);
+        assert_eq!(board.next_message(), None);
+        board.add("a", false, None);
+        board.add("b", false, None);
+        board.feed = vec!["feed".to_owned()];
+        assert_eq!(rotation(&mut board, 4), ["a", "b", "feed", "a"]);
+    }
+
+    #[test]
+    fn pinned_messages_come_up_between_the_others() {
+        let directory = TempDir::new("board");
+        let mut board = board(&directory);
+        board.add("a", false, None);
+        let pinned = board.add("pinned", true, None);
+        board.add("b", false, None);
+        board.add("also pinned", true, None);
+        assert_eq!(
+            rotation(&mut board, 6),
+            ["pinned", "a", "also pinned", "b", "pinned", "a"]
+        );
+
+        // without others the pinned ones repeat
+        board.remove(0);
+        board.remove(2);
+        assert_eq!(
+            rotation(&mut board, 3),
+            ["also pinned", "pinned", "also pinned"]
+        );
+
+        assert!(board.pin(pinned, false));
+        assert!(!board.pin(42, true));
+        assert_eq!(rotation(&mut board, 3), ["pinned", "also pinned", "pinned"]);
+    }
+
+    #[test]
+    fn messages_expire_unless_pinned() {
+        let directory = TempDir::new("board");
+        let mut board = board(&directory);
+        board.add("forever", false, None);
+        board.add("later", false, Some(Duration::from_secs(3600)));
+        board.add("now", false, Some(Duration::from_secs(0)));
+        // too long to count in seconds since 1970 is as good as forever
+        board.add("end of time", false, Some(Duration::from_secs(u64::MAX)));
+        board.add("almost", false, Some(Duration::from_secs(i64::MAX as u64)));
+        let pinned = board.add("pinned", true, Some(Duration::from_secs(0)));
+        board.update();
+        let texts: Vec<&str> = board
+            .messages()
+            .iter()
+            .map(|message| message.text.as_str())
+            .collect();
+        assert_eq!(
+            texts,
+            ["forever", "later", "end of time", "almost", "pinned"]
+        );
+
+        // unpinning lets it expire
+        board.pin(pinned, false);
+        board.update();
+        assert_eq!(board.messages().len(), 4);
+
+        // kept on disk, with the ids going on
+        let mut reloaded = Board::new(&config::Board {
+            messages: board.path.to_string_lossy().into_owned(),
+            ..Default::default()
+        });
+        assert_eq!(reloaded.messages().len(), 4);
+        assert_eq!(reloaded.add("next", false, None), 6);
+    }
+}
@@ -0,0 +1,412 @@
+use glium::{
+    backend::glutin::Display,
+    texture::{texture2d::Texture2d, RawImage2d},
+    Surface,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{blit::Blit, config, poetry::parse_color};
+
+/// Most snapshots replayed by a timelapse.
+pub const TIMELAPSE_FRAMES: usize = 1000;
+
+/// Shortest time between writes of the canvas file.
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The pixels of the canvas, stored in the canvas file and the database.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Snapshot {
+    pub width: u32,
+    pub height: u32,
+    /// Palette indices, rows from the top
+    pub pixels: Vec<u8>,
+}
+
+impl Snapshot {
+    fn blank(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; (width * height) as usize],
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.pixels.len() == (self.width * self.height) as usize
+    }
+}
+
+#[derive(Debug)]
+pub enum PaintError {
+    Outside,
+    UnknownColor,
+    /// How long the painter still has to wait
+    Cooldown(Duration),
+}
+
+impl fmt::Display for PaintError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaintError::Outside => write!(f, "Pixel is outside of the canvas"),
+            PaintError::UnknownColor => write!(f, "Colour is not in the palette"),
+            PaintError::Cooldown(wait) => write!(f, "Wait {} more seconds", wait.as_secs() + 1),
+        }
+    }
+}
+
+/// Pixel canvas painted on by visitors, each waiting for a cooldown between their pixels.
+pub struct Canvas {
+    current: Snapshot,
+    palette: Vec<[u8; 3]>,
+    cooldown: Duration,
+    /// When each painter last painted
+    last_paint: HashMap<String, Instant>,
+    path: PathBuf,
+    /// Counts changes, so views know when to redraw
+    version: u64,
+    snapshot_interval: Duration,
+    last_snapshot: Instant,
+    snapshot_version: u64,
+    last_save: Instant,
+    saved_version: u64,
+}
+
+impl Canvas {
+    pub fn new(config: &config::Canvas) -> Self {
+        let path = Path::new(&config.file);
+        let stored: Option<Snapshot> = match File::open(path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))
+                .map_err(|err| error!("Failed reading canvas {}: {}", path.display(), err))
+                .ok(),
+            Err(_) => None,
+        };
+        let current = match stored {
+            Some(stored)
+                if stored.is_valid()
+                    && stored.width == config.width
+                    && stored.height == config.height =>
+            {
+                stored
+            }
+            Some(_) => {
+                info!("Canvas size changed, starting a blank one");
+                Snapshot::blank(config.width, config.height)
+            }
+            None => Snapshot::blank(config.width, config.height),
+        };
+        let mut palette: Vec<[u8; 3]> = config
+            .palette
+            .iter()
+            .filter_map(|color| {
+                let parsed = parse_color(color);
+                if parsed.is_none() {
+                    error!("Invalid canvas colour {}", color);
+                }
+                parsed
+            })
+            .take(256)
+            .collect();
+        if palette.is_empty() {
+            palette = vec![[0, 0, 0], [255, 255, 255]];
+        }
+        Self {
+            current,
+            palette,
+            cooldown: Duration::from_secs(config.cooldown),
+            last_paint: HashMap::new(),
+            path: path.to_owned(),
+            version: 0,
+            snapshot_interval: Duration::from_secs(config.snapshot_interval.max(1)),
+            last_snapshot: Instant::now(),
+            snapshot_version: 0,
+            last_save: Instant::now(),
+            saved_version: 0,
+        }
+    }
+
+    fn save(&mut self) {
+        self.last_save = Instant::now();
+        self.saved_version = self.version;
+        let result = File::create(&self.path).and_then(|file| {
+            serde_json::to_writer(BufWriter::new(file), &self.current)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+        });
+        if let Err(err) = result {
+            error!("Failed writing canvas {}: {}", self.path.display(), err);
+        }
+    }
+
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.current
+    }
+
+    /// The palette as `#rrggbb`.
+    pub fn palette(&self) -> Vec<String> {
+        self.palette
+            .iter()
+            .map(|[r, g, b]| format!("#{:02x}{:02x}{:02x}", r, g, b))
+            .collect()
+    }
+
+    pub fn cooldown(&self) -> Duration {
+        self.cooldown
+    }
+
+    /// Sets a pixel to the palette entry `color` for `painter`, if their cooldown is over.
+    pub fn paint(&mut self, painter: &str, x: u32, y: u32, color: u8) -> Result<(), PaintError> {
+        if x >= self.current.width || y >= self.current.height {
+            return Err(PaintError::Outside);
+        }
+        if color as usize >= self.palette.len() {
+            return Err(PaintError::UnknownColor);
+        }
+        let cooldown = self.cooldown;
+        if let Some(last) = self.last_paint.get(painter) {
+            let elapsed = last.elapsed();
+            if elapsed < cooldown {
+                return Err(PaintError::Cooldown(cooldown - elapsed));
+            }
+        }
+        self.last_paint.retain(|_, last| last.elapsed() < cooldown);
+        self.last_paint.insert(painter.to_owned(), Instant::now());
+        self.current.pixels[(y * self.current.width + x) as usize] = color;
+        self.version += 1;
+        Ok(())
+    }
+
+    /// Writes the canvas file when it changed and the last write is long enough ago.
+    pub fn save_if_due(&mut self) {
+        if self.version != self.saved_version && self.last_save.elapsed() >= SAVE_INTERVAL {
+            self.save();
+        }
+    }
+
+    /// How long until unsaved changes are due to be written.
+    pub fn next_save(&self) -> Option<Duration> {
+        if self.version == self.saved_version {
+            None
+        } else {
+            Some(SAVE_INTERVAL.saturating_sub(self.last_save.elapsed()))
+        }
+    }
+
+    /// The canvas as JSON when a snapshot is due and it changed since the last one.
+    pub fn take_snapshot(&mut self) -> Option<Vec<u8>> {
+        if self.version == self.snapshot_version
+            || self.last_snapshot.elapsed() < self.snapshot_interval
+        {
+            return None;
+        }
+        self.last_snapshot = Instant::now();
+        self.snapshot_version = self.version;
+        serde_json::to_vec(&self.current)
+            .map_err(|err| error!("Failed encoding canvas snapshot: {}", err))
+            .ok()
+    }
+
+    /// RGBA pixels of `snapshot` scaled to `size`, bottom row first.
+    fn image(&self, snapshot: &Snapshot, size: (u32, u32)) -> Vec<u8> {
+        let mut image = Vec::with_capacity((size.0 * size.1 * 4) as usize);
+        for row in (0..size.1).rev() {
+            let y = row * snapshot.height / size.1;
+            for column in 0..size.0 {
+                let x = column * snapshot.width / size.0;
+                let index = snapshot.pixels[(y * snapshot.width + x) as usize] as usize;
+                let [r, g, b] = self.palette.get(index).unwrap_or(&self.palette[0]);
+                image.extend_from_slice(&[*r, *g, *b, 255]);
+            }
+        }
+        image
+    }
+}
+
+impl Drop for Canvas {
+    fn drop(&mut self) {
+        if self.version != self.saved_version {
+            self.save();
+        }
+    }
+}
+
+/// Replays snapshots from the database.
+struct Timelapse {
+    frames: Vec<Snapshot>,
+    index: usize,
+    interval: Duration,
+    last: Instant,
+}
+
+/// Shows the canvas, or a timelapse of it.
+#[derive(Default)]
+pub struct View {
+    texture: Option<Texture2d>,
+    /// Canvas version shown
+    version: u64,
+    timelapse: Option<Timelapse>,
+}
+
+impl View {
+    /// Plays `frames` at `fps` before showing the live canvas again.
+    pub fn timelapse(frames: Vec<Snapshot>, fps: u32) -> Self {
+        let frames: Vec<Snapshot> = frames.into_iter().filter(Snapshot::is_valid).collect();
+        Self {
+            texture: None,
+            version: 0,
+            timelapse: if frames.is_empty() {
+                None
+            } else {
+                Some(Timelapse {
+                    frames,
+                    index: 0,
+                    interval: Duration::from_secs(1) / fps.max(1),
+                    last: Instant::now(),
+                })
+            },
+        }
+    }
+
+    /// Uploads the picture if it changed.
+    pub fn update(&mut self, display: &Display, canvas: &Canvas, size: (u32, u32)) {
+        let mut changed = self.texture.is_none();
+        if let Some(timelapse) = &mut self.timelapse {
+            if timelapse.last.elapsed() >= timelapse.interval {
+                timelapse.last = Instant::now();
+                timelapse.index += 1;
+                changed = true;
+            }
+            if timelapse.index >= timelapse.frames.len() {
+                self.timelapse = None;
+                changed = true;
+            }
+        }
+        let snapshot = match &self.timelapse {
+            Some(timelapse) => &timelapse.frames[timelapse.index],
+            None => {
+                changed |= self.version != canvas.version;
+                self.version = canvas.version;
+                canvas.snapshot()
+            }
+        };
+        if !changed {
+            return;
+        }
+        let image = RawImage2d::from_raw_rgba(canvas.image(snapshot, size), size);
+        match Texture2d::new(display, image) {
+            Ok(texture) => self.texture = Some(texture),
+            Err(err) => error!("Failed uploading canvas: {}", err),
+        }
+    }
+
+    pub fn draw<S: Surface>(&self, target: &mut S, blit: &Blit) {
+        if let Some(texture) = &self.texture {
+            blit.draw(target, texture);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    const FILE: &str = "canvas.json";
+
+    fn canvas(directory: &TempDir, cooldown: u64) -> Canvas {
+        let path = directory.join(FILE);
+        let config = config::Canvas {
+            width: 4,
+            height: 3,
+            palette: vec!["#000000".to_owned(), "#ffffff".to_owned()],
+            cooldown,
+            file: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        Canvas::new(&config)
+    }
+
+    #[test]
+    fn paint_checks_bounds_and_palette() {
+        let directory = TempDir::new("canvas");
+        let mut canvas = canvas(&directory, 0);
+        assert!(matches!(
+            canvas.paint("a", 4, 0, 1),
+            Err(PaintError::Outside)
+        ));
+        assert!(matches!(
+            canvas.paint("a", 0, 3, 1),
+            Err(PaintError::Outside)
+        ));
+        assert!(matches!(
+            canvas.paint("a", u32::MAX, u32::MAX, 1),
+            Err(PaintError::Outside)
+        ));
+        assert!(matches!(
+            canvas.paint("a", 0, 0, 2),
+            Err(PaintError::UnknownColor)
+        ));
+        canvas.paint("a", 3, 2, 1).unwrap();
+        assert_eq!(canvas.snapshot().pixels[11], 1);
+        assert_eq!(
+            canvas.snapshot().pixels.iter().filter(|p| **p != 0).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn painters_wait_for_their_own_cooldown() {
+        let directory = TempDir::new("canvas");
+        let mut canvas = canvas(&directory, 60);
+        canvas.paint("a", 0, 0, 1).unwrap();
+        match canvas.paint("a", 1, 0, 1) {
+            Err(PaintError::Cooldown(wait)) => {
+                assert!(wait > Duration::from_secs(59) && wait <= Duration::from_secs(60))
+            }
+            _ => panic!("expected a cooldown"),
+        }
+        assert_eq!(canvas.snapshot().pixels[1], 0);
+        canvas.paint("b", 1, 0, 1).unwrap();
+        // as if the cooldown passed
+        canvas
+            .last_paint
+            .insert("a".to_owned(), Instant::now() - Duration::from_secs(60));
+        canvas.paint("a", 2, 0, 1).unwrap();
+    }
+
+    #[test]
+    fn saves_are_debounced() {
+        let directory = TempDir::new("canvas");
+        let path = directory.join(FILE);
+        let mut canvas = canvas(&directory, 0);
+        assert_eq!(canvas.next_save(), None);
+        canvas.paint("a", 1, 1, 1).unwrap();
+        canvas.paint("b", 2, 1, 1).unwrap();
+        canvas.save_if_due();
+        assert!(!path.exists());
+        assert!(canvas.next_save().unwrap() > Duration::from_secs(4));
+
+        canvas.last_save -= SAVE_INTERVAL;
+        assert_eq!(canvas.next_save(), Some(Duration::from_secs(0)));
+        canvas.save_if_due();
+        assert!(path.exists());
+        assert_eq!(canvas.next_save(), None);
+
+        canvas.paint("c", 3, 1, 1).unwrap();
+        drop(canvas);
+        let reloaded = Canvas::new(&config::Canvas {
+            width: 4,
+            height: 3,
+            file: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        });
+        assert_eq!(reloaded.snapshot().pixels[5..8], [1, 1, 1]);
+    }
+}
@@ -0,0 +1,595 @@
+use glium::texture::{texture2d::Texture2d, RawImage2d};
+use log::{error, info};
+use serde::Deserialize;
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::config;
+
+const JPEG_QUALITY: u8 = 80;
+const BOUNDARY: &str = "blinkenwall-frame";
+/// Frames waiting to be written before further ones are dropped
+const RECORDING_QUEUE: usize = 8;
+/// HTTP clients served at once, each has a thread of its own
+const MAX_CLIENTS: usize = 8;
+
+/// A captured frame, RGB rows from the top.
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Frame {
+    pub fn read(texture: &Texture2d) -> Self {
+        Self::from_image(texture.read())
+    }
+
+    /// Converts RGBA rows from the bottom, as OpenGL reads them.
+    pub fn from_image(image: RawImage2d<u8>) -> Self {
+        let (width, height) = (image.width as usize, image.height as usize);
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for row in image.data.chunks(width * 4).rev() {
+            for pixel in row.chunks(4) {
+                pixels.extend_from_slice(&pixel[..3]);
+            }
+        }
+        Self {
+            width: image.width,
+            height: image.height,
+            pixels,
+        }
+    }
+
+    pub fn encode_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+        let mut png = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(BufWriter::new(&mut png), self.width, self.height);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.write_header()?.write_image_data(&self.pixels)?;
+        }
+        Ok(png)
+    }
+
+    pub fn encode_jpeg(&self) -> Result<Vec<u8>, jpeg_encoder::EncodingError> {
+        let mut jpeg = Vec::new();
+        jpeg_encoder::Encoder::new(&mut jpeg, JPEG_QUALITY).encode(
+            &self.pixels,
+            self.width as u16,
+            self.height as u16,
+            jpeg_encoder::ColorType::Rgb,
+        )?;
+        Ok(jpeg)
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingFormat {
+    /// Numbered PNG files in a directory
+    Images,
+    /// Video file encoded by ffmpeg
+    Video,
+}
+
+/// Where a file is written before it's renamed to `path`, so only complete files carry its name.
+fn partial(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+enum Sink {
+    Images(PathBuf),
+    /// ffmpeg writing the video file `path`
+    Ffmpeg {
+        ffmpeg: Child,
+        path: PathBuf,
+    },
+}
+
+impl Sink {
+    fn write(&mut self, frame: &Frame, number: u64) -> io::Result<()> {
+        match self {
+            Sink::Images(directory) => {
+                let png = frame
+                    .encode_png()
+                    .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+                let path = directory.join(format!("{:06}.png", number));
+                fs::write(partial(&path), png)?;
+                fs::rename(partial(&path), path)
+            }
+            Sink::Ffmpeg { ffmpeg, .. } => match &mut ffmpeg.stdin {
+                Some(stdin) => stdin.write_all(&frame.pixels),
+                None => Ok(()),
+            },
+        }
+    }
+
+    fn finish(self) {
+        if let Sink::Ffmpeg { mut ffmpeg, path } = self {
+            // closing stdin makes ffmpeg finish the file
+            drop(ffmpeg.stdin.take());
+            match ffmpeg.wait() {
+                Ok(status) if status.success() => {
+                    if let Err(err) = fs::rename(partial(&path), &path) {
+                        error!("Failed renaming {}: {}", path.display(), err);
+                    }
+                }
+                Ok(status) => error!("ffmpeg failed recording {}: {}", path.display(), status),
+                Err(err) => error!("Failed waiting for ffmpeg: {}", err),
+            }
+        }
+    }
+}
+
+/// Writes the frames it receives to `sink` until the recording stops.
+fn write_frames(mut sink: Sink, frames: Receiver<Arc<Frame>>) {
+    let mut written = 0;
+    for frame in frames {
+        if let Err(err) = sink.write(&frame, written) {
+            error!("Failed writing recording: {}", err);
+            break;
+        }
+        written += 1;
+    }
+    sink.finish();
+    info!("Recorded {} frames", written);
+}
+
+/// A recording in progress, its frames are encoded and written by a thread of its own.
+struct Recording {
+    frames: SyncSender<Arc<Frame>>,
+    writer: JoinHandle<()>,
+    /// Frames due so far, including dropped ones
+    due: u64,
+    dropped: u64,
+    started: Instant,
+    duration: Option<Duration>,
+}
+
+impl Recording {
+    fn start(
+        path: PathBuf,
+        format: RecordingFormat,
+        size: (u32, u32),
+        fps: u32,
+        duration: Option<Duration>,
+    ) -> io::Result<Self> {
+        let sink = match format {
+            RecordingFormat::Images => {
+                fs::create_dir_all(&path)?;
+                Sink::Images(path)
+            }
+            RecordingFormat::Video => {
+                let path = path.with_extension("mp4");
+                let ffmpeg = Command::new("ffmpeg")
+                    .args(["-loglevel", "error", "-y", "-f", "rawvideo"])
+                    .args(["-pix_fmt", "rgb24", "-s", &format!("{}x{}", size.0, size.1)])
+                    .args(["-r", &fps.to_string(), "-i", "-"])
+                    // yuv420p needs even dimensions
+                    .args([
+                        "-vf",
+                        "pad=ceil(iw/2)*2:ceil(ih/2)*2",
+                        "-pix_fmt",
+                        "yuv420p",
+                    ])
+                    // the partial file's extension doesn't tell ffmpeg the format
+                    .args(["-f", "mp4"])
+                    .arg(partial(&path))
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .spawn()?;
+                Sink::Ffmpeg { ffmpeg, path }
+            }
+        };
+        let (frames, receiver) = sync_channel(RECORDING_QUEUE);
+        let writer = thread::Builder::new()
+            .name("Capture recording".to_owned())
+            .spawn(move || write_frames(sink, receiver))?;
+        Ok(Self {
+            frames,
+            writer,
+            due: 0,
+            dropped: 0,
+            started: Instant::now(),
+            duration,
+        })
+    }
+
+    fn is_due(&self, fps: u32) -> bool {
+        self.started.elapsed().as_secs_f64() * fps as f64 >= self.due as f64
+    }
+
+    fn is_finished(&self) -> bool {
+        self.duration
+            .map_or(false, |duration| self.started.elapsed() >= duration)
+    }
+
+    /// Hands `frame` to the writer, dropping it if the writer can't keep up.
+    fn write(&mut self, frame: Arc<Frame>) -> io::Result<()> {
+        self.due += 1;
+        match self.frames.try_send(frame) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "The recording stopped writing",
+            )),
+        }
+    }
+
+    /// Waits for the writer to finish the queued frames and the file.
+    fn finish(self) {
+        if self.dropped > 0 {
+            error!(
+                "Dropped {} of {} frames the recording couldn't keep up with",
+                self.dropped, self.due
+            );
+        }
+        // the writer stops once it has taken the last frame
+        drop(self.frames);
+        if self.writer.join().is_err() {
+            error!("The recording writer panicked");
+        }
+    }
+}
+
+/// Latest frame for the HTTP clients, which wait on `updated` for the next one.
+#[derive(Default)]
+struct Shared {
+    frame: Mutex<Option<Arc<Frame>>>,
+    updated: Condvar,
+    /// Clients being served
+    clients: AtomicUsize,
+}
+
+/// A client's place among the ones served, given back when dropped.
+struct Client(Arc<Shared>);
+
+impl Client {
+    /// `None` if `MAX_CLIENTS` are served already.
+    fn admit(shared: &Arc<Shared>) -> Option<Self> {
+        shared
+            .clients
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                (count < MAX_CLIENTS).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| Client(shared.clone()))
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.0.clients.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn send_screenshot(stream: &mut TcpStream, shared: &Shared) -> io::Result<()> {
+    let frame = shared.frame.lock().unwrap().clone();
+    let png = frame.map(|frame| frame.encode_png());
+    match png {
+        Some(Ok(png)) => {
+            write!(
+                stream,
+                "HTTP/1.0 200 OK\r\nContent-Type: image/png\r\nContent-Length: {}\r\n\r\n",
+                png.len()
+            )?;
+            stream.write_all(&png)
+        }
+        Some(Err(err)) => {
+            error!("Failed encoding screenshot: {}", err);
+            stream.write_all(b"HTTP/1.0 500 Internal Server Error\r\n\r\n")
+        }
+        None => stream.write_all(b"HTTP/1.0 503 Service Unavailable\r\n\r\n"),
+    }
+}
+
+fn send_preview(stream: &mut TcpStream, shared: &Shared) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.0 200 OK\r\nCache-Control: no-cache\r\n\
+         Content-Type: multipart/x-mixed-replace; boundary={BOUNDARY}\r\n\r\n"
+    )?;
+    loop {
+        let frame = {
+            let frame = shared.frame.lock().unwrap();
+            let (frame, _) = shared
+                .updated
+                .wait_timeout(frame, Duration::from_secs(10))
+                .unwrap();
+            frame.clone()
+        };
+        // nothing new while the wall is off, keep the connection and wait
+        let frame = match frame {
+            Some(frame) => frame,
+            None => continue,
+        };
+        let jpeg = frame
+            .encode_jpeg()
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        write!(
+            stream,
+            "--{BOUNDARY}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+            jpeg.len()
+        )?;
+        stream.write_all(&jpeg)?;
+        stream.write_all(b"\r\n")?;
+    }
+}
+
+fn handle_client(mut stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    match request.split_whitespace().nth(1) {
+        Some("/screenshot.png") => send_screenshot(&mut stream, shared),
+        Some("/preview.mjpeg") => send_preview(&mut stream, shared),
+        _ => stream.write_all(b"HTTP/1.0 404 Not Found\r\n\r\n"),
+    }
+}
+
+fn run_server(listener: TcpListener, shared: Arc<Shared>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                error!("Failed accepting capture client: {}", err);
+                continue;
+            }
+        };
+        let client = match Client::admit(&shared) {
+            Some(client) => client,
+            None => {
+                info!("Refusing capture client, serving {} already", MAX_CLIENTS);
+                stream.set_write_timeout(Some(Duration::from_secs(1))).ok();
+                (&stream)
+                    .write_all(b"HTTP/1.0 503 Service Unavailable\r\n\r\n")
+                    .ok();
+                continue;
+            }
+        };
+        thread::Builder::new()
+            .name("Capture client".to_owned())
+            .spawn(move || {
+                if let Err(err) = handle_client(stream, &client.0) {
+                    info!("Capture client disconnected: {}", err);
+                }
+            })
+            .ok();
+    }
+}
+
+/// Reads back rendered frames for the preview server and recordings.
+pub struct Capture {
+    shared: Option<Arc<Shared>>,
+    preview_interval: Duration,
+    last_preview: Option<Instant>,
+    recording: Option<Recording>,
+    directory: PathBuf,
+    record_fps: u32,
+}
+
+impl Capture {
+    pub fn new(config: &config::Capture, address: &str) -> Self {
+        let shared = config.port.and_then(|port| {
+            let listener = match TcpListener::bind((address, port)) {
+                Ok(listener) => listener,
+                Err(err) => {
+                    error!("Failed opening capture server on port {}: {}", port, err);
+                    return None;
+                }
+            };
+            info!("Serving screenshots and preview on port {}", port);
+            let shared = Arc::new(Shared::default());
+            let server_shared = shared.clone();
+            thread::Builder::new()
+                .name("Capture server".to_owned())
+                .spawn(move || run_server(listener, server_shared))
+                .ok()?;
+            Some(shared)
+        });
+        Self {
+            shared,
+            preview_interval: Duration::from_secs(1) / config.preview_fps.max(1),
+            last_preview: None,
+            recording: None,
+            directory: PathBuf::from(&config.directory),
+            record_fps: config.record_fps.max(1),
+        }
+    }
+
+    /// Called with every finished frame, reads it back when the preview or a recording needs it.
+    pub fn update(&mut self, texture: &Texture2d) {
+        let preview_due = self.shared.is_some()
+            && self
+                .last_preview
+                .map_or(true, |last| last.elapsed() >= self.preview_interval);
+        let record_fps = self.record_fps;
+        let record_due = self
+            .recording
+            .as_ref()
+            .map_or(false, |recording| recording.is_due(record_fps));
+        if !preview_due && !record_due {
+            return;
+        }
+        let frame = Arc::new(Frame::read(texture));
+        if let (true, Some(shared)) = (preview_due, &self.shared) {
+            self.last_preview = Some(Instant::now());
+            *shared.frame.lock().unwrap() = Some(frame.clone());
+            shared.updated.notify_all();
+        }
+        if record_due {
+            if let Some(recording) = &mut self.recording {
+                if let Err(err) = recording.write(frame) {
+                    error!("Failed writing recording: {}", err);
+                    self.stop_recording();
+                    return;
+                }
+            }
+        }
+        if self
+            .recording
+            .as_ref()
+            .map_or(false, |recording| recording.is_finished())
+        {
+            self.stop_recording();
+        }
+    }
+
+    /// Starts recording `size` sized frames as `name` in the capture directory, for `duration`
+    /// or until stopped.
+    pub fn start_recording(
+        &mut self,
+        name: &str,
+        format: RecordingFormat,
+        size: (u32, u32),
+        duration: Option<Duration>,
+    ) -> io::Result<()> {
+        if Path::new(name)
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            != Some(name)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid recording name",
+            ));
+        }
+        self.stop_recording();
+        fs::create_dir_all(&self.directory)?;
+        let path = self.directory.join(name);
+        info!("Recording {:?} to {}", format, path.display());
+        self.recording = Some(Recording::start(
+            path,
+            format,
+            size,
+            self.record_fps,
+            duration,
+        )?);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> bool {
+        match self.recording.take() {
+            Some(recording) => {
+                recording.finish();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn frame(value: u8) -> Arc<Frame> {
+        Arc::new(Frame {
+            width: 2,
+            height: 1,
+            pixels: vec![value, 0, 0, 0, value, 0],
+        })
+    }
+
+    #[test]
+    fn png_keeps_the_pixels() {
+        let png = frame(200).encode_png().unwrap();
+        let mut reader = png::Decoder::new(&png[..]).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (2, 1));
+        assert_eq!(pixels, frame(200).pixels);
+    }
+
+    #[test]
+    fn images_are_written_in_the_background() {
+        let directory = TempDir::new("capture");
+        let mut recording = Recording::start(
+            directory.join("images"),
+            RecordingFormat::Images,
+            (2, 1),
+            30,
+            None,
+        )
+        .unwrap();
+        assert!(recording.is_due(30));
+        for value in 0..3 {
+            recording.write(frame(value)).unwrap();
+        }
+        assert!(!recording.is_due(1));
+        assert!(!recording.is_finished());
+        // waits for the writer
+        recording.finish();
+
+        let last = directory.join("images/000002.png");
+        assert_eq!(fs::read(&last).unwrap(), frame(2).encode_png().unwrap());
+        // no partial files left behind
+        assert_eq!(fs::read_dir(directory.join("images")).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn recordings_end_after_their_duration() {
+        let directory = TempDir::new("capture");
+        let recording = Recording::start(
+            directory.join("images"),
+            RecordingFormat::Images,
+            (2, 1),
+            30,
+            Some(Duration::from_secs(0)),
+        )
+        .unwrap();
+        assert!(recording.is_finished());
+        recording.finish();
+    }
+
+    #[test]
+    fn partial_files_are_named_after_the_file() {
+        assert_eq!(
+            partial(Path::new("captures/run.mp4")),
+            Path::new("captures/run.mp4.part")
+        );
+    }
+
+    #[test]
+    fn clients_are_limited() {
+        let shared = Arc::new(Shared::default());
+        let mut clients: Vec<_> = (0..MAX_CLIENTS)
+            .map(|_| Client::admit(&shared).unwrap())
+            .collect();
+        assert!(Client::admit(&shared).is_none());
+        clients.pop();
+        assert!(Client::admit(&shared).is_some());
+        drop(clients);
+        assert_eq!(shared.clients.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn images_are_flipped_without_alpha() {
+        let image = RawImage2d::from_raw_rgba(
+            vec![1, 2, 3, 255, 4, 5, 6, 255, 7, 8, 9, 0, 10, 11, 12, 0],
+            (2, 2),
+        );
+        let frame = Frame::from_image(image);
+        assert_eq!((frame.width, frame.height), (2, 2));
+        assert_eq!(frame.pixels, [7, 8, 9, 10, 11, 12, 1, 2, 3, 4, 5, 6]);
+    }
+}
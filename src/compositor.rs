@@ -0,0 +1,383 @@
+use glium::{backend::glutin::Display, texture::texture2d::Texture2d, Surface};
+use serde::Deserialize;
+use std::{convert::TryFrom, io, path::Path, rc::Rc};
+
+use crate::{
+    audio::Audio,
+    blit::{BlendMode, Blit, Placement},
+    config::{Config, PoetryStyle},
+    emulator::Emulator,
+    poetry::{Fonts, Poetry},
+    shadertoy::ShaderToy,
+    video::{Source, SourceError, Video},
+};
+
+#[derive(Debug)]
+pub enum SceneError {
+    Shader(String, String),
+    Video(SourceError),
+    Emulator(String, io::Error),
+}
+
+impl From<SourceError> for SceneError {
+    fn from(err: SourceError) -> Self {
+        Self::Video(err)
+    }
+}
+
+impl std::fmt::Display for SceneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Shader(id, message) => write!(f, "Failed loading shader {id}: {message}"),
+            Self::Video(err) => write!(f, "{err}"),
+            Self::Emulator(game, err) => write!(f, "Failed starting {game}: {err}"),
+        }
+    }
+}
+
+/// Area covered by a layer, as fractions of the wall from its top left corner.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(try_from = "UncheckedBounds")]
+pub struct Bounds {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// `Bounds` as sent by clients, before making sure they are on the wall.
+#[derive(Deserialize)]
+struct UncheckedBounds {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+
+impl TryFrom<UncheckedBounds> for Bounds {
+    type Error = String;
+
+    fn try_from(bounds: UncheckedBounds) -> Result<Self, String> {
+        let UncheckedBounds {
+            x,
+            y,
+            width,
+            height,
+        } = bounds;
+        if ![x, y, width, height]
+            .iter()
+            .all(|value| value.is_finite() && *value >= 0.0)
+        {
+            return Err("Layer bounds must be numbers from 0 to 1".to_owned());
+        }
+        if x + width > 1.0 + f32::EPSILON || y + height > 1.0 + f32::EPSILON {
+            return Err("Layer bounds must be within the wall".to_owned());
+        }
+        Ok(Bounds {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
+
+impl Bounds {
+    /// Size in pixels on a wall of `size`, at least a pixel.
+    fn pixels(&self, size: (u32, u32)) -> (u32, u32) {
+        let scale = |fraction: f32, size: u32| (fraction * size as f32).round().max(1.0) as u32;
+        (scale(self.width, size.0), scale(self.height, size.1))
+    }
+
+    /// As `Placement::bounds`, which start from the bottom left.
+    fn placement_bounds(&self) -> [f32; 4] {
+        [self.x, 1.0 - self.y - self.height, self.width, self.height]
+    }
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            width: 1.0,
+            height: 1.0,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LayerSource {
+    Shader {
+        id: String,
+    },
+    Poetry {
+        #[serde(default)]
+        text: String,
+        #[serde(default)]
+        style: Option<PoetryStyle>,
+    },
+    Video {
+        url: String,
+    },
+    Emulator {
+        game: String,
+    },
+}
+
+/// A layer as requested by clients, e.g.
+/// `{"type": "video", "url": "...", "opacity": 0.8, "bounds": {...}, "blend": "add"}`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct LayerSpec {
+    #[serde(flatten)]
+    pub source: LayerSource,
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    #[serde(default)]
+    pub bounds: Bounds,
+    #[serde(default)]
+    pub blend: BlendMode,
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+enum Content {
+    Shader(ShaderToy),
+    Poetry(Poetry),
+    /// Renders into its own frame instead of the layer target
+    Video(Video),
+    Emulator(Emulator),
+}
+
+struct Layer {
+    content: Content,
+    target: Texture2d,
+    opacity: f32,
+    bounds: Bounds,
+    blend: BlendMode,
+}
+
+impl Layer {
+    /// Lets the content render its next frame.
+    fn render(&mut self) {
+        let size = self.target.dimensions();
+        match &mut self.content {
+            Content::Shader(shader_toy) => {
+                let mut surface = self.target.as_surface();
+                surface.clear_color(0.0, 0.0, 0.0, 1.0);
+                shader_toy.draw(&mut surface, size, None);
+            }
+            Content::Poetry(poetry) => {
+                let mut surface = self.target.as_surface();
+                surface.clear_color(0.0, 0.0, 0.0, 0.0);
+                poetry.draw(&mut surface, size);
+            }
+            Content::Video(video) => {
+                // scene videos loop, so there is nothing to do when they end
+                video.step();
+            }
+            Content::Emulator(emulator) => {
+                let mut surface = self.target.as_surface();
+                surface.clear_color(0.0, 0.0, 0.0, 1.0);
+                emulator.draw(&mut surface, size);
+            }
+        }
+    }
+
+    fn texture(&self) -> &Texture2d {
+        match &self.content {
+            Content::Video(video) => video.frame(),
+            _ => &self.target,
+        }
+    }
+
+    fn placement(&self) -> Placement {
+        Placement {
+            bounds: self.bounds.placement_bounds(),
+            opacity: self.opacity,
+            translucent: matches!(self.content, Content::Poetry(_)),
+            blend: self.blend,
+        }
+    }
+}
+
+/// Stack of layers drawn bottom to top, each with its own content, area, opacity and blending.
+pub struct Scene {
+    layers: Vec<Layer>,
+}
+
+impl Scene {
+    /// Creates the layers of `specs`, getting shader sources by id from `shader_source`.
+    pub fn new(
+        display: &Display,
+        config: &Config,
+        fonts: &Rc<Fonts>,
+        audio: &mut Audio,
+        specs: &[LayerSpec],
+        shader_source: impl Fn(&str) -> Result<String, String>,
+    ) -> Result<Self, SceneError> {
+        let mut layers = Vec::with_capacity(specs.len());
+        for spec in specs {
+            let (width, height) = spec
+                .bounds
+                .pixels((config.display.width, config.display.height));
+            let content = match &spec.source {
+                LayerSource::Shader { id } => {
+                    let source = shader_source(id)
+                        .map_err(|message| SceneError::Shader(id.clone(), message))?;
+                    Content::Shader(ShaderToy::new_with_audio(display, &source, audio))
+                }
+                LayerSource::Poetry { text, style } => {
+                    let mut poetry =
+                        Poetry::new(display, fonts.clone(), &config.poetry, (width, height));
+                    if !text.is_empty() {
+                        poetry.show_poem(display, text, *style);
+                    }
+                    Content::Poetry(poetry)
+                }
+                LayerSource::Video { url } => {
+                    let source = Source::parse(url, config.video.media.as_ref().map(Path::new))?;
+                    let mut options = config.video.options.for_kind(source.kind()).clone();
+                    options.insert("loop-file".to_owned(), "inf".to_owned());
+                    let mut video = Video::new(display, width, height, audio);
+                    video.play(&source, &options);
+                    Content::Video(video)
+                }
+                LayerSource::Emulator { game } => Content::Emulator(
+                    Emulator::new(display, game, &config.emulator, audio)
+                        .map_err(|err| SceneError::Emulator(game.clone(), err))?,
+                ),
+            };
+            layers.push(Layer {
+                content,
+                target: Texture2d::empty(display, width, height).unwrap(),
+                opacity: spec.opacity.clamp(0.0, 1.0),
+                bounds: spec.bounds,
+                blend: spec.blend,
+            });
+        }
+        Ok(Self { layers })
+    }
+
+    /// Renders all layers and composites them over `target`.
+    pub fn draw<S: Surface>(&mut self, target: &mut S, blit: &Blit) {
+        for layer in &mut self.layers {
+            layer.render();
+        }
+        for layer in &self.layers {
+            blit.draw_with(target, layer.texture(), &layer.placement());
+        }
+    }
+
+    pub fn set_opacity(&mut self, index: usize, opacity: f32) -> bool {
+        match self.layers.get_mut(index) {
+            Some(layer) => {
+                layer.opacity = opacity.clamp(0.0, 1.0);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forwards joypad input to all emulator layers.
+    pub fn emulator_input(&mut self, key: &str, press: bool) {
+        for layer in &mut self.layers {
+            if let Content::Emulator(emulator) = &mut layer.content {
+                emulator.input(key, press);
+            }
+        }
+    }
+
+    /// Sets the volume of all video layers, from 0 to 100.
+    pub fn set_volume(&self, volume: i64) {
+        for layer in &self.layers {
+            if let Content::Video(video) = &layer.content {
+                video.set_volume(volume);
+            }
+        }
+    }
+
+    pub fn stop(&mut self) {
+        for layer in &mut self.layers {
+            if let Content::Video(video) = &mut layer.content {
+                video.stop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_default_to_opaque_and_full() {
+        let spec: LayerSpec = serde_json::from_str(r#"{"type": "shader", "id": "abc"}"#).unwrap();
+        assert!(matches!(&spec.source, LayerSource::Shader { id } if id == "abc"));
+        assert_eq!(spec.opacity, 1.0);
+        assert_eq!(spec.bounds.placement_bounds(), [0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(spec.blend, BlendMode::Normal);
+
+        let spec: LayerSpec = serde_json::from_str(
+            r#"{
+                "type": "poetry",
+                "opacity": 0.5,
+                "blend": "add",
+                "bounds": {"x": 0.5, "y": 0, "width": 0.5, "height": 0.25}
+            }"#,
+        )
+        .unwrap();
+        assert!(
+            matches!(&spec.source, LayerSource::Poetry { text, style: None } if text.is_empty())
+        );
+        assert_eq!(spec.opacity, 0.5);
+        assert_eq!(spec.blend, BlendMode::Add);
+
+        assert!(serde_json::from_str::<LayerSpec>(r#"{"type": "video"}"#).is_err());
+        assert!(serde_json::from_str::<LayerSpec>(
+            r#"{"type": "shader", "id": "abc", "bounds": {"x": 0.5}}"#
+        )
+        .is_err());
+        assert!(serde_json::from_str::<LayerSpec>(r#"{"type": "camera"}"#).is_err());
+    }
+
+    #[test]
+    fn bounds_must_be_on_the_wall() {
+        let bounds = |json| serde_json::from_str::<Bounds>(json);
+        assert!(bounds(r#"{"x": 0.3, "y": 0.1, "width": 0.7, "height": 0.9}"#).is_ok());
+        assert!(bounds(r#"{"x": 0, "y": 0, "width": 0, "height": 0}"#).is_ok());
+        assert!(bounds(r#"{"x": -0.1, "y": 0, "width": 0.5, "height": 0.5}"#).is_err());
+        assert!(bounds(r#"{"x": 0, "y": 0, "width": 1e30, "height": 1}"#).is_err());
+        assert!(bounds(r#"{"x": 0.6, "y": 0, "width": 0.5, "height": 1}"#).is_err());
+        assert!(bounds(r#"{"x": 0, "y": 0.5, "width": 1, "height": 0.6}"#).is_err());
+        let nan = UncheckedBounds {
+            x: 0.0,
+            y: 0.0,
+            width: f32::NAN,
+            height: 1.0,
+        };
+        assert!(Bounds::try_from(nan).is_err());
+    }
+
+    #[test]
+    fn bounds_start_from_the_top_left() {
+        let bounds = Bounds {
+            x: 0.5,
+            y: 0.0,
+            width: 0.5,
+            height: 0.25,
+        };
+        assert_eq!(bounds.placement_bounds(), [0.5, 0.75, 0.5, 0.25]);
+        assert_eq!(bounds.pixels((48, 10)), (24, 3));
+
+        let tiny = Bounds {
+            width: 0.001,
+            height: 0.0,
+            ..bounds
+        };
+        assert_eq!(tiny.pixels((48, 10)), (1, 1));
+    }
+}
@@ -1,8 +1,12 @@
-use serde::{Deserialize, Serialize};
+use chrono::format::{Item, StrftimeItems};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs::File;
 use std::path::Path;
 
+use crate::video::SourceKind;
+
 #[derive(Serialize, Deserialize)]
 pub struct Database {
     pub repository: String,
@@ -19,14 +23,294 @@ pub struct Server {
 pub struct Display {
     pub width: u32,
     pub height: u32,
+    /// Don't show the window, e.g. when only driving network pixel outputs
+    #[serde(default)]
+    pub hidden: bool,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Poetry {
+    /// BDF, TTF or OTF font
     pub font: String,
+    /// Fonts tried in order for characters missing in `font`, like emoji or CJK
+    #[serde(default)]
+    pub fallback: Vec<String>,
+    /// Pixel size of TTF and OTF fonts, BDF fonts are drawn at their own size
+    #[serde(default = "default_font_size")]
+    pub size: f32,
+    pub speed: f32,
+    /// Alignment of the lines within a poem
+    #[serde(default = "default_align")]
+    pub align: Align,
+    /// Animation of poems not asking for a style
+    #[serde(default = "default_poetry_style")]
+    pub style: PoetryStyle,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+fn default_align() -> Align {
+    Align::Left
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PoetryStyle {
+    /// Fades out linearly
+    Fade,
+    /// Letters appear one after another
+    Typewriter,
+    /// Slides in from the right
+    Ticker,
+    /// Letters drop in from the top
+    Falling,
+    /// Letters jump around and change colours
+    Glitch,
+    /// Colours cycle through the rainbow
+    Rainbow,
+    /// Letters hop with the beat of the music
+    Pulse,
+}
+
+fn default_poetry_style() -> PoetryStyle {
+    PoetryStyle::Fade
+}
+
+fn default_font_size() -> f32 {
+    12.0
+}
+
+/// Message board ticker, `refresh` in seconds and `speed` in pixels per second.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Board {
+    /// Where posted messages are stored
+    #[serde(default = "default_board_messages")]
+    pub messages: String,
+    /// RSS or Atom feeds whose headlines are shown
+    #[serde(default)]
+    pub feeds: Vec<String>,
+    /// Text file with a message per line
+    pub file: Option<String>,
+    /// Headlines taken from each feed
+    #[serde(default = "default_feed_items")]
+    pub feed_items: usize,
+    #[serde(default = "default_board_refresh")]
+    pub refresh: u64,
+    #[serde(default = "default_ticker_speed")]
     pub speed: f32,
 }
 
+impl Default for Board {
+    fn default() -> Self {
+        Self {
+            messages: default_board_messages(),
+            feeds: Vec::new(),
+            file: None,
+            feed_items: default_feed_items(),
+            refresh: default_board_refresh(),
+            speed: default_ticker_speed(),
+        }
+    }
+}
+
+fn default_board_messages() -> String {
+    "messages.json".to_owned()
+}
+
+fn default_feed_items() -> usize {
+    5
+}
+
+fn default_board_refresh() -> u64 {
+    600
+}
+
+fn default_ticker_speed() -> f32 {
+    30.0
+}
+
+/// Widgets shown by the ambient display, `refresh` in seconds for the ones that are fetched.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Ambient {
+    /// Id of the shader drawn behind the widgets
+    pub background: Option<String>,
+    /// Seconds the wall has to be off and unused for the ambient display to start
+    pub idle: Option<u64>,
+    #[serde(default = "default_ambient_refresh")]
+    pub refresh: u64,
+    #[serde(default)]
+    pub widgets: Vec<WidgetSpec>,
+}
+
+impl Default for Ambient {
+    fn default() -> Self {
+        Self {
+            background: None,
+            idle: None,
+            refresh: default_ambient_refresh(),
+            widgets: Vec::new(),
+        }
+    }
+}
+
+fn default_ambient_refresh() -> u64 {
+    300
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WidgetSpec {
+    #[serde(flatten)]
+    pub widget: Widget,
+    /// Anchor from the top left, as fractions of the display
+    #[serde(default)]
+    pub x: f32,
+    #[serde(default)]
+    pub y: f32,
+    /// Which side of the widget is at the anchor
+    #[serde(default = "default_align")]
+    pub align: Align,
+    /// Multiplier of the font size or clock face
+    #[serde(default = "default_widget_scale")]
+    pub scale: f32,
+    /// Colour name or `#rrggbb`
+    #[serde(default = "default_widget_color")]
+    pub color: String,
+}
+
+fn default_widget_scale() -> f32 {
+    1.0
+}
+
+fn default_widget_color() -> String {
+    "white".to_owned()
+}
+
+/// `format`s use strftime syntax.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Widget {
+    Clock {
+        #[serde(default)]
+        analog: bool,
+        #[serde(default = "default_clock_format", deserialize_with = "strftime")]
+        format: String,
+    },
+    Date {
+        #[serde(default = "default_date_format", deserialize_with = "strftime")]
+        format: String,
+    },
+    /// Upcoming events of an iCal file
+    Events {
+        file: String,
+        #[serde(default = "default_event_count")]
+        count: usize,
+    },
+    Weather {
+        provider: WeatherProvider,
+    },
+    /// Open or closed state of a SpaceAPI endpoint
+    Space {
+        url: String,
+        #[serde(default = "default_space_open")]
+        open: String,
+        #[serde(default = "default_space_closed")]
+        closed: String,
+    },
+}
+
+/// A strftime format chrono understands, as formatting with any other panics.
+fn strftime<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let format = String::deserialize(deserializer)?;
+    if StrftimeItems::new(&format).any(|item| item == Item::Error) {
+        return Err(de::Error::custom(format!("Invalid time format {}", format)));
+    }
+    Ok(format)
+}
+
+fn default_clock_format() -> String {
+    "%H:%M".to_owned()
+}
+
+fn default_date_format() -> String {
+    "%a %e %b".to_owned()
+}
+
+fn default_event_count() -> usize {
+    3
+}
+
+fn default_space_open() -> String {
+    "Open".to_owned()
+}
+
+fn default_space_closed() -> String {
+    "Closed".to_owned()
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WeatherProvider {
+    OpenMeteo {
+        latitude: f64,
+        longitude: f64,
+    },
+    /// Shows the first line printed by a shell command
+    Command {
+        command: String,
+    },
+}
+
+/// mpv options applied per kind of video source, e.g. `ytdl-format` for youtube-dl.
+#[derive(Serialize, Deserialize, Default)]
+pub struct SourceOptions {
+    #[serde(default)]
+    pub local: HashMap<String, String>,
+    #[serde(default)]
+    pub stream: HashMap<String, String>,
+    #[serde(default)]
+    pub ytdl: HashMap<String, String>,
+}
+
+impl SourceOptions {
+    pub fn for_kind(&self, kind: SourceKind) -> &HashMap<String, String> {
+        match kind {
+            SourceKind::Local => &self.local,
+            SourceKind::Stream => &self.stream,
+            SourceKind::Ytdl => &self.ytdl,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Video {
+    #[serde(default = "default_queue")]
+    pub queue: String,
+    /// Directory of local media files
+    pub media: Option<String>,
+    #[serde(default)]
+    pub options: SourceOptions,
+}
+
+impl Default for Video {
+    fn default() -> Self {
+        Self {
+            queue: default_queue(),
+            media: None,
+            options: SourceOptions::default(),
+        }
+    }
+}
+
+fn default_queue() -> String {
+    "queue.json".to_owned()
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Emulator {
     pub roms: String,
@@ -34,6 +318,17 @@ pub struct Emulator {
     pub dmg: bool,
     #[serde(default = "default_fps")]
     pub fps: u32,
+    #[serde(default)]
+    pub upload_keys: Vec<String>,
+    #[serde(default = "default_recordings")]
+    pub recordings: String,
+    pub attract: Option<Attract>,
+}
+
+/// Replays random recordings when the wall has been off and untouched for `idle` seconds.
+#[derive(Serialize, Deserialize)]
+pub struct Attract {
+    pub idle: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -45,10 +340,458 @@ pub struct Mqtt {
     pub topic: String,
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Hotkey {
+    TurnOff,
+    Next,
+    VolumeUp,
+    VolumeDown,
+}
+
+/// Local evdev input devices. Controls are named like evdev's constants (`KEY_A`, `BTN_SOUTH`),
+/// hat axes with a direction suffix (`ABS_HAT0X-`).
+#[derive(Serialize, Deserialize)]
+pub struct Input {
+    /// Device nodes to read, all devices with keys if empty
+    #[serde(default)]
+    pub devices: Vec<String>,
+    #[serde(default = "default_joypad_mapping")]
+    pub joypad: HashMap<String, String>,
+    #[serde(default)]
+    pub hotkeys: HashMap<String, Hotkey>,
+    #[serde(default = "default_volume_step")]
+    pub volume_step: u8,
+}
+
+fn default_joypad_mapping() -> HashMap<String, String> {
+    [
+        ("KEY_UP", "up"),
+        ("KEY_DOWN", "down"),
+        ("KEY_LEFT", "left"),
+        ("KEY_RIGHT", "right"),
+        ("KEY_X", "a"),
+        ("KEY_Z", "b"),
+        ("KEY_ENTER", "start"),
+        ("KEY_RIGHTSHIFT", "select"),
+        ("BTN_DPAD_UP", "up"),
+        ("BTN_DPAD_DOWN", "down"),
+        ("BTN_DPAD_LEFT", "left"),
+        ("BTN_DPAD_RIGHT", "right"),
+        ("ABS_HAT0Y-", "up"),
+        ("ABS_HAT0Y+", "down"),
+        ("ABS_HAT0X-", "left"),
+        ("ABS_HAT0X+", "right"),
+        ("BTN_SOUTH", "a"),
+        ("BTN_EAST", "b"),
+        ("BTN_START", "start"),
+        ("BTN_SELECT", "select"),
+    ]
+    .iter()
+    .map(|(control, key)| (control.to_string(), key.to_string()))
+    .collect()
+}
+
+fn default_volume_step() -> u8 {
+    5
+}
+
 fn default_fps() -> u32 {
     60
 }
 
+fn default_recordings() -> String {
+    "recordings".to_owned()
+}
+
+/// Screenshots, preview stream and recordings of what the wall shows.
+#[derive(Serialize, Deserialize)]
+pub struct Capture {
+    /// HTTP port serving `/screenshot.png` and the `/preview.mjpeg` stream, off if not set
+    pub port: Option<u16>,
+    #[serde(default = "default_preview_fps")]
+    pub preview_fps: u32,
+    /// Where recordings are stored
+    #[serde(default = "default_captures")]
+    pub directory: String,
+    #[serde(default = "default_record_fps")]
+    pub record_fps: u32,
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Self {
+            port: None,
+            preview_fps: default_preview_fps(),
+            directory: default_captures(),
+            record_fps: default_record_fps(),
+        }
+    }
+}
+
+fn default_preview_fps() -> u32 {
+    5
+}
+
+fn default_captures() -> String {
+    "captures".to_owned()
+}
+
+fn default_record_fps() -> u32 {
+    25
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PixelProtocol {
+    ArtNet,
+    E131,
+    Ddp,
+}
+
+/// LED matrix driven over the network, gets the rendered frame scaled down to `width`×`height`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PixelOutput {
+    pub protocol: PixelProtocol,
+    /// Host, optionally with port, the protocol's default port is used otherwise
+    pub address: String,
+    pub width: u32,
+    pub height: u32,
+    /// First DMX universe for Art-Net and E1.31
+    #[serde(default)]
+    pub universe: u16,
+    /// Every other row runs right to left
+    #[serde(default)]
+    pub serpentine: bool,
+}
+
+/// Part of the rendered frame shown on one physical pixel module.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Module {
+    /// x, y, width and height in rendered pixels, from the top left corner
+    pub source: [u32; 4],
+    /// x, y, width and height in output pixels, from the top left corner
+    pub target: [u32; 4],
+    /// Clockwise in degrees, a multiple of 90
+    #[serde(default)]
+    pub rotation: u16,
+    #[serde(default)]
+    pub mirror_x: bool,
+    #[serde(default)]
+    pub mirror_y: bool,
+}
+
+/// Mapping of the rendered frame onto the physical wall, applied to everything shown.
+#[derive(Serialize, Deserialize)]
+pub struct Output {
+    /// Shows the whole frame on the whole window if empty
+    #[serde(default)]
+    pub modules: Vec<Module>,
+    /// Per channel gamma correction
+    #[serde(default = "default_color_correction")]
+    pub gamma: [f32; 3],
+    /// Per channel gain, applied before gamma
+    #[serde(default = "default_color_correction")]
+    pub gain: [f32; 3],
+    #[serde(default = "default_brightness")]
+    pub brightness: f32,
+    /// Upper limit for `brightness`, also when changed at runtime
+    #[serde(default = "default_brightness")]
+    pub max_brightness: f32,
+    /// Output pixels that are always kept dark, from the top left corner
+    #[serde(default)]
+    pub dead_pixels: Vec<[u32; 2]>,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self {
+            modules: Vec::new(),
+            gamma: default_color_correction(),
+            gain: default_color_correction(),
+            brightness: default_brightness(),
+            max_brightness: default_brightness(),
+            dead_pixels: Vec::new(),
+        }
+    }
+}
+
+fn default_color_correction() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_brightness() -> f32 {
+    1.0
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TransitionEffect {
+    Fade,
+    Wipe,
+    Dissolve,
+    Pulse,
+}
+
+/// Crossfade between states, `duration` in milliseconds, 0 switches immediately.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Transition {
+    #[serde(default = "default_transition_duration")]
+    pub duration: u64,
+    #[serde(default = "default_transition_effect")]
+    pub effect: TransitionEffect,
+}
+
+impl Default for Transition {
+    fn default() -> Self {
+        Self {
+            duration: default_transition_duration(),
+            effect: default_transition_effect(),
+        }
+    }
+}
+
+fn default_transition_duration() -> u64 {
+    500
+}
+
+fn default_transition_effect() -> TransitionEffect {
+    TransitionEffect::Fade
+}
+
+/// Pixel canvas visitors paint on together. `cooldown` is the seconds each visitor waits between
+/// pixels, `snapshot_interval` the seconds between snapshots in the database.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Canvas {
+    #[serde(default = "default_canvas_width")]
+    pub width: u32,
+    #[serde(default = "default_canvas_height")]
+    pub height: u32,
+    /// Colour names or `#rrggbb`, the first one is the background
+    #[serde(default = "default_canvas_palette")]
+    pub palette: Vec<String>,
+    #[serde(default = "default_canvas_cooldown")]
+    pub cooldown: u64,
+    /// Where the current canvas is kept
+    #[serde(default = "default_canvas_file")]
+    pub file: String,
+    #[serde(default = "default_canvas_snapshot_interval")]
+    pub snapshot_interval: u64,
+    /// Snapshots shown per second by the timelapse
+    #[serde(default = "default_canvas_timelapse_fps")]
+    pub timelapse_fps: u32,
+}
+
+impl Default for Canvas {
+    fn default() -> Self {
+        Self {
+            width: default_canvas_width(),
+            height: default_canvas_height(),
+            palette: default_canvas_palette(),
+            cooldown: default_canvas_cooldown(),
+            file: default_canvas_file(),
+            snapshot_interval: default_canvas_snapshot_interval(),
+            timelapse_fps: default_canvas_timelapse_fps(),
+        }
+    }
+}
+
+fn default_canvas_width() -> u32 {
+    48
+}
+
+fn default_canvas_height() -> u32 {
+    36
+}
+
+fn default_canvas_palette() -> Vec<String> {
+    [
+        "#000000", "#ffffff", "#888888", "#e50000", "#e59500", "#e5d900", "#02be01", "#0083c7",
+        "#0000ea", "#820080", "#cf6ee4", "#a06a42",
+    ]
+    .iter()
+    .map(|color| (*color).to_owned())
+    .collect()
+}
+
+fn default_canvas_cooldown() -> u64 {
+    5
+}
+
+fn default_canvas_file() -> String {
+    "canvas.json".to_owned()
+}
+
+fn default_canvas_snapshot_interval() -> u64 {
+    600
+}
+
+fn default_canvas_timelapse_fps() -> u32 {
+    10
+}
+
+/// VNC server shown by the VNC state.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Vnc {
+    /// `host:port` used when a request doesn't name one
+    pub server: Option<String>,
+    /// Only sent to `server`, other servers are connected to without one
+    pub password: Option<String>,
+    /// Other servers requests may name, `host:port` like `server`
+    #[serde(default)]
+    pub allowed: Vec<String>,
+    /// Let other viewers stay connected
+    #[serde(default = "default_vnc_shared")]
+    pub shared: bool,
+}
+
+impl Default for Vnc {
+    fn default() -> Self {
+        Self {
+            server: None,
+            password: None,
+            allowed: Vec::new(),
+            shared: default_vnc_shared(),
+        }
+    }
+}
+
+fn default_vnc_shared() -> bool {
+    true
+}
+
+/// Audio devices, the source shaders listen to on `iChannel0` and the master volume. Shaders
+/// get silence if the source can't be opened.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Audio {
+    /// Name or part of the name of the input device as listed over the API, the default input
+    /// if not set
+    pub device: Option<String>,
+    /// Output device, like `device`, also used by mpv as its ALSA device
+    pub output: Option<String>,
+    /// Channel of the device to listen to, from 0
+    #[serde(default)]
+    pub channel: u16,
+    #[serde(default = "default_audio_sample_rate")]
+    pub sample_rate: u32,
+    /// Samples per spectrum
+    #[serde(default = "default_audio_fft_size")]
+    pub fft_size: usize,
+    /// Share of the previous spectrum kept in each one, from 0 to 1, Shadertoy uses 0.8
+    #[serde(default)]
+    pub smoothing: f32,
+    /// Listened to instead of the input device
+    pub source: Option<AudioSource>,
+    /// Master volume from 0 to 100
+    #[serde(default = "default_audio_volume")]
+    pub volume: u8,
+    /// Share of the volume left while ducked, from 0 to 1
+    #[serde(default = "default_audio_duck")]
+    pub duck: f32,
+}
+
+/// Audio decoded with ffmpeg.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AudioSource {
+    /// Files or URLs played one after another, over and over
+    Playlist { files: Vec<String> },
+    /// HTTP or Icecast stream, played and reconnected to when it drops
+    Stream { url: String },
+    /// PulseAudio or PipeWire source, like the monitor of the speakers, only listened to
+    Monitor { source: String },
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Self {
+            device: None,
+            output: None,
+            channel: 0,
+            sample_rate: default_audio_sample_rate(),
+            fft_size: default_audio_fft_size(),
+            smoothing: 0.0,
+            source: None,
+            volume: default_audio_volume(),
+            duck: default_audio_duck(),
+        }
+    }
+}
+
+fn default_audio_sample_rate() -> u32 {
+    44100
+}
+
+fn default_audio_fft_size() -> usize {
+    1024
+}
+
+fn default_audio_volume() -> u8 {
+    100
+}
+
+fn default_audio_duck() -> f32 {
+    0.3
+}
+
+/// Serves the wall's picture to VNC viewers.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct VncServer {
+    /// Off if not set
+    pub port: Option<u16>,
+    #[serde(default = "default_vnc_server_fps")]
+    pub fps: u32,
+    pub password: Option<String>,
+    /// Pass pointer input on to shaders as `iMouse`, otherwise viewers can only watch
+    #[serde(default)]
+    pub interactive: bool,
+}
+
+impl Default for VncServer {
+    fn default() -> Self {
+        Self {
+            port: None,
+            fps: default_vnc_server_fps(),
+            password: None,
+            interactive: false,
+        }
+    }
+}
+
+fn default_vnc_server_fps() -> u32 {
+    10
+}
+
+/// An external program taking over the wall, like the Tox video phone. Commands run with `sh -c`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct App {
+    pub start: String,
+    pub stop: Option<String>,
+    /// Virtual terminal to switch to while the app runs
+    pub vt: Option<u16>,
+    /// Virtual terminal to switch back to afterwards
+    #[serde(default = "default_app_return_vt")]
+    pub return_vt: u16,
+    /// Command failing once the app stopped working, which turns the wall off
+    pub health: Option<String>,
+    /// Seconds between health checks
+    #[serde(default = "default_app_health_interval")]
+    pub health_interval: u64,
+    /// Seconds after which the app is stopped
+    pub timeout: Option<u64>,
+    /// Whether showing messages keeps the app running in the background
+    #[serde(default)]
+    pub messages: bool,
+}
+
+fn default_app_return_vt() -> u16 {
+    1
+}
+
+fn default_app_health_interval() -> u64 {
+    10
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Config {
     pub logconfig: String,
@@ -56,8 +799,33 @@ pub struct Config {
     pub server: Server,
     pub display: Display,
     pub poetry: Poetry,
+    #[serde(default)]
+    pub video: Video,
     pub emulator: Emulator,
     pub mqtt: Option<Mqtt>,
+    pub input: Option<Input>,
+    #[serde(default)]
+    pub transition: Transition,
+    #[serde(default)]
+    pub output: Output,
+    #[serde(default)]
+    pub pixel_outputs: Vec<PixelOutput>,
+    #[serde(default)]
+    pub capture: Capture,
+    #[serde(default)]
+    pub board: Board,
+    #[serde(default)]
+    pub ambient: Ambient,
+    #[serde(default)]
+    pub apps: HashMap<String, App>,
+    #[serde(default)]
+    pub vnc: Vnc,
+    #[serde(default)]
+    pub vnc_server: VncServer,
+    #[serde(default)]
+    pub canvas: Canvas,
+    #[serde(default)]
+    pub audio: Audio,
 }
 
 impl Config {
@@ -68,3 +836,23 @@ impl Config {
         Ok(u)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_formats_are_checked() {
+        let widget = |json| serde_json::from_str::<Widget>(json);
+        assert!(matches!(
+            widget(r#"{"type": "clock"}"#),
+            Ok(Widget::Clock { format, .. }) if format == "%H:%M"
+        ));
+        assert!(matches!(
+            widget(r#"{"type": "date", "format": "%A %-d."}"#),
+            Ok(Widget::Date { format }) if format == "%A %-d."
+        ));
+        assert!(widget(r#"{"type": "clock", "format": "%H:%"}"#).is_err());
+        assert!(widget(r#"{"type": "date", "format": "%Q"}"#).is_err());
+    }
+}
@@ -7,6 +7,8 @@ pub struct Database {
 }
 
 const BRANCH_PREFIX: &str = "shader-";
+const CANVAS_BRANCH: &str = "canvas";
+const CANVAS_FILE: &str = "canvas.json";
 
 impl Database {
     pub fn new(path: &str) -> Database {
@@ -186,6 +188,47 @@ impl Database {
         Ok(format!("{}", commit_oid))
     }
 
+    /// Commits a canvas snapshot on top of the previous ones.
+    pub fn save_canvas(&self, snapshot: &[u8], message: &str) -> Result<String, Error> {
+        let parent = match self
+            .repository
+            .find_branch(CANVAS_BRANCH, BranchType::Local)
+        {
+            Ok(branch) => Some(branch.get().peel_to_commit()?),
+            Err(_) => None,
+        };
+        let blob = self.repository.blob(snapshot)?;
+        let mut treebuilder = self.repository.treebuilder(None)?;
+        treebuilder.insert(CANVAS_FILE, blob, 0o100644)?;
+        let commit_oid = self.commit_treebuilder(parent.as_ref(), &treebuilder, message)?;
+        let commit = self.repository.find_commit(commit_oid)?;
+        self.repository.branch(CANVAS_BRANCH, &commit, true)?;
+        Ok(format!("{}", commit_oid))
+    }
+
+    /// The latest `limit` canvas snapshots, oldest first.
+    pub fn canvas_history(&self, limit: usize) -> Result<Vec<Vec<u8>>, Error> {
+        let branch = self
+            .repository
+            .find_branch(CANVAS_BRANCH, BranchType::Local)?;
+        let mut commit = Some(branch.get().peel_to_commit()?);
+        let mut snapshots = Vec::new();
+        while let Some(current) = commit {
+            if snapshots.len() >= limit {
+                break;
+            }
+            if let Some(entry) = current.tree()?.get_name(CANVAS_FILE) {
+                let object = entry.to_object(&self.repository)?;
+                if let Some(blob) = object.as_blob() {
+                    snapshots.push(blob.content().to_vec());
+                }
+            }
+            commit = current.parents().next();
+        }
+        snapshots.reverse();
+        Ok(snapshots)
+    }
+
     pub fn remove(&self, name: &str) -> Result<(), Error> {
         let mut branch = self
             .repository
@@ -1,4 +1,4 @@
-use std::{borrow::Cow, path::Path};
+use std::{borrow::Cow, io, path::Path};
 
 use glium::{
     backend::glutin::Display,
@@ -8,10 +8,14 @@ use glium::{
     uniforms::{MagnifySamplerFilter, MinifySamplerFilter, Sampler},
     Rect, Surface, {implement_vertex, program},
 };
+use log::{error, info};
 use mizu_core::{GameBoy, GameboyConfig, JoypadButton};
 
-mod audio;
-use audio::AudioPlayer;
+pub mod library;
+pub mod recording;
+use recording::{InputEvent, Recording};
+
+use crate::audio::{Audio, Player};
 
 pub const GB_WIDTH: u32 = 160;
 pub const GB_HEIGHT: u32 = 144;
@@ -47,49 +51,161 @@ void main() {
 }
 ";
 
+/// Keys of all joypad buttons.
+const KEYS: [&str; 8] = ["a", "b", "select", "start", "up", "down", "left", "right"];
+
+pub fn button_for_key(key: &str) -> Option<JoypadButton> {
+    Some(match key {
+        "a" => JoypadButton::A,
+        "b" => JoypadButton::B,
+        "select" => JoypadButton::Select,
+        "start" => JoypadButton::Start,
+        "up" => JoypadButton::Up,
+        "down" => JoypadButton::Down,
+        "left" => JoypadButton::Left,
+        "right" => JoypadButton::Right,
+        _ => return None,
+    })
+}
+
 #[derive(Copy, Clone)]
 pub struct Vertex {
     pub position: [f32; 2],
     pub texcoords: [f32; 2],
 }
 
-pub struct Emulator {
-    _game_name: String,
+struct Replay {
+    recording: Recording,
+    next: usize,
+    looping: bool,
+}
+
+/// The Game Boy with the recording or replay of its input, everything of the emulator apart from
+/// drawing and sound.
+struct Console {
+    game_name: String,
     gameboy: GameBoy,
+    frame: u64,
+    recording: Option<Recording>,
+    replay: Option<Replay>,
+}
+
+impl Console {
+    fn new(game: &str, config: &crate::config::Emulator) -> io::Result<Self> {
+        let mizu_config = GameboyConfig { is_dmg: config.dmg };
+        let mut file_path = <String as AsRef<Path>>::as_ref(&config.roms).to_path_buf();
+        file_path.push(game);
+        let gameboy = GameBoy::new(file_path, None, mizu_config)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{err:?}")))?;
+        Ok(Self {
+            game_name: game.to_owned(),
+            gameboy,
+            frame: 0,
+            recording: None,
+            replay: None,
+        })
+    }
+
+    fn input(&mut self, key: &str, press: bool) {
+        let button = match button_for_key(key) {
+            Some(button) => button,
+            None => return,
+        };
+        if self.replay.take().is_some() {
+            info!("Replay interrupted by user input");
+            // nobody else pressed anything while the replay ran
+            for button in KEYS.iter().filter_map(|key| button_for_key(key)) {
+                self.gameboy.release_joypad(button);
+            }
+        }
+        if let Some(recording) = &mut self.recording {
+            recording.events.push(InputEvent {
+                frame: self.frame,
+                key: key.to_owned(),
+                press,
+            });
+        }
+        if press {
+            self.gameboy.press_joypad(button);
+        } else {
+            self.gameboy.release_joypad(button);
+        }
+    }
+
+    fn start_recording(&mut self) -> io::Result<()> {
+        let state = recording::save_state(&self.gameboy)?;
+        self.recording = Some(Recording::new(&self.game_name, &state));
+        self.frame = 0;
+        Ok(())
+    }
+
+    fn start_replay(&mut self, recording: Recording, looping: bool) -> io::Result<()> {
+        recording::load_state(&mut self.gameboy, &recording.state()?)?;
+        self.frame = 0;
+        self.recording = None;
+        self.replay = Some(Replay {
+            recording,
+            next: 0,
+            looping,
+        });
+        Ok(())
+    }
+
+    /// Emulates one frame, with the input of a running replay.
+    fn step(&mut self) {
+        if let Some(replay) = &mut self.replay {
+            if self.frame >= replay.recording.frames {
+                if replay.looping {
+                    let gameboy = &mut self.gameboy;
+                    if let Err(err) = replay
+                        .recording
+                        .state()
+                        .and_then(|state| recording::load_state(gameboy, &state))
+                    {
+                        error!("Failed restarting replay: {}", err);
+                        self.replay = None;
+                    } else {
+                        self.frame = 0;
+                        replay.next = 0;
+                    }
+                } else {
+                    info!("Replay finished");
+                    self.replay = None;
+                }
+            }
+        }
+        if let Some(replay) = &mut self.replay {
+            replay.next = replay
+                .recording
+                .apply(&mut self.gameboy, self.frame, replay.next);
+        }
+        self.gameboy.clock_for_frame();
+        self.frame += 1;
+        if let Some(recording) = &mut self.recording {
+            recording.frames = self.frame;
+        }
+    }
+}
+
+pub struct Emulator {
+    console: Console,
     _fps: u32,
     texture: Texture2d,
     program: glium::Program,
     vertex_buffer: glium::VertexBuffer<Vertex>,
     index_buffer: glium::IndexBuffer<u16>,
-    audio_player: AudioPlayer,
+    /// Silent if the output couldn't be opened
+    audio_player: Option<Player>,
 }
 
 impl Emulator {
-    pub fn available_roms(roms: impl AsRef<Path>) -> Result<Vec<String>, std::io::Error> {
-        let path: &Path = roms.as_ref();
-        std::fs::read_dir(path)?
-            .map(|file| -> Result<Option<_>, _> {
-                let path = file?.path();
-                Ok(path
-                    .extension()
-                    .and_then(|extension| {
-                        (extension.to_str() == Some("gb") || extension.to_str() == Some("gbc"))
-                            .then(|| path.as_path())
-                    })
-                    .and_then(|path| {
-                        path.file_name()
-                            .and_then(|file_name| file_name.to_str().map(|s| s.to_owned()))
-                    }))
-            })
-            .filter_map(std::result::Result::transpose)
-            .collect()
-    }
-
-    pub fn new(display: &Display, game: &str, config: &crate::config::Emulator) -> Self {
-        let mizu_config = GameboyConfig { is_dmg: config.dmg };
-        let mut file_path = <String as AsRef<Path>>::as_ref(&config.roms).to_path_buf();
-        file_path.push(game);
-
+    pub fn new(
+        display: &Display,
+        game: &str,
+        config: &crate::config::Emulator,
+        audio: &Audio,
+    ) -> io::Result<Self> {
+        let console = Console::new(game, config)?;
         implement_vertex!(Vertex, position, texcoords);
 
         let vertex_buffer = glium::VertexBuffer::new(
@@ -131,54 +247,50 @@ impl Emulator {
         )
         .unwrap();
 
-        let audio_player = AudioPlayer::new(44100);
-        audio_player.play();
+        let audio_player = audio
+            .player(44100)
+            .map_err(|err| error!("Failed opening audio output for the emulator: {}", err))
+            .ok();
 
-        Self {
-            _game_name: game.to_owned(),
-            gameboy: GameBoy::new(file_path, None, mizu_config).unwrap(),
+        Ok(Self {
+            console,
             _fps: config.fps,
             texture,
             program,
             vertex_buffer,
             index_buffer,
             audio_player,
-        }
+        })
     }
 
-    // pub fn game_name(&self) -> &str {
-    //     self.gameboy.game_title()
-    // }
-
     pub fn input(&mut self, key: &str, press: bool) {
-        let button = match key {
-            "a" => JoypadButton::A,
-            "b" => JoypadButton::B,
-            "select" => JoypadButton::Select,
-            "start" => JoypadButton::Start,
-            "up" => JoypadButton::Up,
-            "down" => JoypadButton::Down,
-            "left" => JoypadButton::Left,
-            "right" => JoypadButton::Right,
-            _ => return,
-        };
-        if press {
-            self.gameboy.press_joypad(button);
-        } else {
-            self.gameboy.release_joypad(button);
-        }
+        self.console.input(key, press);
     }
 
-    pub fn step(&mut self, display: &Display) {
-        let size = display.gl_window().window().inner_size();
-        // TODO: handle key inputs
-        self.gameboy.clock_for_frame();
+    pub fn start_recording(&mut self) -> io::Result<()> {
+        self.console.start_recording()
+    }
 
-        let audio_buffer = self.gameboy.audio_buffer();
-        self.audio_player.queue(&audio_buffer);
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        self.console.recording.take()
+    }
+
+    /// Restores the save state of `recording` and replays its input, optionally forever.
+    pub fn start_replay(&mut self, recording: Recording, looping: bool) -> io::Result<()> {
+        self.console.start_replay(recording, looping)
+    }
+
+    /// Emulates one frame and draws the screen centered into `target`.
+    pub fn draw<S: Surface>(&mut self, target: &mut S, size: (u32, u32)) {
+        self.console.step();
+
+        let audio_buffer = self.console.gameboy.audio_buffer();
+        if let Some(audio_player) = &mut self.audio_player {
+            audio_player.queue(&audio_buffer);
+        }
 
         let raw_image = RawImage2d {
-            data: Cow::Borrowed(self.gameboy.screen_buffer()),
+            data: Cow::Borrowed(self.console.gameboy.screen_buffer()),
             width: GB_WIDTH,
             height: GB_HEIGHT,
             format: ClientFormat::U8U8U8,
@@ -193,14 +305,11 @@ impl Emulator {
             raw_image,
         );
 
-        let mut target = display.draw();
-        target.clear_color_and_depth((0.0, 0.0, 0.0, 1.0), 1.0);
-
         let uniforms = uniform! {
             tex: Sampler::new(&self.texture)
                 .minify_filter(MinifySamplerFilter::Nearest)
                 .magnify_filter(MagnifySamplerFilter::Nearest),
-            transform: [GB_WIDTH as f32 / size.width as f32, GB_HEIGHT as f32 / size.height as f32, 0.0, 0.0]
+            transform: [GB_WIDTH as f32 / size.0 as f32, GB_HEIGHT as f32 / size.1 as f32, 0.0, 0.0]
         };
 
         target
@@ -212,6 +321,5 @@ impl Emulator {
                 &Default::default(),
             )
             .unwrap();
-        target.finish().ok();
     }
 }
@@ -0,0 +1,360 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Sender},
+        Mutex,
+    },
+    thread,
+};
+
+use mizu_core::{GameBoy, GameboyConfig};
+use serde::Serialize;
+
+use super::{GB_HEIGHT, GB_WIDTH};
+
+const HEADER_TITLE: std::ops::Range<usize> = 0x134..0x144;
+const HEADER_MANUFACTURER: usize = 0x13f;
+const HEADER_CGB_FLAG: usize = 0x143;
+const HEADER_CHECKSUM: usize = 0x14d;
+const HEADER_END: usize = 0x150;
+
+/// Number of frames to run a ROM before taking its screenshot, enough to get past the boot logo.
+const SCREENSHOT_FRAMES: usize = 300;
+const SCREENSHOT_DIRECTORY: &str = ".screenshots";
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    Png(png::EncodingError),
+    InvalidName,
+    TooShort,
+    Checksum { expected: u8, actual: u8 },
+    Emulator(String),
+}
+
+impl From<io::Error> for RomError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<png::EncodingError> for RomError {
+    fn from(err: png::EncodingError) -> Self {
+        Self::Png(err)
+    }
+}
+
+impl std::fmt::Display for RomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => err.fmt(f),
+            Self::Png(err) => err.fmt(f),
+            Self::InvalidName => write!(f, "ROM file name must end in .gb or .gbc"),
+            Self::TooShort => write!(f, "ROM is too short to contain a cartridge header"),
+            Self::Checksum { expected, actual } => write!(
+                f,
+                "Header checksum mismatch (expected {expected:#04x}, got {actual:#04x})"
+            ),
+            Self::Emulator(err) => write!(f, "Emulator error: {err}"),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CgbSupport {
+    Dmg,
+    Compatible,
+    Exclusive,
+}
+
+#[derive(Serialize)]
+pub struct RomInfo {
+    pub file: String,
+    pub title: String,
+    pub cgb: CgbSupport,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub screenshot: Option<String>,
+}
+
+/// Encodes an RGB Game Boy screen buffer as PNG.
+pub fn encode_screen(screen: &[u8]) -> Result<Vec<u8>, png::EncodingError> {
+    let mut png = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(BufWriter::new(&mut png), GB_WIDTH, GB_HEIGHT);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(screen)?;
+    }
+    Ok(png)
+}
+
+pub struct Header {
+    pub title: String,
+    pub cgb: CgbSupport,
+}
+
+impl Header {
+    /// Parses the cartridge header and verifies its checksum.
+    pub fn parse(rom: &[u8]) -> Result<Self, RomError> {
+        if rom.len() < HEADER_END {
+            return Err(RomError::TooShort);
+        }
+        let actual = rom[HEADER_TITLE.start..HEADER_CHECKSUM]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_sub(*byte).wrapping_sub(1));
+        let expected = rom[HEADER_CHECKSUM];
+        if actual != expected {
+            return Err(RomError::Checksum { expected, actual });
+        }
+
+        let cgb = match rom[HEADER_CGB_FLAG] {
+            0x80 => CgbSupport::Compatible,
+            0xc0 => CgbSupport::Exclusive,
+            _ => CgbSupport::Dmg,
+        };
+        // CGB cartridges use the last bytes of the title area for the manufacturer code and flag
+        let title_end = if cgb == CgbSupport::Dmg {
+            HEADER_TITLE.end
+        } else {
+            HEADER_MANUFACTURER
+        };
+        let title = rom[HEADER_TITLE.start..title_end]
+            .iter()
+            .take_while(|byte| **byte != 0)
+            .map(|byte| *byte as char)
+            .filter(|ch| ch.is_ascii_graphic() || *ch == ' ')
+            .collect::<String>()
+            .trim()
+            .to_owned();
+
+        Ok(Self { title, cgb })
+    }
+}
+
+fn screenshot_path(roms: &Path, name: &str) -> PathBuf {
+    let mut path = roms.join(SCREENSHOT_DIRECTORY);
+    path.push(format!("{name}.png"));
+    path
+}
+
+/// Runs the ROM headless for a few seconds and stores the screen as a PNG.
+fn create_screenshot(roms: &Path, dmg: bool, name: &str) -> Result<(), RomError> {
+    let mut gameboy = GameBoy::new(roms.join(name), None, GameboyConfig { is_dmg: dmg })
+        .map_err(|err| RomError::Emulator(format!("{err:?}")))?;
+    for _ in 0..SCREENSHOT_FRAMES {
+        gameboy.clock_for_frame();
+    }
+
+    let png = encode_screen(gameboy.screen_buffer())?;
+    let path = screenshot_path(roms, name);
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    fs::write(path, &png)?;
+    Ok(())
+}
+
+pub struct RomLibrary {
+    path: PathBuf,
+    upload_keys: Vec<String>,
+    /// ROMs handed to the screenshot thread, kept so failing ones aren't retried on every list
+    queued: Mutex<HashSet<String>>,
+    screenshots: Mutex<Sender<String>>,
+}
+
+impl RomLibrary {
+    pub fn new(config: &crate::config::Emulator) -> Self {
+        let path: PathBuf = (&config.roms).into();
+        let (screenshots, names) = channel::<String>();
+        let (roms, dmg) = (path.clone(), config.dmg);
+        thread::Builder::new()
+            .name("ROM screenshots".to_owned())
+            .spawn(move || {
+                for name in names {
+                    if let Err(err) = create_screenshot(&roms, dmg, &name) {
+                        log::error!("Failed creating screenshot for {name}: {err}");
+                    }
+                }
+            })
+            .map_err(|err| log::error!("Failed starting ROM screenshot thread: {err}"))
+            .ok();
+        Self {
+            path,
+            upload_keys: config.upload_keys.clone(),
+            queued: Mutex::new(HashSet::new()),
+            screenshots: Mutex::new(screenshots),
+        }
+    }
+
+    pub fn is_authorized(&self, key: Option<&str>) -> bool {
+        key.map(|key| self.upload_keys.iter().any(|allowed| allowed == key))
+            .unwrap_or(false)
+    }
+
+    fn is_rom_name(name: &str) -> bool {
+        let path = Path::new(name);
+        path.file_name().and_then(|file_name| file_name.to_str()) == Some(name)
+            && matches!(
+                path.extension().and_then(|extension| extension.to_str()),
+                Some("gb") | Some("gbc")
+            )
+    }
+
+    fn screenshot_path(&self, name: &str) -> PathBuf {
+        screenshot_path(&self.path, name)
+    }
+
+    /// Has the screenshot thread create the missing screenshot of a ROM.
+    fn queue_screenshot(&self, name: &str) {
+        if self.queued.lock().unwrap().insert(name.to_owned()) {
+            self.screenshots.lock().unwrap().send(name.to_owned()).ok();
+        }
+    }
+
+    pub fn available_roms(&self) -> Result<Vec<String>, io::Error> {
+        let mut roms = fs::read_dir(&self.path)?
+            .map(|file| -> Result<Option<_>, io::Error> {
+                Ok(file?
+                    .file_name()
+                    .to_str()
+                    .filter(|name| Self::is_rom_name(name))
+                    .map(|name| name.to_owned()))
+            })
+            .filter_map(std::result::Result::transpose)
+            .collect::<Result<Vec<_>, _>>()?;
+        roms.sort();
+        Ok(roms)
+    }
+
+    pub fn info(&self, name: &str) -> Result<RomInfo, RomError> {
+        if !Self::is_rom_name(name) {
+            return Err(RomError::InvalidName);
+        }
+        let rom = fs::read(self.path.join(name))?;
+        let header = Header::parse(&rom)?;
+        // listed without one until the screenshot thread is done
+        let screenshot = match fs::read(self.screenshot_path(name)) {
+            Ok(png) => Some(png),
+            Err(_) => {
+                self.queue_screenshot(name);
+                None
+            }
+        };
+        Ok(RomInfo {
+            file: name.to_owned(),
+            title: header.title,
+            cgb: header.cgb,
+            screenshot: screenshot.map(base64::encode),
+        })
+    }
+
+    pub fn list(&self) -> Result<Vec<RomInfo>, io::Error> {
+        Ok(self
+            .available_roms()?
+            .iter()
+            .filter_map(|name| {
+                self.info(name)
+                    .map_err(|err| log::error!("Skipping ROM {name}: {err}"))
+                    .ok()
+            })
+            .collect())
+    }
+
+    pub fn upload(&self, name: &str, rom: &[u8]) -> Result<RomInfo, RomError> {
+        if !Self::is_rom_name(name) {
+            return Err(RomError::InvalidName);
+        }
+        Header::parse(rom)?;
+        fs::write(self.path.join(name), rom)?;
+        fs::remove_file(self.screenshot_path(name)).ok();
+        self.queued.lock().unwrap().remove(name);
+        self.info(name)
+    }
+
+    pub fn remove(&self, name: &str) -> Result<(), RomError> {
+        if !Self::is_rom_name(name) {
+            return Err(RomError::InvalidName);
+        }
+        fs::remove_file(self.path.join(name))?;
+        fs::remove_file(self.screenshot_path(name)).ok();
+        self.queued.lock().unwrap().remove(name);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ROM with `title` and the CGB `flag` and a correct header checksum.
+    fn rom(title: &[u8], flag: u8) -> Vec<u8> {
+        let mut rom = vec![0u8; 0x8000];
+        rom[HEADER_TITLE.start..HEADER_TITLE.start + title.len()].copy_from_slice(title);
+        rom[HEADER_CGB_FLAG] = flag;
+        rom[HEADER_CHECKSUM] = rom[HEADER_TITLE.start..HEADER_CHECKSUM]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_sub(*byte).wrapping_sub(1));
+        rom
+    }
+
+    #[test]
+    fn header_checksum() {
+        // the header of Tetris, revision A
+        let mut tetris = vec![0u8; HEADER_END];
+        tetris[HEADER_TITLE.start..HEADER_TITLE.start + 6].copy_from_slice(b"TETRIS");
+        // licensee and version
+        tetris[0x14b] = 1;
+        tetris[0x14c] = 1;
+        tetris[HEADER_CHECKSUM] = 0x0a;
+        assert_eq!(Header::parse(&tetris).unwrap().title, "TETRIS");
+
+        tetris[HEADER_CHECKSUM] = 0x0b;
+        match Header::parse(&tetris) {
+            Err(RomError::Checksum { expected, actual }) => {
+                assert_eq!((expected, actual), (0x0b, 0x0a))
+            }
+            _ => panic!("expected a checksum mismatch"),
+        }
+        assert!(matches!(
+            Header::parse(&tetris[..HEADER_END - 1]),
+            Err(RomError::TooShort)
+        ));
+    }
+
+    #[test]
+    fn header_title_and_cgb_support() {
+        let header = Header::parse(&rom(b"POKEMON RED", 0)).unwrap();
+        assert_eq!(header.title, "POKEMON RED");
+        assert_eq!(header.cgb, CgbSupport::Dmg);
+
+        // the last title bytes are the manufacturer code and the flag on CGB cartridges
+        let header = Header::parse(&rom(b"POKEMON_SLVAAXE", 0x80)).unwrap();
+        assert_eq!(header.title, "POKEMON_SLV");
+        assert_eq!(header.cgb, CgbSupport::Compatible);
+        let header = Header::parse(&rom(b"SHANTAE\0\0\0\0", 0xc0)).unwrap();
+        assert_eq!(header.title, "SHANTAE");
+        assert_eq!(header.cgb, CgbSupport::Exclusive);
+
+        // a full DMG title ends in what would be the flag
+        let header = Header::parse(&rom(b"SIXTEEN CHARS!!", b'X')).unwrap();
+        assert_eq!(header.title, "SIXTEEN CHARS!!X");
+        assert_eq!(header.cgb, CgbSupport::Dmg);
+
+        let header = Header::parse(&rom(b" \x01TAB\tBED ", 0)).unwrap();
+        assert_eq!(header.title, "TABBED");
+    }
+
+    #[test]
+    fn rom_names() {
+        assert!(RomLibrary::is_rom_name("tetris.gb"));
+        assert!(RomLibrary::is_rom_name("zelda dx.gbc"));
+        assert!(!RomLibrary::is_rom_name("tetris.gba"));
+        assert!(!RomLibrary::is_rom_name("../tetris.gb"));
+        assert!(!RomLibrary::is_rom_name("roms/tetris.gb"));
+        assert!(!RomLibrary::is_rom_name(".gb"));
+    }
+}
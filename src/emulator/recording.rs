@@ -0,0 +1,308 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Cursor},
+    path::Path,
+};
+
+use mizu_core::{GameBoy, GameboyConfig};
+use serde::{Deserialize, Serialize};
+
+use super::button_for_key;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct InputEvent {
+    pub frame: u64,
+    pub key: String,
+    pub press: bool,
+}
+
+/// A joypad input stream together with the save state it was recorded from.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Recording {
+    pub rom: String,
+    /// Base64 encoded mizu save state at frame 0
+    pub state: String,
+    pub frames: u64,
+    pub events: Vec<InputEvent>,
+}
+
+impl Recording {
+    pub fn new(rom: &str, state: &[u8]) -> Self {
+        Self {
+            rom: rom.to_owned(),
+            state: base64::encode(state),
+            frames: 0,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        let file = File::create(path)?;
+        serde_json::to_writer(BufWriter::new(file), self)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+    }
+
+    pub fn list(directory: impl AsRef<Path>) -> io::Result<Vec<String>> {
+        let mut names = fs::read_dir(directory)?
+            .map(|file| -> io::Result<Option<String>> {
+                let path = file?.path();
+                Ok(path
+                    .extension()
+                    .filter(|extension| extension.to_str() == Some("json"))
+                    .and_then(|_| path.file_stem())
+                    .and_then(|stem| stem.to_str().map(|s| s.to_owned())))
+            })
+            .filter_map(std::result::Result::transpose)
+            .collect::<io::Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn state(&self) -> io::Result<Vec<u8>> {
+        base64::decode(&self.state).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Applies the recorded input of `frame` to `gameboy`, starting at event index `next`.
+    /// Returns the index of the first event of a later frame.
+    pub fn apply(&self, gameboy: &mut GameBoy, frame: u64, mut next: usize) -> usize {
+        while let Some(event) = self.events.get(next).filter(|event| event.frame <= frame) {
+            if let Some(button) = button_for_key(&event.key) {
+                if event.press {
+                    gameboy.press_joypad(button);
+                } else {
+                    gameboy.release_joypad(button);
+                }
+            }
+            next += 1;
+        }
+        next
+    }
+}
+
+pub fn load_state(gameboy: &mut GameBoy, state: &[u8]) -> io::Result<()> {
+    gameboy
+        .load_state(Cursor::new(state))
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{err:?}")))
+}
+
+pub fn save_state(gameboy: &GameBoy) -> io::Result<Vec<u8>> {
+    let mut state = Vec::new();
+    gameboy
+        .save_state(&mut state)
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("{err:?}")))?;
+    Ok(state)
+}
+
+/// Replays a recording without a display and returns the final screen buffer.
+pub fn replay_headless(
+    config: &crate::config::Emulator,
+    recording: &Recording,
+) -> io::Result<Vec<u8>> {
+    let mut gameboy = GameBoy::new(
+        Path::new(&config.roms).join(&recording.rom),
+        None,
+        GameboyConfig { is_dmg: config.dmg },
+    )
+    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{err:?}")))?;
+    load_state(&mut gameboy, &recording.state()?)?;
+
+    let mut next = 0;
+    for frame in 0..recording.frames {
+        next = recording.apply(&mut gameboy, frame, next);
+        gameboy.clock_for_frame();
+    }
+    Ok(gameboy.screen_buffer().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{emulator::Console, testing::TempDir};
+    use std::{
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+    };
+
+    const ROM: &str = "palette.gb";
+
+    /// A ROM that shades the whole screen by the left and right buttons, written to its own
+    /// directory.
+    fn fixture() -> TempDir {
+        let directory = TempDir::new("recording");
+        let mut rom = vec![0u8; 0x8000];
+        // nop; jp 0x150
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+        rom[0x104..0x134].copy_from_slice(&[
+            0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c,
+            0x00, 0x0d, 0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6,
+            0xdd, 0xdd, 0xd9, 0x99, 0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc,
+            0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+        ]);
+        rom[0x134..0x13b].copy_from_slice(b"PALETTE");
+        rom[0x14d] = rom[0x134..0x14d]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_sub(*byte).wrapping_sub(1));
+        rom[0x150..0x15e].copy_from_slice(&[
+            0x3e, 0x20, // ld a, 0x20: select the direction buttons
+            0xe0, 0x00, // ldh (0x00), a
+            0xf0, 0x00, // ldh a, (0x00)
+            0xf0, 0x00, // ldh a, (0x00)
+            0xe6, 0x03, // and 0x03: right and left, 0 while pressed
+            0xe0, 0x47, // ldh (0x47), a: shade of the background
+            0x18, 0xf2, // jr 0x150
+        ]);
+        fs::write(directory.join(ROM), rom).unwrap();
+        directory
+    }
+
+    fn config(roms: &Path) -> crate::config::Emulator {
+        crate::config::Emulator {
+            roms: roms.to_str().unwrap().to_owned(),
+            dmg: true,
+            fps: 60,
+            upload_keys: Vec::new(),
+            recordings: roms.to_str().unwrap().to_owned(),
+            attract: None,
+        }
+    }
+
+    fn hash(screen: &[u8]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        screen.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn event(frame: u64, key: &str, press: bool) -> InputEvent {
+        InputEvent {
+            frame,
+            key: key.to_owned(),
+            press,
+        }
+    }
+
+    /// Plays `events` on the ROM like a player would while recording them, returns the recording
+    /// and the final frame.
+    fn record(
+        config: &crate::config::Emulator,
+        frames: u64,
+        events: &[InputEvent],
+    ) -> (Recording, u64) {
+        let mut console = Console::new(ROM, config).unwrap();
+        // let the ROM settle before recording starts
+        for _ in 0..10 {
+            console.step();
+        }
+        console.start_recording().unwrap();
+        for frame in 0..frames {
+            for event in events.iter().filter(|event| event.frame == frame) {
+                console.input(&event.key, event.press);
+            }
+            console.step();
+        }
+        let played = hash(console.gameboy.screen_buffer());
+        (console.recording.take().unwrap(), played)
+    }
+
+    #[test]
+    fn replay_reproduces_the_played_frame() {
+        let directory = fixture();
+        let config = config(directory.path());
+        let events = [
+            event(5, "right", true),
+            event(20, "right", false),
+            event(25, "left", true),
+            event(25, "start", true),
+            event(30, "unknown", true),
+        ];
+        let (recording, played) = record(&config, 40, &events);
+        assert_eq!(recording.rom, ROM);
+        assert_eq!(recording.frames, 40);
+        let recorded: Vec<_> = recording
+            .events
+            .iter()
+            .map(|event| (event.frame, event.key.as_str(), event.press))
+            .collect();
+        assert_eq!(
+            recorded,
+            [
+                (5, "right", true),
+                (20, "right", false),
+                (25, "left", true),
+                (25, "start", true),
+            ]
+        );
+
+        let path = directory.join("replay.json");
+        recording.save(&path).unwrap();
+        let loaded = Recording::load(&path).unwrap();
+        assert_eq!(hash(&replay_headless(&config, &loaded).unwrap()), played);
+
+        // the input makes a difference to the frame
+        let (idle, _) = record(&config, 40, &[]);
+        assert_ne!(hash(&replay_headless(&config, &idle).unwrap()), played);
+    }
+
+    #[test]
+    fn input_interrupting_a_replay_releases_its_buttons() {
+        let directory = fixture();
+        let config = config(directory.path());
+        let (recording, _) = record(&config, 40, &[event(0, "right", true)]);
+
+        let mut console = Console::new(ROM, &config).unwrap();
+        console.start_replay(recording.clone(), false).unwrap();
+        for _ in 0..5 {
+            console.step();
+        }
+        console.input("left", true);
+        assert!(console.replay.is_none());
+        for _ in 0..5 {
+            console.step();
+        }
+
+        // only left is held, as if the replay never pressed right
+        let mut expected = Console::new(ROM, &config).unwrap();
+        expected.start_replay(recording, false).unwrap();
+        expected.replay = None;
+        for _ in 0..5 {
+            expected.step();
+        }
+        expected.input("left", true);
+        for _ in 0..5 {
+            expected.step();
+        }
+        assert_eq!(
+            hash(console.gameboy.screen_buffer()),
+            hash(expected.gameboy.screen_buffer())
+        );
+    }
+
+    #[test]
+    fn replay_of_a_missing_rom_fails() {
+        let directory = fixture();
+        let config = config(directory.path());
+        let (mut recording, _) = record(&config, 1, &[]);
+        recording.rom = "deleted.gb".to_owned();
+        assert!(replay_headless(&config, &recording).is_err());
+    }
+
+    #[test]
+    fn list_only_names_recordings() {
+        let directory = fixture();
+        fs::write(directory.join("b.json"), "{}").unwrap();
+        fs::write(directory.join("a.json"), "{}").unwrap();
+        assert_eq!(
+            Recording::list(directory.path()).unwrap(),
+            vec!["a".to_owned(), "b".to_owned()]
+        );
+    }
+}
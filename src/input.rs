@@ -0,0 +1,287 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::mpsc::Sender,
+    thread,
+};
+
+use evdev::{AbsoluteAxisType, Device, InputEventKind, Key};
+
+use crate::{
+    config::{Hotkey, Input},
+    server::{connection, Command},
+};
+
+/// A physical control that can be mapped, either a key/button or one direction of an axis
+/// (written as e.g. `ABS_HAT0X-` or `ABS_HAT0Y+` in the config).
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Control {
+    Key(Key),
+    Axis(u16, bool),
+}
+
+impl FromStr for Control {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        if let Some(axis) = name.strip_prefix("ABS_HAT") {
+            let (axis, positive) = match axis.as_bytes().last() {
+                Some(b'+') => (&name[..name.len() - 1], true),
+                Some(b'-') => (&name[..name.len() - 1], false),
+                _ => return Err(format!("Axis {name} needs a + or - suffix")),
+            };
+            let axis = match axis {
+                "ABS_HAT0X" => AbsoluteAxisType::ABS_HAT0X,
+                "ABS_HAT0Y" => AbsoluteAxisType::ABS_HAT0Y,
+                "ABS_HAT1X" => AbsoluteAxisType::ABS_HAT1X,
+                "ABS_HAT1Y" => AbsoluteAxisType::ABS_HAT1Y,
+                _ => return Err(format!("Unknown axis {axis}")),
+            };
+            Ok(Self::Axis(axis.0, positive))
+        } else {
+            Key::from_str(name)
+                .map(Self::Key)
+                .map_err(|_| format!("Unknown key {name}"))
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Action {
+    Joypad(&'static str),
+    Hotkey(Hotkey),
+}
+
+fn joypad_key(name: &str) -> Option<&'static str> {
+    ["a", "b", "select", "start", "up", "down", "left", "right"]
+        .iter()
+        .find(|key| **key == name)
+        .copied()
+}
+
+fn build_mapping(config: &Input) -> HashMap<Control, Action> {
+    let mut mapping = HashMap::new();
+    for (control, key) in &config.joypad {
+        match (control.parse::<Control>(), joypad_key(key)) {
+            (Ok(control), Some(key)) => {
+                mapping.insert(control, Action::Joypad(key));
+            }
+            (Err(err), _) => log::error!("Invalid input mapping: {err}"),
+            (_, None) => log::error!("Invalid joypad key {key} for {control}"),
+        }
+    }
+    for (control, hotkey) in &config.hotkeys {
+        match control.parse::<Control>() {
+            Ok(control) => {
+                mapping.insert(control, Action::Hotkey(*hotkey));
+            }
+            Err(err) => log::error!("Invalid hotkey: {err}"),
+        }
+    }
+    mapping
+}
+
+/// Directions of `axis` released and pressed when it moves from `previous` to `value`, which
+/// are -1, 0 or 1.
+fn axis_moves(axis: u16, previous: i32, value: i32) -> impl Iterator<Item = (Control, bool)> {
+    let moved = previous != value;
+    let release = (moved && previous != 0).then_some((Control::Axis(axis, previous > 0), false));
+    let press = (moved && value != 0).then_some((Control::Axis(axis, value > 0), true));
+    release.into_iter().chain(press)
+}
+
+fn trigger(
+    action: Action,
+    press: bool,
+    volume_step: i16,
+    command_sender: &Sender<(Command, Option<connection::ResponseHandler>)>,
+) {
+    let command = match action {
+        Action::Joypad(key) => Command::EmulatorInput(key.to_owned(), press),
+        Action::Hotkey(_) if !press => return,
+        Action::Hotkey(Hotkey::TurnOff) => Command::TurnOff,
+        Action::Hotkey(Hotkey::Next) => Command::Next,
+        Action::Hotkey(Hotkey::VolumeUp) => Command::AdjustVolume(volume_step),
+        Action::Hotkey(Hotkey::VolumeDown) => Command::AdjustVolume(-volume_step),
+    };
+    command_sender.send((command, None)).ok();
+}
+
+fn run_device(
+    path: &Path,
+    mut device: Device,
+    mapping: &HashMap<Control, Action>,
+    volume_step: i16,
+    command_sender: &Sender<(Command, Option<connection::ResponseHandler>)>,
+) {
+    let mut axes = HashMap::new();
+    loop {
+        let events = match device.fetch_events() {
+            Ok(events) => events,
+            Err(err) => {
+                log::error!("Lost input device {}: {err}", path.display());
+                return;
+            }
+        };
+        for event in events {
+            match event.kind() {
+                InputEventKind::Key(key) => {
+                    // value 2 is auto repeat
+                    if event.value() == 2 {
+                        continue;
+                    }
+                    if let Some(action) = mapping.get(&Control::Key(key)) {
+                        trigger(*action, event.value() == 1, volume_step, command_sender);
+                    }
+                }
+                InputEventKind::AbsAxis(AbsoluteAxisType(axis)) => {
+                    let value = event.value().signum();
+                    let previous = axes.insert(axis, value).unwrap_or(0);
+                    for (control, press) in axis_moves(axis, previous, value) {
+                        if let Some(action) = mapping.get(&control) {
+                            trigger(*action, press, volume_step, command_sender);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn open_devices(config: &Input) -> Vec<(PathBuf, Device)> {
+    if config.devices.is_empty() {
+        evdev::enumerate()
+            .filter(|(_, device)| device.supported_keys().is_some())
+            .collect()
+    } else {
+        config
+            .devices
+            .iter()
+            .filter_map(|path| match Device::open(path) {
+                Ok(device) => Some((PathBuf::from(path), device)),
+                Err(err) => {
+                    log::error!("Failed opening input device {path}: {err}");
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// Spawns one thread per local input device, forwarding mapped events as commands.
+pub fn run_input(
+    config: &Input,
+    command_sender: Sender<(Command, Option<connection::ResponseHandler>)>,
+) {
+    let mapping = build_mapping(config);
+    let volume_step = config.volume_step as i16;
+    for (path, device) in open_devices(config) {
+        log::info!(
+            "Reading input from {} ({})",
+            path.display(),
+            device.name().unwrap_or("unnamed")
+        );
+        let mapping = mapping.clone();
+        let command_sender = command_sender.clone();
+        if let Err(err) = thread::Builder::new()
+            .name(format!("Input {}", path.display()))
+            .spawn(move || {
+                run_device(&path, device, &mapping, volume_step, &command_sender);
+            })
+        {
+            log::error!("Failed spawning input thread: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    const HAT0X: u16 = AbsoluteAxisType::ABS_HAT0X.0;
+
+    #[test]
+    fn controls_are_parsed() {
+        assert!(matches!("KEY_X".parse(), Ok(Control::Key(Key::KEY_X))));
+        assert!(matches!(
+            "BTN_SOUTH".parse(),
+            Ok(Control::Key(Key::BTN_SOUTH))
+        ));
+        assert_eq!("ABS_HAT0X-".parse(), Ok(Control::Axis(HAT0X, false)));
+        assert_eq!(
+            "ABS_HAT1Y+".parse(),
+            Ok(Control::Axis(AbsoluteAxisType::ABS_HAT1Y.0, true))
+        );
+        assert!("ABS_HAT0X".parse::<Control>().is_err());
+        assert!("ABS_HAT2X+".parse::<Control>().is_err());
+        assert!("KEY_NOPE".parse::<Control>().is_err());
+    }
+
+    #[test]
+    fn the_default_mapping_is_valid() {
+        let config: Input = serde_json::from_str("{}").unwrap();
+        let mapping = build_mapping(&config);
+        assert_eq!(mapping.len(), config.joypad.len());
+        assert!(matches!(
+            mapping.get(&Control::Axis(HAT0X, true)),
+            Some(Action::Joypad("right"))
+        ));
+    }
+
+    #[test]
+    fn invalid_mappings_are_skipped() {
+        let config: Input = serde_json::from_str(
+            r#"{
+                "joypad": {"KEY_A": "a", "KEY_B": "turbo", "KEY_NOPE": "b"},
+                "hotkeys": {"KEY_POWER": "turn_off", "ABS_HAT0X": "next"}
+            }"#,
+        )
+        .unwrap();
+        let mapping = build_mapping(&config);
+        assert_eq!(mapping.len(), 2);
+        assert!(matches!(
+            mapping.get(&Control::Key(Key::KEY_A)),
+            Some(Action::Joypad("a"))
+        ));
+        assert!(matches!(
+            mapping.get(&Control::Key(Key::KEY_POWER)),
+            Some(Action::Hotkey(Hotkey::TurnOff))
+        ));
+    }
+
+    #[test]
+    fn hat_moves_release_the_previous_direction() {
+        let moves = |previous, value| axis_moves(HAT0X, previous, value).collect::<Vec<_>>();
+        let left = Control::Axis(HAT0X, false);
+        let right = Control::Axis(HAT0X, true);
+        assert_eq!(moves(0, 1), [(right, true)]);
+        assert_eq!(moves(1, 1), []);
+        assert_eq!(moves(1, 0), [(right, false)]);
+        assert_eq!(moves(1, -1), [(right, false), (left, true)]);
+        assert_eq!(moves(0, 0), []);
+    }
+
+    #[test]
+    fn actions_become_commands() {
+        let (sender, commands) = channel();
+        trigger(Action::Joypad("a"), true, 5, &sender);
+        trigger(Action::Joypad("a"), false, 5, &sender);
+        trigger(Action::Hotkey(Hotkey::VolumeDown), true, 5, &sender);
+        // hotkeys act on the press only
+        trigger(Action::Hotkey(Hotkey::Next), false, 5, &sender);
+        trigger(Action::Hotkey(Hotkey::VolumeUp), true, 5, &sender);
+        let commands: Vec<Command> = commands.try_iter().map(|(command, _)| command).collect();
+        assert!(matches!(
+            &commands[..],
+            [
+                Command::EmulatorInput(a, true),
+                Command::EmulatorInput(released, false),
+                Command::AdjustVolume(-5),
+                Command::AdjustVolume(5),
+            ] if a == "a" && released == "a"
+        ));
+    }
+}
@@ -4,26 +4,41 @@ use glium::glutin;
 use gpio_cdev::{Chip, LineRequestFlags};
 use log::{error, info};
 use once_cell::sync::OnceCell;
-use std::{path::PathBuf, process, sync::mpsc, thread};
+use std::{process, sync::mpsc, thread};
 use tokio::sync::mpsc::unbounded_channel;
 
+mod ambient;
+mod app;
+mod audio;
+mod blit;
+mod board;
+mod canvas;
+mod capture;
+mod compositor;
 mod config;
 mod database;
 mod frontpanel;
 use frontpanel::LedControl;
 mod emulator;
+mod input;
 mod mqtt;
+mod output;
+mod pixels;
 mod poetry;
 mod server;
 mod shadertoy;
 mod states;
+#[cfg(test)]
+mod testing;
+mod transition;
 mod video;
+mod vnc;
 
 const RCK: u32 = 13;
 const CLR: u32 = 19;
 const WRENCH: u32 = 12;
 
-static ROMS_PATH: OnceCell<PathBuf> = OnceCell::new();
+static ROM_LIBRARY: OnceCell<emulator::library::RomLibrary> = OnceCell::new();
 
 fn handle_message(
     cmd: &server::Command,
@@ -31,6 +46,7 @@ fn handle_message(
     database: &database::Database,
     state_machine: &mut states::StateMachine,
 ) {
+    state_machine.touch();
     match cmd {
         server::Command::ListShaders => {
             if let Some(resp) = resp {
@@ -98,7 +114,7 @@ fn handle_message(
             );
             match database.read(id) {
                 Ok(shader) => {
-                    state_machine.to_shader_toy(&shader.source, &shader.title);
+                    state_machine.to_shader_toy(id, &shader.source, &shader.title);
                     if let Some(resp) = resp {
                         resp.send_ok().ok();
                     }
@@ -111,27 +127,210 @@ fn handle_message(
             }
         }
         server::Command::PlayVideo(ref url) => {
-            state_machine.to_video(url);
+            let result = state_machine.to_video(url);
+            if let Some(resp) = resp {
+                match result {
+                    Ok(()) => resp.send_ok().ok(),
+                    Err(error) => resp.send_error(400, &format!("{}", error)).ok(),
+                };
+            }
+        }
+        server::Command::VideoEffect(ref id) => {
+            let shader = match id {
+                Some(id) => match database.read(id) {
+                    Ok(shader) => Some(shader.source),
+                    Err(error) => {
+                        if let Some(resp) = resp {
+                            resp.send_error(404, error.message()).ok();
+                        }
+                        return;
+                    }
+                },
+                None => None,
+            };
+            let applied = state_machine.video_effect(shader.as_deref());
+            if let Some(resp) = resp {
+                if applied {
+                    resp.send_ok().ok();
+                } else {
+                    resp.send_error(400, "No video is playing").ok();
+                }
+            }
+        }
+        server::Command::ShowPoetryOverlay(ref text, style) => {
+            if !state_machine.video_poetry(text, style) {
+                state_machine.to_poetry(text, style);
+            }
             if let Some(resp) = resp {
                 resp.send_ok().ok();
             }
         }
+        server::Command::ShowScene(ref layers) => {
+            let result = state_machine.to_scene(layers, |id| {
+                database
+                    .read(id)
+                    .map(|shader| shader.source)
+                    .map_err(|error| error.message().to_owned())
+            });
+            if let Some(resp) = resp {
+                match result {
+                    Ok(()) => resp.send_ok().ok(),
+                    Err(error) => resp.send_error(400, &format!("{}", error)).ok(),
+                };
+            }
+        }
+        server::Command::SceneOpacity(layer, opacity) => {
+            let changed = state_machine.scene_opacity(*layer, *opacity);
+            if let Some(resp) = resp {
+                if changed {
+                    resp.send_ok().ok();
+                } else {
+                    resp.send_error(400, "No such scene layer").ok();
+                }
+            }
+        }
+        server::Command::SetTransition(effect, duration) => {
+            state_machine.set_transition(*effect, *duration);
+            if let Some(resp) = resp {
+                resp.send_ok().ok();
+            }
+        }
+        server::Command::SetBrightness(value) => {
+            let applied = state_machine.set_brightness(*value);
+            info!("Brightness set to {applied}");
+            if let Some(resp) = resp {
+                resp.send_ok().ok();
+            }
+        }
+        server::Command::Screenshot => {
+            if let Some(resp) = resp {
+                match state_machine.screenshot() {
+                    Ok(png) => resp.send_screenshot(&png).ok(),
+                    Err(error) => resp.send_error(500, &format!("{}", error)).ok(),
+                };
+            }
+        }
+        server::Command::CaptureRecordStart(ref name, format, duration) => {
+            let result = state_machine.capture_record_start(
+                name,
+                *format,
+                duration.map(std::time::Duration::from_secs),
+            );
+            if let Some(resp) = resp {
+                match result {
+                    Ok(()) => resp.send_ok().ok(),
+                    Err(error) => resp.send_error(400, &format!("{}", error)).ok(),
+                };
+            }
+        }
+        server::Command::CaptureRecordStop => {
+            let stopped = state_machine.capture_record_stop();
+            if let Some(resp) = resp {
+                if stopped {
+                    resp.send_ok().ok();
+                } else {
+                    resp.send_error(400, "Not recording").ok();
+                }
+            }
+        }
+        server::Command::ListMedia => {
+            if let Some(resp) = resp {
+                match state_machine.list_media() {
+                    Ok(media) => resp.send_media_list(media).ok(),
+                    Err(error) => resp.send_error(404, &format!("{}", error)).ok(),
+                };
+            }
+        }
         server::Command::TurnOff => {
             state_machine.to_off();
             if let Some(resp) = resp {
                 resp.send_ok().ok();
             }
         }
-        server::Command::ShowPoetry(ref text) => {
-            state_machine.to_poetry(text);
+        server::Command::ShowPoetry(ref text, style) => {
+            state_machine.to_poetry(text, style);
             if let Some(resp) = resp {
                 resp.send_ok().ok();
             }
         }
-        server::Command::StartTox => {
-            state_machine.to_tox();
+        server::Command::StartApp(ref name) => {
+            let result = state_machine.to_app(name);
             if let Some(resp) = resp {
-                resp.send_ok().ok();
+                match result {
+                    Ok(()) => resp.send_ok().ok(),
+                    Err(error) => resp.send_error(500, &format!("{}", error)).ok(),
+                };
+            } else if let Err(error) = result {
+                error!("Failed starting {}: {}", name, error);
+            }
+        }
+        server::Command::ShaderPointer(x, y, down) => {
+            let applied = state_machine.shader_pointer(*x, *y, *down);
+            if let Some(resp) = resp {
+                if applied {
+                    resp.send_ok().ok();
+                } else {
+                    resp.send_error(400, "No shader is running").ok();
+                }
+            }
+        }
+        server::Command::ShaderKey(code, down) => {
+            let applied = state_machine.shader_key(*code, *down);
+            if let Some(resp) = resp {
+                if applied {
+                    resp.send_ok().ok();
+                } else {
+                    resp.send_error(400, "No shader is running").ok();
+                }
+            }
+        }
+        server::Command::ShowVnc(ref server) => {
+            let result = state_machine.to_vnc(server.as_deref());
+            if let Some(resp) = resp {
+                match result {
+                    Ok(()) => resp.send_ok().ok(),
+                    Err(error) => {
+                        let status = match error.kind() {
+                            std::io::ErrorKind::InvalidInput => 400,
+                            std::io::ErrorKind::PermissionDenied => 403,
+                            _ => 500,
+                        };
+                        resp.send_error(status, &format!("{}", error)).ok()
+                    }
+                };
+            } else if let Err(error) = result {
+                error!("Failed connecting to VNC server: {}", error);
+            }
+        }
+        server::Command::VncPointer(x, y, buttons) => {
+            let result = state_machine.vnc_pointer(*x, *y, *buttons);
+            if let Some(resp) = resp {
+                match result {
+                    Ok(()) => resp.send_ok().ok(),
+                    Err(error) => resp.send_error(400, &format!("{}", error)).ok(),
+                };
+            }
+        }
+        server::Command::VncKey(ref key, down) => {
+            let result = state_machine.vnc_key(key, *down);
+            if let Some(resp) = resp {
+                match result {
+                    Ok(()) => resp.send_ok().ok(),
+                    Err(error) => resp.send_error(400, &format!("{}", error)).ok(),
+                };
+            }
+        }
+        server::Command::AppList => {
+            if let Some(resp) = resp {
+                resp.send_apps(&state_machine.apps()).ok();
+            }
+        }
+        server::Command::AudioDevices => {
+            if let Some(resp) = resp {
+                match state_machine.audio_devices() {
+                    Ok((inputs, outputs)) => resp.send_audio_devices(&inputs, &outputs).ok(),
+                    Err(error) => resp.send_error(500, &format!("{}", error)).ok(),
+                };
             }
         }
         server::Command::ToxMessage(ref text) => {
@@ -141,9 +340,14 @@ fn handle_message(
             }
         }
         server::Command::StartEmulator(game) => {
-            state_machine.to_emulator(game.clone());
+            let result = state_machine.to_emulator(game.clone());
             if let Some(resp) = resp {
-                resp.send_ok().ok();
+                match result {
+                    Ok(()) => resp.send_ok().ok(),
+                    Err(error) => resp.send_error(404, &format!("{}", error)).ok(),
+                };
+            } else if let Err(error) = result {
+                error!("Failed starting emulator: {}", error);
             }
         }
         server::Command::EmulatorInput(key, press) => {
@@ -152,6 +356,240 @@ fn handle_message(
                 resp.send_ok().ok();
             }
         }
+        server::Command::EmulatorRecordStart => {
+            let result = state_machine.emulator_record_start();
+            if let Some(resp) = resp {
+                match result {
+                    Ok(()) => resp.send_ok().ok(),
+                    Err(error) => resp.send_error(400, &format!("{}", error)).ok(),
+                };
+            }
+        }
+        server::Command::EmulatorRecordStop(ref name) => {
+            let result = state_machine.emulator_record_stop(name);
+            if let Some(resp) = resp {
+                match result {
+                    Ok(()) => resp.send_ok().ok(),
+                    Err(error) => resp.send_error(400, &format!("{}", error)).ok(),
+                };
+            }
+        }
+        server::Command::EmulatorReplay(ref name) => {
+            let result = state_machine.to_replay(name, false);
+            if let Some(resp) = resp {
+                match result {
+                    Ok(()) => resp.send_ok().ok(),
+                    Err(error) => resp.send_error(404, &format!("{}", error)).ok(),
+                };
+            }
+        }
+        server::Command::ListRecordings => {
+            if let Some(resp) = resp {
+                match state_machine.list_recordings() {
+                    Ok(recordings) => resp.send_recording_list(recordings).ok(),
+                    Err(error) => resp.send_error(500, &format!("{}", error)).ok(),
+                };
+            }
+        }
+        server::Command::AdjustVolume(delta) => {
+            let value = state_machine.adjust_volume(*delta);
+            if let Some(sender) = state_machine.get_sender() {
+                sender.send(mqtt::State::Volume(value)).ok();
+            }
+            if let Some(resp) = resp {
+                resp.send_ok().ok();
+            }
+        }
+        server::Command::Next => {
+            if state_machine.video_status().is_some() {
+                state_machine.video_next();
+            } else if let Some(current) = state_machine.shader_id().map(|id| id.to_owned()) {
+                match database.list() {
+                    Ok(ids) if !ids.is_empty() => {
+                        let index = ids
+                            .iter()
+                            .position(|id| *id == current)
+                            .map(|index| (index + 1) % ids.len())
+                            .unwrap_or(0);
+                        match database.read(&ids[index]) {
+                            Ok(shader) => {
+                                state_machine.to_shader_toy(
+                                    &ids[index],
+                                    &shader.source,
+                                    &shader.title,
+                                );
+                            }
+                            Err(error) => error!("Failed reading next shader: {}", error),
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(error) => error!("Failed listing shaders: {}", error),
+                }
+            }
+            if let Some(resp) = resp {
+                resp.send_ok().ok();
+            }
+        }
+        server::Command::VideoPause | server::Command::VideoResume => {
+            let paused = state_machine.video_set_paused(matches!(cmd, server::Command::VideoPause));
+            if let Some(resp) = resp {
+                if paused {
+                    resp.send_ok().ok();
+                } else {
+                    resp.send_error(400, "No video is playing").ok();
+                }
+            }
+        }
+        server::Command::VideoSeek(position, relative) => {
+            let seeked = state_machine.video_seek(*position, *relative);
+            if let Some(resp) = resp {
+                if seeked {
+                    resp.send_ok().ok();
+                } else {
+                    resp.send_error(400, "No video is playing").ok();
+                }
+            }
+        }
+        server::Command::VideoNext | server::Command::VideoPrevious => {
+            let switched = if matches!(cmd, server::Command::VideoNext) {
+                state_machine.video_next()
+            } else {
+                state_machine.video_previous()
+            };
+            if let Some(resp) = resp {
+                if switched {
+                    resp.send_ok().ok();
+                } else {
+                    resp.send_error(404, "No more videos in the queue").ok();
+                }
+            }
+        }
+        server::Command::VideoStatus => {
+            if let Some(resp) = resp {
+                match state_machine.video_status() {
+                    Some(status) => resp.send_video_status(status).ok(),
+                    None => resp.send_error(404, "No video is playing").ok(),
+                };
+            }
+        }
+        server::Command::VideoQueueAdd(ref url) => {
+            let result = state_machine.queue_video(url);
+            if let Some(resp) = resp {
+                match result {
+                    Ok(()) => resp.send_video_queue(state_machine.video_queue()).ok(),
+                    Err(error) => resp.send_error(400, &format!("{}", error)).ok(),
+                };
+            }
+        }
+        server::Command::VideoQueueRemove(index) => {
+            let removed = state_machine.video_queue_mut().remove(*index);
+            if let Some(resp) = resp {
+                if removed {
+                    resp.send_video_queue(state_machine.video_queue()).ok();
+                } else {
+                    resp.send_error(404, "No such queue entry").ok();
+                }
+            }
+        }
+        server::Command::VideoQueueClear => {
+            state_machine.video_queue_mut().clear();
+            if let Some(resp) = resp {
+                resp.send_ok().ok();
+            }
+        }
+        server::Command::VideoQueueList => {
+            if let Some(resp) = resp {
+                resp.send_video_queue(state_machine.video_queue()).ok();
+            }
+        }
+        server::Command::ShowCanvas => {
+            state_machine.to_canvas();
+            if let Some(resp) = resp {
+                resp.send_ok().ok();
+            }
+        }
+        server::Command::CanvasGet => {
+            if let Some(resp) = resp {
+                resp.send_canvas(state_machine.canvas()).ok();
+            }
+        }
+        server::Command::CanvasPaint(x, y, color, ref painter) => {
+            let result = state_machine.canvas_paint(painter, *x, *y, *color);
+            if let Some(resp) = resp {
+                match result {
+                    Ok(()) => resp.send_ok().ok(),
+                    Err(error @ canvas::PaintError::Cooldown(_)) => {
+                        resp.send_error(429, &format!("{}", error)).ok()
+                    }
+                    Err(error) => resp.send_error(400, &format!("{}", error)).ok(),
+                };
+            }
+        }
+        server::Command::CanvasTimelapse => {
+            match database.canvas_history(canvas::TIMELAPSE_FRAMES) {
+                Ok(snapshots) => {
+                    state_machine.canvas_timelapse(&snapshots);
+                    if let Some(resp) = resp {
+                        resp.send_ok().ok();
+                    }
+                }
+                Err(error) => {
+                    if let Some(resp) = resp {
+                        resp.send_error(404, error.message()).ok();
+                    }
+                }
+            }
+        }
+        server::Command::ShowAmbient => {
+            state_machine.to_ambient(|id| {
+                database
+                    .read(id)
+                    .map(|shader| shader.source)
+                    .map_err(|error| error.message().to_owned())
+            });
+            if let Some(resp) = resp {
+                resp.send_ok().ok();
+            }
+        }
+        server::Command::ShowBoard => {
+            state_machine.to_board();
+            if let Some(resp) = resp {
+                resp.send_ok().ok();
+            }
+        }
+        server::Command::BoardList => {
+            if let Some(resp) = resp {
+                resp.send_board(state_machine.board().messages()).ok();
+            }
+        }
+        server::Command::BoardAdd(ref text, pinned, ttl) => {
+            state_machine
+                .board_mut()
+                .add(text, *pinned, ttl.map(std::time::Duration::from_secs));
+            if let Some(resp) = resp {
+                resp.send_board(state_machine.board().messages()).ok();
+            }
+        }
+        server::Command::BoardRemove(id) => {
+            let removed = state_machine.board_mut().remove(*id);
+            if let Some(resp) = resp {
+                if removed {
+                    resp.send_board(state_machine.board().messages()).ok();
+                } else {
+                    resp.send_error(404, "No such message").ok();
+                }
+            }
+        }
+        server::Command::BoardPin(id, pinned) => {
+            let found = state_machine.board_mut().pin(*id, *pinned);
+            if let Some(resp) = resp {
+                if found {
+                    resp.send_board(state_machine.board().messages()).ok();
+                } else {
+                    resp.send_error(404, "No such message").ok();
+                }
+            }
+        }
         server::Command::SetVolume(value) => {
             state_machine.set_volume(*value);
             if let Some(resp) = resp {
@@ -161,6 +599,40 @@ fn handle_message(
                 sender.send(mqtt::State::Volume(*value as _)).ok();
             }
         }
+        server::Command::SetMute(muted) => {
+            state_machine.set_muted(*muted);
+            if let Some(resp) = resp {
+                resp.send_ok().ok();
+            }
+            if let Some(sender) = state_machine.get_sender() {
+                sender.send(mqtt::State::Muted(*muted)).ok();
+            }
+        }
+        server::Command::Duck(ducked) => {
+            state_machine.set_ducked(*ducked);
+            if let Some(resp) = resp {
+                resp.send_ok().ok();
+            }
+            if let Some(sender) = state_machine.get_sender() {
+                sender.send(mqtt::State::Ducked(*ducked)).ok();
+            }
+        }
+    }
+}
+
+/// Replays an emulator recording headless and writes the final frame as PNG,
+/// for regression testing the emulator path.
+fn replay_recording(config: &config::Config, recording: &str, output: &str) {
+    let result = emulator::recording::Recording::load(recording)
+        .and_then(|recording| emulator::recording::replay_headless(&config.emulator, &recording))
+        .and_then(|screen| {
+            emulator::library::encode_screen(&screen)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))
+        })
+        .and_then(|png| std::fs::write(output, png));
+    if let Err(err) = result {
+        error!("Replay failed: {}", err);
+        process::exit(-1);
     }
 }
 
@@ -173,6 +645,14 @@ fn main() {
         }
         Ok(config) => config,
     };
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, command, recording, output] = &args[..] {
+        if command == "replay" {
+            env_logger::init();
+            replay_recording(&config, recording, output);
+            return;
+        }
+    }
     if let Err(e) = log4rs::init_file(config.logconfig.clone(), Default::default()) {
         env_logger::init();
         error!("Error: {}", e);
@@ -180,10 +660,16 @@ fn main() {
     }
     let database = database::Database::new(&config.database.repository);
     let events_loop = glutin::event_loop::EventLoop::new();
-    let window = glutin::window::WindowBuilder::new()
-        .with_fullscreen(Some(glutin::window::Fullscreen::Borderless(
+    let fullscreen = if config.display.hidden {
+        None
+    } else {
+        Some(glutin::window::Fullscreen::Borderless(
             events_loop.primary_monitor(),
-        )))
+        ))
+    };
+    let window = glutin::window::WindowBuilder::new()
+        .with_visible(!config.display.hidden)
+        .with_fullscreen(fullscreen)
         .with_inner_size(glutin::dpi::LogicalSize::new(
             config.display.width as f64,
             config.display.height as f64,
@@ -194,9 +680,13 @@ fn main() {
         .with_hardware_acceleration(Some(true));
     let display = glium::Display::new(window, context, &events_loop).unwrap();
 
-    let (server_thread, command_receiver, command_sender) =
+    let (server_thread, command_receiver, command_sender, broadcaster) =
         server::open_server(&config.server.address, config.server.port);
 
+    if let Some(input) = &config.input {
+        input::run_input(input, command_sender.clone());
+    }
+
     let mqtt_thread = config.mqtt.as_ref().map(|mqtt| {
         let config = mqtt.clone();
         let (tx, rx) = unbounded_channel::<mqtt::State>();
@@ -239,16 +729,33 @@ fn main() {
     } else {
         None
     };
-    ROMS_PATH.set((&config.emulator.roms).into()).ok();
+    ROM_LIBRARY
+        .set(emulator::library::RomLibrary::new(&config.emulator))
+        .ok();
     let mut state_machine = states::StateMachine::new(
         display,
         led_control,
         config,
         mqtt_thread.as_ref().map(|(_, sender)| sender.clone()),
+        broadcaster,
     );
 
     loop {
+        if state_machine.ambient_due() {
+            state_machine.to_ambient(|id| {
+                database
+                    .read(id)
+                    .map(|shader| shader.source)
+                    .map_err(|error| error.message().to_owned())
+            });
+        }
         state_machine.update();
+        state_machine.canvas_mut().save_if_due();
+        if let Some(snapshot) = state_machine.canvas_mut().take_snapshot() {
+            if let Err(error) = database.save_canvas(&snapshot, "Canvas snapshot") {
+                error!("Failed storing canvas snapshot: {}", error);
+            }
+        }
         match state_machine.interval() {
             None => match command_receiver.recv() {
                 Ok((cmd, resp)) => {
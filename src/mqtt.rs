@@ -10,13 +10,21 @@ use crate::server::{connection, Command};
 
 pub enum State {
     PlayVideo(String),
-    Tox,
+    VideoStatus(crate::video::Status),
+    App(String),
+    Vnc(String),
     Poetry,
     ShaderToy(String),
     Emulator,
+    Scene,
+    Board,
+    Ambient,
+    Canvas,
     Stopped,
     Shutdown,
     Volume(u8),
+    Muted(bool),
+    Ducked(bool),
 }
 
 enum Error {
@@ -49,7 +57,11 @@ impl std::fmt::Display for Error {
 #[serde(tag = "command", content = "args", rename_all = "snake_case")]
 enum MqttCommand {
     Play,
+    Pause,
     Stop,
+    Next,
+    Previous,
+    Seek { position: String },
     VolumeSet { volume: String },
 }
 
@@ -70,7 +82,21 @@ async fn main_loop(
                                 command_sender.send((Command::TurnOff, None)).ok();
                             }
                             MqttCommand::Play => {
-                                log::info!("Got play command!");
+                                command_sender.send((Command::VideoResume, None)).ok();
+                            }
+                            MqttCommand::Pause => {
+                                command_sender.send((Command::VideoPause, None)).ok();
+                            }
+                            MqttCommand::Next => {
+                                command_sender.send((Command::VideoNext, None)).ok();
+                            }
+                            MqttCommand::Previous => {
+                                command_sender.send((Command::VideoPrevious, None)).ok();
+                            }
+                            MqttCommand::Seek { position } => {
+                                if let Ok(position) = position.parse() {
+                                    command_sender.send((Command::VideoSeek(position, false), None)).ok();
+                                }
                             }
                             MqttCommand::VolumeSet { volume } => {
                                 if let Ok(value) = volume.parse() {
@@ -87,8 +113,24 @@ async fn main_loop(
                         client.publish(format!("{topic}/TITLE"), QoS::AtLeastOnce, true, url.as_bytes()).await?;
                         client.publish(format!("{topic}/STATUS"), QoS::AtLeastOnce, true, r"playing").await?;
                     }
-                    Some(State::Tox) => {
-                        client.publish(format!("{topic}/TITLE"), QoS::AtLeastOnce, true, r"Tox").await?;
+                    Some(State::VideoStatus(status)) => {
+                        if let Some(title) = status.title {
+                            client.publish(format!("{topic}/TITLE"), QoS::AtLeastOnce, true, title.as_bytes()).await?;
+                        }
+                        if let Some(position) = status.position {
+                            client.publish(format!("{topic}/POSITION"), QoS::AtMostOnce, false, position.to_string()).await?;
+                        }
+                        if let Some(duration) = status.duration {
+                            client.publish(format!("{topic}/DURATION"), QoS::AtLeastOnce, true, duration.to_string()).await?;
+                        }
+                        client.publish(format!("{topic}/STATUS"), QoS::AtLeastOnce, true, if status.paused { r"paused" } else { r"playing" }).await?;
+                    }
+                    Some(State::Vnc(server)) => {
+                        client.publish(format!("{topic}/TITLE"), QoS::AtLeastOnce, true, format!("VNC {}", server).as_bytes()).await?;
+                        client.publish(format!("{topic}/STATUS"), QoS::AtLeastOnce, true, r"playing").await?;
+                    }
+                    Some(State::App(name)) => {
+                        client.publish(format!("{topic}/TITLE"), QoS::AtLeastOnce, true, name.as_bytes()).await?;
                         client.publish(format!("{topic}/STATUS"), QoS::AtLeastOnce, true, r"playing").await?;
                     }
                     Some(State::Poetry) => {
@@ -103,6 +145,22 @@ async fn main_loop(
                         client.publish(format!("{topic}/TITLE"), QoS::AtLeastOnce, true, r"Emulator").await?;
                         client.publish(format!("{topic}/STATUS"), QoS::AtLeastOnce, true, r"playing").await?;
                     }
+                    Some(State::Scene) => {
+                        client.publish(format!("{topic}/TITLE"), QoS::AtLeastOnce, true, r"Scene").await?;
+                        client.publish(format!("{topic}/STATUS"), QoS::AtLeastOnce, true, r"playing").await?;
+                    }
+                    Some(State::Board) => {
+                        client.publish(format!("{topic}/TITLE"), QoS::AtLeastOnce, true, r"Message board").await?;
+                        client.publish(format!("{topic}/STATUS"), QoS::AtLeastOnce, true, r"playing").await?;
+                    }
+                    Some(State::Canvas) => {
+                        client.publish(format!("{topic}/TITLE"), QoS::AtLeastOnce, true, r"Canvas").await?;
+                        client.publish(format!("{topic}/STATUS"), QoS::AtLeastOnce, true, r"playing").await?;
+                    }
+                    Some(State::Ambient) => {
+                        client.publish(format!("{topic}/TITLE"), QoS::AtLeastOnce, true, r"Ambient").await?;
+                        client.publish(format!("{topic}/STATUS"), QoS::AtLeastOnce, true, r"playing").await?;
+                    }
                     Some(State::Stopped) => {
                         client.publish(format!("{topic}/STATUS"), QoS::AtLeastOnce, true, r"stopped").await?;
                         client.publish(format!("{topic}/TITLE"), QoS::AtLeastOnce, true, []).await?;
@@ -110,6 +168,12 @@ async fn main_loop(
                     Some(State::Volume(value)) => {
                         client.publish(format!("{topic}/VOLUME"), QoS::AtLeastOnce, true, value.to_string()).await?;
                     }
+                    Some(State::Muted(muted)) => {
+                        client.publish(format!("{topic}/MUTED"), QoS::AtLeastOnce, true, muted.to_string()).await?;
+                    }
+                    Some(State::Ducked(ducked)) => {
+                        client.publish(format!("{topic}/DUCKED"), QoS::AtLeastOnce, true, ducked.to_string()).await?;
+                    }
                     Some(State::Shutdown) | None => {
                         client.publish(format!("{topic}/STATUS"), QoS::AtLeastOnce, true, r"off").await?;
                         client.disconnect().await.ok();
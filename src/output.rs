@@ -0,0 +1,328 @@
+use glium::{
+    backend::glutin::Display,
+    index::PrimitiveType,
+    program,
+    texture::{
+        texture2d::Texture2d, ClientFormat, MipmapsOption, RawImage2d, UncompressedFloatFormat,
+    },
+    uniform,
+    uniforms::{MagnifySamplerFilter, MinifySamplerFilter, Sampler},
+    Surface,
+};
+use log::error;
+use std::borrow::Cow;
+
+use crate::{
+    blit::{Vertex, QUAD_VERTEX_SHADER},
+    config::{self, Config},
+};
+
+const FRAGMENT_SHADER: &str = "#version 140
+
+in vec2 vTexCoords;
+out vec4 fragColor;
+
+uniform sampler2D frame;
+uniform sampler2D mask;
+uniform vec3 gain;
+uniform vec3 gamma;
+uniform float brightness;
+
+void main() {
+    vec3 color = pow(clamp(texture(frame, vTexCoords).rgb * gain, 0.0, 1.0), gamma);
+    float alive = texelFetch(mask, ivec2(gl_FragCoord.xy), 0).r;
+    fragColor = vec4(color * brightness * alive, 1.0);
+}
+";
+
+/// Corners of a rectangle given from the top left in a `size` sized area, in the order bottom
+/// left, top left, top right, bottom right, scaled to 0..1 with the origin at the bottom left.
+fn corners(rect: [u32; 4], size: (u32, u32)) -> [[f32; 2]; 4] {
+    let left = rect[0] as f32 / size.0 as f32;
+    let right = rect[0].saturating_add(rect[2]) as f32 / size.0 as f32;
+    let top = 1.0 - rect[1] as f32 / size.1 as f32;
+    let bottom = 1.0 - rect[1].saturating_add(rect[3]) as f32 / size.1 as f32;
+    [[left, bottom], [left, top], [right, top], [right, bottom]]
+}
+
+fn module_vertices(module: &config::Module, frame: (u32, u32), output: (u32, u32)) -> [Vertex; 4] {
+    let [bl, tl, tr, br] = corners(module.source, frame);
+    let mut texcoords = [bl, tl, tr, br];
+    if module.mirror_x {
+        texcoords = [br, tr, tl, bl];
+    }
+    if module.mirror_y {
+        let [bl, tl, tr, br] = texcoords;
+        texcoords = [tl, bl, br, tr];
+    }
+    if module.rotation % 90 != 0 {
+        error!(
+            "Module rotation {} is not a multiple of 90 degrees",
+            module.rotation
+        );
+    }
+    texcoords.rotate_right((module.rotation / 90 % 4) as usize);
+    let positions = corners(module.target, output);
+    let mut vertices = [Vertex {
+        position: [0.0, 0.0],
+        texcoords: [0.0, 0.0],
+    }; 4];
+    for (vertex, (position, texcoords)) in vertices.iter_mut().zip(positions.iter().zip(&texcoords))
+    {
+        vertex.position = [position[0] * 2.0 - 1.0, position[1] * 2.0 - 1.0];
+        vertex.texcoords = *texcoords;
+    }
+    vertices
+}
+
+/// One byte per output pixel, 0 for dead pixels and 255 for the others, with rows stored bottom
+/// up.
+fn mask(dead_pixels: &[[u32; 2]], size: (u32, u32)) -> Vec<u8> {
+    let mut mask = vec![255u8; (size.0 * size.1) as usize];
+    for &[x, y] in dead_pixels {
+        if x < size.0 && y < size.1 {
+            mask[((size.1 - 1 - y) * size.0 + x) as usize] = 0;
+        } else {
+            error!("Dead pixel {},{} is outside of the output", x, y);
+        }
+    }
+    mask
+}
+
+/// Final pass putting the rendered frame onto the physical wall: module layout, colour
+/// correction, brightness limit and dead pixels.
+pub struct Output {
+    /// Everything is rendered in here first
+    frame: Texture2d,
+    /// What the wall shows, in the size of the window
+    shown: Texture2d,
+    mask: Texture2d,
+    vertex_buffer: glium::VertexBuffer<Vertex>,
+    index_buffer: glium::IndexBuffer<u16>,
+    program: glium::Program,
+    /// Configured modules, laid out again when the output is resized
+    modules: Vec<config::Module>,
+    dead_pixels: Vec<[u32; 2]>,
+    gain: [f32; 3],
+    gamma: [f32; 3],
+    brightness: f32,
+    max_brightness: f32,
+}
+
+impl Output {
+    pub fn new(display: &Display, config: &Config) -> Self {
+        let frame_size = (config.display.width, config.display.height);
+        let output_size = display.gl_window().window().inner_size();
+        let output_size = (output_size.width, output_size.height);
+        let output = &config.output;
+
+        let program = program!(display, 140 => {
+            vertex: QUAD_VERTEX_SHADER,
+            fragment: FRAGMENT_SHADER
+        })
+        .unwrap();
+        let (vertex_buffer, index_buffer) =
+            Self::layout(display, &output.modules, frame_size, output_size);
+        let max_brightness = output.max_brightness.clamp(0.0, 1.0);
+        Self {
+            frame: Texture2d::empty(display, frame_size.0, frame_size.1).unwrap(),
+            shown: Texture2d::empty(display, output_size.0, output_size.1).unwrap(),
+            mask: Self::mask_texture(display, &output.dead_pixels, output_size),
+            vertex_buffer,
+            index_buffer,
+            program,
+            modules: output.modules.clone(),
+            dead_pixels: output.dead_pixels.clone(),
+            gain: output.gain,
+            gamma: output.gamma,
+            brightness: output.brightness.clamp(0.0, max_brightness),
+            max_brightness,
+        }
+    }
+
+    fn layout(
+        display: &Display,
+        modules: &[config::Module],
+        frame_size: (u32, u32),
+        output_size: (u32, u32),
+    ) -> (glium::VertexBuffer<Vertex>, glium::IndexBuffer<u16>) {
+        let full = [config::Module {
+            source: [0, 0, frame_size.0, frame_size.1],
+            target: [0, 0, output_size.0, output_size.1],
+            rotation: 0,
+            mirror_x: false,
+            mirror_y: false,
+        }];
+        let modules = if modules.is_empty() {
+            &full[..]
+        } else {
+            modules
+        };
+        let vertices: Vec<Vertex> = modules
+            .iter()
+            .flat_map(|module| module_vertices(module, frame_size, output_size))
+            .collect();
+        let indices: Vec<u16> = (0..modules.len() as u16)
+            .flat_map(|module| {
+                let base = module * 4;
+                [base, base + 1, base + 2, base + 2, base + 3, base]
+            })
+            .collect();
+        (
+            glium::VertexBuffer::new(display, &vertices).unwrap(),
+            glium::IndexBuffer::new(display, PrimitiveType::TrianglesList, &indices).unwrap(),
+        )
+    }
+
+    fn mask_texture(display: &Display, dead_pixels: &[[u32; 2]], size: (u32, u32)) -> Texture2d {
+        Texture2d::with_format(
+            display,
+            RawImage2d {
+                data: Cow::from(mask(dead_pixels, size)),
+                width: size.0,
+                height: size.1,
+                format: ClientFormat::U8,
+            },
+            UncompressedFloatFormat::U8,
+            MipmapsOption::NoMipmap,
+        )
+        .unwrap()
+    }
+
+    /// Lays the modules and dead pixels out again if the output size changed.
+    pub fn resize(&mut self, display: &Display, size: (u32, u32)) {
+        if size == self.shown.dimensions() {
+            return;
+        }
+        let (vertex_buffer, index_buffer) =
+            Self::layout(display, &self.modules, self.frame.dimensions(), size);
+        self.vertex_buffer = vertex_buffer;
+        self.index_buffer = index_buffer;
+        self.shown = Texture2d::empty(display, size.0, size.1).unwrap();
+        self.mask = Self::mask_texture(display, &self.dead_pixels, size);
+    }
+
+    /// Texture the content is rendered into before `draw` puts it on the wall.
+    pub fn frame(&self) -> &Texture2d {
+        &self.frame
+    }
+
+    /// The last frame as put on the wall by `draw`, after module layout and colour correction.
+    pub fn shown(&self) -> &Texture2d {
+        &self.shown
+    }
+
+    /// Sets the brightness within the configured limit, returns the one applied.
+    pub fn set_brightness(&mut self, brightness: f32) -> f32 {
+        self.brightness = brightness.clamp(0.0, self.max_brightness);
+        self.brightness
+    }
+
+    /// Puts the frame on the wall through `shown`, which keeps it for screenshots.
+    pub fn draw<S: Surface>(&self, target: &mut S) {
+        let mut shown = self.shown.as_surface();
+        shown.clear_color(0.0, 0.0, 0.0, 1.0);
+        let uniforms = uniform! {
+            frame: Sampler::new(&self.frame)
+                .minify_filter(MinifySamplerFilter::Nearest)
+                .magnify_filter(MagnifySamplerFilter::Nearest),
+            mask: Sampler::new(&self.mask)
+                .minify_filter(MinifySamplerFilter::Nearest)
+                .magnify_filter(MagnifySamplerFilter::Nearest),
+            gain: self.gain,
+            gamma: self.gamma,
+            brightness: self.brightness,
+        };
+        shown
+            .draw(
+                &self.vertex_buffer,
+                &self.index_buffer,
+                &self.program,
+                &uniforms,
+                &Default::default(),
+            )
+            .unwrap();
+        shown.fill(&*target, MagnifySamplerFilter::Nearest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(rotation: u16, mirror_x: bool, mirror_y: bool) -> config::Module {
+        config::Module {
+            // right half of a 4x2 frame onto the left half of an 8x4 output
+            source: [2, 0, 2, 2],
+            target: [0, 0, 4, 4],
+            rotation,
+            mirror_x,
+            mirror_y,
+        }
+    }
+
+    /// Texture coordinates of the bottom left, top left, top right and bottom right corner.
+    fn texcoords(module: &config::Module) -> Vec<[f32; 2]> {
+        module_vertices(module, (4, 2), (8, 4))
+            .iter()
+            .map(|vertex| vertex.texcoords)
+            .collect()
+    }
+
+    const BL: [f32; 2] = [0.5, 0.0];
+    const TL: [f32; 2] = [0.5, 1.0];
+    const TR: [f32; 2] = [1.0, 1.0];
+    const BR: [f32; 2] = [1.0, 0.0];
+
+    #[test]
+    fn corners_are_flipped_to_the_bottom_left() {
+        assert_eq!(
+            corners([1, 1, 2, 1], (4, 4)),
+            [[0.25, 0.5], [0.25, 0.75], [0.75, 0.75], [0.75, 0.5]]
+        );
+    }
+
+    #[test]
+    fn modules_are_placed_in_clip_space() {
+        let positions: Vec<[f32; 2]> = module_vertices(&module(0, false, false), (4, 2), (8, 4))
+            .iter()
+            .map(|vertex| vertex.position)
+            .collect();
+        assert_eq!(
+            positions,
+            [[-1.0, -1.0], [-1.0, 1.0], [0.0, 1.0], [0.0, -1.0]]
+        );
+        assert_eq!(texcoords(&module(0, false, false)), [BL, TL, TR, BR]);
+    }
+
+    #[test]
+    fn modules_are_mirrored_and_rotated() {
+        assert_eq!(texcoords(&module(0, true, false)), [BR, TR, TL, BL]);
+        assert_eq!(texcoords(&module(0, false, true)), [TL, BL, BR, TR]);
+        assert_eq!(texcoords(&module(0, true, true)), [TR, BR, BL, TL]);
+        // clockwise, so the top left of the module shows the bottom left of the source
+        assert_eq!(texcoords(&module(90, false, false)), [BR, BL, TL, TR]);
+        assert_eq!(texcoords(&module(180, false, false)), [TR, BR, BL, TL]);
+        assert_eq!(texcoords(&module(270, false, false)), [TL, TR, BR, BL]);
+        assert_eq!(texcoords(&module(360, false, false)), [BL, TL, TR, BR]);
+    }
+
+    #[test]
+    fn corners_of_huge_rects_saturate() {
+        let [_, _, top_right, _] = corners([1, 0, u32::MAX, u32::MAX], (4, 4));
+        assert_eq!(top_right, [u32::MAX as f32 / 4.0, 1.0]);
+    }
+
+    #[test]
+    fn dead_pixels_are_masked_bottom_up() {
+        assert_eq!(
+            mask(&[[0, 0], [2, 1], [3, 0]], (3, 2)),
+            [255, 255, 0, 0, 255, 255]
+        );
+        // the same pixels on a larger output
+        assert_eq!(
+            mask(&[[0, 0], [2, 1], [3, 0]], (4, 2)),
+            [255, 255, 0, 255, 0, 255, 255, 0]
+        );
+    }
+}
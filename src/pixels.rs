@@ -0,0 +1,407 @@
+use glium::{
+    backend::glutin::Display,
+    texture::{texture2d::Texture2d, RawImage2d},
+};
+use log::{error, info};
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Arc,
+    },
+    thread,
+};
+
+use crate::{
+    blit::Blit,
+    config::{PixelOutput, PixelProtocol},
+};
+
+const ARTNET_PORT: u16 = 6454;
+const E131_PORT: u16 = 5568;
+const DDP_PORT: u16 = 4048;
+/// Whole RGB pixels fitting into a DMX universe
+const UNIVERSE_PIXELS: usize = 170;
+/// Pixels per DDP packet, keeps packets below the usual MTU
+const DDP_PIXELS: usize = 480;
+
+fn artnet_packet(universe: u16, sequence: u8, data: &[u8]) -> Vec<u8> {
+    // DMX data length has to be even
+    let length = data.len() + data.len() % 2;
+    let mut packet = Vec::with_capacity(18 + length);
+    packet.extend_from_slice(b"Art-Net\0");
+    // OpDmx
+    packet.extend_from_slice(&0x5000u16.to_le_bytes());
+    packet.extend_from_slice(&14u16.to_be_bytes());
+    packet.push(sequence);
+    packet.push(0);
+    // SubUni followed by Net
+    packet.extend_from_slice(&(universe & 0x7fff).to_le_bytes());
+    packet.extend_from_slice(&(length as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+    packet.resize(18 + length, 0);
+    packet
+}
+
+fn e131_packet(cid: &[u8; 16], universe: u16, sequence: u8, data: &[u8]) -> Vec<u8> {
+    let length = 126 + data.len();
+    let mut packet = Vec::with_capacity(length);
+    // root layer
+    packet.extend_from_slice(&0x0010u16.to_be_bytes());
+    packet.extend_from_slice(&0u16.to_be_bytes());
+    packet.extend_from_slice(b"ASC-E1.17\0\0\0");
+    packet.extend_from_slice(&(0x7000 | (length - 16) as u16).to_be_bytes());
+    packet.extend_from_slice(&4u32.to_be_bytes());
+    packet.extend_from_slice(cid);
+    // framing layer
+    packet.extend_from_slice(&(0x7000 | (length - 38) as u16).to_be_bytes());
+    packet.extend_from_slice(&2u32.to_be_bytes());
+    let mut source_name = [0u8; 64];
+    source_name[..11].copy_from_slice(b"Blinkenwall");
+    packet.extend_from_slice(&source_name);
+    // priority, synchronization address, sequence, options
+    packet.push(100);
+    packet.extend_from_slice(&0u16.to_be_bytes());
+    packet.push(sequence);
+    packet.push(0);
+    packet.extend_from_slice(&universe.to_be_bytes());
+    // DMP layer
+    packet.extend_from_slice(&(0x7000 | (length - 115) as u16).to_be_bytes());
+    packet.push(0x02);
+    packet.push(0xa1);
+    packet.extend_from_slice(&0u16.to_be_bytes());
+    packet.extend_from_slice(&1u16.to_be_bytes());
+    packet.extend_from_slice(&(data.len() as u16 + 1).to_be_bytes());
+    // DMX start code
+    packet.push(0);
+    packet.extend_from_slice(data);
+    packet
+}
+
+fn ddp_packet(sequence: u8, offset: usize, data: &[u8], last: bool) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(10 + data.len());
+    // version 1, the last packet of a frame pushes it to the LEDs
+    packet.push(if last { 0x41 } else { 0x40 });
+    packet.push(sequence % 15 + 1);
+    // RGB, 8 bit per channel
+    packet.push(0x0b);
+    // default output device
+    packet.push(0x01);
+    packet.extend_from_slice(&(offset as u32).to_be_bytes());
+    packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+    packet
+}
+
+fn resolve(address: &str, default_port: u16) -> io::Result<SocketAddr> {
+    address
+        .to_socket_addrs()
+        .or_else(|_| (address, default_port).to_socket_addrs())?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Address not found"))
+}
+
+/// Sends frames of one output, runs in its own thread.
+struct Sender {
+    config: PixelOutput,
+    socket: UdpSocket,
+    address: SocketAddr,
+    sequence: u8,
+    /// E1.31 source id
+    cid: [u8; 16],
+    /// Set to turn the pixels off instead of sending the next frame
+    blank: Arc<AtomicBool>,
+}
+
+impl Sender {
+    fn new(config: &PixelOutput) -> io::Result<Self> {
+        let default_port = match config.protocol {
+            PixelProtocol::ArtNet => ARTNET_PORT,
+            PixelProtocol::E131 => E131_PORT,
+            PixelProtocol::Ddp => DDP_PORT,
+        };
+        let address = resolve(&config.address, default_port)?;
+        let socket = UdpSocket::bind(if address.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        })?;
+        socket.set_broadcast(true)?;
+        Ok(Self {
+            config: config.clone(),
+            socket,
+            address,
+            sequence: 0,
+            cid: *uuid::Uuid::new_v4().as_bytes(),
+            blank: Arc::default(),
+        })
+    }
+
+    /// Sends RGB `pixels`, split into as many packets as needed.
+    fn send(&mut self, pixels: &[u8]) -> io::Result<()> {
+        // 0 means "no sequence" for Art-Net
+        self.sequence = self.sequence % 255 + 1;
+        match self.config.protocol {
+            PixelProtocol::ArtNet => {
+                for (index, data) in pixels.chunks(UNIVERSE_PIXELS * 3).enumerate() {
+                    let universe = self.config.universe + index as u16;
+                    self.socket
+                        .send_to(&artnet_packet(universe, self.sequence, data), self.address)?;
+                }
+            }
+            PixelProtocol::E131 => {
+                // E1.31 universes start at 1
+                let first = self.config.universe.max(1);
+                for (index, data) in pixels.chunks(UNIVERSE_PIXELS * 3).enumerate() {
+                    let universe = first + index as u16;
+                    self.socket.send_to(
+                        &e131_packet(&self.cid, universe, self.sequence, data),
+                        self.address,
+                    )?;
+                }
+            }
+            PixelProtocol::Ddp => {
+                let packets = (pixels.len() + DDP_PIXELS * 3 - 1) / (DDP_PIXELS * 3);
+                for (index, data) in pixels.chunks(DDP_PIXELS * 3).enumerate() {
+                    self.socket.send_to(
+                        &ddp_packet(
+                            self.sequence,
+                            index * DDP_PIXELS * 3,
+                            data,
+                            index + 1 == packets,
+                        ),
+                        self.address,
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn run(mut self, frames: Receiver<Vec<u8>>) {
+        for mut pixels in frames {
+            if self.blank.swap(false, Ordering::SeqCst) {
+                pixels.iter_mut().for_each(|value| *value = 0);
+            }
+            if let Err(err) = self.send(&pixels) {
+                error!("Failed sending pixels to {}: {}", self.address, err);
+            }
+        }
+    }
+}
+
+/// RGB pixels of a bottom up RGBA image, in rows from the top left.
+fn grid_pixels(image: &RawImage2d<u8>, serpentine: bool) -> Vec<u8> {
+    let (width, height) = (image.width as usize, image.height as usize);
+    let mut pixels = Vec::with_capacity(width * height * 3);
+    for row in 0..height {
+        let line = &image.data[(height - 1 - row) * width * 4..][..width * 4];
+        for column in 0..width {
+            let x = if serpentine && row % 2 == 1 {
+                width - 1 - column
+            } else {
+                column
+            };
+            pixels.extend_from_slice(&line[x * 4..x * 4 + 3]);
+        }
+    }
+    pixels
+}
+
+struct Target {
+    grid: Texture2d,
+    serpentine: bool,
+    frames: SyncSender<Vec<u8>>,
+    blank: Arc<AtomicBool>,
+}
+
+/// LED matrices and other blinkenlights getting a scaled down copy of every frame.
+pub struct PixelOutputs {
+    targets: Vec<Target>,
+}
+
+impl PixelOutputs {
+    pub fn new(display: &Display, configs: &[PixelOutput]) -> Self {
+        let targets = configs
+            .iter()
+            .filter_map(|config| {
+                let sender = match Sender::new(config) {
+                    Ok(sender) => sender,
+                    Err(err) => {
+                        error!("Failed opening pixel output {}: {}", config.address, err);
+                        return None;
+                    }
+                };
+                info!(
+                    "Sending {}×{} pixels to {} via {:?}",
+                    config.width, config.height, sender.address, config.protocol
+                );
+                // a single frame is buffered, frames are dropped while it is sent
+                let (frames, receiver) = sync_channel(1);
+                let blank = sender.blank.clone();
+                thread::Builder::new()
+                    .name(format!("Pixel output {}", config.address))
+                    .spawn(move || sender.run(receiver))
+                    .map_err(|err| error!("Failed spawning pixel output thread: {}", err))
+                    .ok()?;
+                Some(Target {
+                    grid: Texture2d::empty(display, config.width, config.height).unwrap(),
+                    serpentine: config.serpentine,
+                    frames,
+                    blank,
+                })
+            })
+            .collect();
+        Self { targets }
+    }
+
+    /// Scales `frame` down to the grid of every output and queues it for sending.
+    pub fn send(&self, frame: &Texture2d, blit: &Blit) {
+        for target in &self.targets {
+            blit.draw(&mut target.grid.as_surface(), frame);
+            let image: RawImage2d<u8> = target.grid.read();
+            target.blank.store(false, Ordering::SeqCst);
+            target
+                .frames
+                .try_send(grid_pixels(&image, target.serpentine))
+                .ok();
+        }
+    }
+
+    /// Turns all pixels off, replacing the frame waiting to be sent if there is one.
+    pub fn blank(&self) {
+        for target in &self.targets {
+            let (width, height) = target.grid.dimensions();
+            target.blank.store(true, Ordering::SeqCst);
+            target
+                .frames
+                .try_send(vec![0; (width * height * 3) as usize])
+                .ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn artnet_packets_pad_to_even_lengths() {
+        let packet = artnet_packet(0x1234, 7, &[1, 2, 3]);
+        assert_eq!(&packet[..8], b"Art-Net\0");
+        // OpDmx, protocol version 14, sequence, physical port
+        assert_eq!(packet[8..14], [0x00, 0x50, 0, 14, 7, 0]);
+        // universe little endian, length big endian
+        assert_eq!(packet[14..18], [0x34, 0x12, 0, 4]);
+        assert_eq!(packet[18..], [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn e131_packets_have_the_layer_lengths() {
+        let cid = [9u8; 16];
+        let data = [10u8; 510];
+        let packet = e131_packet(&cid, 3, 42, &data);
+        assert_eq!(packet.len(), 126 + 510);
+        assert_eq!(packet[..4], [0x00, 0x10, 0x00, 0x00]);
+        assert_eq!(&packet[4..16], b"ASC-E1.17\0\0\0");
+        // root layer
+        assert_eq!(packet[16..18], (0x7000u16 | 620).to_be_bytes());
+        assert_eq!(packet[18..22], [0, 0, 0, 4]);
+        assert_eq!(packet[22..38], cid);
+        // framing layer
+        assert_eq!(packet[38..40], (0x7000u16 | 598).to_be_bytes());
+        assert_eq!(packet[40..44], [0, 0, 0, 2]);
+        assert_eq!(&packet[44..55], b"Blinkenwall");
+        assert_eq!(packet[108], 100);
+        assert_eq!(packet[111], 42);
+        assert_eq!(packet[113..115], [0, 3]);
+        // DMP layer with the start code and 510 slots
+        assert_eq!(packet[115..117], (0x7000u16 | 521).to_be_bytes());
+        assert_eq!(packet[117..125], [0x02, 0xa1, 0, 0, 0, 1, 0x01, 0xff]);
+        assert_eq!(packet[125], 0);
+        assert_eq!(packet[126..], data[..]);
+    }
+
+    #[test]
+    fn ddp_packets_describe_rgb_data() {
+        let packet = ddp_packet(16, 1440, &[1, 2, 3], true);
+        // version 1 with push, sequence from 1 to 15, RGB 8 bit, default output
+        assert_eq!(packet[..4], [0x41, 2, 0x0b, 0x01]);
+        assert_eq!(packet[4..8], 1440u32.to_be_bytes());
+        assert_eq!(packet[8..10], [0, 3]);
+        assert_eq!(packet[10..], [1, 2, 3]);
+        assert_eq!(ddp_packet(14, 0, &[], false)[..2], [0x40, 15]);
+    }
+
+    #[test]
+    fn grids_start_at_the_top_left() {
+        // rows bottom up: 3 4, then 1 2
+        let data = [3, 0, 0, 255, 4, 0, 0, 255, 1, 0, 0, 255, 2, 0, 0, 255];
+        let image = RawImage2d::from_raw_rgba(data.to_vec(), (2, 2));
+        assert_eq!(
+            grid_pixels(&image, false),
+            [1, 0, 0, 2, 0, 0, 3, 0, 0, 4, 0, 0]
+        );
+        assert_eq!(
+            grid_pixels(&image, true),
+            [1, 0, 0, 2, 0, 0, 4, 0, 0, 3, 0, 0]
+        );
+    }
+
+    fn listen() -> (UdpSocket, PixelOutput) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let config = PixelOutput {
+            protocol: PixelProtocol::Ddp,
+            address: socket.local_addr().unwrap().to_string(),
+            width: 20,
+            height: 30,
+            universe: 0,
+            serpentine: false,
+        };
+        (socket, config)
+    }
+
+    fn receive(socket: &UdpSocket) -> Vec<u8> {
+        let mut buffer = [0u8; 2048];
+        let length = socket.recv(&mut buffer).unwrap();
+        buffer[..length].to_vec()
+    }
+
+    #[test]
+    fn frames_are_split_into_packets() {
+        let (socket, config) = listen();
+        let mut sender = Sender::new(&config).unwrap();
+        let pixels: Vec<u8> = (0..600 * 3).map(|i| i as u8).collect();
+        sender.send(&pixels).unwrap();
+        let first = receive(&socket);
+        assert_eq!(first[0], 0x40);
+        assert_eq!(first[4..10], [0, 0, 0, 0, 0x05, 0xa0]);
+        assert_eq!(first[10..], pixels[..1440]);
+        let last = receive(&socket);
+        assert_eq!(last[0], 0x41);
+        assert_eq!(last[4..10], [0, 0, 0x05, 0xa0, 0x01, 0x68]);
+        assert_eq!(last[10..], pixels[1440..]);
+    }
+
+    #[test]
+    fn blanking_replaces_the_queued_frame() {
+        let (socket, mut config) = listen();
+        config.width = 2;
+        config.height = 1;
+        let sender = Sender::new(&config).unwrap();
+        let blank = sender.blank.clone();
+        let (frames, receiver) = sync_channel(1);
+        frames.send(vec![255; 6]).unwrap();
+        blank.store(true, Ordering::SeqCst);
+        thread::spawn(move || sender.run(receiver));
+        assert_eq!(receive(&socket)[10..], [0; 6]);
+        frames.send(vec![255; 6]).unwrap();
+        assert_eq!(receive(&socket)[10..], [255; 6]);
+    }
+}
@@ -0,0 +1,250 @@
+use log::{error, warn};
+use std::{fs, path::Path};
+
+/// Font compiled in, for when the configured one can't be loaded.
+const BUILT_IN_FONT: &[u8] = include_bytes!("../../tom-thumb.bdf");
+
+#[derive(Debug)]
+pub struct FontError {
+    path: String,
+    message: String,
+}
+
+impl std::fmt::Display for FontError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cannot load font {}: {}", self.path, self.message)
+    }
+}
+
+/// A rasterized glyph.
+pub struct Glyph {
+    pub width: usize,
+    pub height: usize,
+    /// Coverage from 0 to 255, rows from the top
+    pub coverage: Vec<u8>,
+    /// Offset of the left edge from the pen position
+    pub left: i32,
+    /// Distance from the baseline up to the top row
+    pub top: i32,
+    pub advance: i32,
+}
+
+impl Glyph {
+    /// Scales up by an integer `factor`, for bitmap fonts.
+    fn scaled(self, factor: usize) -> Self {
+        if factor == 1 {
+            return self;
+        }
+        let width = self.width * factor;
+        let mut coverage = vec![0; width * self.height * factor];
+        for (index, value) in coverage.iter_mut().enumerate() {
+            let (x, y) = (index % width / factor, index / width / factor);
+            *value = self.coverage[y * self.width + x];
+        }
+        Self {
+            width,
+            height: self.height * factor,
+            coverage,
+            left: self.left * factor as i32,
+            top: self.top * factor as i32,
+            advance: self.advance * factor as i32,
+        }
+    }
+
+    /// Fakes a bold face by smearing the glyph to the right.
+    fn emboldened(self, amount: usize) -> Self {
+        let width = self.width + amount;
+        let mut coverage = vec![0; width * self.height];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let value = self.coverage[y * self.width + x];
+                for offset in 0..=amount {
+                    let target = &mut coverage[y * width + x + offset];
+                    *target = (*target).max(value);
+                }
+            }
+        }
+        Self {
+            width,
+            coverage,
+            advance: self.advance + amount as i32,
+            ..self
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct LineMetrics {
+    /// Distance from the top of the line down to the baseline
+    pub ascent: i32,
+    pub height: i32,
+}
+
+enum Face {
+    Bitmap(bdf::Font),
+    Outline(fontdue::Font),
+}
+
+impl Face {
+    fn open(path: &str) -> Result<Self, FontError> {
+        let error = |message: String| FontError {
+            path: path.to_owned(),
+            message,
+        };
+        let is_bdf = Path::new(path)
+            .extension()
+            .map_or(false, |extension| extension.eq_ignore_ascii_case("bdf"));
+        if is_bdf {
+            bdf::open(path)
+                .map(Self::Bitmap)
+                .map_err(|err| error(format!("{:?}", err)))
+        } else {
+            let data = fs::read(path).map_err(|err| error(err.to_string()))?;
+            fontdue::Font::from_bytes(data, fontdue::FontSettings::default())
+                .map(Self::Outline)
+                .map_err(|err| error(err.to_owned()))
+        }
+    }
+
+    fn has_glyph(&self, ch: char) -> bool {
+        match self {
+            Self::Bitmap(font) => font.glyphs().contains_key(&ch),
+            Self::Outline(font) => font.lookup_glyph_index(ch) != 0,
+        }
+    }
+
+    /// Bitmap fonts are scaled by whole pixels only.
+    fn bitmap_factor(scale: f32) -> usize {
+        scale.round().max(1.0) as usize
+    }
+
+    fn metrics(&self, size: f32, scale: f32) -> LineMetrics {
+        match self {
+            Self::Bitmap(font) => {
+                let bounds = font.bounds();
+                let factor = Self::bitmap_factor(scale) as i32;
+                LineMetrics {
+                    ascent: bounds.height as i32 * factor,
+                    height: (bounds.height as i32 + 1) * factor,
+                }
+            }
+            Self::Outline(font) => {
+                let px = size * scale;
+                match font.horizontal_line_metrics(px) {
+                    Some(metrics) => LineMetrics {
+                        ascent: metrics.ascent.ceil() as i32,
+                        height: metrics.new_line_size.ceil() as i32,
+                    },
+                    None => LineMetrics {
+                        ascent: px.ceil() as i32,
+                        height: (px * 1.2).ceil() as i32,
+                    },
+                }
+            }
+        }
+    }
+
+    fn glyph(&self, ch: char, size: f32, scale: f32) -> Option<Glyph> {
+        match self {
+            Self::Bitmap(font) => {
+                let glyph = font.glyphs().get(&ch)?;
+                let bounds = glyph.bounds();
+                let (width, height) = (bounds.width as usize, bounds.height as usize);
+                let mut coverage = vec![0; width * height];
+                for ((x, y), value) in glyph.pixels() {
+                    if value {
+                        coverage[y as usize * width + x as usize] = u8::MAX;
+                    }
+                }
+                // bitmap fonts are laid out in fixed cells
+                let cell = font.bounds();
+                let glyph = Glyph {
+                    width,
+                    height,
+                    coverage,
+                    left: bounds.x,
+                    top: bounds.height as i32 + bounds.y,
+                    advance: cell.width as i32 + 1,
+                };
+                Some(glyph.scaled(Self::bitmap_factor(scale)))
+            }
+            Self::Outline(font) => {
+                let (metrics, coverage) = font.rasterize(ch, size * scale);
+                Some(Glyph {
+                    width: metrics.width,
+                    height: metrics.height,
+                    coverage,
+                    left: metrics.xmin,
+                    top: metrics.ymin + metrics.height as i32,
+                    advance: metrics.advance_width.round() as i32,
+                })
+            }
+        }
+    }
+}
+
+/// A font with fallbacks for characters it doesn't have, like emoji or CJK.
+pub struct Fonts {
+    faces: Vec<Face>,
+    /// Pixel size of outline fonts
+    size: f32,
+}
+
+impl Fonts {
+    /// Loads `font` and the `fallback` fonts, which are skipped if they fail to load.
+    pub fn load(font: &str, fallback: &[String], size: f32) -> Result<Self, FontError> {
+        let mut faces = vec![Face::open(font)?];
+        for path in fallback {
+            match Face::open(path) {
+                Ok(face) => faces.push(face),
+                Err(err) => error!("{}", err),
+            }
+        }
+        Ok(Self { faces, size })
+    }
+
+    /// Just the built-in bitmap font.
+    pub fn built_in(size: f32) -> Self {
+        let font = bdf::read(BUILT_IN_FONT).expect("Built-in font is valid");
+        Self {
+            faces: vec![Face::Bitmap(font)],
+            size,
+        }
+    }
+
+    /// Line metrics of the primary font, with the size multiplied by `scale`.
+    pub fn metrics(&self, scale: f32) -> LineMetrics {
+        self.faces[0].metrics(self.size, scale)
+    }
+
+    /// Rasterizes `ch` with the first font that has it, using a replacement character if none
+    /// does.
+    pub fn glyph(&self, ch: char, scale: f32, bold: bool) -> Option<Glyph> {
+        let face = self
+            .faces
+            .iter()
+            .find(|face| face.has_glyph(ch))
+            .or_else(|| {
+                warn!("No font has a glyph for {:?}", ch);
+                None
+            });
+        let glyph = match face {
+            Some(face) => face.glyph(ch, self.size, scale),
+            None => ['\u{fffd}', '?']
+                .iter()
+                .find_map(|replacement| {
+                    self.faces
+                        .iter()
+                        .find(|face| face.has_glyph(*replacement))
+                        .map(|face| (face, *replacement))
+                })
+                .and_then(|(face, replacement)| face.glyph(replacement, self.size, scale)),
+        }?;
+        Some(if bold {
+            let amount = (glyph.height / 12).max(1);
+            glyph.emboldened(amount)
+        } else {
+            glyph
+        })
+    }
+}
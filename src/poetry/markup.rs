@@ -0,0 +1,310 @@
+/// Text style set by markup tags.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Style {
+    pub bold: bool,
+    /// The poem's colour if not set
+    pub color: Option<[u8; 3]>,
+    /// Multiplier of the font size
+    pub scale: f32,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            bold: false,
+            color: None,
+            scale: 1.0,
+        }
+    }
+}
+
+pub struct Span {
+    pub text: String,
+    pub style: Style,
+}
+
+const COLORS: &[(&str, [u8; 3])] = &[
+    ("black", [0, 0, 0]),
+    ("white", [255, 255, 255]),
+    ("red", [255, 0, 0]),
+    ("green", [0, 255, 0]),
+    ("blue", [0, 0, 255]),
+    ("yellow", [255, 255, 0]),
+    ("cyan", [0, 255, 255]),
+    ("magenta", [255, 0, 255]),
+    ("orange", [255, 165, 0]),
+    ("pink", [255, 105, 180]),
+    ("purple", [128, 0, 128]),
+];
+
+/// Limits of the font size multiplier, also when sizes are nested
+const MIN_SCALE: f32 = 0.25;
+const MAX_SCALE: f32 = 8.0;
+
+/// Parses a colour name or `#rgb`/`#rrggbb`.
+pub fn parse_color(color: &str) -> Option<[u8; 3]> {
+    if let Some(hex) = color.strip_prefix('#') {
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        return match hex.len() {
+            3 => Some([
+                ((value >> 8) & 0xf) as u8 * 17,
+                ((value >> 4) & 0xf) as u8 * 17,
+                (value & 0xf) as u8 * 17,
+            ]),
+            6 => Some([(value >> 16) as u8, (value >> 8) as u8, value as u8]),
+            _ => None,
+        };
+    }
+    COLORS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(color))
+        .map(|(_, rgb)| *rgb)
+}
+
+/// Applies an opening tag to `style`, returns the tag's name to match the closing tag against.
+fn open_tag(tag: &str, style: &mut Style) -> Option<&'static str> {
+    let (name, value) = match tag.split_once('=') {
+        Some((name, value)) => (name.trim(), Some(value.trim())),
+        None => (tag.trim(), None),
+    };
+    match (name, value) {
+        ("b", None) => {
+            style.bold = true;
+            Some("b")
+        }
+        ("color", Some(value)) => {
+            style.color = Some(parse_color(value)?);
+            Some("color")
+        }
+        ("size", Some(value)) => {
+            let scale = value
+                .parse::<f32>()
+                .ok()
+                .filter(|scale| scale.is_finite())?;
+            style.scale = (style.scale * scale).clamp(MIN_SCALE, MAX_SCALE);
+            Some("size")
+        }
+        _ => None,
+    }
+}
+
+/// Splits `text` into lines of a span each in the default style `scale` times as large, without
+/// interpreting any tags.
+pub fn plain(text: &str, scale: f32) -> Vec<Vec<Span>> {
+    let style = Style {
+        scale,
+        ..Style::default()
+    };
+    text.split('\n')
+        .map(|line| {
+            let text = line.trim_end_matches('\r');
+            if text.is_empty() {
+                Vec::new()
+            } else {
+                vec![Span {
+                    text: text.to_owned(),
+                    style,
+                }]
+            }
+        })
+        .collect()
+}
+
+/// Splits `text` into lines of styled spans. Understands `<b>…</b>`, `<color=red>…</color>`
+/// (names or `#rgb`/`#rrggbb`) and `<size=2>…</size>`, anything else is kept as text.
+pub fn parse(text: &str) -> Vec<Vec<Span>> {
+    let mut lines = vec![Vec::new()];
+    let mut stack: Vec<(&str, Style)> = Vec::new();
+    let mut style = Style::default();
+    let mut current = String::new();
+
+    fn flush(lines: &mut [Vec<Span>], current: &mut String, style: Style) {
+        if !current.is_empty() {
+            lines.last_mut().unwrap().push(Span {
+                text: std::mem::take(current),
+                style,
+            });
+        }
+    }
+
+    let mut rest = text;
+    while let Some(ch) = rest.chars().next() {
+        if ch == '<' {
+            if let Some(end) = rest.find('>') {
+                let tag = &rest[1..end];
+                if let Some(name) = tag.strip_prefix('/') {
+                    if stack.last().map_or(false, |(open, _)| *open == name.trim()) {
+                        flush(&mut lines, &mut current, style);
+                        style = stack.pop().unwrap().1;
+                        rest = &rest[end + 1..];
+                        continue;
+                    }
+                } else {
+                    let mut next = style;
+                    if let Some(name) = open_tag(tag, &mut next) {
+                        flush(&mut lines, &mut current, style);
+                        stack.push((name, style));
+                        style = next;
+                        rest = &rest[end + 1..];
+                        continue;
+                    }
+                }
+            }
+        }
+        if ch == '\n' {
+            flush(&mut lines, &mut current, style);
+            lines.push(Vec::new());
+        } else if ch != '\r' {
+            current.push(ch);
+        }
+        rest = &rest[ch.len_utf8()..];
+    }
+    flush(&mut lines, &mut current, style);
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Text and style of every span, line by line.
+    fn spans(text: &str) -> Vec<Vec<(String, Style)>> {
+        parse(text)
+            .into_iter()
+            .map(|line| {
+                line.into_iter()
+                    .map(|span| (span.text, span.style))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn style(bold: bool, color: Option<[u8; 3]>, scale: f32) -> Style {
+        Style { bold, color, scale }
+    }
+
+    #[test]
+    fn plain_text_keeps_tags() {
+        let lines: Vec<Vec<(String, Style)>> = plain("<size=8>12°C\r\n\n</size>", 2.0)
+            .into_iter()
+            .map(|line| {
+                line.into_iter()
+                    .map(|span| (span.text, span.style))
+                    .collect()
+            })
+            .collect();
+        let big = style(false, None, 2.0);
+        assert_eq!(
+            lines,
+            [
+                vec![("<size=8>12°C".to_owned(), big)],
+                vec![],
+                vec![("</size>".to_owned(), big)]
+            ]
+        );
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(parse_color("#f80"), Some([255, 136, 0]));
+        assert_eq!(parse_color("#12ab3C"), Some([0x12, 0xab, 0x3c]));
+        assert_eq!(parse_color("Orange"), Some([255, 165, 0]));
+        assert_eq!(parse_color("#1234"), None);
+        assert_eq!(parse_color("#ggg"), None);
+        assert_eq!(parse_color("#"), None);
+        assert_eq!(parse_color("mauve"), None);
+    }
+
+    #[test]
+    fn lines_and_plain_text() {
+        let plain = Style::default();
+        assert_eq!(
+            spans("roses\r\nare red\n\n"),
+            vec![
+                vec![("roses".to_owned(), plain)],
+                vec![("are red".to_owned(), plain)],
+                vec![],
+                vec![],
+            ]
+        );
+        assert_eq!(spans(""), vec![vec![]]);
+    }
+
+    #[test]
+    fn nested_tags_restore_the_outer_style() {
+        let red = Some([255, 0, 0]);
+        assert_eq!(
+            spans("a<b>b<color=red>c<size=2>d</size>e</color>f</b>g"),
+            vec![vec![
+                ("a".to_owned(), Style::default()),
+                ("b".to_owned(), style(true, None, 1.0)),
+                ("c".to_owned(), style(true, red, 1.0)),
+                ("d".to_owned(), style(true, red, 2.0)),
+                ("e".to_owned(), style(true, red, 1.0)),
+                ("f".to_owned(), style(true, None, 1.0)),
+                ("g".to_owned(), Style::default()),
+            ]]
+        );
+        // sizes multiply and are clamped
+        assert_eq!(
+            spans("<size=4><size=4>x</size></size><size=0>y</size><size=0.5><size=0.5>z"),
+            vec![vec![
+                ("x".to_owned(), style(false, None, 8.0)),
+                ("y".to_owned(), style(false, None, 0.25)),
+                ("z".to_owned(), style(false, None, 0.25)),
+            ]]
+        );
+        assert_eq!(
+            spans("<size=3><size=2>x</size>y</size>"),
+            vec![vec![
+                ("x".to_owned(), style(false, None, 6.0)),
+                ("y".to_owned(), style(false, None, 3.0)),
+            ]]
+        );
+    }
+
+    #[test]
+    fn styles_continue_over_lines_until_closed() {
+        let bold = style(true, None, 1.0);
+        assert_eq!(
+            spans("<b>one\ntwo</b>\nthree"),
+            vec![
+                vec![("one".to_owned(), bold)],
+                vec![("two".to_owned(), bold)],
+                vec![("three".to_owned(), Style::default())],
+            ]
+        );
+        // unclosed tags last to the end
+        assert_eq!(
+            spans("<b>never closed"),
+            vec![vec![("never closed".to_owned(), bold)]]
+        );
+    }
+
+    #[test]
+    fn invalid_tags_are_text() {
+        let plain = Style::default();
+        assert_eq!(
+            spans("<color=mauve>x</color>"),
+            vec![vec![("<color=mauve>x</color>".to_owned(), plain)]]
+        );
+        assert_eq!(
+            spans("<size=big>a <i>b</i> 1 < 2 <b"),
+            vec![vec![("<size=big>a <i>b</i> 1 < 2 <b".to_owned(), plain)]]
+        );
+        assert_eq!(
+            spans("<size=inf>a</size><size=NaN>b</size>"),
+            vec![vec![(
+                "<size=inf>a</size><size=NaN>b</size>".to_owned(),
+                plain
+            )]]
+        );
+        // closing tags only close the innermost open tag
+        let bold = style(true, None, 1.0);
+        let green = style(true, Some([0, 255, 0]), 1.0);
+        assert_eq!(
+            spans("<b><color=green>x</b>y</color>z"),
+            vec![vec![("x</b>y".to_owned(), green), ("z".to_owned(), bold),]]
+        );
+    }
+}
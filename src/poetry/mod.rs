@@ -1,121 +1,182 @@
 use glium::backend::glutin::Display;
-use glium::index::PrimitiveType;
-use glium::{implement_vertex, program};
+use glium::{implement_vertex, program, Surface};
 use rand::SeedableRng;
 use rand_xoshiro::Xoshiro256Plus;
-use std::f32;
+use std::rc::Rc;
 
+use crate::{
+    config::{self, Align, PoetryStyle},
+    shadertoy::Levels,
+};
+
+mod font;
+mod markup;
 mod render;
+mod ticker;
+pub use self::font::Fonts;
+pub use self::markup::parse_color;
 use self::render::Vertex;
+pub use self::render::{Animation, Text};
+pub use self::ticker::Ticker;
 
 pub struct Poetry {
     speed: f32,
-    font: bdf::Font,
+    align: Align,
+    /// Size of the area poems are laid out in
+    size: (u32, u32),
+    fonts: Rc<Fonts>,
     poems: Vec<render::Poem>,
     rand: Xoshiro256Plus,
     program: glium::Program,
-    vertex_buffer: glium::VertexBuffer<Vertex>,
-    index_buffer: glium::IndexBuffer<u16>,
+    style: PoetryStyle,
+    /// Beat of the music the poems move with
+    beat: f32,
 }
 
 const VERTEX_SHADER: &str = "#version 140
 
+#define TYPEWRITER 1
+#define FALLING 3
+#define GLITCH 4
+#define PULSE 6
+
 in vec2 position;
 in vec2 texcoords;
+in float order;
 
 out vec2 vTexCoords;
-
-uniform vec4 bounds;
+out vec2 vPosition;
+out float vVisible;
+
+// top left of the poem in pixels from the bottom left
+uniform vec2 origin;
+uniform vec2 area;
+uniform int style;
+uniform float time;
+uniform float progress;
+uniform float beat;
+
+float hash(float n) {
+    return fract(sin(n) * 43758.5453);
+}
 
 void main() {
-    gl_Position = vec4((position * bounds.zw + bounds.xy) * 2.0 - 1.0, 0.0, 1.0);
+    vec2 pixel = origin + vec2(position.x, -position.y);
+    vVisible = 1.0;
+    if (style == TYPEWRITER) {
+        vVisible = step(order, progress);
+    } else if (style == FALLING) {
+        // letters start one after another and take half of the intro to land
+        float fall = clamp((progress - order * 0.5) * 2.0, 0.0, 1.0);
+        pixel.y += (1.0 - fall * fall) * area.y;
+    } else if (style == GLITCH) {
+        float jump = hash(order * 97.0 + floor(time * 12.0));
+        if (jump > 0.85) {
+            pixel.x += (hash(jump) - 0.5) * area.x * 0.05;
+        }
+    } else if (style == PULSE) {
+        pixel.y += beat * (0.5 + 0.5 * hash(order * 31.0)) * area.y * 0.03;
+    }
+    vPosition = pixel;
+    gl_Position = vec4(pixel / area * 2.0 - 1.0, 0.0, 1.0);
     vTexCoords = texcoords;
 }
 ";
 
 const FRAGMENT_SHADER: &str = "#version 140
 
+#define GLITCH 4
+#define RAINBOW 5
+
 in vec2 vTexCoords;
+in vec2 vPosition;
+in float vVisible;
 out vec4 fragColor;
 
 uniform vec4 color;
 uniform sampler2D text;
+uniform vec2 area;
+uniform int style;
+uniform float time;
+
+float hash(float n) {
+    return fract(sin(n) * 43758.5453);
+}
+
+vec3 hue(float h) {
+    return clamp(abs(mod(h * 6.0 + vec3(0.0, 4.0, 2.0), 6.0) - 3.0) - 1.0, 0.0, 1.0);
+}
 
 void main() {
-    fragColor = texture(text, vTexCoords).rrrr * color;
+    vec4 texel = texture(text, vTexCoords);
+    if (style == RAINBOW) {
+        texel.rgb = hue(fract(vPosition.x / area.x + time * 0.25));
+    } else if (style == GLITCH && hash(floor(time * 12.0) + floor(vPosition.y / 4.0)) > 0.9) {
+        texel.rgb = texel.gbr;
+    }
+    fragColor = texel * color * vec4(1.0, 1.0, 1.0, vVisible);
 }
 ";
 
+/// Program drawing `Text`.
+pub fn text_program(display: &Display) -> glium::Program {
+    implement_vertex!(Vertex, position, texcoords, order);
+
+    program!(display, 140 => { vertex: VERTEX_SHADER, fragment: FRAGMENT_SHADER }).unwrap()
+}
+
 impl Poetry {
-    pub fn new(display: &Display, font: &str, speed: f32) -> Poetry {
-        implement_vertex!(Vertex, position, texcoords);
-
-        let vertex_buffer = glium::VertexBuffer::new(
-            display,
-            &[
-                Vertex {
-                    position: [0.0, 0.0],
-                    texcoords: [0.0, 0.0],
-                },
-                Vertex {
-                    position: [0.0, -1.0],
-                    texcoords: [0.0, 1.0],
-                },
-                Vertex {
-                    position: [1.0, -1.0],
-                    texcoords: [1.0, 1.0],
-                },
-                Vertex {
-                    position: [1.0, 0.0],
-                    texcoords: [1.0, 0.0],
-                },
-            ],
-        )
-        .unwrap();
-        let index_buffer = glium::IndexBuffer::new(
-            display,
-            PrimitiveType::TrianglesList,
-            &[0u16, 1, 2, 2, 3, 0],
-        )
-        .unwrap();
-        let program =
-            program!(display, 140 => { vertex: VERTEX_SHADER, fragment: FRAGMENT_SHADER }).unwrap();
-        let font = bdf::open(font).expect("Cannot load font file");
+    pub fn new(
+        display: &Display,
+        fonts: Rc<Fonts>,
+        config: &config::Poetry,
+        size: (u32, u32),
+    ) -> Poetry {
+        let program = text_program(display);
 
         Poetry {
-            speed,
-            font,
+            speed: config.speed,
+            align: config.align,
+            size,
+            fonts,
             poems: Vec::new(),
             rand: Xoshiro256Plus::from_entropy(),
             program,
-            vertex_buffer,
-            index_buffer,
+            style: config.style,
+            beat: 0.0,
         }
     }
 
-    pub fn show_poem(&mut self, display: &Display, text: &str) {
-        self.poems
-            .push(render::Poem::new(display, &self.font, text, &mut self.rand));
+    /// Shows `text` animated with `style`, or the configured default style.
+    pub fn show_poem(&mut self, display: &Display, text: &str, style: Option<PoetryStyle>) {
+        let color = render::random_color(&mut self.rand);
+        let text = render::Text::new(display, &self.fonts, text, color, self.align, self.size.0);
+        let poem = render::Poem::new(
+            text,
+            style.unwrap_or(self.style),
+            self.size,
+            &self.poems,
+            &mut self.rand,
+        );
+        self.poems.push(poem);
     }
 
-    pub fn step(&mut self, display: &Display) {
-        // fade poems
+    /// Lets the poems move with the `levels` of the music.
+    pub fn set_levels(&mut self, levels: Levels) {
+        self.beat = levels.beat;
+    }
+
+    fn animate(&mut self) {
         for i in (0..self.poems.len()).rev() {
-            let duration = self.poems[i].created.elapsed();
-            let alpha = 1.0
-                - (duration.as_secs() as f32 + duration.subsec_nanos() as f32 / 1e9) / self.speed;
-            self.poems[i].color.alpha = alpha;
-            if (alpha * 255.0).round() < f32::EPSILON {
+            if !self.poems[i].animate(self.speed, self.beat) {
                 self.poems.swap_remove(i);
             }
         }
+    }
 
-        render::Poem::render_all(
-            display,
-            &self.poems,
-            &self.vertex_buffer,
-            &self.index_buffer,
-            &self.program,
-        );
+    /// Animates the poems and draws them on top of whatever is already in `target`.
+    pub fn draw<S: Surface>(&mut self, target: &mut S, size: (u32, u32)) {
+        self.animate();
+        render::Poem::render_all(target, size, &self.poems, &self.program);
     }
 }
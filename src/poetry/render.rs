@@ -1,162 +1,449 @@
 use glium::{
     backend::glutin::Display,
+    index::PrimitiveType,
     texture::{
         texture2d::Texture2d, ClientFormat, MipmapsOption, RawImage2d, UncompressedFloatFormat,
     },
     uniform,
     uniforms::{MagnifySamplerFilter, MinifySamplerFilter, Sampler},
-    Frame, Surface,
+    Surface,
 };
 use log::info;
-use palette::{Alpha, Hsv, IntoColor, RgbHue, Srgba};
+use palette::{Hsv, IntoColor, RgbHue, Srgb, Srgba};
 use rand::Rng;
-use std::{borrow::Cow, cmp, time::Instant, u8};
+use std::{borrow::Cow, cmp, f32, time::Instant, u8};
 use unicode_normalization::UnicodeNormalization;
 
+use super::{
+    font::{Fonts, Glyph, LineMetrics},
+    markup,
+};
+use crate::config::{Align, PoetryStyle};
+
+/// Matches the defines in the shaders.
+fn style_uniform(style: PoetryStyle) -> i32 {
+    match style {
+        PoetryStyle::Fade => 0,
+        PoetryStyle::Typewriter => 1,
+        PoetryStyle::Ticker => 2,
+        PoetryStyle::Falling => 3,
+        PoetryStyle::Glitch => 4,
+        PoetryStyle::Rainbow => 5,
+        PoetryStyle::Pulse => 6,
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct Vertex {
     pub position: [f32; 2],
     pub texcoords: [f32; 2],
+    /// Position of the glyph in reading order, from 0 to 1
+    pub order: f32,
 }
 
-pub struct Poem {
-    pub created: Instant,
-    pub color: Srgba,
+/// Widest and tallest text rendered, longer text is wrapped or cut off
+pub const MAX_TEXT_SIZE: u32 = 4096;
+/// Seconds between letters of the typewriter style
+const TYPEWRITER_INTERVAL: f32 = 0.08;
+/// Seconds the ticker and falling styles take to bring in the text
+const INTRO_TIME: f32 = 2.0;
+/// Random positions tried to find a spot not covered by other poems
+const PLACEMENT_ATTEMPTS: usize = 20;
+
+struct Item {
+    glyph: Glyph,
+    color: [u8; 3],
+    metrics: LineMetrics,
+    space: bool,
+}
+
+struct Row {
+    items: Vec<Item>,
+    /// Without trailing spaces
+    width: i32,
+    metrics: LineMetrics,
+}
+
+/// Greedy word wrapping, words wider than a row are broken between characters.
+struct Wrapper {
+    max_width: i32,
+    /// Height of empty rows
+    default_metrics: LineMetrics,
+    rows: Vec<Row>,
+    row: Vec<Item>,
+    /// Advance of the items in `row`, trailing spaces included
+    row_width: i32,
+    word: Vec<Item>,
+    word_width: i32,
+    /// The current row was started by wrapping, leading spaces are dropped
+    wrapped: bool,
+}
+
+impl Wrapper {
+    fn new(max_width: i32, default_metrics: LineMetrics) -> Self {
+        Self {
+            max_width,
+            default_metrics,
+            rows: Vec::new(),
+            row: Vec::new(),
+            row_width: 0,
+            word: Vec::new(),
+            word_width: 0,
+            wrapped: false,
+        }
+    }
+
+    fn break_row(&mut self, wrapped: bool) {
+        let mut items = std::mem::take(&mut self.row);
+        let mut width = std::mem::take(&mut self.row_width);
+        while items.last().map_or(false, |item| item.space) {
+            width -= items.pop().unwrap().glyph.advance;
+        }
+        let metrics = items
+            .iter()
+            .map(|item| item.metrics)
+            .max_by_key(|metrics| metrics.height)
+            .unwrap_or(self.default_metrics);
+        self.rows.push(Row {
+            items,
+            width,
+            metrics,
+        });
+        self.wrapped = wrapped;
+    }
+
+    fn push_to_row(&mut self, item: Item) {
+        self.row_width += item.glyph.advance;
+        self.row.push(item);
+    }
+
+    fn push_word(&mut self) {
+        let word = std::mem::take(&mut self.word);
+        let word_width = std::mem::take(&mut self.word_width);
+        if !self.row.is_empty() && self.row_width + word_width > self.max_width {
+            self.break_row(true);
+        }
+        for item in word {
+            if !self.row.is_empty() && self.row_width + item.glyph.advance > self.max_width {
+                self.break_row(true);
+            }
+            self.push_to_row(item);
+        }
+    }
+
+    fn push(&mut self, item: Item) {
+        if item.space {
+            self.push_word();
+            if !(self.wrapped && self.row.is_empty()) {
+                self.push_to_row(item);
+            }
+        } else {
+            self.word_width += item.glyph.advance;
+            self.word.push(item);
+        }
+    }
+
+    fn end_line(&mut self) {
+        self.push_word();
+        self.break_row(false);
+    }
+}
+
+/// Wraps styled `lines` to `max_width`, in `color` where markup doesn't set one.
+fn layout(
+    fonts: &Fonts,
+    lines: Vec<Vec<markup::Span>>,
+    color: [u8; 3],
+    max_width: u32,
+) -> Vec<Row> {
+    let mut wrapper = Wrapper::new(max_width.min(MAX_TEXT_SIZE) as i32, fonts.metrics(1.0));
+    for line in lines {
+        for span in &line {
+            let style = span.style;
+            for ch in span.text.chars() {
+                let space = ch.is_whitespace();
+                let glyph = fonts.glyph(if space { ' ' } else { ch }, style.scale, style.bold);
+                if let Some(glyph) = glyph {
+                    wrapper.push(Item {
+                        glyph,
+                        color: style.color.unwrap_or(color),
+                        metrics: fonts.metrics(style.scale),
+                        space,
+                    });
+                }
+            }
+        }
+        wrapper.end_line();
+    }
+    wrapper.rows
+}
+
+/// Where a row of `row_width` starts in text `width` wide.
+fn row_start(align: Align, width: i32, row_width: i32) -> i32 {
+    match align {
+        Align::Left => 0,
+        Align::Center => (width - row_width) / 2,
+        Align::Right => width - row_width,
+    }
+}
+
+/// Rectangle as left, bottom, right and top.
+type Rect = [i32; 4];
+
+/// Area covered by both `a` and `b`.
+fn overlap(a: Rect, b: Rect) -> i64 {
+    let width = cmp::min(a[2], b[2]) - cmp::max(a[0], b[0]);
+    let height = cmp::min(a[3], b[3]) - cmp::max(a[1], b[1]);
+    width.max(0) as i64 * height.max(0) as i64
+}
+
+/// Visible part of text of `size` with its top left at `x`, `y` from the bottom left of `area`.
+fn visible_rect(x: i32, y: i32, size: (u32, u32), area: (u32, u32)) -> Rect {
+    let height = cmp::min(size.1, area.1) as i32;
+    [x, y - height, x + size.0 as i32, y]
+}
+
+/// Tries random positions for text of `size` within `area`, returns the top left of the first
+/// one not overlapping `others` or the one overlapping the least. Text taller than the area
+/// starts at the top.
+fn place<R: Rng>(size: (u32, u32), area: (u32, u32), others: &[Rect], rand: &mut R) -> (i32, i32) {
+    let free_x = area.0.saturating_sub(size.0);
+    let free_y = area.1.saturating_sub(size.1);
+    let visible_height = cmp::min(size.1, area.1);
+    let mut best = (0, visible_height as i32, i64::MAX);
+    for _ in 0..PLACEMENT_ATTEMPTS {
+        let x = rand.gen_range(0..=free_x) as i32;
+        let y = (visible_height + rand.gen_range(0..=free_y)) as i32;
+        let rect = visible_rect(x, y, size, area);
+        let covered = others.iter().map(|other| overlap(rect, *other)).sum();
+        if covered < best.2 {
+            best = (x, y, covered);
+        }
+        if covered == 0 {
+            break;
+        }
+    }
+    (best.0, best.1)
+}
+
+/// A random, fully saturated colour for text without markup colours.
+pub fn random_color<R: Rng>(rand: &mut R) -> [u8; 3] {
+    let color: Srgb = Hsv::new(
+        RgbHue::from_degrees(rand.gen_range(0..36000) as f32 / 100.0),
+        1.0,
+        1.0,
+    )
+    .into_color();
+    let color: Srgb<u8> = color.into_format();
+    [color.red, color.green, color.blue]
+}
+
+/// State of the animation style passed to the shaders.
+#[derive(Clone, Copy)]
+pub struct Animation {
+    pub style: PoetryStyle,
+    /// Seconds since the text was shown
+    pub time: f32,
+    /// Progress of the style's intro, from 0 to 1
+    pub progress: f32,
+    /// Beat of the music, see `Levels::beat`
+    pub beat: f32,
+}
+
+/// Rendered text with a quad per glyph.
+pub struct Text {
     texture: Texture2d,
-    x: u16,
-    y: u16,
-    width: u16,
-    height: u16,
+    vertex_buffer: glium::VertexBuffer<Vertex>,
+    index_buffer: glium::IndexBuffer<u32>,
+    glyphs: usize,
+    /// Size of the text within the texture
+    pub width: u32,
+    pub height: u32,
 }
 
-impl Poem {
-    pub fn new<R: Rng>(display: &Display, font: &bdf::Font, text: &str, rand: &mut R) -> Poem {
-        let window_size = display.gl_window().window().inner_size();
-        let font_size = font.bounds();
-        let char_size = (
-            font_size.width as usize + 1_usize,
-            font_size.height as usize + 1_usize,
-        );
+impl Text {
+    /// Renders `text` wrapped to `max_width`, in `color` where markup doesn't set one.
+    pub fn new(
+        display: &Display,
+        fonts: &Fonts,
+        text: &str,
+        color: [u8; 3],
+        align: Align,
+        max_width: u32,
+    ) -> Text {
+        let text: String = text.nfc().collect();
+        let lines = markup::parse(&text);
+        Self::from_lines(display, fonts, &text, lines, color, align, max_width)
+    }
 
-        let width = cmp::min(
-            window_size.width as usize / char_size.0,
-            text.lines().map(|line| line.len()).max().unwrap(),
-        );
-        let height = cmp::min(
-            window_size.height as usize / char_size.1,
-            text.lines().count(),
-        );
-        let real_w = cmp::min(window_size.width as usize, char_size.0 * width);
-        let real_h = cmp::min(window_size.height as usize, char_size.1 * height);
+    /// Renders `text` like `new`, but `scale` times the font size and without markup, for text
+    /// from elsewhere.
+    pub fn plain(
+        display: &Display,
+        fonts: &Fonts,
+        text: &str,
+        scale: f32,
+        color: [u8; 3],
+        align: Align,
+        max_width: u32,
+    ) -> Text {
+        let text: String = text.nfc().collect();
+        let lines = markup::plain(&text, scale);
+        Self::from_lines(display, fonts, &text, lines, color, align, max_width)
+    }
+
+    /// Renders `text` with markup in a single line, in pieces of at most `MAX_TEXT_SIZE` to be
+    /// shown one after another a space apart.
+    pub fn pieces(display: &Display, fonts: &Fonts, text: &str, color: [u8; 3]) -> Vec<Text> {
+        let text: String = text
+            .nfc()
+            .map(|ch| if ch == '\n' { ' ' } else { ch })
+            .collect();
+        let lines = markup::parse(&text);
+        layout(fonts, lines, color, MAX_TEXT_SIZE)
+            .into_iter()
+            .map(|row| Self::from_rows(display, &text, vec![row], Align::Left))
+            .collect()
+    }
+
+    fn from_lines(
+        display: &Display,
+        fonts: &Fonts,
+        text: &str,
+        lines: Vec<Vec<markup::Span>>,
+        color: [u8; 3],
+        align: Align,
+        max_width: u32,
+    ) -> Text {
+        let rows = layout(fonts, lines, color, max_width);
+        Self::from_rows(display, text, rows, align)
+    }
+
+    fn from_rows(display: &Display, text: &str, mut rows: Vec<Row>, align: Align) -> Text {
+        let mut text_height = 0;
+        let visible = rows
+            .iter()
+            .take_while(|row| {
+                text_height += row.metrics.height as u32;
+                text_height <= MAX_TEXT_SIZE
+            })
+            .count();
+        rows.truncate(visible);
+        let real_w = rows.iter().map(|row| row.width).max().unwrap_or(0).max(1) as usize;
+        let real_h = (rows.iter().map(|row| row.metrics.height).sum::<i32>() as usize).max(1);
         let pixel_w = real_w.next_power_of_two();
         let pixel_h = real_h.next_power_of_two();
         info!("Got string: \"{}\", size {}x{}", text, pixel_w, pixel_h);
 
-        let mut data = vec![u8::MIN; pixel_w * pixel_h];
-
-        let glyphs = font.glyphs();
-        let mut y = char_size.1 - 1_usize;
-        for line in text.lines() {
-            let mut pos = 0;
-            for ch in line.nfc() {
-                if (pos + 1) * char_size.0 > pixel_w {
-                    break;
+        // RGBA, the alpha channel holds the glyph coverage
+        let mut data = vec![u8::MIN; pixel_w * pixel_h * 4];
+        let glyphs = rows
+            .iter()
+            .flat_map(|row| &row.items)
+            .filter(|item| item.glyph.width > 0 && item.glyph.height > 0)
+            .count();
+        let mut vertices = Vec::with_capacity(glyphs * 4);
+        let mut top = 0;
+        for row in rows {
+            let baseline = top + row.metrics.ascent;
+            let mut pen = row_start(align, real_w as i32, row.width);
+            for item in row.items {
+                let glyph = &item.glyph;
+                if glyph.width > 0 && glyph.height > 0 {
+                    let left = (pen + glyph.left) as f32;
+                    let top = (baseline - glyph.top) as f32;
+                    let (right, bottom) = (left + glyph.width as f32, top + glyph.height as f32);
+                    let order = (vertices.len() / 4) as f32 / glyphs as f32;
+                    for &(x, y) in &[(left, top), (left, bottom), (right, bottom), (right, top)] {
+                        vertices.push(Vertex {
+                            position: [x, y],
+                            texcoords: [x / pixel_w as f32, y / pixel_h as f32],
+                            order,
+                        });
+                    }
                 }
-                if let Some(glyph) = glyphs.get(&ch) {
-                    let bounds = glyph.bounds();
-                    for ((gx, gy), value) in glyph.pixels() {
-                        if value {
-                            data[(bounds.x
-                                + (pos * char_size.0) as i32
-                                + gx as i32
-                                + (y as i32 - bounds.height as i32 - bounds.y + gy as i32)
-                                    * pixel_w as i32) as usize] = u8::MAX;
+                for gy in 0..glyph.height {
+                    let y = baseline - glyph.top + gy as i32;
+                    if y < 0 || y as usize >= pixel_h {
+                        continue;
+                    }
+                    for gx in 0..glyph.width {
+                        let x = pen + glyph.left + gx as i32;
+                        let coverage = glyph.coverage[gy * glyph.width + gx];
+                        if x < 0 || x as usize >= pixel_w || coverage == 0 {
+                            continue;
                         }
+                        let pixel = &mut data[(y as usize * pixel_w + x as usize) * 4..][..4];
+                        pixel[..3].copy_from_slice(&item.color);
+                        pixel[3] = pixel[3].max(coverage);
                     }
-                    pos += 1;
                 }
+                pen += glyph.advance;
             }
-
-            y += char_size.1;
-            if y >= pixel_h {
-                break;
-            }
+            top += row.metrics.height;
         }
 
         let rawimage = RawImage2d {
             data: Cow::from(&data[..]),
             width: pixel_w as u32,
             height: pixel_h as u32,
-            format: ClientFormat::U8,
+            format: ClientFormat::U8U8U8U8,
         };
         let texture = Texture2d::with_format(
             display,
             rawimage,
-            UncompressedFloatFormat::U8,
+            UncompressedFloatFormat::U8U8U8U8,
             MipmapsOption::NoMipmap,
         )
         .unwrap();
+        let indices: Vec<u32> = (0..glyphs as u32)
+            .flat_map(|glyph| {
+                let base = glyph * 4;
+                [base, base + 1, base + 2, base + 2, base + 3, base]
+            })
+            .collect();
+        let vertex_buffer = glium::VertexBuffer::new(display, &vertices).unwrap();
+        let index_buffer =
+            glium::IndexBuffer::new(display, PrimitiveType::TrianglesList, &indices).unwrap();
 
-        Poem {
-            created: Instant::now(),
-            color: Alpha {
-                color: Hsv::new(
-                    RgbHue::from_degrees(rand.gen_range(0..36000) as f32 / 100.0),
-                    1.0,
-                    1.0,
-                )
-                .into_color(),
-                alpha: 1.0,
-            },
+        Text {
             texture,
-            x: real_w as u16 + rand.gen_range(0..(window_size.width as usize - real_w)) as u16,
-            y: real_h as u16 + rand.gen_range(0..(window_size.height as usize - real_h)) as u16,
-            height: pixel_h as u16,
-            width: pixel_w as u16,
+            vertex_buffer,
+            index_buffer,
+            glyphs,
+            width: real_w as u32,
+            height: real_h as u32,
         }
     }
 
-    pub fn render_all(
-        display: &Display,
-        poems: &[Poem],
-        vertex_buffer: &glium::VertexBuffer<Vertex>,
-        index_buffer: &glium::IndexBuffer<u16>,
-        program: &glium::Program,
-    ) {
-        let size = display.gl_window().window().inner_size();
-        let mut target = display.draw();
-        target.clear_color_and_depth((0.0, 0.0, 0.0, 0.0), 1.0);
-
-        for poem in poems {
-            poem.render(
-                &mut target,
-                &(size.width as u32, size.height as u32),
-                vertex_buffer,
-                index_buffer,
-                program,
-            );
-        }
-        target.finish().unwrap();
-    }
-
-    fn render(
+    /// Draws the text with its top left at `origin`, in pixels from the bottom left of the
+    /// `size` sized target.
+    pub fn draw<S: Surface>(
         &self,
-        target: &mut Frame,
-        size: &(u32, u32),
-        vertex_buffer: &glium::VertexBuffer<Vertex>,
-        index_buffer: &glium::IndexBuffer<u16>,
+        target: &mut S,
         program: &glium::Program,
+        origin: [f32; 2],
+        size: (u32, u32),
+        color: Srgba,
+        animation: Animation,
     ) {
-        let c = &self.color;
+        if self.glyphs == 0 {
+            return;
+        }
         let uniforms = uniform! {
-            color: [c.red, c.green, c.blue, c.alpha],
+            color: [color.red, color.green, color.blue, color.alpha],
             text: Sampler::new(&self.texture).minify_filter(MinifySamplerFilter::Nearest).magnify_filter(MagnifySamplerFilter::Nearest),
-            bounds: [self.x as f32 / size.0 as f32, self.y as f32 / size.1 as f32, self.width as f32 / size.0 as f32, self.height as f32 / size.1 as f32],
+            origin: origin,
+            area: [size.0 as f32, size.1 as f32],
+            style: style_uniform(animation.style),
+            time: animation.time,
+            progress: animation.progress,
+            beat: animation.beat,
         };
         target
             .draw(
-                vertex_buffer,
-                index_buffer,
+                &self.vertex_buffer,
+                &self.index_buffer,
                 program,
                 &uniforms,
                 &glium::DrawParameters {
@@ -167,3 +454,317 @@ impl Poem {
             .unwrap();
     }
 }
+
+/// Seconds `style` takes to bring in text of `glyphs` glyphs.
+fn intro_time(style: PoetryStyle, glyphs: usize, speed: f32) -> f32 {
+    match style {
+        PoetryStyle::Typewriter => (glyphs as f32 * TYPEWRITER_INTERVAL).min(speed),
+        PoetryStyle::Ticker | PoetryStyle::Falling => INTRO_TIME,
+        PoetryStyle::Fade | PoetryStyle::Glitch | PoetryStyle::Rainbow | PoetryStyle::Pulse => 0.0,
+    }
+}
+
+/// Where a poem is in its intro, scrolling and fading out.
+#[derive(Debug, PartialEq)]
+struct Timeline {
+    /// Progress of the style's intro, from 0 to 1
+    progress: f32,
+    /// Pixels scrolled up
+    scroll: f32,
+    alpha: f32,
+}
+
+impl Timeline {
+    /// `time` seconds into showing text `height` pixels high in an area `area_height` pixels
+    /// high, see `Poem::animate`.
+    fn at(time: f32, intro_time: f32, speed: f32, height: u32, area_height: u32) -> Self {
+        let progress = if intro_time > 0.0 {
+            (time / intro_time).min(1.0)
+        } else {
+            1.0
+        };
+        let elapsed = (time - intro_time).max(0.0);
+        let overflow = height.saturating_sub(area_height) as f32;
+        let scroll_time = speed * overflow / area_height.max(1) as f32;
+        let scroll = if scroll_time > 0.0 {
+            overflow * (elapsed / scroll_time).min(1.0)
+        } else {
+            0.0
+        };
+        Timeline {
+            progress,
+            scroll,
+            alpha: 1.0 - (elapsed - scroll_time).max(0.0) / speed,
+        }
+    }
+}
+
+pub struct Poem {
+    pub created: Instant,
+    /// Multiplied with the texture, which has the text colours
+    pub color: Srgba,
+    text: Text,
+    /// Left edge, and top edge from the bottom of the area
+    x: i32,
+    y: i32,
+    area: (u32, u32),
+    animation: Animation,
+    /// Pixels moved in from the right by the ticker style
+    slide: f32,
+    /// Pixels scrolled up, for text taller than the area
+    scroll: f32,
+}
+
+impl Poem {
+    /// Shows `text` animated with `style`, placed where it covers the least of `others`.
+    pub fn new<R: Rng>(
+        text: Text,
+        style: PoetryStyle,
+        area: (u32, u32),
+        others: &[Poem],
+        rand: &mut R,
+    ) -> Poem {
+        let others: Vec<Rect> = others.iter().map(Poem::rect).collect();
+        let (x, y) = place((text.width, text.height), area, &others, rand);
+        Poem {
+            created: Instant::now(),
+            color: Srgba::new(1.0, 1.0, 1.0, 1.0),
+            text,
+            x,
+            y,
+            area,
+            animation: Animation {
+                style,
+                time: 0.0,
+                progress: 0.0,
+                beat: 0.0,
+            },
+            slide: 0.0,
+            scroll: 0.0,
+        }
+    }
+
+    fn rect(&self) -> Rect {
+        visible_rect(
+            self.x,
+            self.y,
+            (self.text.width, self.text.height),
+            self.area,
+        )
+    }
+
+    /// Plays the style's intro, scrolls through text taller than the area, then fades out over
+    /// `speed` seconds, moving with `beat`. Returns whether the poem is still visible.
+    pub fn animate(&mut self, speed: f32, beat: f32) -> bool {
+        let time = self.created.elapsed().as_secs_f32();
+        let intro_time = intro_time(self.animation.style, self.text.glyphs, speed);
+        let timeline = Timeline::at(time, intro_time, speed, self.text.height, self.area.1);
+        self.animation.time = time;
+        self.animation.progress = timeline.progress;
+        self.animation.beat = beat;
+        self.slide = if self.animation.style == PoetryStyle::Ticker {
+            (self.area.0 as i32 - self.x) as f32 * (1.0 - timeline.progress)
+        } else {
+            0.0
+        };
+        self.scroll = timeline.scroll;
+        self.color.alpha = timeline.alpha;
+        (self.color.alpha * 255.0).round() >= f32::EPSILON
+    }
+
+    pub fn render_all<S: Surface>(
+        target: &mut S,
+        size: (u32, u32),
+        poems: &[Poem],
+        program: &glium::Program,
+    ) {
+        for poem in poems {
+            poem.render(target, &size, program);
+        }
+    }
+
+    fn render<S: Surface>(&self, target: &mut S, size: &(u32, u32), program: &glium::Program) {
+        self.text.draw(
+            target,
+            program,
+            [self.x as f32 + self.slide, self.y as f32 + self.scroll],
+            *size,
+            self.color,
+            self.animation,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_xoshiro::Xoshiro256Plus;
+
+    const METRICS: LineMetrics = LineMetrics {
+        ascent: 5,
+        height: 6,
+    };
+
+    /// Every character is 4 pixels wide.
+    fn item(ch: char) -> Item {
+        Item {
+            glyph: Glyph {
+                width: 3,
+                height: 5,
+                coverage: vec![255; 15],
+                left: 0,
+                top: 5,
+                advance: 4,
+            },
+            color: [ch as u8, 0, 0],
+            metrics: METRICS,
+            space: ch == ' ',
+        }
+    }
+
+    /// Wraps the lines of `text` to `max_width` and returns the rows and their widths.
+    fn wrap(text: &str, max_width: i32) -> Vec<(String, i32)> {
+        let mut wrapper = Wrapper::new(max_width, METRICS);
+        for line in text.split('\n') {
+            for ch in line.chars() {
+                wrapper.push(item(ch));
+            }
+            wrapper.end_line();
+        }
+        wrapper
+            .rows
+            .iter()
+            .map(|row| {
+                let text = row.items.iter().map(|item| item.color[0] as char).collect();
+                (text, row.width)
+            })
+            .collect()
+    }
+
+    fn rows(rows: &[(&str, i32)]) -> Vec<(String, i32)> {
+        rows.iter()
+            .map(|(text, width)| (text.to_string(), *width))
+            .collect()
+    }
+
+    #[test]
+    fn words_wrap_at_spaces() {
+        assert_eq!(
+            wrap("the quick brown fox", 40),
+            rows(&[("the quick", 36), ("brown fox", 36)])
+        );
+        // spaces at the wrap are dropped, the ones the text starts with are kept
+        assert_eq!(wrap("  a   b", 12), rows(&[("  a", 12), ("b", 4)]));
+        assert_eq!(wrap("fits exactly", 48), rows(&[("fits exactly", 48)]));
+    }
+
+    #[test]
+    fn long_words_break_between_characters() {
+        assert_eq!(
+            wrap("a wonderful day", 20),
+            rows(&[("a", 4), ("wonde", 20), ("rful", 16), ("day", 12)])
+        );
+        // a single character wider than the row still gets one
+        assert_eq!(wrap("ab", 2), rows(&[("a", 4), ("b", 4)]));
+    }
+
+    #[test]
+    fn lines_end_rows() {
+        assert_eq!(
+            wrap("one\n\ntwo  ", 100),
+            rows(&[("one", 12), ("", 0), ("two", 12)])
+        );
+        let mut wrapper = Wrapper::new(100, METRICS);
+        wrapper.end_line();
+        assert_eq!(wrapper.rows[0].metrics.height, METRICS.height);
+    }
+
+    #[test]
+    fn rows_are_aligned() {
+        assert_eq!(row_start(Align::Left, 40, 12), 0);
+        assert_eq!(row_start(Align::Center, 40, 12), 14);
+        assert_eq!(row_start(Align::Center, 40, 13), 13);
+        assert_eq!(row_start(Align::Right, 40, 12), 28);
+        assert_eq!(row_start(Align::Right, 40, 40), 0);
+    }
+
+    #[test]
+    fn overlapping_area() {
+        assert_eq!(overlap([0, 0, 10, 10], [5, 5, 20, 20]), 25);
+        assert_eq!(overlap([0, 0, 10, 10], [2, 3, 4, 5]), 4);
+        assert_eq!(overlap([0, 0, 10, 10], [10, 0, 20, 10]), 0);
+        assert_eq!(overlap([0, 0, 10, 10], [20, 20, 30, 30]), 0);
+    }
+
+    #[test]
+    fn poems_are_placed_inside_the_area() {
+        let mut rand = Xoshiro256Plus::seed_from_u64(1);
+        for _ in 0..100 {
+            let (x, y) = place((30, 10), (100, 50), &[], &mut rand);
+            assert!((0..=70).contains(&x), "x {}", x);
+            assert!((10..=50).contains(&y), "y {}", y);
+        }
+        // too large text starts at the top left
+        assert_eq!(place((200, 80), (100, 50), &[], &mut rand), (0, 50));
+    }
+
+    #[test]
+    fn poems_avoid_others() {
+        let mut rand = Xoshiro256Plus::seed_from_u64(2);
+        // only the right half is free
+        let others = [[0, 0, 50, 50]];
+        for _ in 0..20 {
+            let (x, y) = place((20, 20), (100, 50), &others, &mut rand);
+            assert_eq!(
+                overlap(visible_rect(x, y, (20, 20), (100, 50)), others[0]),
+                0
+            );
+        }
+        // with no free spot, the least covered one is taken
+        let others = [[0, 0, 60, 50], [60, 0, 100, 50], [60, 0, 100, 50]];
+        let (x, y) = place((20, 50), (100, 50), &others, &mut rand);
+        assert_eq!(y, 50);
+        assert!(x <= 40, "x {}", x);
+    }
+
+    #[test]
+    fn styles_take_their_time_to_bring_in_text() {
+        assert_eq!(intro_time(PoetryStyle::Fade, 10, 5.0), 0.0);
+        assert_eq!(intro_time(PoetryStyle::Ticker, 10, 5.0), INTRO_TIME);
+        assert_eq!(
+            intro_time(PoetryStyle::Typewriter, 10, 5.0),
+            10.0 * TYPEWRITER_INTERVAL
+        );
+        // long poems type faster rather than eat into reading time
+        assert_eq!(intro_time(PoetryStyle::Typewriter, 1000, 5.0), 5.0);
+    }
+
+    #[test]
+    fn poems_fade_out_after_the_intro() {
+        let timeline = |time| Timeline::at(time, 2.0, 10.0, 50, 100);
+        let at = |progress, scroll, alpha| Timeline {
+            progress,
+            scroll,
+            alpha,
+        };
+        assert_eq!(timeline(0.0), at(0.0, 0.0, 1.0));
+        assert_eq!(timeline(1.0), at(0.5, 0.0, 1.0));
+        assert_eq!(timeline(2.0), at(1.0, 0.0, 1.0));
+        assert_eq!(timeline(7.0), at(1.0, 0.0, 0.5));
+        assert_eq!(timeline(12.0), at(1.0, 0.0, 0.0));
+        assert_eq!(Timeline::at(0.0, 0.0, 10.0, 50, 100), at(1.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn tall_poems_scroll_before_fading() {
+        // 50 pixels to scroll at 100 pixels per 10 seconds
+        let timeline = |time| Timeline::at(time, 2.0, 10.0, 150, 100);
+        assert_eq!(timeline(2.0).scroll, 0.0);
+        assert_eq!(timeline(4.5).scroll, 25.0);
+        assert_eq!(timeline(4.5).alpha, 1.0);
+        assert_eq!(timeline(7.0).scroll, 50.0);
+        assert_eq!(timeline(12.0).scroll, 50.0);
+        assert_eq!(timeline(12.0).alpha, 0.5);
+    }
+}
@@ -0,0 +1,191 @@
+use glium::{backend::glutin::Display, Surface};
+use palette::Srgba;
+use rand::SeedableRng;
+use rand_xoshiro::Xoshiro256Plus;
+use std::{collections::VecDeque, rc::Rc, time::Instant};
+
+use super::{
+    font::Fonts,
+    render::{self, Animation, Text},
+    text_program,
+};
+use crate::config::PoetryStyle;
+
+/// Gap between messages, in line heights
+const GAP_LINES: i32 = 3;
+
+/// Where a piece of a message is, in pixels.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Slot {
+    /// Left edge
+    x: f32,
+    width: u32,
+}
+
+/// Places of the pieces of messages moving from right to left in a single line, oldest first.
+struct Lane {
+    slots: VecDeque<Slot>,
+}
+
+impl Lane {
+    fn new() -> Self {
+        Self {
+            slots: VecDeque::new(),
+        }
+    }
+
+    /// Whether the last piece is at least `gap` pixels left of the right edge at `right`.
+    fn has_room(&self, right: u32, gap: f32) -> bool {
+        self.slots.back().map_or(true, |slot| {
+            slot.x + slot.width as f32 + gap <= right as f32
+        })
+    }
+
+    /// Adds the pieces of a message, `widths` wide and `spacing` apart, just right of the right
+    /// edge at `right`.
+    fn push(&mut self, widths: &[u32], right: u32, spacing: f32) {
+        let mut x = right as f32;
+        for &width in widths {
+            self.slots.push_back(Slot { x, width });
+            x += width as f32 + spacing;
+        }
+    }
+
+    /// Moves everything `pixels` to the left and drops what has left the line, returns how many
+    /// pieces were dropped.
+    fn advance(&mut self, pixels: f32) -> usize {
+        for slot in &mut self.slots {
+            slot.x -= pixels;
+        }
+        let gone = self
+            .slots
+            .iter()
+            .take_while(|slot| slot.x + (slot.width as f32) < 0.0)
+            .count();
+        self.slots.drain(..gone);
+        gone
+    }
+}
+
+/// Messages scrolling from right to left in a single line.
+pub struct Ticker {
+    fonts: Rc<Fonts>,
+    program: glium::Program,
+    /// Pixels per second
+    speed: f32,
+    lane: Lane,
+    /// Pieces of the messages in the order of their slots in `lane`
+    texts: VecDeque<Text>,
+    last_frame: Option<Instant>,
+    rand: Xoshiro256Plus,
+}
+
+impl Ticker {
+    pub fn new(display: &Display, fonts: Rc<Fonts>, speed: f32) -> Self {
+        Self {
+            fonts,
+            program: text_program(display),
+            speed,
+            lane: Lane::new(),
+            texts: VecDeque::new(),
+            last_frame: None,
+            rand: Xoshiro256Plus::from_entropy(),
+        }
+    }
+
+    /// Whether the last message has moved far enough for the next one to follow it.
+    pub fn wants_message(&self, width: u32) -> bool {
+        let gap = (self.fonts.metrics(1.0).height * GAP_LINES) as f32;
+        self.lane.has_room(width, gap)
+    }
+
+    /// Adds `message` to the right of the `width` wide ticker, in a single line however long.
+    pub fn push(&mut self, display: &Display, message: &str, width: u32) {
+        let color = render::random_color(&mut self.rand);
+        let pieces = Text::pieces(display, &self.fonts, message, color);
+        let widths: Vec<_> = pieces.iter().map(|text| text.width).collect();
+        let space = self
+            .fonts
+            .glyph(' ', 1.0, false)
+            .map_or(0, |glyph| glyph.advance);
+        self.lane.push(&widths, width, space as f32);
+        self.texts.extend(pieces);
+    }
+
+    /// Moves the messages on and draws them centered vertically on top of `target`.
+    pub fn draw<S: Surface>(&mut self, target: &mut S, size: (u32, u32)) {
+        let elapsed = self
+            .last_frame
+            .map_or(0.0, |last_frame| last_frame.elapsed().as_secs_f32());
+        self.last_frame = Some(Instant::now());
+        let gone = self.lane.advance(elapsed * self.speed);
+        self.texts.drain(..gone);
+
+        let animation = Animation {
+            style: PoetryStyle::Fade,
+            time: 0.0,
+            progress: 1.0,
+            beat: 0.0,
+        };
+        for (slot, text) in self.lane.slots.iter().zip(&self.texts) {
+            let top = ((size.1 + text.height) / 2) as f32;
+            text.draw(
+                target,
+                &self.program,
+                [slot.x.round(), top],
+                size,
+                Srgba::new(1.0, 1.0, 1.0, 1.0),
+                animation,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(lane: &Lane) -> Vec<(f32, u32)> {
+        lane.slots.iter().map(|slot| (slot.x, slot.width)).collect()
+    }
+
+    #[test]
+    fn messages_follow_each_other_with_a_gap() {
+        let mut lane = Lane::new();
+        assert!(lane.has_room(100, 10.0));
+        lane.push(&[30], 100, 4.0);
+        assert!(!lane.has_room(100, 10.0));
+        assert_eq!(lane.advance(39.0), 0);
+        assert!(!lane.has_room(100, 10.0));
+        lane.advance(1.0);
+        assert!(lane.has_room(100, 10.0));
+        lane.push(&[50], 100, 4.0);
+        assert_eq!(positions(&lane), [(60.0, 30), (100.0, 50)]);
+    }
+
+    #[test]
+    fn long_messages_are_pieces_in_a_row() {
+        let mut lane = Lane::new();
+        lane.push(&[40, 40, 10], 100, 4.0);
+        assert_eq!(positions(&lane), [(100.0, 40), (144.0, 40), (188.0, 10)]);
+        // the next message waits for the last piece
+        lane.advance(100.0);
+        assert!(!lane.has_room(100, 10.0));
+        lane.advance(8.0);
+        assert!(lane.has_room(100, 10.0));
+    }
+
+    #[test]
+    fn messages_are_dropped_once_gone() {
+        let mut lane = Lane::new();
+        lane.push(&[30], 100, 4.0);
+        lane.push(&[50], 120, 4.0);
+        assert_eq!(lane.advance(130.0), 0);
+        assert_eq!(positions(&lane), [(-30.0, 30), (-10.0, 50)]);
+        assert_eq!(lane.advance(1.0), 1);
+        assert_eq!(positions(&lane), [(-11.0, 50)]);
+        assert_eq!(lane.advance(40.0), 1);
+        assert_eq!(positions(&lane), []);
+        assert!(lane.has_room(100, 10.0));
+    }
+}
@@ -1,9 +1,17 @@
 use super::Command;
-use crate::server::ShaderData;
+use crate::{
+    audio::Device,
+    canvas::PaintError,
+    capture::RecordingFormat,
+    compositor::LayerSpec,
+    config::{PoetryStyle, TransitionEffect},
+    emulator::library::{RomInfo, RomLibrary},
+    server::ShaderData,
+};
 use log::{error, info};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::{path::Path, sync::mpsc};
+use std::sync::mpsc;
 use ws::{self, CloseCode, Error, ErrorKind, Handler, Handshake, Result, Sender};
 
 #[derive(Serialize, Deserialize)]
@@ -156,22 +164,404 @@ impl Handler for Connection {
                                 );
                             }
                         }
+                        "video pause" => {
+                            self.channel
+                                .send((Command::VideoPause, Some(resp)))
+                                .unwrap();
+                        }
+                        "video resume" => {
+                            self.channel
+                                .send((Command::VideoResume, Some(resp)))
+                                .unwrap();
+                        }
+                        "video seek" => {
+                            if let Some(position) = obj["position"].as_f64() {
+                                let relative = obj["relative"].as_bool().unwrap_or(false);
+                                self.channel
+                                    .send((Command::VideoSeek(position, relative), Some(resp)))
+                                    .unwrap();
+                            } else {
+                                resp.send_error(400, "Message needs numeric position, ignored.")
+                                    .unwrap();
+                            }
+                        }
+                        "video next" => {
+                            self.channel.send((Command::VideoNext, Some(resp))).unwrap();
+                        }
+                        "video previous" => {
+                            self.channel
+                                .send((Command::VideoPrevious, Some(resp)))
+                                .unwrap();
+                        }
+                        "video status" => {
+                            self.channel
+                                .send((Command::VideoStatus, Some(resp)))
+                                .unwrap();
+                        }
+                        "video effect" => match &obj["id"] {
+                            serde_json::Value::String(id) => self
+                                .channel
+                                .send((Command::VideoEffect(Some(id.clone())), Some(resp)))
+                                .unwrap(),
+                            serde_json::Value::Null => self
+                                .channel
+                                .send((Command::VideoEffect(None), Some(resp)))
+                                .unwrap(),
+                            _ => resp
+                                .send_error(400, "Message needs shader id or null, ignored.")
+                                .unwrap(),
+                        },
+                        "video media" => {
+                            self.channel.send((Command::ListMedia, Some(resp))).unwrap();
+                        }
+                        "video queue" => {
+                            self.channel
+                                .send((Command::VideoQueueList, Some(resp)))
+                                .unwrap();
+                        }
+                        "video queue add" => {
+                            if let serde_json::Value::String(url) = &obj["url"] {
+                                self.channel
+                                    .send((Command::VideoQueueAdd(url.clone()), Some(resp)))
+                                    .unwrap();
+                            } else {
+                                resp.send_error(400, "Message needs string in url key, ignored.")
+                                    .unwrap();
+                            }
+                        }
+                        "video queue remove" => {
+                            if let Some(index) = obj["index"].as_u64() {
+                                self.channel
+                                    .send((Command::VideoQueueRemove(index as usize), Some(resp)))
+                                    .unwrap();
+                            } else {
+                                resp.send_error(400, "Message needs numeric index, ignored.")
+                                    .unwrap();
+                            }
+                        }
+                        "video queue clear" => {
+                            self.channel
+                                .send((Command::VideoQueueClear, Some(resp)))
+                                .unwrap();
+                        }
                         "turnoff" => {
                             self.channel.send((Command::TurnOff, Some(resp))).unwrap();
                         }
                         "show poetry" => {
-                            if let serde_json::Value::String(text) = &obj["text"] {
+                            let text = obj["text"].as_str().unwrap_or_default().to_owned();
+                            let style = match &obj["style"] {
+                                serde_json::Value::Null => Ok(None),
+                                style => {
+                                    serde_json::from_value::<PoetryStyle>(style.clone()).map(Some)
+                                }
+                            };
+                            match style {
+                                Ok(style) if obj["overlay"].as_bool().unwrap_or(false) => self
+                                    .channel
+                                    .send((Command::ShowPoetryOverlay(text, style), Some(resp)))
+                                    .unwrap(),
+                                Ok(style) => self
+                                    .channel
+                                    .send((Command::ShowPoetry(text, style), Some(resp)))
+                                    .unwrap(),
+                                Err(err) => resp
+                                    .send_error(400, &format!("Invalid poetry style: {err}"))
+                                    .unwrap(),
+                            }
+                        }
+                        "show scene" => {
+                            match serde_json::from_value::<Vec<LayerSpec>>(obj["layers"].clone()) {
+                                Ok(layers) => self
+                                    .channel
+                                    .send((Command::ShowScene(layers), Some(resp)))
+                                    .unwrap(),
+                                Err(err) => resp
+                                    .send_error(400, &format!("Invalid scene layers: {err}"))
+                                    .unwrap(),
+                            }
+                        }
+                        "scene opacity" => {
+                            if let (Some(layer), Some(opacity)) =
+                                (obj["layer"].as_u64(), obj["opacity"].as_f64())
+                            {
+                                self.channel
+                                    .send((
+                                        Command::SceneOpacity(layer as usize, opacity as f32),
+                                        Some(resp),
+                                    ))
+                                    .unwrap();
+                            } else {
+                                resp.send_error(400, "Message needs layer and opacity, ignored.")
+                                    .unwrap();
+                            }
+                        }
+                        "transition" => {
+                            let effect = match &obj["effect"] {
+                                serde_json::Value::Null => Ok(None),
+                                effect => {
+                                    serde_json::from_value::<TransitionEffect>(effect.clone())
+                                        .map(Some)
+                                }
+                            };
+                            match effect {
+                                Ok(effect) => self
+                                    .channel
+                                    .send((
+                                        Command::SetTransition(effect, obj["duration"].as_u64()),
+                                        Some(resp),
+                                    ))
+                                    .unwrap(),
+                                Err(err) => resp
+                                    .send_error(400, &format!("Invalid transition effect: {err}"))
+                                    .unwrap(),
+                            }
+                        }
+                        "brightness" => {
+                            if let Some(value) = obj["value"].as_f64() {
+                                self.channel
+                                    .send((Command::SetBrightness(value as f32), Some(resp)))
+                                    .unwrap();
+                            } else {
+                                resp.send_error(400, "Message needs brightness value, ignored.")
+                                    .unwrap();
+                            }
+                        }
+                        "screenshot" => {
+                            self.channel
+                                .send((Command::Screenshot, Some(resp)))
+                                .unwrap();
+                        }
+                        "capture record start" => {
+                            let format = match &obj["format"] {
+                                serde_json::Value::Null => Ok(RecordingFormat::Images),
+                                format => serde_json::from_value(format.clone()),
+                            };
+                            match (&obj["name"], format) {
+                                (serde_json::Value::String(name), Ok(format)) => self
+                                    .channel
+                                    .send((
+                                        Command::CaptureRecordStart(
+                                            name.clone(),
+                                            format,
+                                            obj["duration"].as_u64(),
+                                        ),
+                                        Some(resp),
+                                    ))
+                                    .unwrap(),
+                                (_, Err(err)) => resp
+                                    .send_error(400, &format!("Invalid recording format: {err}"))
+                                    .unwrap(),
+                                _ => resp
+                                    .send_error(400, "Message needs string in name key, ignored.")
+                                    .unwrap(),
+                            }
+                        }
+                        "capture record stop" => {
+                            self.channel
+                                .send((Command::CaptureRecordStop, Some(resp)))
+                                .unwrap();
+                        }
+                        "show board" => {
+                            self.channel.send((Command::ShowBoard, Some(resp))).unwrap();
+                        }
+                        "show canvas" => {
+                            self.channel
+                                .send((Command::ShowCanvas, Some(resp)))
+                                .unwrap();
+                        }
+                        "canvas" => {
+                            self.channel.send((Command::CanvasGet, Some(resp))).unwrap();
+                        }
+                        "canvas paint" => {
+                            if let (Some(x), Some(y), Some(color)) = (
+                                obj["x"].as_u64(),
+                                obj["y"].as_u64(),
+                                obj["color"].as_u64().filter(|color| *color < 256),
+                            ) {
+                                if x > u32::MAX as u64 || y > u32::MAX as u64 {
+                                    resp.send_error(400, &format!("{}", PaintError::Outside))
+                                        .unwrap();
+                                } else {
+                                    // cooldowns are per visitor, not per connection
+                                    let painter = self
+                                        .address
+                                        .rsplit_once(':')
+                                        .map_or(self.address.as_str(), |(host, _)| host)
+                                        .to_owned();
+                                    self.channel
+                                        .send((
+                                            Command::CanvasPaint(
+                                                x as u32,
+                                                y as u32,
+                                                color as u8,
+                                                painter,
+                                            ),
+                                            Some(resp),
+                                        ))
+                                        .unwrap();
+                                }
+                            } else {
+                                resp.send_error(400, "Pixel needs x, y and color, ignored.")
+                                    .unwrap();
+                            }
+                        }
+                        "canvas timelapse" => {
+                            self.channel
+                                .send((Command::CanvasTimelapse, Some(resp)))
+                                .unwrap();
+                        }
+                        "show ambient" => {
+                            self.channel
+                                .send((Command::ShowAmbient, Some(resp)))
+                                .unwrap();
+                        }
+                        "board" => {
+                            self.channel.send((Command::BoardList, Some(resp))).unwrap();
+                        }
+                        "board add" => {
+                            if let Some(text) = obj["text"].as_str() {
+                                self.channel
+                                    .send((
+                                        Command::BoardAdd(
+                                            text.to_owned(),
+                                            obj["pinned"].as_bool().unwrap_or(false),
+                                            obj["ttl"].as_u64(),
+                                        ),
+                                        Some(resp),
+                                    ))
+                                    .unwrap();
+                            } else {
+                                resp.send_error(400, "Message needs string in text key, ignored.")
+                                    .unwrap();
+                            }
+                        }
+                        "board remove" => {
+                            if let Some(id) = obj["id"].as_u64() {
+                                self.channel
+                                    .send((Command::BoardRemove(id), Some(resp)))
+                                    .unwrap();
+                            } else {
+                                resp.send_error(400, "Message needs numeric id, ignored.")
+                                    .unwrap();
+                            }
+                        }
+                        "board pin" => {
+                            if let Some(id) = obj["id"].as_u64() {
+                                self.channel
+                                    .send((
+                                        Command::BoardPin(
+                                            id,
+                                            obj["pinned"].as_bool().unwrap_or(true),
+                                        ),
+                                        Some(resp),
+                                    ))
+                                    .unwrap();
+                            } else {
+                                resp.send_error(400, "Message needs numeric id, ignored.")
+                                    .unwrap();
+                            }
+                        }
+                        "app start" => {
+                            if let Some(name) = obj["name"].as_str() {
                                 self.channel
-                                    .send((Command::ShowPoetry(text.clone()), Some(resp)))
+                                    .send((Command::StartApp(name.to_owned()), Some(resp)))
                                     .unwrap();
                             } else {
+                                resp.send_error(400, "App needs name, ignored.").unwrap();
+                            }
+                        }
+                        "shader pointer" => {
+                            if let (Some(x), Some(y)) = (obj["x"].as_f64(), obj["y"].as_f64()) {
+                                let down = obj["down"].as_bool().unwrap_or(false);
                                 self.channel
-                                    .send((Command::ShowPoetry(String::new()), Some(resp)))
+                                    .send((
+                                        Command::ShaderPointer(x as f32, y as f32, down),
+                                        Some(resp),
+                                    ))
+                                    .unwrap();
+                            } else {
+                                resp.send_error(400, "Pointer needs x and y, ignored.")
                                     .unwrap();
                             }
                         }
+                        "shader key" => {
+                            if let Some(key) = obj["key"].as_u64().filter(|key| *key < 256) {
+                                let down = obj["down"].as_bool().unwrap_or(true);
+                                self.channel
+                                    .send((Command::ShaderKey(key as u8, down), Some(resp)))
+                                    .unwrap();
+                            } else {
+                                resp.send_error(400, "Key needs key code, ignored.")
+                                    .unwrap();
+                            }
+                        }
+                        "show vnc" => {
+                            self.channel
+                                .send((
+                                    Command::ShowVnc(obj["server"].as_str().map(str::to_owned)),
+                                    Some(resp),
+                                ))
+                                .unwrap();
+                        }
+                        "vnc pointer" => {
+                            if let (Some(x), Some(y)) = (obj["x"].as_f64(), obj["y"].as_f64()) {
+                                let buttons = obj["buttons"].as_u64().unwrap_or(0) as u8;
+                                self.channel
+                                    .send((
+                                        Command::VncPointer(x as f32, y as f32, buttons),
+                                        Some(resp),
+                                    ))
+                                    .unwrap();
+                            } else {
+                                resp.send_error(400, "Pointer needs x and y, ignored.")
+                                    .unwrap();
+                            }
+                        }
+                        "vnc key" => {
+                            if let Some(key) = obj["key"].as_str() {
+                                let down = obj["down"].as_bool().unwrap_or(true);
+                                self.channel
+                                    .send((Command::VncKey(key.to_owned(), down), Some(resp)))
+                                    .unwrap();
+                            } else {
+                                resp.send_error(400, "Key needs key, ignored.").unwrap();
+                            }
+                        }
+                        "apps" => {
+                            self.channel.send((Command::AppList, Some(resp))).unwrap();
+                        }
+                        // "audio inputs" is the name from before outputs were listed too
+                        "audio devices" | "audio inputs" => {
+                            self.channel
+                                .send((Command::AudioDevices, Some(resp)))
+                                .unwrap();
+                        }
+                        "volume" => {
+                            if let Some(volume) = obj["volume"].as_u64().filter(|v| *v <= 100) {
+                                self.channel
+                                    .send((Command::SetVolume(volume as u8), Some(resp)))
+                                    .unwrap();
+                            } else {
+                                resp.send_error(400, "Volume needs volume from 0 to 100, ignored.")
+                                    .unwrap();
+                            }
+                        }
+                        "mute" => {
+                            let muted = obj["mute"].as_bool().unwrap_or(true);
+                            self.channel
+                                .send((Command::SetMute(muted), Some(resp)))
+                                .unwrap();
+                        }
+                        "duck" => {
+                            let ducked = obj["duck"].as_bool().unwrap_or(true);
+                            self.channel
+                                .send((Command::Duck(ducked), Some(resp)))
+                                .unwrap();
+                        }
                         "tox start" => {
-                            self.channel.send((Command::StartTox, Some(resp))).unwrap();
+                            self.channel
+                                .send((Command::StartApp("tox".to_owned()), Some(resp)))
+                                .unwrap();
                         }
                         "tox message" => {
                             if let serde_json::Value::String(text) = &obj["text"] {
@@ -185,9 +575,55 @@ impl Handler for Connection {
                             }
                         }
                         "emulator list" => {
-                            resp.send_emulator_list(crate::ROMS_PATH.get().unwrap())
+                            resp.send_emulator_list(crate::ROM_LIBRARY.get().unwrap())
                                 .unwrap();
                         }
+                        "emulator upload" => {
+                            let library = crate::ROM_LIBRARY.get().unwrap();
+                            if !library.is_authorized(obj["key"].as_str()) {
+                                resp.send_error(403, "Not authorized to upload ROMs.")
+                                    .unwrap();
+                            } else if let (
+                                serde_json::Value::String(rom),
+                                serde_json::Value::String(data),
+                            ) = (&obj["rom"], &obj["data"])
+                            {
+                                match base64::decode(data) {
+                                    Ok(data) => match library.upload(rom, &data) {
+                                        Ok(info) => resp.send_rom_info(&info).unwrap(),
+                                        Err(error) => {
+                                            resp.send_error(400, &format!("{}", error)).unwrap()
+                                        }
+                                    },
+                                    Err(error) => {
+                                        resp.send_error(400, &format!("{}", error)).unwrap()
+                                    }
+                                }
+                            } else {
+                                resp.send_error(
+                                    400,
+                                    "Message needs rom and base64 encoded data, ignored.",
+                                )
+                                .unwrap();
+                            }
+                        }
+                        "emulator delete" => {
+                            let library = crate::ROM_LIBRARY.get().unwrap();
+                            if !library.is_authorized(obj["key"].as_str()) {
+                                resp.send_error(403, "Not authorized to delete ROMs.")
+                                    .unwrap();
+                            } else if let serde_json::Value::String(rom) = &obj["rom"] {
+                                match library.remove(rom) {
+                                    Ok(()) => resp.send_ok().unwrap(),
+                                    Err(error) => {
+                                        resp.send_error(400, &format!("{}", error)).unwrap()
+                                    }
+                                }
+                            } else {
+                                resp.send_error(400, "Message needs string in rom key, ignored.")
+                                    .unwrap();
+                            }
+                        }
                         "emulator start" => {
                             if let serde_json::Value::String(rom) = &obj["rom"] {
                                 self.channel
@@ -215,6 +651,36 @@ impl Handler for Connection {
                                 .unwrap();
                             }
                         }
+                        "emulator record start" => {
+                            self.channel
+                                .send((Command::EmulatorRecordStart, Some(resp)))
+                                .unwrap();
+                        }
+                        "emulator record stop" => {
+                            if let serde_json::Value::String(name) = &obj["name"] {
+                                self.channel
+                                    .send((Command::EmulatorRecordStop(name.clone()), Some(resp)))
+                                    .unwrap();
+                            } else {
+                                resp.send_error(400, "Message needs string in name key, ignored.")
+                                    .unwrap();
+                            }
+                        }
+                        "emulator replay" => {
+                            if let serde_json::Value::String(name) = &obj["name"] {
+                                self.channel
+                                    .send((Command::EmulatorReplay(name.clone()), Some(resp)))
+                                    .unwrap();
+                            } else {
+                                resp.send_error(400, "Message needs string in name key, ignored.")
+                                    .unwrap();
+                            }
+                        }
+                        "emulator recordings" => {
+                            self.channel
+                                .send((Command::ListRecordings, Some(resp)))
+                                .unwrap();
+                        }
                         _ => resp.send_error(404, "Unknown command").unwrap(),
                     }
                 } else {
@@ -306,11 +772,135 @@ impl ResponseHandler {
         )
     }
 
-    pub fn send_emulator_list(&self, path: impl AsRef<Path>) -> Result<()> {
+    pub fn send_emulator_list(&self, library: &RomLibrary) -> Result<()> {
+        self.out.send(
+            json!({
+                "req": self.req,
+                "roms": library.list()?,
+                "status": "ok"
+            })
+            .to_string(),
+        )
+    }
+
+    pub fn send_rom_info(&self, info: &RomInfo) -> Result<()> {
+        info!("[{}] Sending ROM info", self.address);
+        self.out.send(
+            json!({
+                "req": self.req,
+                "rom": info,
+                "status": "ok"
+            })
+            .to_string(),
+        )
+    }
+
+    pub fn send_screenshot(&self, png: &[u8]) -> Result<()> {
+        info!("[{}] Sending screenshot", self.address);
+        self.out.send(
+            json!({
+                "req": self.req,
+                "screenshot": base64::encode(png),
+                "status": "ok"
+            })
+            .to_string(),
+        )
+    }
+
+    pub fn send_recording_list(&self, recordings: Vec<String>) -> Result<()> {
+        info!("[{}] Sending recording list", self.address);
+        self.out.send(
+            json!({
+                "req": self.req,
+                "recordings": recordings,
+                "status": "ok"
+            })
+            .to_string(),
+        )
+    }
+
+    pub fn send_video_status(&self, status: &crate::video::Status) -> Result<()> {
+        info!("[{}] Sending video status", self.address);
+        self.out.send(
+            json!({
+                "req": self.req,
+                "video": status,
+                "status": "ok"
+            })
+            .to_string(),
+        )
+    }
+
+    pub fn send_media_list(&self, media: Vec<String>) -> Result<()> {
+        info!("[{}] Sending media list", self.address);
+        self.out.send(
+            json!({
+                "req": self.req,
+                "media": media,
+                "status": "ok"
+            })
+            .to_string(),
+        )
+    }
+
+    pub fn send_video_queue(&self, queue: &crate::video::Queue) -> Result<()> {
+        info!("[{}] Sending video queue", self.address);
+        self.out.send(
+            json!({
+                "req": self.req,
+                "items": queue.items(),
+                "current": queue.current(),
+                "status": "ok"
+            })
+            .to_string(),
+        )
+    }
+
+    pub fn send_board(&self, messages: &[crate::board::Message]) -> Result<()> {
+        info!("[{}] Sending board messages", self.address);
+        self.out.send(
+            json!({
+                "req": self.req,
+                "messages": messages,
+                "status": "ok"
+            })
+            .to_string(),
+        )
+    }
+
+    pub fn send_canvas(&self, canvas: &crate::canvas::Canvas) -> Result<()> {
+        info!("[{}] Sending canvas", self.address);
+        self.out.send(
+            json!({
+                "req": self.req,
+                "canvas": canvas.snapshot(),
+                "palette": canvas.palette(),
+                "cooldown": canvas.cooldown().as_secs(),
+                "status": "ok"
+            })
+            .to_string(),
+        )
+    }
+
+    pub fn send_apps(&self, apps: &[String]) -> Result<()> {
+        info!("[{}] Sending apps", self.address);
+        self.out.send(
+            json!({
+                "req": self.req,
+                "apps": apps,
+                "status": "ok"
+            })
+            .to_string(),
+        )
+    }
+
+    pub fn send_audio_devices(&self, inputs: &[Device], outputs: &[Device]) -> Result<()> {
+        info!("[{}] Sending audio devices", self.address);
         self.out.send(
             json!({
                 "req": self.req,
-                "roms": crate::emulator::Emulator::available_roms(path)?,
+                "inputs": inputs,
+                "outputs": outputs,
                 "status": "ok"
             })
             .to_string(),
@@ -1,6 +1,13 @@
 pub mod connection;
 use self::connection::Connection;
-use log::info;
+use log::{error, info};
+use serde_json::json;
+
+use crate::{
+    capture::RecordingFormat,
+    compositor::LayerSpec,
+    config::{PoetryStyle, TransitionEffect},
+};
 use std::{
     sync::mpsc::{channel, Receiver, Sender},
     thread,
@@ -22,12 +29,84 @@ pub enum Command {
     ActivateShader(String),
     PlayVideo(String),
     TurnOff,
-    ShowPoetry(String),
-    StartTox,
+    ShowPoetry(String, Option<PoetryStyle>),
+    StartApp(String),
+    ShowVnc(Option<String>),
+    ShaderPointer(f32, f32, bool),
+    ShaderKey(u8, bool),
+    VncPointer(f32, f32, u8),
+    VncKey(String, bool),
+    AppList,
+    AudioDevices,
     ToxMessage(String),
     StartEmulator(String),
     EmulatorInput(String, bool),
+    EmulatorRecordStart,
+    EmulatorRecordStop(String),
+    EmulatorReplay(String),
+    ListRecordings,
     SetVolume(u8),
+    AdjustVolume(i16),
+    SetMute(bool),
+    Duck(bool),
+    Next,
+    VideoPause,
+    VideoResume,
+    VideoSeek(f64, bool),
+    VideoNext,
+    VideoPrevious,
+    VideoStatus,
+    VideoQueueAdd(String),
+    VideoQueueRemove(usize),
+    VideoQueueClear,
+    VideoQueueList,
+    ListMedia,
+    VideoEffect(Option<String>),
+    ShowPoetryOverlay(String, Option<PoetryStyle>),
+    ShowScene(Vec<LayerSpec>),
+    SceneOpacity(usize, f32),
+    SetTransition(Option<TransitionEffect>, Option<u64>),
+    SetBrightness(f32),
+    Screenshot,
+    CaptureRecordStart(String, RecordingFormat, Option<u64>),
+    CaptureRecordStop,
+    ShowBoard,
+    BoardList,
+    BoardAdd(String, bool, Option<u64>),
+    BoardRemove(u64),
+    BoardPin(u64, bool),
+    ShowAmbient,
+    ShowCanvas,
+    CanvasGet,
+    /// x, y, palette index and who paints
+    CanvasPaint(u32, u32, u8, String),
+    CanvasTimelapse,
+}
+
+/// Sends unsolicited events to all connected websocket clients.
+#[derive(Clone)]
+pub struct Broadcaster {
+    out: ws::Sender,
+}
+
+impl Broadcaster {
+    fn broadcast(&self, event: &str, data: serde_json::Value) {
+        let mut message = json!({ "event": event });
+        if let (Some(message), serde_json::Value::Object(data)) = (message.as_object_mut(), data) {
+            message.extend(data);
+        }
+        if let Err(err) = self.out.broadcast(message.to_string()) {
+            error!("Failed broadcasting {}: {}", event, err);
+        }
+    }
+
+    pub fn send_video_status(&self, status: &crate::video::Status) {
+        self.broadcast("video status", json!({ "video": status }));
+    }
+
+    pub fn send_canvas_pixel(&self, x: u32, y: u32, color: u8) {
+        self.broadcast("canvas pixel", json!({ "x": x, "y": y, "color": color }));
+    }
 }
 
 pub fn open_server(
@@ -37,20 +116,25 @@ pub fn open_server(
     thread::JoinHandle<ws::Result<()>>,
     Receiver<(Command, Option<connection::ResponseHandler>)>,
     Sender<(Command, Option<connection::ResponseHandler>)>,
+    Broadcaster,
 ) {
     let addr = format!("{}:{}", ip, port);
     info!("Listening on {}...", addr);
     let (tx, rx) = channel();
     let tx_2 = tx.clone();
-    (
-        thread::Builder::new()
-            .name("Websocket Server".to_string())
-            .spawn(move || {
-                let result = ws::listen(addr.as_str(), |out| Connection::new(out, tx.clone()));
-                result
-            })
-            .unwrap(),
-        rx,
-        tx_2,
-    )
+    let (broadcaster_tx, broadcaster_rx) = channel();
+    let thread = thread::Builder::new()
+        .name("Websocket Server".to_string())
+        .spawn(move || {
+            let socket = ws::WebSocket::new(|out| Connection::new(out, tx.clone()))?;
+            broadcaster_tx.send(socket.broadcaster()).ok();
+            socket.listen(addr.as_str()).map(|_| ())
+        })
+        .unwrap();
+    let broadcaster = Broadcaster {
+        out: broadcaster_rx
+            .recv()
+            .expect("Failed to start websocket server"),
+    };
+    (thread, rx, tx_2, broadcaster)
 }
@@ -0,0 +1,281 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// Upper edges of the bass and mid bands in Hz, treble is everything above.
+const BAND_EDGES: [f32; 2] = [250.0, 4000.0];
+/// Seconds of spectral flux the onset threshold is based on
+const FLUX_HISTORY: f32 = 1.5;
+/// How far above the average flux an onset has to be, in standard deviations
+const ONSET_SENSITIVITY: f32 = 1.5;
+/// Flux below this is silence or noise
+const MIN_FLUX: f32 = 0.01;
+/// Shortest time between beats in seconds, 240 BPM
+const MIN_BEAT_INTERVAL: f32 = 0.25;
+/// Beat intervals the tempo is estimated from
+const BEAT_HISTORY: usize = 16;
+/// Seconds without a beat after which the tempo is forgotten
+const TEMPO_TIMEOUT: f32 = 8.0;
+/// Tempo range estimates are folded into by doubling or halving
+const BPM_RANGE: (f32, f32) = (70.0, 180.0);
+/// How fast `Levels::beat` decays after a beat, per second
+const BEAT_DECAY: f32 = 8.0;
+/// Share of the previous value kept when the level falls
+const RELEASE: f32 = 0.85;
+
+/// What the music is doing right now, for shaders and anything else moving with it.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Levels {
+    /// RMS of the samples
+    pub level: f32,
+    /// Average bass, mid and treble of the spectrum, from 0 to 1
+    pub bands: [f32; 3],
+    /// 1 on a beat, decaying towards 0 until the next one
+    pub beat: f32,
+    /// Estimated tempo, 0 until there were enough beats
+    pub bpm: f32,
+}
+
+/// Follows levels, bands and beats of consecutive buffers of audio.
+pub struct Analysis {
+    sample_rate: f32,
+    /// Spectrum bins where the mid and treble bands start
+    band_bins: [usize; 2],
+    /// Seconds of audio processed
+    time: f32,
+    previous: Vec<f32>,
+    flux: VecDeque<f32>,
+    /// Audio time of the last beat
+    last_beat: Option<f32>,
+    /// When the last beat was detected, for the decay between buffers
+    last_beat_at: Option<Instant>,
+    intervals: VecDeque<f32>,
+    levels: Levels,
+}
+
+impl Analysis {
+    /// For spectra of `fft_size` samples at `sample_rate`.
+    pub fn new(sample_rate: u32, fft_size: usize) -> Self {
+        let bin_width = sample_rate as f32 / fft_size as f32;
+        let bin = |frequency: f32| ((frequency / bin_width).round() as usize).max(1);
+        Self {
+            sample_rate: sample_rate as f32,
+            band_bins: [bin(BAND_EDGES[0]), bin(BAND_EDGES[1])],
+            time: 0.0,
+            previous: Vec::new(),
+            flux: VecDeque::new(),
+            last_beat: None,
+            last_beat_at: None,
+            intervals: VecDeque::new(),
+            levels: Levels::default(),
+        }
+    }
+
+    /// Takes the next buffer of `samples` and their `spectrum` as produced by `AudioFFT`.
+    pub fn process(&mut self, samples: &[f32], spectrum: &[f32]) {
+        let duration = samples.len() as f32 / self.sample_rate;
+        self.time += duration;
+
+        let rms = (samples.iter().map(|sample| sample * sample).sum::<f32>()
+            / samples.len().max(1) as f32)
+            .sqrt();
+        self.levels.level = smooth(self.levels.level, rms);
+
+        // the DC bin says nothing about the music
+        let [mid, treble] = self.band_bins;
+        let ranges = [(1, mid), (mid, treble), (treble, spectrum.len())];
+        for (band, (start, end)) in self.levels.bands.iter_mut().zip(ranges.iter()) {
+            let end = (*end).min(spectrum.len());
+            let value = if *start < end {
+                spectrum[*start..end].iter().sum::<f32>() / (end - start) as f32
+            } else {
+                0.0
+            };
+            *band = smooth(*band, value);
+        }
+
+        // onsets show as a sudden rise across the spectrum
+        let flux = if self.previous.len() == spectrum.len() {
+            spectrum
+                .iter()
+                .zip(&self.previous)
+                .map(|(current, previous)| (current - previous).max(0.0))
+                .sum::<f32>()
+                / spectrum.len().max(1) as f32
+        } else {
+            0.0
+        };
+        self.previous.clear();
+        self.previous.extend_from_slice(spectrum);
+
+        let history = (FLUX_HISTORY / duration).ceil().max(1.0) as usize;
+        let count = self.flux.len().max(1) as f32;
+        let mean = self.flux.iter().sum::<f32>() / count;
+        let variance = self
+            .flux
+            .iter()
+            .map(|f| (f - mean) * (f - mean))
+            .sum::<f32>()
+            / count;
+        let onset = self.flux.len() >= history / 2
+            && flux > MIN_FLUX
+            && flux > mean + ONSET_SENSITIVITY * variance.sqrt();
+        self.flux.push_back(flux);
+        while self.flux.len() > history {
+            self.flux.pop_front();
+        }
+
+        let since_beat = self.last_beat.map(|last| self.time - last);
+        if onset && since_beat.map_or(true, |since| since >= MIN_BEAT_INTERVAL) {
+            if let Some(interval) = since_beat {
+                if interval < 60.0 / BPM_RANGE.0 * 2.0 {
+                    self.intervals.push_back(interval);
+                    if self.intervals.len() > BEAT_HISTORY {
+                        self.intervals.pop_front();
+                    }
+                }
+            }
+            self.last_beat = Some(self.time);
+            self.last_beat_at = Some(Instant::now());
+            self.levels.bpm = self.tempo();
+        } else if since_beat.map_or(false, |since| since > TEMPO_TIMEOUT) {
+            self.intervals.clear();
+            self.levels.bpm = 0.0;
+        }
+    }
+
+    /// Median of the beat intervals as beats per minute.
+    fn tempo(&self) -> f32 {
+        if self.intervals.len() < 4 {
+            return 0.0;
+        }
+        let mut intervals: Vec<f32> = self.intervals.iter().copied().collect();
+        intervals.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mut bpm = 60.0 / intervals[intervals.len() / 2];
+        while bpm < BPM_RANGE.0 {
+            bpm *= 2.0;
+        }
+        while bpm > BPM_RANGE.1 {
+            bpm /= 2.0;
+        }
+        bpm
+    }
+
+    pub fn levels(&self) -> Levels {
+        Levels {
+            beat: self.last_beat_at.map_or(0.0, |last| {
+                (-last.elapsed().as_secs_f32() * BEAT_DECAY).exp()
+            }),
+            ..self.levels
+        }
+    }
+}
+
+/// Follows rises immediately and falls off slowly.
+fn smooth(previous: f32, value: f32) -> f32 {
+    if value > previous {
+        value
+    } else {
+        previous * RELEASE + value * (1.0 - RELEASE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+    const BUFFER: usize = 500;
+
+    /// Feeds `seconds` of clicks at `bpm`, with a quiet noise floor, and returns the analysis
+    /// and the number of buffers a beat was detected in that didn't start with a click.
+    fn click_track(bpm: f32, seconds: f32) -> (Analysis, usize) {
+        let mut analysis = Analysis::new(SAMPLE_RATE, BUFFER * 2);
+        let beat_samples = (SAMPLE_RATE as f32 * 60.0 / bpm) as usize;
+        let mut seed = 1u32;
+        let mut noise = move || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (seed >> 8) as f32 / (1 << 24) as f32 * 0.01
+        };
+        let mut wrong = 0;
+        let buffers = (seconds * SAMPLE_RATE as f32) as usize / BUFFER;
+        for buffer in 0..buffers {
+            let start = buffer * BUFFER;
+            // a click decaying over a few buffers, every beat
+            let since_click = (start % beat_samples) as f32 / BUFFER as f32;
+            let click = 0.5 * (-since_click).exp();
+            let samples: Vec<f32> = (0..BUFFER)
+                .map(|index| if index % 2 == 0 { click } else { -click } + noise())
+                .collect();
+            // clicks are broadband, so every bin rises with them
+            let spectrum: Vec<f32> = (0..BUFFER).map(|_| click + noise()).collect();
+            let beats = analysis.last_beat;
+            analysis.process(&samples, &spectrum);
+            if analysis.last_beat != beats && start % beat_samples != 0 {
+                wrong += 1;
+            }
+        }
+        (analysis, wrong)
+    }
+
+    #[test]
+    fn finds_the_tempo_of_a_click_track() {
+        for &bpm in &[120.0, 90.0] {
+            let (analysis, wrong) = click_track(bpm, 10.0);
+            assert_eq!(wrong, 0, "beats off the clicks at {} BPM", bpm);
+            let levels = analysis.levels();
+            assert!(
+                (levels.bpm - bpm).abs() < 0.5,
+                "{} BPM estimated as {}",
+                bpm,
+                levels.bpm
+            );
+            // the last beat was moments ago
+            assert!(levels.beat > 0.5, "beat {}", levels.beat);
+        }
+    }
+
+    #[test]
+    fn slow_tempos_are_doubled() {
+        let (analysis, _) = click_track(40.0, 12.0);
+        assert!((analysis.levels().bpm - 80.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn no_beats_without_onsets() {
+        let mut analysis = Analysis::new(SAMPLE_RATE, BUFFER * 2);
+        let silence = vec![0.0; BUFFER];
+        for _ in 0..SAMPLE_RATE as usize / BUFFER * 4 {
+            analysis.process(&silence, &silence);
+        }
+        let levels = analysis.levels();
+        assert_eq!(levels.beat, 0.0);
+        assert_eq!(levels.bpm, 0.0);
+        assert_eq!(levels.level, 0.0);
+    }
+
+    #[test]
+    fn tempo_is_forgotten_after_a_pause() {
+        let (mut analysis, _) = click_track(120.0, 10.0);
+        let silence = vec![0.0; BUFFER];
+        let buffers = (TEMPO_TIMEOUT + 1.0) * SAMPLE_RATE as f32 / BUFFER as f32;
+        for _ in 0..buffers as usize {
+            analysis.process(&silence, &silence);
+        }
+        assert_eq!(analysis.levels().bpm, 0.0);
+    }
+
+    #[test]
+    fn bands_follow_the_spectrum() {
+        // 48 Hz bins, so bass is bins 1 to 4, mid up to 82
+        let mut analysis = Analysis::new(SAMPLE_RATE, 1000);
+        let mut spectrum = vec![0.0; 500];
+        spectrum[2] = 0.8;
+        spectrum[..5].iter_mut().for_each(|bin| *bin += 0.2);
+        analysis.process(&[0.5; 1000], &spectrum);
+        let levels = analysis.levels();
+        assert!((levels.level - 0.5).abs() < 1e-6);
+        assert!((levels.bands[0] - 0.4).abs() < 1e-6, "{:?}", levels.bands);
+        assert_eq!(levels.bands[1], 0.0);
+        assert_eq!(levels.bands[2], 0.0);
+    }
+}
@@ -7,9 +7,13 @@ use glium::{implement_vertex, program, uniform, Surface};
 use std::borrow::Cow;
 use std::time::Instant;
 
-mod audio;
+use crate::audio;
+
+mod analysis;
 mod audio_fft;
 
+pub use self::analysis::Levels;
+
 const VERTEX_SHADER: &str = "#version 140
 
 in vec2 position;
@@ -34,7 +38,13 @@ uniform vec3 iResolution;
 uniform vec4 iMouse;
 uniform vec4 iDate;
 uniform int iFrame;
+uniform float iLevel;
+uniform vec3 iBands;
+uniform float iBeat;
+uniform float iBPM;
 uniform sampler2D iChannel0;
+uniform sampler2D iChannel1;
+uniform sampler2D iChannel2;
 
 void mainImage(out vec4, in vec2);
 
@@ -50,10 +60,122 @@ struct Vertex {
     texcoords: [f32; 2],
 }
 
+/// Shadertoy's keyboard texture, indexed by JavaScript key code: whether a key is down, whether
+/// it was pressed this frame and whether it was toggled by an odd number of presses.
+const KEYBOARD_SIZE: (u32, u32) = (256, 3);
+
+/// Pointer state as Shadertoy passes it in `iMouse`.
+#[derive(Default)]
+struct Mouse {
+    uniform: [f32; 4],
+    down: bool,
+}
+
+impl Mouse {
+    /// Like on Shadertoy, `iMouse.xy` follows the pointer while the button is held and
+    /// `iMouse.zw` is where it was pressed, negated once released.
+    fn set(&mut self, x: f32, y: f32, down: bool) {
+        if down {
+            self.uniform[0] = x;
+            self.uniform[1] = y;
+            if !self.down {
+                self.uniform[2] = x;
+                self.uniform[3] = y;
+            }
+        } else if self.down {
+            self.uniform[2] = -self.uniform[2].abs();
+            self.uniform[3] = -self.uniform[3].abs();
+        }
+        self.down = down;
+    }
+}
+
+/// Contents of the keyboard texture.
+struct Keys {
+    data: Vec<u8>,
+    /// Whether the texture is out of date
+    changed: bool,
+}
+
+impl Keys {
+    fn new() -> Self {
+        Self {
+            data: vec![0; (KEYBOARD_SIZE.0 * KEYBOARD_SIZE.1) as usize],
+            // the texture starts out undefined
+            changed: true,
+        }
+    }
+
+    fn set(&mut self, code: u8, down: bool) {
+        let (code, row) = (code as usize, KEYBOARD_SIZE.0 as usize);
+        let was_down = self.data[code] != 0;
+        if down && !was_down {
+            self.data[row + code] = 255;
+            self.data[row * 2 + code] ^= 255;
+        }
+        self.data[code] = if down { 255 } else { 0 };
+        self.changed = true;
+    }
+
+    /// Clears the presses once a frame has shown them, as they only last a frame.
+    fn next_frame(&mut self) {
+        let row = KEYBOARD_SIZE.0 as usize;
+        let presses = &mut self.data[row..row * 2];
+        self.changed |= presses.iter().any(|&key| key != 0);
+        for key in presses {
+            *key = 0;
+        }
+    }
+}
+
 struct Audio {
-    input: audio::AudioInput,
+    /// Silence if the source couldn't be opened
+    input: Option<audio::Listener>,
     texture: Texture2d,
     fft: audio_fft::AudioFFT,
+    analysis: analysis::Analysis,
+    /// Share of the previous spectrum kept in `spectrum`
+    smoothing: f32,
+    spectrum: Vec<f32>,
+}
+
+/// Follows the levels of the audio source for states without a shader listening to it.
+pub struct Meter {
+    input: audio::Listener,
+    fft: audio_fft::AudioFFT,
+    analysis: analysis::Analysis,
+}
+
+impl Meter {
+    /// `None` if the source can't be opened.
+    pub fn new(audio: &mut audio::Audio) -> Option<Meter> {
+        let fft_size = audio.fft_size();
+        let input = audio.listen()?;
+        Some(Meter {
+            input,
+            fft: audio_fft::AudioFFT::new(fft_size),
+            analysis: analysis::Analysis::new(audio.config().sample_rate, fft_size),
+        })
+    }
+
+    /// Takes the audio that arrived since the last call, returns the current levels.
+    pub fn levels(&mut self) -> Levels {
+        while let Some(buffer) = self.input.next_buffer() {
+            let spectrum = self.fft.process(&buffer);
+            self.analysis.process(&buffer, &spectrum);
+        }
+        self.analysis.levels()
+    }
+}
+
+/// Averages or repeats `input` to fill `output`.
+fn resample(input: &[f32], output: &mut [f32]) {
+    let size = output.len();
+    for (index, value) in output.iter_mut().enumerate() {
+        let start = index * input.len() / size;
+        let end = ((index + 1) * input.len() / size).max(start + 1);
+        *value = input[start..end].iter().sum::<f32>() / (end - start) as f32;
+    }
 }
 
 pub struct ShaderToy {
@@ -63,6 +185,12 @@ pub struct ShaderToy {
     index_buffer: glium::IndexBuffer<u16>,
     program: glium::Program,
     audio: Option<Audio>,
+    /// Bound to channels without input
+    empty: Texture2d,
+    mouse: Mouse,
+    keys: Keys,
+    /// Bound to `iChannel2`
+    keyboard: Texture2d,
 }
 
 impl ShaderToy {
@@ -110,16 +238,34 @@ impl ShaderToy {
             index_buffer,
             program,
             audio,
+            empty: Texture2d::new(display, vec![vec![(0u8, 0u8, 0u8, 0u8)]]).unwrap(),
+            mouse: Mouse::default(),
+            keys: Keys::new(),
+            keyboard: Texture2d::empty_with_format(
+                display,
+                UncompressedFloatFormat::U8,
+                MipmapsOption::NoMipmap,
+                KEYBOARD_SIZE.0,
+                KEYBOARD_SIZE.1,
+            )
+            .unwrap(),
         }
     }
 
-    // pub fn new(display: &Display, shader: &str) -> ShaderToy {
-    //     Self::new_internal(display, shader, None)
-    // }
+    pub fn new(display: &Display, shader: &str) -> ShaderToy {
+        Self::new_internal(display, shader, None)
+    }
 
-    pub fn new_with_audio(display: &Display, shader: &str) -> ShaderToy {
-        let mut input = audio::AudioInput::new();
-        let data = [0.0; 1024];
+    /// Listens to the configured audio source, or to silence if it can't be opened.
+    pub fn new_with_audio(display: &Display, shader: &str, audio: &mut audio::Audio) -> ShaderToy {
+        let fft_size = audio.fft_size();
+        let input = audio.listen();
+        let config = audio.config();
+        // silence, a flat spectrum and a waveform in the middle
+        let mut data = [0.5; 1024];
+        for texel in &mut data[..512] {
+            *texel = 0.0;
+        }
         let rawimage = RawImage2d {
             data: Cow::from(&data[..]),
             width: 512,
@@ -134,45 +280,84 @@ impl ShaderToy {
         )
         .unwrap();
 
-        input.start().ok();
         Self::new_internal(
             display,
             shader,
             Some(Audio {
                 input,
                 texture,
-                fft: audio_fft::AudioFFT::new(1024),
+                fft: audio_fft::AudioFFT::new(fft_size),
+                analysis: analysis::Analysis::new(config.sample_rate, fft_size),
+                smoothing: config.smoothing.clamp(0.0, 1.0),
+                spectrum: vec![0.0; fft_size / 2],
             }),
         )
     }
 
-    pub fn step(&mut self, display: &Display) {
+    /// Moves the pointer to `x`, `y` in pixels from the bottom left, with the button held if
+    /// `down`.
+    pub fn set_mouse(&mut self, x: f32, y: f32, down: bool) {
+        self.mouse.set(x, y, down);
+    }
+
+    /// Presses or releases the key with JavaScript key code `code`.
+    pub fn set_key(&mut self, code: u8, down: bool) {
+        self.keys.set(code, down);
+    }
+
+    fn upload_keys(&mut self) {
+        let rawimage = RawImage2d {
+            data: Cow::from(&self.keys.data[..]),
+            width: KEYBOARD_SIZE.0,
+            height: KEYBOARD_SIZE.1,
+            format: ClientFormat::U8,
+        };
+        self.keyboard.write(
+            glium::Rect {
+                left: 0,
+                bottom: 0,
+                width: KEYBOARD_SIZE.0,
+                height: KEYBOARD_SIZE.1,
+            },
+            rawimage,
+        );
+    }
+
+    /// Level, bands and beat of the audio input, if the shader has one.
+    pub fn levels(&self) -> Option<Levels> {
+        self.audio.as_ref().map(|audio| audio.analysis.levels())
+    }
+
+    /// Renders a frame into `target`, with `channel1` as `iChannel1` input (like a video).
+    pub fn draw<S: Surface>(
+        &mut self,
+        target: &mut S,
+        size: (u32, u32),
+        channel1: Option<&Texture2d>,
+    ) {
         let elapsed = self.startup_time.elapsed();
         let utc: DateTime<Utc> = Utc::now();
-        let size = display.gl_window().window().inner_size();
         let time = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 / 1.0e9;
-        let uniforms = uniform! {
-            iGlobalTime: time,
-            iTime: time,
-            iResolution: [size.width as f32, size.height as f32, 1.0],
-            iMouse: [0.0_f32, 0.0, 0.0, 0.0],
-            iDate: [utc.year() as f32, utc.month0() as f32, utc.day0() as f32, utc.num_seconds_from_midnight() as f32 + utc.nanosecond() as f32 / 1.0e9],
-            iFrame: self.frame,
-        };
-        let mut target = display.draw();
-        target.clear_color_and_depth((0.0, 0.0, 0.0, 0.0), 1.0);
 
         if let Some(ref mut audio) = self.audio {
-            if let Some(buffer) = audio.input.poll() {
-                let mut texels = [0.; 1024];
-                // time domain
-                for index in 0..512 {
-                    texels[index + 512] = buffer[index * 2] * 0.5 + 0.5;
+            let mut latest = None;
+            if let Some(input) = &audio.input {
+                while let Some(buffer) = input.next_buffer() {
+                    let fft = audio.fft.process(&buffer);
+                    audio.analysis.process(&buffer, &fft);
+                    for (smoothed, value) in audio.spectrum.iter_mut().zip(&fft) {
+                        *smoothed = *smoothed * audio.smoothing + value * (1.0 - audio.smoothing);
+                    }
+                    latest = Some(buffer);
                 }
+            }
+            if let Some(buffer) = latest {
+                let mut texels = [0.; 1024];
                 // frequency domain
-                let fft = audio.fft.process(&buffer);
-                for (index, sample) in fft.iter().enumerate() {
-                    texels[index] = *sample;
+                resample(&audio.spectrum, &mut texels[..512]);
+                // time domain
+                for (index, texel) in texels[512..].iter_mut().enumerate() {
+                    *texel = buffer[index * buffer.len() / 512] * 0.5 + 0.5;
                 }
                 let rawimage = RawImage2d {
                     data: Cow::from(&texels[..]),
@@ -190,29 +375,115 @@ impl ShaderToy {
                     rawimage,
                 );
             }
-            let uniforms = uniforms.add("iChannel0", &audio.texture);
-            target
-                .draw(
-                    &self.vertex_buffer,
-                    &self.index_buffer,
-                    &self.program,
-                    &uniforms,
-                    &Default::default(),
-                )
-                .unwrap();
-        } else {
-            target
-                .draw(
-                    &self.vertex_buffer,
-                    &self.index_buffer,
-                    &self.program,
-                    &uniforms,
-                    &Default::default(),
-                )
-                .unwrap();
         }
+        let levels = self.levels().unwrap_or_default();
+
+        if self.keys.changed {
+            self.upload_keys();
+            self.keys.changed = false;
+        }
+
+        let uniforms = uniform! {
+            iGlobalTime: time,
+            iTime: time,
+            iResolution: [size.0 as f32, size.1 as f32, 1.0],
+            iMouse: self.mouse.uniform,
+            iDate: [utc.year() as f32, utc.month0() as f32, utc.day0() as f32, utc.num_seconds_from_midnight() as f32 + utc.nanosecond() as f32 / 1.0e9],
+            iFrame: self.frame,
+            iLevel: levels.level,
+            iBands: levels.bands,
+            iBeat: levels.beat,
+            iBPM: levels.bpm,
+            iChannel0: self.audio.as_ref().map_or(&self.empty, |audio| &audio.texture),
+            iChannel1: channel1.unwrap_or(&self.empty),
+            iChannel2: &self.keyboard,
+        };
+        target
+            .draw(
+                &self.vertex_buffer,
+                &self.index_buffer,
+                &self.program,
+                &uniforms,
+                &Default::default(),
+            )
+            .unwrap();
 
-        target.finish().unwrap();
         self.frame += 1;
+        self.keys.next_frame();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mouse_follows_shadertoy() {
+        let mut mouse = Mouse::default();
+        mouse.set(10.0, 20.0, false);
+        assert_eq!(mouse.uniform, [0.0; 4]);
+        mouse.set(10.0, 20.0, true);
+        assert_eq!(mouse.uniform, [10.0, 20.0, 10.0, 20.0]);
+        mouse.set(30.0, 5.0, true);
+        assert_eq!(mouse.uniform, [30.0, 5.0, 10.0, 20.0]);
+        mouse.set(40.0, 0.0, false);
+        assert_eq!(mouse.uniform, [30.0, 5.0, -10.0, -20.0]);
+        mouse.set(50.0, 50.0, false);
+        assert_eq!(mouse.uniform, [30.0, 5.0, -10.0, -20.0]);
+        mouse.set(1.0, 2.0, true);
+        assert_eq!(mouse.uniform, [1.0, 2.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn keys_are_down_pressed_and_toggled() {
+        let row = KEYBOARD_SIZE.0 as usize;
+        let key = |keys: &Keys, code: usize| {
+            [
+                keys.data[code],
+                keys.data[row + code],
+                keys.data[row * 2 + code],
+            ]
+        };
+        let mut keys = Keys::new();
+        keys.changed = false;
+
+        keys.set(32, true);
+        assert!(keys.changed);
+        assert_eq!(key(&keys, 32), [255, 255, 255]);
+        keys.changed = false;
+        keys.next_frame();
+        // the cleared press still has to be uploaded
+        assert!(keys.changed);
+        assert_eq!(key(&keys, 32), [255, 0, 255]);
+
+        // repeats while held are no new presses
+        keys.set(32, true);
+        assert_eq!(key(&keys, 32), [255, 0, 255]);
+        keys.set(32, false);
+        assert_eq!(key(&keys, 32), [0, 0, 255]);
+        keys.set(32, true);
+        assert_eq!(key(&keys, 32), [255, 255, 0]);
+
+        keys.changed = false;
+        keys.next_frame();
+        keys.changed = false;
+        keys.next_frame();
+        assert!(!keys.changed);
+        assert_eq!(key(&keys, 33), [0, 0, 0]);
+    }
+
+    #[test]
+    fn spectra_are_resampled() {
+        let mut output = [0.0; 2];
+        resample(&[1.0, 3.0, 5.0, 7.0], &mut output);
+        assert_eq!(output, [2.0, 6.0]);
+
+        let mut output = [0.0; 4];
+        resample(&[1.0, 2.0], &mut output);
+        assert_eq!(output, [1.0, 1.0, 2.0, 2.0]);
+
+        let mut output = [0.0; 3];
+        resample(&[1.0, 2.0, 3.0], &mut output);
+        assert_eq!(output, [1.0, 2.0, 3.0]);
     }
 }
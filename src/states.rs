@@ -1,21 +1,38 @@
 #![allow(clippy::wrong_self_convention)]
-use glium::backend::glutin::Display;
-use libmpv::events::{Event, PropertyData};
+use glium::{backend::glutin::Display, texture::texture2d::Texture2d, Surface};
 use log::{error, info};
+use rand::seq::SliceRandom;
 use std::{
-    process::Command,
+    io,
+    path::{Path, PathBuf},
+    rc::Rc,
     time::{Duration, Instant},
 };
 use tokio::sync::mpsc::UnboundedSender;
 
 use crate::{
-    config::Config,
-    emulator::Emulator,
+    ambient::Ambient,
+    app::App,
+    audio::{self, Audio},
+    blit::Blit,
+    board::Board,
+    canvas::{self, Canvas, PaintError},
+    capture::{Capture, Frame, RecordingFormat},
+    compositor::{LayerSpec, Scene, SceneError},
+    config::{Config, PoetryStyle, TransitionEffect},
+    emulator::{recording::Recording, Emulator},
     frontpanel::{Led, LedControl},
     mqtt,
-    poetry::Poetry,
-    shadertoy::ShaderToy,
-    video::Video,
+    output::Output,
+    pixels::PixelOutputs,
+    poetry::{Fonts, Poetry, Ticker},
+    server::Broadcaster,
+    shadertoy::{Levels, Meter, ShaderToy},
+    transition::Transitions,
+    video::{
+        list_media, Queue, Source, SourceError, Status as VideoStatus, Update as VideoUpdate, Video,
+    },
+    vnc,
 };
 
 #[allow(clippy::large_enum_variant, unused)]
@@ -23,30 +40,173 @@ pub enum State {
     Off,
     ShaderToy {
         shader_toy: ShaderToy,
+        id: String,
     },
     Video {
         video: Video,
+        /// Post-processing shader getting the video as `iChannel1`
+        effect: Option<ShaderToy>,
+        overlay: Option<Poetry>,
     },
     Emulator {
         emulator: Emulator,
         last_frame: Instant,
     },
-    Vnc,
+    Vnc {
+        client: vnc::Client,
+    },
     Poetry {
         poetry: Poetry,
     },
-    Tox,
+    Scene {
+        scene: Scene,
+    },
+    Board {
+        ticker: Ticker,
+    },
+    Ambient {
+        ambient: Ambient,
+    },
+    Canvas {
+        view: canvas::View,
+    },
+    App {
+        app: App,
+    },
     ToxMessage {
         poetry: Poetry,
     },
 }
 
+/// How long a crossfade waits for the incoming state to show something, like a video that is
+/// still loading.
+const TRANSITION_READY_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often to check on a VNC client that is still connecting
+const VNC_CONNECTING_INTERVAL: Duration = Duration::from_millis(100);
+
+impl State {
+    /// Whether the state renders to the display, the others leave it to another program.
+    fn is_drawn(&self) -> bool {
+        !matches!(self, State::Off | State::App { .. })
+    }
+
+    /// Whether the state has something to show yet.
+    fn is_ready(&self) -> bool {
+        match self {
+            // the playback position is only known once the first frame was decoded
+            State::Video { video, .. } => video.status().position.is_some(),
+            _ => true,
+        }
+    }
+
+    /// Level, bands and beat of the music the state listens to.
+    fn levels(&self) -> Option<Levels> {
+        match self {
+            State::ShaderToy { shader_toy, .. } => shader_toy.levels(),
+            State::Video {
+                effect: Some(effect),
+                ..
+            } => effect.levels(),
+            _ => None,
+        }
+    }
+
+    /// Poems the state shows.
+    fn poetry(&mut self) -> Option<&mut Poetry> {
+        match self {
+            State::Poetry { poetry } | State::ToxMessage { poetry } => Some(poetry),
+            State::Video { overlay, .. } => overlay.as_mut(),
+            _ => None,
+        }
+    }
+
+    /// Renders the next frame into `target`.
+    fn draw<S: Surface>(
+        &mut self,
+        target: &mut S,
+        size: (u32, u32),
+        blit: &Blit,
+    ) -> Option<VideoUpdate> {
+        target.clear_color_and_depth((0.0, 0.0, 0.0, 1.0), 1.0);
+        match self {
+            State::ShaderToy { shader_toy, .. } => shader_toy.draw(target, size, None),
+            State::Video {
+                video,
+                effect,
+                overlay,
+            } => {
+                let update = video.step();
+                match effect {
+                    Some(effect) => effect.draw(target, size, Some(video.frame())),
+                    None => blit.draw(target, video.frame()),
+                }
+                if let Some(overlay) = overlay {
+                    overlay.draw(target, size);
+                }
+                return update;
+            }
+            State::Emulator {
+                emulator,
+                last_frame,
+            } => {
+                *last_frame = Instant::now();
+                emulator.draw(target, size);
+            }
+            State::Poetry { poetry } | State::ToxMessage { poetry } => poetry.draw(target, size),
+            State::Scene { scene } => scene.draw(target, blit),
+            State::Board { ticker } => ticker.draw(target, size),
+            State::Ambient { ambient } => ambient.draw(target, size, blit),
+            State::Canvas { view } => view.draw(target, blit),
+            State::Vnc { client } => client.draw(target, size, blit),
+            State::Off | State::App { .. } => {}
+        }
+        None
+    }
+
+    /// Stops what keeps running in the background once the state is gone.
+    fn release(&mut self) {
+        match self {
+            State::Video { video, .. } => video.stop(),
+            State::Scene { scene } => scene.stop(),
+            _ => {}
+        }
+    }
+}
+
+/// Outgoing state that is still drawn while blending over to the current one.
+struct Crossfade {
+    outgoing: State,
+    from: Texture2d,
+    to: Texture2d,
+    created: Instant,
+    /// Set once the incoming state is ready
+    started: Option<Instant>,
+}
+
 pub struct StateMachine {
     display: Display,
     state: State,
     config: Config,
     led_control: Option<LedControl>,
     state_sender: Option<UnboundedSender<mqtt::State>>,
+    broadcaster: Broadcaster,
+    blit: Blit,
+    transitions: Transitions,
+    crossfade: Option<Crossfade>,
+    output: Output,
+    pixel_outputs: PixelOutputs,
+    capture: Capture,
+    vnc_server: vnc::Server,
+    fonts: Rc<Fonts>,
+    board: Board,
+    canvas: Canvas,
+    audio: Audio,
+    /// Levels of the music for poems, which have no shader listening to it
+    meter: Option<Meter>,
+    /// VNC client that is still connecting, shown once it's connected
+    vnc_connecting: Option<vnc::Client>,
+    video_queue: Queue,
+    last_activity: Instant,
 }
 
 impl StateMachine {
@@ -55,13 +215,46 @@ impl StateMachine {
         led_control: Option<LedControl>,
         config: Config,
         state_sender: Option<UnboundedSender<mqtt::State>>,
+        broadcaster: Broadcaster,
     ) -> Self {
+        let output = Output::new(&display, &config);
+        let vnc_server = vnc::Server::new(
+            &config.vnc_server,
+            &config.server.address,
+            output.frame().dimensions(),
+        );
         StateMachine {
             state: State::Off,
+            blit: Blit::new(&display),
+            transitions: Transitions::new(&display),
+            crossfade: None,
+            output,
+            pixel_outputs: PixelOutputs::new(&display, &config.pixel_outputs),
+            capture: Capture::new(&config.capture, &config.server.address),
+            vnc_server,
+            fonts: Rc::new(
+                Fonts::load(
+                    &config.poetry.font,
+                    &config.poetry.fallback,
+                    config.poetry.size,
+                )
+                .unwrap_or_else(|err| {
+                    error!("{}, using the built-in font", err);
+                    Fonts::built_in(config.poetry.size)
+                }),
+            ),
+            board: Board::new(&config.board),
+            canvas: Canvas::new(&config.canvas),
+            audio: Audio::new(&config.audio),
+            meter: None,
+            vnc_connecting: None,
             display,
+            video_queue: Queue::load(&config.video.queue),
             config,
             led_control,
             state_sender,
+            broadcaster,
+            last_activity: Instant::now(),
         }
     }
 
@@ -84,12 +277,12 @@ impl StateMachine {
                 //     error!("{}", err);
                 // });
             }
-            State::ShaderToy { shader_toy: _ } => {
+            State::ShaderToy { .. } => {
                 // frontpanel::write_display("Blinkenwall     ShaderToy").unwrap_or_else(|err| {
                 //     error!("{}", err);
                 // });
             }
-            State::Video { video: _ } => {
+            State::Video { .. } => {
                 // frontpanel::write_display("Blinkenwall     YouTube").unwrap_or_else(|err| {
                 //     error!("{}", err);
                 // });
@@ -99,11 +292,6 @@ impl StateMachine {
                 //     error!("{}", err);
                 // });
             }
-            State::Tox => {
-                // frontpanel::write_display("Blinkenwall     Tox").unwrap_or_else(|err| {
-                //     error!("{}", err);
-                // });
-            }
             State::ToxMessage { poetry: _ } => {
                 // frontpanel::write_display("Blinkenwall     Tox Messages").unwrap_or_else(|err| {
                 //     error!("{}", err);
@@ -126,49 +314,137 @@ impl StateMachine {
             State::ShaderToy { .. } => {
                 info!("Exit ShaderToy state");
             }
-            State::Video { ref mut video } => {
+            State::Video { .. } => {
                 info!("Exit Video state");
-                video.stop();
             }
             State::Emulator { .. } => {
                 info!("Exit Emulator state");
             }
-            State::Vnc => {
+            State::Vnc { .. } => {
                 info!("Exit VNC state");
             }
             State::Poetry { .. } => {
                 info!("Exit Poetry state");
             }
-            State::Tox => {
-                info!("Exit Tox state");
-                match next {
-                    State::ToxMessage { .. } => {}
-                    _ => {
-                        #[cfg(target_os = "linux")]
-                        Command::new("/usr/bin/sudo")
-                            .arg("-Hu")
-                            .arg("zoff")
-                            .arg("/home/zoff/ToxBlinkenwall/toxblinkenwall/initscript.sh")
-                            .arg("stop")
-                            .output()
-                            .expect("failed to execute process");
-                    }
-                }
-                #[cfg(target_os = "linux")]
-                Command::new("/usr/bin/sudo")
-                    .arg("/bin/chvt")
-                    .arg("1")
-                    .output()
-                    .expect("failed to execute process");
+            State::Scene { .. } => {
+                info!("Exit Scene state");
+            }
+            State::Board { .. } => {
+                info!("Exit Board state");
+            }
+            State::Ambient { .. } => {
+                info!("Exit Ambient state");
+            }
+            State::Canvas { .. } => {
+                info!("Exit Canvas state");
+            }
+            State::App { ref mut app } => {
+                info!("Exit {} state", app.name);
+                app.exit(matches!(next, State::ToxMessage { .. }));
             }
             State::ToxMessage { .. } => {
                 info!("Exit Tox Message state");
             }
         };
     }
+    /// Switches to `next`, crossfading from the current state if both are drawn.
+    fn set_state(&mut self, next: State) {
+        // whatever is shown instead wins over a VNC server that is still connecting
+        self.vnc_connecting = None;
+        self.exit_transition(&next);
+        let mut previous = std::mem::replace(&mut self.state, next);
+        if let State::Off = self.state {
+            self.pixel_outputs.blank();
+        }
+        self.meter = match self.state {
+            State::Poetry { .. } | State::ToxMessage { .. } => {
+                self.meter.take().or_else(|| Meter::new(&mut self.audio))
+            }
+            _ => None,
+        };
+        if let Some(mut crossfade) = self.crossfade.take() {
+            crossfade.outgoing.release();
+        }
+        // fading in from off starts from black
+        let from_drawn = previous.is_drawn() || matches!(previous, State::Off);
+        if self.config.transition.duration == 0 || !from_drawn || !self.state.is_drawn() {
+            previous.release();
+            return;
+        }
+        let (width, height) = self.output.frame().dimensions();
+        self.crossfade = Some(Crossfade {
+            outgoing: previous,
+            from: Texture2d::empty(&self.display, width, height).unwrap(),
+            to: Texture2d::empty(&self.display, width, height).unwrap(),
+            created: Instant::now(),
+            started: None,
+        });
+    }
+
+    /// Sets the wall brightness within the configured limit, returns the one applied.
+    pub fn set_brightness(&mut self, brightness: f32) -> f32 {
+        self.output.set_brightness(brightness)
+    }
+
+    /// PNG of the last frame as shown on the wall.
+    pub fn screenshot(&self) -> Result<Vec<u8>, png::EncodingError> {
+        Frame::read(self.output.shown()).encode_png()
+    }
+
+    pub fn capture_record_start(
+        &mut self,
+        name: &str,
+        format: RecordingFormat,
+        duration: Option<Duration>,
+    ) -> io::Result<()> {
+        let size = self.output.shown().dimensions();
+        self.capture.start_recording(name, format, size, duration)
+    }
+
+    pub fn capture_record_stop(&mut self) -> bool {
+        self.capture.stop_recording()
+    }
+
+    /// Changes the crossfade used for following state changes.
+    pub fn set_transition(&mut self, effect: Option<TransitionEffect>, duration: Option<u64>) {
+        if let Some(effect) = effect {
+            self.config.transition.effect = effect;
+        }
+        if let Some(duration) = duration {
+            self.config.transition.duration = duration;
+        }
+    }
+
     pub fn interval(&self) -> Option<Duration> {
+        match (self.state_interval(), self.canvas.next_save()) {
+            (Some(state), Some(save)) => Some(state.min(save)),
+            (state, save) => state.or(save),
+        }
+    }
+
+    fn state_interval(&self) -> Option<Duration> {
+        if self.crossfade.is_some() {
+            return Some(Duration::from_secs(0));
+        }
+        if self.vnc_connecting.is_some() {
+            return Some(VNC_CONNECTING_INTERVAL);
+        }
         match self.state {
-            State::Off => None,
+            State::Off => {
+                let attract = self
+                    .config
+                    .emulator
+                    .attract
+                    .as_ref()
+                    .map(|attract| attract.idle);
+                [attract, self.config.ambient.idle]
+                    .iter()
+                    .flatten()
+                    .map(|idle| {
+                        Duration::from_secs(*idle).saturating_sub(self.last_activity.elapsed())
+                    })
+                    .min()
+            }
             State::ShaderToy { .. } => Some(Duration::from_secs(0)),
             State::Video { .. } => Some(Duration::from_secs(0)),
             State::Emulator { .. } => {
@@ -181,9 +457,13 @@ impl StateMachine {
                 //     Some(time_per_frame - frame_time)
                 // }
             }
-            State::Vnc => None,
+            State::Vnc { .. } => Some(Duration::from_secs(0)),
             State::Poetry { .. } | State::ToxMessage { .. } => Some(Duration::from_secs(0)),
-            State::Tox => None,
+            State::Scene { .. }
+            | State::Board { .. }
+            | State::Ambient { .. }
+            | State::Canvas { .. } => Some(Duration::from_secs(0)),
+            State::App { ref app } => app.next_check(),
         }
     }
 
@@ -193,80 +473,360 @@ impl StateMachine {
                 sender.send(mqtt::State::Stopped).ok();
             }
             let next = State::Off;
-            self.exit_transition(&next);
-            self.state = next;
+            self.set_state(next);
             info!("Enter Off state");
         }
     }
 
-    pub fn to_shader_toy(&mut self, shader: &str, title: &str) {
+    pub fn to_shader_toy(&mut self, id: &str, shader: &str, title: &str) {
         if let State::ShaderToy { .. } = self.state {
         } else {
             if let Some(sender) = &self.state_sender {
                 sender.send(mqtt::State::ShaderToy(title.to_owned())).ok();
             }
             let next = State::ShaderToy {
-                shader_toy: ShaderToy::new_with_audio(&self.display, shader),
+                shader_toy: ShaderToy::new_with_audio(&self.display, shader, &mut self.audio),
+                id: id.to_owned(),
             };
-            self.exit_transition(&next);
-            self.state = next;
+            self.set_state(next);
             info!("Enter ShaderToy state");
             return;
         }
-        self.state = State::ShaderToy {
-            shader_toy: ShaderToy::new_with_audio(&self.display, shader),
-        };
+        self.set_state(State::ShaderToy {
+            shader_toy: ShaderToy::new_with_audio(&self.display, shader, &mut self.audio),
+            id: id.to_owned(),
+        });
+    }
+
+    /// Level, bands and beat of the music, from the current state or the one fading out.
+    fn audio_levels(&self) -> Option<Levels> {
+        self.state.levels().or_else(|| {
+            self.crossfade
+                .as_ref()
+                .and_then(|crossfade| crossfade.outgoing.levels())
+        })
+    }
+
+    pub fn shader_id(&self) -> Option<&str> {
+        if let State::ShaderToy { id, .. } = &self.state {
+            Some(id)
+        } else {
+            None
+        }
     }
 
-    pub fn to_video(&mut self, url: &str) {
-        if let State::Video { .. } = self.state {
+    pub fn to_video(&mut self, url: &str) -> Result<(), SourceError> {
+        let source = self.video_source(url)?;
+        self.video_queue.play_now(url);
+        self.play_video(url, &source);
+        Ok(())
+    }
+
+    fn video_source(&self, url: &str) -> Result<Source, SourceError> {
+        Source::parse(url, self.config.video.media.as_ref().map(Path::new))
+    }
+
+    fn play_video(&mut self, url: &str, source: &Source) {
+        let options = self.config.video.options.for_kind(source.kind());
+        if let State::Video { ref mut video, .. } = self.state {
+            video.play(source, options);
+            if let Some(sender) = &self.state_sender {
+                sender.send(mqtt::State::PlayVideo(url.to_owned())).ok();
+            }
         } else {
-            let mut video = Video::new(&self.display);
-            video.play(url);
+            let mut video = Video::new(
+                &self.display,
+                self.config.display.width,
+                self.config.display.height,
+                &self.audio,
+            );
+            video.play(source, options);
             if let Some(sender) = &self.state_sender {
                 sender.send(mqtt::State::PlayVideo(url.to_owned())).ok();
-                sender
-                    .send(mqtt::State::Volume(video.get_volume() as _))
-                    .ok();
+                sender.send(mqtt::State::Volume(self.audio.volume())).ok();
+                sender.send(mqtt::State::Muted(self.audio.muted())).ok();
+                sender.send(mqtt::State::Ducked(self.audio.ducked())).ok();
             }
-            let next = State::Video { video };
-            self.exit_transition(&next);
-            self.state = next;
+            let next = State::Video {
+                video,
+                effect: None,
+                overlay: None,
+            };
+            self.set_state(next);
             info!("Enter Video state");
         }
     }
 
-    pub fn to_tox(&mut self) {
-        if let State::Tox = self.state {
+    /// Plays the next playable queued video, returns false if the queue is exhausted.
+    pub fn video_next(&mut self) -> bool {
+        while let Some(url) = self.video_queue.next().map(|url| url.to_owned()) {
+            match self.video_source(&url) {
+                Ok(source) => {
+                    self.play_video(&url, &source);
+                    return true;
+                }
+                Err(err) => error!("Skipping queued video: {}", err),
+            }
+        }
+        false
+    }
+
+    pub fn video_previous(&mut self) -> bool {
+        while let Some(url) = self.video_queue.previous().map(|url| url.to_owned()) {
+            match self.video_source(&url) {
+                Ok(source) => {
+                    self.play_video(&url, &source);
+                    return true;
+                }
+                Err(err) => error!("Skipping queued video: {}", err),
+            }
+        }
+        false
+    }
+
+    pub fn queue_video(&mut self, url: &str) -> Result<(), SourceError> {
+        self.video_source(url)?;
+        self.video_queue.push(url);
+        Ok(())
+    }
+
+    pub fn list_media(&self) -> io::Result<Vec<String>> {
+        match &self.config.video.media {
+            Some(media) => list_media(media),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No media library configured",
+            )),
+        }
+    }
+
+    pub fn video_set_paused(&self, paused: bool) -> bool {
+        if let State::Video { video, .. } = &self.state {
+            video.set_paused(paused);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn video_seek(&self, position: f64, relative: bool) -> bool {
+        if let State::Video { video, .. } = &self.state {
+            video.seek(position, relative);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn video_status(&self) -> Option<&VideoStatus> {
+        if let State::Video { video, .. } = &self.state {
+            Some(video.status())
+        } else {
+            None
+        }
+    }
+
+    pub fn video_queue(&self) -> &Queue {
+        &self.video_queue
+    }
+
+    pub fn video_queue_mut(&mut self) -> &mut Queue {
+        &mut self.video_queue
+    }
+
+    /// Sets or removes the shader post-processing the playing video.
+    pub fn video_effect(&mut self, shader: Option<&str>) -> bool {
+        let display = &self.display;
+        if let State::Video { ref mut effect, .. } = self.state {
+            *effect = shader.map(|shader| ShaderToy::new(display, shader));
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Shows a poem on top of the playing video.
+    pub fn video_poetry(&mut self, text: &str, style: Option<PoetryStyle>) -> bool {
+        let (display, config, fonts) = (&self.display, &self.config, &self.fonts);
+        if let State::Video {
+            ref mut overlay, ..
+        } = self.state
+        {
+            let poetry = overlay.get_or_insert_with(|| {
+                Poetry::new(
+                    display,
+                    fonts.clone(),
+                    &config.poetry,
+                    (config.display.width, config.display.height),
+                )
+            });
+            if !text.is_empty() {
+                poetry.show_poem(display, text, style);
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn report_video_status(&self, status: &VideoStatus) {
+        self.broadcaster.send_video_status(status);
+        if let Some(sender) = &self.state_sender {
+            sender.send(mqtt::State::VideoStatus(status.clone())).ok();
+        }
+    }
+
+    /// Hands the wall over to the configured external application `name`.
+    pub fn to_app(&mut self, name: &str) -> io::Result<()> {
+        if matches!(&self.state, State::App { app } if app.name == name) {
+            return Ok(());
+        }
+        let config = self.config.apps.get(name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("Unknown app {}", name))
+        })?;
+        if let State::App { app } = &mut self.state {
+            // gives the terminal back before the next app takes it
+            app.exit(false);
+        }
+        let app = match App::start(name, config) {
+            Ok(app) => app,
+            Err(err) => {
+                if matches!(self.state, State::App { .. }) {
+                    self.to_off();
+                }
+                return Err(err);
+            }
+        };
+        if let Some(sender) = &self.state_sender {
+            sender.send(mqtt::State::App(name.to_owned())).ok();
+        }
+        let next = State::App { app };
+        self.set_state(next);
+        info!("Enter {} state", name);
+        Ok(())
+    }
+
+    /// Connects to the VNC `server`, or the configured one, and shows its screen once connected.
+    /// Other servers than the configured one need to be allowed, and the configured password is
+    /// only sent to the configured server.
+    pub fn to_vnc(&mut self, server: Option<&str>) -> io::Result<()> {
+        let (server, configured) = vnc::choose(
+            server,
+            self.config.vnc.server.as_deref(),
+            &self.config.vnc.allowed,
+        )?;
+        if matches!(&self.state, State::Vnc { client } if client.server == server) {
+            return Ok(());
+        }
+        let password = if configured {
+            self.config.vnc.password.as_deref()
         } else {
+            None
+        };
+        self.vnc_connecting = Some(vnc::Client::connect(
+            &server,
+            password,
+            self.config.vnc.shared,
+        )?);
+        Ok(())
+    }
+
+    /// Switches to the VNC client once it's connected.
+    fn update_vnc_connecting(&mut self) {
+        let client = match self.vnc_connecting.take() {
+            Some(client) => client,
+            None => return,
+        };
+        if let Some(reason) = client.closed() {
+            error!(
+                "Failed connecting to VNC server {}: {}",
+                client.server, reason
+            );
+        } else if client.is_connected() {
             if let Some(sender) = &self.state_sender {
-                sender.send(mqtt::State::Tox).ok();
-            }
-            let next = State::Tox;
-            self.exit_transition(&next);
-            #[cfg(target_os = "linux")]
-            Command::new("/usr/bin/sudo")
-                .arg("/bin/chvt")
-                .arg("2")
-                .output()
-                .expect("failed to execute process");
-            #[cfg(target_os = "linux")]
-            Command::new("/usr/bin/sudo")
-                .arg("-Hu")
-                .arg("zoff")
-                .arg("/home/zoff/ToxBlinkenwall/toxblinkenwall/initscript.sh")
-                .arg("start")
-                .output()
-                .expect("failed to execute process");
-            self.state = next;
-            info!("Enter Tox state");
-        }
-    }
-
-    pub fn to_poetry(&mut self, text: &str) {
+                sender.send(mqtt::State::Vnc(client.server.clone())).ok();
+            }
+            let next = State::Vnc { client };
+            self.set_state(next);
+            info!("Enter VNC state");
+        } else {
+            self.vnc_connecting = Some(client);
+        }
+    }
+
+    /// Forwards pointer input to the VNC server, `x` and `y` as fractions from the top left.
+    pub fn vnc_pointer(&self, x: f32, y: f32, buttons: u8) -> io::Result<()> {
+        match &self.state {
+            State::Vnc { client } => client.pointer(x, y, buttons),
+            _ => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "VNC is not running",
+            )),
+        }
+    }
+
+    /// Forwards a key named like `KeyboardEvent.key` to the VNC server.
+    pub fn vnc_key(&self, key: &str, down: bool) -> io::Result<()> {
+        let keysym = vnc::keysym(key).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown key {}", key))
+        })?;
+        match &self.state {
+            State::Vnc { client } => client.key(keysym, down),
+            _ => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "VNC is not running",
+            )),
+        }
+    }
+
+    /// Passes pointer input to the running shader as `iMouse`, `x` and `y` in pixels from the
+    /// bottom left of the frame. Returns whether a shader is running.
+    pub fn set_mouse(&mut self, x: f32, y: f32, down: bool) -> bool {
+        match &mut self.state {
+            State::ShaderToy { shader_toy, .. } => shader_toy.set_mouse(x, y, down),
+            State::Video {
+                effect: Some(effect),
+                ..
+            } => effect.set_mouse(x, y, down),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Like `set_mouse`, with `x` and `y` as fractions of the frame from the top left, as sent by
+    /// touchpads.
+    pub fn shader_pointer(&mut self, x: f32, y: f32, down: bool) -> bool {
+        let (width, height) = self.output.frame().dimensions();
+        let x = x.clamp(0.0, 1.0) * width as f32;
+        let y = (1.0 - y.clamp(0.0, 1.0)) * height as f32;
+        self.set_mouse(x, y, down)
+    }
+
+    /// Presses or releases a key in the keyboard texture of the running shader, returns whether
+    /// a shader is running.
+    pub fn shader_key(&mut self, code: u8, down: bool) -> bool {
+        match &mut self.state {
+            State::ShaderToy { shader_toy, .. } => shader_toy.set_key(code, down),
+            State::Video {
+                effect: Some(effect),
+                ..
+            } => effect.set_key(code, down),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Names of the configured external applications.
+    pub fn apps(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.config.apps.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn to_poetry(&mut self, text: &str, style: Option<PoetryStyle>) {
         if let State::Poetry { ref mut poetry } = self.state {
             if !text.is_empty() {
-                poetry.show_poem(&self.display, text);
+                poetry.show_poem(&self.display, text, style);
             }
         } else {
             if let Some(sender) = &self.state_sender {
@@ -274,106 +834,456 @@ impl StateMachine {
             }
             let mut poetry = Poetry::new(
                 &self.display,
-                &self.config.poetry.font,
-                self.config.poetry.speed,
+                self.fonts.clone(),
+                &self.config.poetry,
+                (self.config.display.width, self.config.display.height),
             );
             if !text.is_empty() {
-                poetry.show_poem(&self.display, text);
+                poetry.show_poem(&self.display, text, style);
             }
             let next = State::Poetry { poetry };
-            self.exit_transition(&next);
-            self.state = next;
+            self.set_state(next);
             info!("Enter Poetry state");
         }
     }
 
+    pub fn to_board(&mut self) {
+        if !matches!(self.state, State::Board { .. }) {
+            if let Some(sender) = &self.state_sender {
+                sender.send(mqtt::State::Board).ok();
+            }
+            let ticker = Ticker::new(&self.display, self.fonts.clone(), self.config.board.speed);
+            let next = State::Board { ticker };
+            self.set_state(next);
+            info!("Enter Board state");
+        }
+    }
+
+    /// Shows the ambient widgets, `shader_source` looks up the configured background shader.
+    pub fn to_ambient<F>(&mut self, shader_source: F)
+    where
+        F: Fn(&str) -> Result<String, String>,
+    {
+        if !matches!(self.state, State::Ambient { .. }) {
+            if let Some(sender) = &self.state_sender {
+                sender.send(mqtt::State::Ambient).ok();
+            }
+            let background = self.config.ambient.background.as_ref().and_then(|id| {
+                shader_source(id)
+                    .map_err(|err| error!("Failed loading ambient background {}: {}", id, err))
+                    .ok()
+            });
+            let ambient = Ambient::new(
+                &self.display,
+                self.fonts.clone(),
+                &self.config.ambient,
+                background.as_deref(),
+            );
+            let next = State::Ambient { ambient };
+            self.set_state(next);
+            info!("Enter Ambient state");
+        }
+    }
+
+    /// Whether the wall has been off and unused long enough to start the ambient display.
+    pub fn ambient_due(&self) -> bool {
+        matches!(self.state, State::Off)
+            && self.config.ambient.idle.map_or(false, |idle| {
+                self.last_activity.elapsed() >= Duration::from_secs(idle)
+            })
+    }
+
+    pub fn to_canvas(&mut self) {
+        if !matches!(self.state, State::Canvas { .. }) {
+            self.enter_canvas(canvas::View::default());
+        }
+    }
+
+    /// Replays the JSON `snapshots` of the canvas, then shows it live.
+    pub fn canvas_timelapse(&mut self, snapshots: &[Vec<u8>]) {
+        let frames = snapshots
+            .iter()
+            .filter_map(|snapshot| {
+                serde_json::from_slice(snapshot)
+                    .map_err(|err| error!("Invalid canvas snapshot: {}", err))
+                    .ok()
+            })
+            .collect();
+        let view = canvas::View::timelapse(frames, self.config.canvas.timelapse_fps);
+        if let State::Canvas {
+            view: ref mut current,
+        } = self.state
+        {
+            *current = view;
+        } else {
+            self.enter_canvas(view);
+        }
+    }
+
+    fn enter_canvas(&mut self, view: canvas::View) {
+        if let Some(sender) = &self.state_sender {
+            sender.send(mqtt::State::Canvas).ok();
+        }
+        let next = State::Canvas { view };
+        self.set_state(next);
+        info!("Enter Canvas state");
+    }
+
+    pub fn canvas(&self) -> &Canvas {
+        &self.canvas
+    }
+
+    pub fn canvas_mut(&mut self) -> &mut Canvas {
+        &mut self.canvas
+    }
+
+    /// Paints a pixel for `painter` and tells everyone connected about it.
+    pub fn canvas_paint(
+        &mut self,
+        painter: &str,
+        x: u32,
+        y: u32,
+        color: u8,
+    ) -> Result<(), PaintError> {
+        self.canvas.paint(painter, x, y, color)?;
+        self.broadcaster.send_canvas_pixel(x, y, color);
+        Ok(())
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn board_mut(&mut self) -> &mut Board {
+        &mut self.board
+    }
+
     pub fn to_tox_message(&mut self, text: &str) {
         if let State::ToxMessage { ref mut poetry } = self.state {
             if !text.is_empty() {
-                poetry.show_poem(&self.display, text);
+                poetry.show_poem(&self.display, text, None);
             }
         } else {
             if let Some(sender) = &self.state_sender {
-                sender.send(mqtt::State::Tox).ok();
+                sender.send(mqtt::State::Poetry).ok();
             }
             let mut poetry = Poetry::new(
                 &self.display,
-                &self.config.poetry.font,
-                self.config.poetry.speed,
+                self.fonts.clone(),
+                &self.config.poetry,
+                (self.config.display.width, self.config.display.height),
             );
             if !text.is_empty() {
-                poetry.show_poem(&self.display, text);
+                poetry.show_poem(&self.display, text, None);
             }
             let next = State::ToxMessage { poetry };
-            self.exit_transition(&next);
-            self.state = next;
+            self.set_state(next);
             info!("Enter Tox Message state");
         }
     }
 
-    pub fn to_emulator(&mut self, game: String) {
+    /// Replaces the current state with a scene of `layers`, shader layers are looked up with
+    /// `shader_source`.
+    pub fn to_scene(
+        &mut self,
+        layers: &[LayerSpec],
+        shader_source: impl Fn(&str) -> Result<String, String>,
+    ) -> Result<(), SceneError> {
+        let scene = Scene::new(
+            &self.display,
+            &self.config,
+            &self.fonts,
+            &mut self.audio,
+            layers,
+            shader_source,
+        )?;
+        if let Some(sender) = &self.state_sender {
+            sender.send(mqtt::State::Scene).ok();
+        }
+        let next = State::Scene { scene };
+        self.set_state(next);
+        info!("Enter Scene state");
+        Ok(())
+    }
+
+    pub fn scene_opacity(&mut self, layer: usize, opacity: f32) -> bool {
+        if let State::Scene { scene } = &mut self.state {
+            scene.set_opacity(layer, opacity)
+        } else {
+            false
+        }
+    }
+
+    pub fn to_emulator(&mut self, game: String) -> io::Result<()> {
+        let emulator = crate::emulator::Emulator::new(
+            &self.display,
+            &game,
+            &self.config.emulator,
+            &self.audio,
+        )?;
+        self.enter_emulator(emulator);
+        Ok(())
+    }
+
+    pub fn to_replay(&mut self, name: &str, looping: bool) -> io::Result<()> {
+        let recording = Recording::load(self.recording_path(name)?)?;
+        let mut emulator = crate::emulator::Emulator::new(
+            &self.display,
+            &recording.rom,
+            &self.config.emulator,
+            &self.audio,
+        )?;
+        emulator.start_replay(recording, looping)?;
+        self.enter_emulator(emulator);
+        Ok(())
+    }
+
+    fn enter_emulator(&mut self, emulator: Emulator) {
         if let Some(sender) = &self.state_sender {
             sender.send(mqtt::State::Emulator).ok();
         }
-        let emulator = crate::emulator::Emulator::new(&self.display, &game, &self.config.emulator);
         let next = State::Emulator {
             emulator,
             last_frame: Instant::now(),
         };
-        self.exit_transition(&next);
-        self.state = next;
+        self.set_state(next);
         info!("Enter Emulator state");
     }
 
     pub fn emulator_input(&mut self, key: &str, press: bool) {
+        match &mut self.state {
+            State::Emulator { emulator, .. } => emulator.input(key, press),
+            State::Scene { scene } => scene.emulator_input(key, press),
+            _ => {}
+        }
+    }
+
+    fn recording_path(&self, name: &str) -> io::Result<PathBuf> {
+        if Path::new(name)
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            != Some(name)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid recording name",
+            ));
+        }
+        Ok(Path::new(&self.config.emulator.recordings).join(format!("{name}.json")))
+    }
+
+    pub fn list_recordings(&self) -> io::Result<Vec<String>> {
+        Recording::list(&self.config.emulator.recordings)
+    }
+
+    pub fn emulator_record_start(&mut self) -> io::Result<()> {
         if let State::Emulator { emulator, .. } = &mut self.state {
-            emulator.input(key, press);
+            emulator.start_recording()
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Emulator is not running",
+            ))
         }
     }
 
-    pub fn set_volume(&self, value: u8) {
-        if let State::Video { video } = &self.state {
-            video.set_volume(value as _);
+    pub fn emulator_record_stop(&mut self, name: &str) -> io::Result<()> {
+        let path = self.recording_path(name)?;
+        if let State::Emulator { emulator, .. } = &mut self.state {
+            match emulator.stop_recording() {
+                Some(recording) => recording.save(path),
+                None => Err(io::Error::new(io::ErrorKind::Other, "Not recording")),
+            }
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Emulator is not running",
+            ))
+        }
+    }
+
+    /// Records user activity, which postpones attract mode.
+    pub fn touch(&mut self) {
+        self.last_activity = Instant::now();
+    }
+
+    fn start_attract(&mut self) {
+        self.touch();
+        let mut recordings = match self.list_recordings() {
+            Ok(recordings) => recordings,
+            Err(err) => {
+                error!("Failed listing recordings for attract mode: {}", err);
+                return;
+            }
+        };
+        // recordings of deleted ROMs are skipped
+        recordings.shuffle(&mut rand::thread_rng());
+        for name in &recordings {
+            info!("Starting attract mode with {}", name);
+            match self.to_replay(name, true) {
+                Ok(()) => return,
+                Err(err) => error!("Skipping recording {} in attract mode: {}", name, err),
+            }
         }
     }
 
+    /// Passes the master volume on to mpv, which plays the sound of videos itself.
+    fn apply_volume(&self) {
+        let volume = self.audio.output_volume();
+        let outgoing = self.crossfade.as_ref().map(|crossfade| &crossfade.outgoing);
+        for state in std::iter::once(&self.state).chain(outgoing) {
+            match state {
+                State::Video { video, .. } => video.set_volume(volume),
+                State::Scene { scene } => scene.set_volume(volume),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn set_volume(&self, value: u8) {
+        self.audio.set_volume(value);
+        self.apply_volume();
+    }
+
+    /// Changes the volume relative to the current one, returning the new volume.
+    pub fn adjust_volume(&self, delta: i16) -> u8 {
+        let value = self.audio.adjust_volume(delta);
+        self.apply_volume();
+        value
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.audio.set_muted(muted);
+        self.apply_volume();
+    }
+
+    pub fn set_ducked(&self, ducked: bool) {
+        self.audio.set_ducked(ducked);
+        self.apply_volume();
+    }
+
+    /// Input and output devices.
+    pub fn audio_devices(&self) -> io::Result<(Vec<audio::Device>, Vec<audio::Device>)> {
+        Ok((self.audio.inputs()?, self.audio.outputs()?))
+    }
+
     pub fn update(&mut self) {
-        match self.state {
-            State::Off => {}
-            State::ShaderToy { ref mut shader_toy } => {
-                shader_toy.step(&self.display);
-            }
-            State::Video { ref mut video } => {
-                match video.step(&self.display) {
-                    None => {}
-                    Some(evt) => {
-                        if let Event::PropertyChange {
-                            name: "idle-active",
-                            change: PropertyData::Flag(idle),
-                            ..
-                        } = evt
-                        {
-                            if idle {
-                                self.to_off();
-                            }
-                        } else {
-                            info!("MPV event: {:?}", evt);
-                        }
-                    }
-                };
+        if let State::Off = self.state {
+            if let Some(attract) = &self.config.emulator.attract {
+                if self.last_activity.elapsed() >= Duration::from_secs(attract.idle) {
+                    self.start_attract();
+                }
             }
-            State::Emulator {
-                ref mut emulator,
-                ref mut last_frame,
-            } => {
-                *last_frame = Instant::now();
-                emulator.step(&self.display);
+        }
+        let height = self.output.frame().dimensions().1;
+        while let Some(pointer) = self.vnc_server.pointer() {
+            let y = height.saturating_sub(pointer.y as u32 + 1);
+            self.set_mouse(pointer.x as f32, y as f32, pointer.buttons & 1 != 0);
+        }
+        self.board.update();
+        if let State::Board { ticker } = &mut self.state {
+            let width = self.config.display.width;
+            if ticker.wants_message(width) {
+                if let Some(message) = self.board.next_message() {
+                    ticker.push(&self.display, &message, width);
+                }
+            }
+        }
+        if let State::Ambient { ambient } = &mut self.state {
+            ambient.update(&self.display);
+        }
+        if let State::Canvas { view } = &mut self.state {
+            let size = self.output.frame().dimensions();
+            view.update(&self.display, &self.canvas, size);
+        }
+        if let State::App { app } = &mut self.state {
+            if !app.check() {
+                self.to_off();
             }
-            State::Vnc => {}
-            State::Poetry { ref mut poetry } | State::ToxMessage { ref mut poetry } => {
-                poetry.step(&self.display);
+        }
+        self.update_vnc_connecting();
+        if let State::Vnc { client } = &mut self.state {
+            client.update(&self.display);
+            if let Some(reason) = client.closed() {
+                error!("VNC connection to {} closed: {}", client.server, reason);
+                self.to_off();
+            }
+        }
+        if !self.state.is_drawn() {
+            return;
+        }
+
+        let levels = match &mut self.meter {
+            Some(meter) => meter.levels(),
+            None => self.audio_levels().unwrap_or_default(),
+        };
+        if let Some(poetry) = self.state.poetry() {
+            poetry.set_levels(levels);
+        }
+        let mut finished = false;
+        let update = match &mut self.crossfade {
+            Some(crossfade) => {
+                let size = crossfade.from.dimensions();
+                crossfade
+                    .outgoing
+                    .draw(&mut crossfade.from.as_surface(), size, &self.blit);
+                let update = self
+                    .state
+                    .draw(&mut crossfade.to.as_surface(), size, &self.blit);
+                if crossfade.started.is_none()
+                    && (self.state.is_ready()
+                        || crossfade.created.elapsed() >= TRANSITION_READY_TIMEOUT)
+                {
+                    crossfade.started = Some(Instant::now());
+                }
+                let duration = Duration::from_millis(self.config.transition.duration);
+                let progress = crossfade.started.map_or(0.0, |started| {
+                    (started.elapsed().as_secs_f32() / duration.as_secs_f32()).min(1.0)
+                });
+                self.transitions.draw(
+                    &mut self.output.frame().as_surface(),
+                    &self.blit,
+                    self.config.transition.effect,
+                    &crossfade.from,
+                    &crossfade.to,
+                    progress,
+                    levels,
+                );
+                finished = progress >= 1.0;
+                update
+            }
+            None => {
+                let frame = self.output.frame();
+                self.state
+                    .draw(&mut frame.as_surface(), frame.dimensions(), &self.blit)
+            }
+        };
+        self.output
+            .resize(&self.display, self.display.get_framebuffer_dimensions());
+        let mut target = self.display.draw();
+        self.output.draw(&mut target);
+        target.finish().unwrap();
+        self.pixel_outputs.send(self.output.frame(), &self.blit);
+        self.capture.update(self.output.shown());
+        self.vnc_server.update(self.output.frame());
+        if finished {
+            if let Some(mut crossfade) = self.crossfade.take() {
+                crossfade.outgoing.release();
+            }
+        }
+
+        match update {
+            None => {}
+            Some(VideoUpdate::Idle) => {
+                if !self.video_next() {
+                    self.to_off();
+                }
+            }
+            Some(VideoUpdate::StatusChanged) => {
+                if let Some(status) = self.video_status().cloned() {
+                    self.report_video_status(&status);
+                }
             }
-            State::Tox => {}
         };
     }
 }
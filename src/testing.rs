@@ -0,0 +1,42 @@
+//! Helpers shared by the tests.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static DIRECTORIES: AtomicUsize = AtomicUsize::new(0);
+
+/// A new directory in the system's temporary directory, removed with its contents when dropped,
+/// also when a test panics.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "blinkenwall-{}-{}-{}",
+            name,
+            process::id(),
+            DIRECTORIES.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::remove_dir_all(&path).ok();
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
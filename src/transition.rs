@@ -0,0 +1,120 @@
+use glium::{
+    backend::glutin::Display,
+    program,
+    texture::texture2d::Texture2d,
+    uniform,
+    uniforms::{MagnifySamplerFilter, MinifySamplerFilter, Sampler},
+    Surface,
+};
+
+use crate::{
+    blit::{Blit, QUAD_VERTEX_SHADER},
+    config::TransitionEffect,
+    shadertoy::Levels,
+};
+
+const FRAGMENT_SHADER_PREAMBLE: &str = "#version 140
+
+in vec2 vTexCoords;
+out vec4 fragColor;
+
+uniform sampler2D from;
+uniform sampler2D to;
+uniform float progress;
+// level, bands and beat of the music, if a state is listening to some
+uniform float level;
+uniform vec3 bands;
+uniform float beat;
+
+vec4 transition(vec4 a, vec4 b);
+
+void main() {
+    fragColor = transition(texture(from, vTexCoords), texture(to, vTexCoords));
+}
+
+";
+
+const FADE: &str = "vec4 transition(vec4 a, vec4 b) {
+    return mix(a, b, progress);
+}
+";
+
+/// Soft edge moving from left to right
+const WIPE: &str = "vec4 transition(vec4 a, vec4 b) {
+    float edge = progress * 1.1 - 0.05;
+    return mix(a, b, smoothstep(vTexCoords.x - 0.05, vTexCoords.x + 0.05, edge));
+}
+";
+
+/// Pixels switch over one by one in random order
+const DISSOLVE: &str = "vec4 transition(vec4 a, vec4 b) {
+    float noise = fract(sin(dot(floor(gl_FragCoord.xy), vec2(12.9898, 78.233))) * 43758.5453);
+    return noise < progress ? b : a;
+}
+";
+
+/// Fade flashing with the beat of the music
+const PULSE: &str = "vec4 transition(vec4 a, vec4 b) {
+    float flash = beat * (1.0 - abs(progress * 2.0 - 1.0));
+    return mix(a, b, progress) + vec4(vec3(flash * 0.5), 0.0);
+}
+";
+
+/// Blends two frames with one of the transition effects.
+pub struct Transitions {
+    fade: glium::Program,
+    wipe: glium::Program,
+    dissolve: glium::Program,
+    pulse: glium::Program,
+}
+
+impl Transitions {
+    pub fn new(display: &Display) -> Self {
+        let compile = |effect: &str| {
+            let fragment_shader = String::from(FRAGMENT_SHADER_PREAMBLE) + effect;
+            program!(display, 140 => { vertex: QUAD_VERTEX_SHADER, fragment: &fragment_shader })
+                .unwrap()
+        };
+
+        Self {
+            fade: compile(FADE),
+            wipe: compile(WIPE),
+            dissolve: compile(DISSOLVE),
+            pulse: compile(PULSE),
+        }
+    }
+
+    /// Draws the transition from `from` to `to` at `progress` between 0 and 1, moving with the
+    /// `levels` of the music.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw<S: Surface>(
+        &self,
+        target: &mut S,
+        blit: &Blit,
+        effect: TransitionEffect,
+        from: &Texture2d,
+        to: &Texture2d,
+        progress: f32,
+        levels: Levels,
+    ) {
+        let program = match effect {
+            TransitionEffect::Fade => &self.fade,
+            TransitionEffect::Wipe => &self.wipe,
+            TransitionEffect::Dissolve => &self.dissolve,
+            TransitionEffect::Pulse => &self.pulse,
+        };
+        let uniforms = uniform! {
+            from: Sampler::new(from)
+                .minify_filter(MinifySamplerFilter::Linear)
+                .magnify_filter(MagnifySamplerFilter::Linear),
+            to: Sampler::new(to)
+                .minify_filter(MinifySamplerFilter::Linear)
+                .magnify_filter(MagnifySamplerFilter::Linear),
+            progress: progress,
+            level: levels.level,
+            bands: levels.bands,
+            beat: levels.beat,
+        };
+        blit.draw_quad(target, program, &uniforms, &Default::default());
+    }
+}
@@ -1,6 +1,6 @@
-use glium::backend::{glutin::Display, Context};
+use glium::{backend::glutin::Display, texture::texture2d::Texture2d};
 use libmpv::{
-    events::Event,
+    events::{Event, PropertyData},
     render::{OpenGLInitParams, RenderContext, RenderParam, RenderParamApiType},
     Format, Mpv,
 };
@@ -9,11 +9,38 @@ use libmpv::{
 //     mpv_render_context_set_update_callback, mpv_render_context_update,
 // };
 use log::{error, info};
-use std::os::raw::c_void;
+use serde::Serialize;
+use std::{collections::HashMap, os::raw::c_void};
+
+use crate::audio::Audio;
+
+mod framebuffer;
+use framebuffer::VideoFrame;
+mod queue;
+pub use queue::Queue;
+mod source;
+pub use source::{list_media, Source, SourceError, SourceKind};
+
+#[derive(Serialize, Clone, Default, PartialEq)]
+pub struct Status {
+    pub title: Option<String>,
+    pub position: Option<f64>,
+    pub duration: Option<f64>,
+    pub paused: bool,
+}
+
+pub enum Update {
+    /// mpv finished playing and is waiting for a new file
+    Idle,
+    StatusChanged,
+}
 
 pub struct Video {
     render_context: RenderContext,
     player: Mpv,
+    status: Status,
+    overridden: Vec<(String, String)>,
+    frame: VideoFrame,
 }
 
 fn get_proc_address(display: &Display, name: &str) -> *mut c_void {
@@ -21,11 +48,18 @@ fn get_proc_address(display: &Display, name: &str) -> *mut c_void {
 }
 
 impl Video {
-    pub fn new(display: &Display) -> Video {
+    /// Creates a player that renders into a `width`×`height` texture and plays on the output of
+    /// `audio` at its master volume.
+    pub fn new(display: &Display, width: u32, height: u32, audio: &Audio) -> Video {
         let mut player = Mpv::with_initializer(|config| {
             config
                 .set_option("ytdl", "yes")
                 .and_then(|_| config.set_option("idle", "yes"))
+                .and_then(|_| config.set_option("volume", audio.output_volume()))
+                .and_then(|_| match audio.mpv_device() {
+                    Some(device) => config.set_option("audio-device", device.as_str()),
+                    None => Ok(()),
+                })
         })
         .expect("Error while creating MPV");
         let render_context = RenderContext::new(
@@ -40,51 +74,142 @@ impl Video {
         )
         .expect("Failed creating render context");
         // mpv.set_property("ytdl-format", "worst").unwrap();
-        player
-            .event_context()
+        let events = player.event_context();
+        events
             .observe_property("idle-active", Format::Flag, 0)
             .unwrap();
+        events.observe_property("pause", Format::Flag, 0).unwrap();
+        events
+            .observe_property("time-pos", Format::Double, 0)
+            .unwrap();
+        events
+            .observe_property("duration", Format::Double, 0)
+            .unwrap();
+        events
+            .observe_property("media-title", Format::String, 0)
+            .unwrap();
         Video {
             player,
             render_context,
+            status: Status::default(),
+            overridden: Vec::new(),
+            frame: VideoFrame::new(display, width, height)
+                .expect("Error while creating the video framebuffer"),
         }
     }
 
-    pub fn play(&mut self, url: &str) {
-        info!("Loading URL {}", url);
-        self.player
-            .command("loadfile", &[&format!("ytdl://{}", url), "replace"])
-            .unwrap();
+    /// Sets per-source mpv options, restoring the ones overridden for the previous source.
+    fn apply_options(&mut self, options: &HashMap<String, String>) {
+        for (name, value) in self.overridden.drain(..) {
+            self.player.set_property(&name, value.as_str()).ok();
+        }
+        for (name, value) in options {
+            if let Ok(previous) = self.player.get_property::<String>(name) {
+                self.overridden.push((name.clone(), previous));
+            }
+            if let Err(err) = self.player.set_property(name, value.as_str()) {
+                error!("Failed setting mpv option {}={}: {}", name, value, err);
+            }
+        }
+    }
+
+    pub fn play(&mut self, source: &Source, options: &HashMap<String, String>) {
+        let url = source.mpv_url();
+        info!("Loading {:?} source {}", source.kind(), url);
+        self.apply_options(options);
+        self.player.command("loadfile", &[&url, "replace"]).unwrap();
     }
 
     pub fn stop(&mut self) {
         self.player.command("stop", &[]).unwrap();
     }
 
-    pub fn step(&mut self, context: &Context) -> Option<Event> {
+    pub fn set_paused(&self, paused: bool) {
+        self.player.set_property("pause", paused).ok();
+    }
+
+    pub fn seek(&self, position: f64, relative: bool) {
+        self.player
+            .command(
+                "seek",
+                &[
+                    &position.to_string(),
+                    if relative { "relative" } else { "absolute" },
+                ],
+            )
+            .unwrap_or_else(|err| error!("Seek failed: {}", err));
+    }
+
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    fn poll_event(&mut self) -> Option<Update> {
         let event = self.player.event_context_mut().wait_event(0.0);
         match event {
-            Some(Ok(event)) => Some(event),
-            Some(Err(err)) => {
-                error!("MPV Error: {}", err);
+            Some(Ok(Event::PropertyChange {
+                name: "idle-active",
+                change: PropertyData::Flag(idle),
+                ..
+            })) => {
+                if idle {
+                    self.status = Status::default();
+                    Some(Update::Idle)
+                } else {
+                    None
+                }
+            }
+            Some(Ok(Event::PropertyChange { name, change, .. })) => {
+                let mut status = self.status.clone();
+                match (name, change) {
+                    ("pause", PropertyData::Flag(paused)) => status.paused = paused,
+                    // only report whole seconds, mpv updates this every frame
+                    ("time-pos", PropertyData::Double(position)) => {
+                        status.position = Some(position.floor())
+                    }
+                    ("duration", PropertyData::Double(duration)) => {
+                        status.duration = Some(duration)
+                    }
+                    ("media-title", PropertyData::Str(title)) => {
+                        status.title = Some(title.to_owned())
+                    }
+                    (name, change) => info!("MPV property {} changed: {:?}", name, change),
+                }
+                if status != self.status {
+                    self.status = status;
+                    Some(Update::StatusChanged)
+                } else {
+                    None
+                }
+            }
+            Some(Ok(event)) => {
+                info!("MPV event: {:?}", event);
                 None
             }
-            None => {
-                let (width, height) = context.get_framebuffer_dimensions();
-                self.render_context
-                    .render::<Display>(0, width as _, height as _, true)
-                    .expect("Failed to draw on glutin window");
-                context.swap_buffers().unwrap();
+            Some(Err(err)) => {
+                error!("MPV Error: {}", err);
                 None
             }
+            None => None,
         }
     }
 
-    pub fn get_volume(&self) -> i64 {
-        self.player.get_property("ao-volume").unwrap_or(0)
+    /// Handles pending mpv events and renders the current video frame into `frame()`.
+    pub fn step(&mut self) -> Option<Update> {
+        let update = self.poll_event();
+        let render_context = &self.render_context;
+        self.frame
+            .render(|fbo, width, height| render_context.render::<Display>(fbo, width, height, true))
+            .expect("Failed to render video frame");
+        update
+    }
+
+    pub fn frame(&self) -> &Texture2d {
+        self.frame.texture()
     }
 
+    /// Sets mpv's own volume from 0 to 100, leaving the system mixer alone.
     pub fn set_volume(&self, value: i64) {
-        self.player.set_property("ao-volume", value).ok();
+        self.player.set_property("volume", value).ok();
     }
 }
@@ -0,0 +1,142 @@
+use glium::{
+    backend::glutin::Display,
+    texture::{texture2d::Texture2d, MipmapsOption, UncompressedFloatFormat},
+    GlObject,
+};
+use std::{
+    ffi::c_void,
+    io,
+    os::raw::{c_int, c_uint},
+};
+
+const GL_FRAMEBUFFER: c_uint = 0x8d40;
+const GL_FRAMEBUFFER_BINDING: c_uint = 0x8ca6;
+const GL_COLOR_ATTACHMENT0: c_uint = 0x8ce0;
+const GL_TEXTURE_2D: c_uint = 0x0de1;
+
+/// The few raw GL entry points needed to give mpv a framebuffer object, glium doesn't expose
+/// the ids of its own.
+struct Gl {
+    gen_framebuffers: unsafe extern "system" fn(c_int, *mut c_uint),
+    delete_framebuffers: unsafe extern "system" fn(c_int, *const c_uint),
+    bind_framebuffer: unsafe extern "system" fn(c_uint, c_uint),
+    framebuffer_texture_2d: unsafe extern "system" fn(c_uint, c_uint, c_uint, c_uint, c_int),
+    get_integerv: unsafe extern "system" fn(c_uint, *mut c_int),
+}
+
+/// The `address` of the GL function `name`, an error if the driver doesn't have it.
+fn function(name: &str, address: *const c_void) -> io::Result<*const c_void> {
+    if address.is_null() {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("OpenGL function {} not available", name),
+        ))
+    } else {
+        Ok(address)
+    }
+}
+
+impl Gl {
+    fn load(display: &Display) -> io::Result<Self> {
+        let gl_window = display.gl_window();
+        let context = gl_window.context();
+        let load = |name| function(name, context.get_proc_address(name));
+        unsafe {
+            Ok(Self {
+                gen_framebuffers: std::mem::transmute(load("glGenFramebuffers")?),
+                delete_framebuffers: std::mem::transmute(load("glDeleteFramebuffers")?),
+                bind_framebuffer: std::mem::transmute(load("glBindFramebuffer")?),
+                framebuffer_texture_2d: std::mem::transmute(load("glFramebufferTexture2D")?),
+                get_integerv: std::mem::transmute(load("glGetIntegerv")?),
+            })
+        }
+    }
+
+    /// Runs `action` with `fbo` bound, restoring the previous binding so glium's state cache
+    /// stays valid.
+    fn with_framebuffer<T>(&self, fbo: c_uint, action: impl FnOnce() -> T) -> T {
+        let mut previous = 0;
+        unsafe {
+            (self.get_integerv)(GL_FRAMEBUFFER_BINDING, &mut previous);
+            (self.bind_framebuffer)(GL_FRAMEBUFFER, fbo);
+        }
+        let result = action();
+        unsafe {
+            (self.bind_framebuffer)(GL_FRAMEBUFFER, previous as c_uint);
+        }
+        result
+    }
+}
+
+/// Offscreen texture mpv renders into.
+pub struct VideoFrame {
+    gl: Gl,
+    fbo: c_uint,
+    texture: Texture2d,
+}
+
+impl VideoFrame {
+    /// Fails if the GL driver lacks framebuffer objects.
+    pub fn new(display: &Display, width: u32, height: u32) -> io::Result<Self> {
+        let texture = Texture2d::empty_with_format(
+            display,
+            UncompressedFloatFormat::U8U8U8U8,
+            MipmapsOption::NoMipmap,
+            width,
+            height,
+        )
+        .unwrap();
+        let gl = Gl::load(display)?;
+        let mut fbo = 0;
+        unsafe {
+            (gl.gen_framebuffers)(1, &mut fbo);
+        }
+        gl.with_framebuffer(fbo, || unsafe {
+            (gl.framebuffer_texture_2d)(
+                GL_FRAMEBUFFER,
+                GL_COLOR_ATTACHMENT0,
+                GL_TEXTURE_2D,
+                texture.get_id(),
+                0,
+            )
+        });
+        Ok(Self { gl, fbo, texture })
+    }
+
+    /// Calls `render` with the framebuffer id and size to draw into.
+    pub fn render<T>(&self, render: impl FnOnce(i32, i32, i32) -> T) -> T {
+        let (width, height) = self.texture.dimensions();
+        self.gl.with_framebuffer(self.fbo, || {
+            render(self.fbo as i32, width as i32, height as i32)
+        })
+    }
+
+    pub fn texture(&self) -> &Texture2d {
+        &self.texture
+    }
+}
+
+impl Drop for VideoFrame {
+    fn drop(&mut self) {
+        unsafe {
+            (self.gl.delete_framebuffers)(1, &self.fbo);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_functions_are_errors() {
+        let address = 0x1000 as *const c_void;
+        assert_eq!(function("glGetIntegerv", address).unwrap(), address);
+        let error = function("glGenFramebuffers", std::ptr::null()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert_eq!(
+            error.to_string(),
+            "OpenGL function glGenFramebuffers not available"
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+
+/// Most played items kept before the current one.
+const HISTORY: usize = 50;
+
+/// Video playlist that is stored on disk after every change. The last `HISTORY` played items
+/// are kept so `previous` can go back to them.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Queue {
+    items: Vec<String>,
+    current: Option<usize>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl Queue {
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let mut queue = match File::open(path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|err| {
+                error!("Failed reading video queue {}: {}", path.display(), err);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        };
+        if queue
+            .current
+            .map_or(false, |current| current >= queue.items.len())
+        {
+            queue.current = None;
+        }
+        queue.trim_history();
+        queue.path = path.to_owned();
+        queue
+    }
+
+    /// Drops the oldest played items beyond `HISTORY`.
+    fn trim_history(&mut self) {
+        if let Some(current) = self.current.filter(|current| *current > HISTORY) {
+            self.items.drain(..current - HISTORY);
+            self.current = Some(HISTORY);
+        }
+    }
+
+    fn save(&self) {
+        let result = File::create(&self.path).and_then(|file| {
+            serde_json::to_writer_pretty(BufWriter::new(file), self)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+        });
+        if let Err(err) = result {
+            error!(
+                "Failed writing video queue {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+
+    pub fn items(&self) -> &[String] {
+        &self.items
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn push(&mut self, url: &str) {
+        self.items.push(url.to_owned());
+        self.save();
+    }
+
+    /// Inserts `url` right after the current item and makes it current.
+    pub fn play_now(&mut self, url: &str) {
+        let index = self.current.map_or(self.items.len(), |current| current + 1);
+        self.items.insert(index, url.to_owned());
+        self.current = Some(index);
+        self.trim_history();
+        self.save();
+    }
+
+    pub fn remove(&mut self, index: usize) -> bool {
+        if index >= self.items.len() {
+            return false;
+        }
+        self.items.remove(index);
+        self.current = match self.current {
+            Some(current) if current > index => Some(current - 1),
+            Some(current) if current == index => current.checked_sub(1),
+            current => current,
+        };
+        self.save();
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.current = None;
+        self.save();
+    }
+
+    pub fn next(&mut self) -> Option<&str> {
+        let next = self.current.map_or(0, |current| current + 1);
+        if next < self.items.len() {
+            self.current = Some(next);
+            self.trim_history();
+            self.save();
+            let current = self.current?;
+            Some(&self.items[current])
+        } else {
+            None
+        }
+    }
+
+    pub fn previous(&mut self) -> Option<&str> {
+        let previous = self.current?.checked_sub(1)?;
+        self.current = Some(previous);
+        self.save();
+        Some(&self.items[previous])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn queue(directory: &TempDir) -> Queue {
+        Queue::load(directory.join("queue.json"))
+    }
+
+    #[test]
+    fn next_and_previous_walk_the_queue() {
+        let directory = TempDir::new("queue");
+        let mut queue = queue(&directory);
+        queue.push("a");
+        queue.push("b");
+        assert_eq!(queue.previous(), None);
+        assert_eq!(queue.next(), Some("a"));
+        assert_eq!(queue.next(), Some("b"));
+        assert_eq!(queue.next(), None);
+        assert_eq!(queue.previous(), Some("a"));
+
+        queue.play_now("c");
+        assert_eq!(queue.items(), ["a", "c", "b"]);
+        assert_eq!(queue.current(), Some(1));
+
+        // stored and loaded again
+        let reloaded = Queue::load(&queue.path);
+        assert_eq!(reloaded.items(), ["a", "c", "b"]);
+        assert_eq!(reloaded.current(), Some(1));
+    }
+
+    #[test]
+    fn removing_keeps_the_current_item() {
+        let directory = TempDir::new("queue");
+        let mut queue = queue(&directory);
+        for url in &["a", "b", "c"] {
+            queue.push(url);
+        }
+        queue.next();
+        queue.next();
+        assert!(queue.remove(0));
+        assert_eq!(queue.current(), Some(0));
+        assert!(!queue.remove(2));
+        assert!(queue.remove(0));
+        assert_eq!(queue.current(), None);
+        assert_eq!(queue.next(), Some("c"));
+    }
+
+    #[test]
+    fn played_history_is_capped() {
+        let directory = TempDir::new("queue");
+        let mut queue = queue(&directory);
+        for index in 0..HISTORY + 10 {
+            queue.play_now(&index.to_string());
+        }
+        assert_eq!(queue.items().len(), HISTORY + 1);
+        assert_eq!(queue.current(), Some(HISTORY));
+        assert_eq!(queue.items()[0], "9");
+
+        queue.push("next");
+        assert_eq!(queue.next(), Some("next"));
+        assert_eq!(queue.items().len(), HISTORY + 1);
+        assert_eq!(queue.items()[0], "10");
+        assert_eq!(queue.previous(), Some(&*(HISTORY + 9).to_string()));
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+const STREAM_SCHEMES: &[&str] = &[
+    "rtsp", "rtsps", "rtmp", "rtmps", "rtp", "udp", "srt", "mms", "tcp",
+];
+const MEDIA_EXTENSIONS: &[&str] = &[
+    "mp4", "mkv", "webm", "avi", "mov", "m4v", "ts", "m3u8", "mpd", "mp3", "ogg", "opus", "flac",
+    "wav", "aac", "m4a", "gif",
+];
+/// Prefix for files in the configured media library
+const MEDIA_SCHEME: &str = "media:";
+
+#[derive(Debug)]
+pub enum SourceError {
+    NoMediaLibrary,
+    NotInLibrary(String),
+    Unsupported(String),
+}
+
+impl std::fmt::Display for SourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoMediaLibrary => write!(f, "No media library configured"),
+            Self::NotInLibrary(name) => write!(f, "{name} is not in the media library"),
+            Self::Unsupported(url) => write!(f, "Don't know how to play {url}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SourceKind {
+    Local,
+    Stream,
+    Ytdl,
+}
+
+/// Where a video comes from, which decides how mpv is asked to open it.
+#[derive(Clone, Debug)]
+pub enum Source {
+    /// File in the media library
+    Local(PathBuf),
+    /// URL mpv can open directly, like RTSP cameras or plain HTTP media files
+    Stream(String),
+    /// Web page that needs youtube-dl to extract the media
+    Ytdl(String),
+}
+
+fn has_media_extension(path: &str) -> bool {
+    let path = path
+        .split(|ch| ch == '?' || ch == '#')
+        .next()
+        .unwrap_or(path);
+    Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map_or(false, |extension| {
+            MEDIA_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+        })
+}
+
+fn library_path(media: Option<&Path>, name: &str) -> Result<PathBuf, SourceError> {
+    let media = media
+        .ok_or(SourceError::NoMediaLibrary)?
+        .canonicalize()
+        .map_err(|_| SourceError::NoMediaLibrary)?;
+    media
+        .join(name)
+        .canonicalize()
+        .ok()
+        .filter(|path| path.starts_with(&media) && path.is_file())
+        .ok_or_else(|| SourceError::NotInLibrary(name.to_owned()))
+}
+
+impl Source {
+    pub fn parse(url: &str, media: Option<&Path>) -> Result<Self, SourceError> {
+        if let Some(url) = url.strip_prefix("ytdl://") {
+            return Ok(Self::Ytdl(url.to_owned()));
+        }
+        if let Some(name) = url.strip_prefix(MEDIA_SCHEME) {
+            return library_path(media, name).map(Self::Local);
+        }
+        match url.split_once("://") {
+            Some((scheme, _)) if STREAM_SCHEMES.contains(&scheme) => {
+                Ok(Self::Stream(url.to_owned()))
+            }
+            Some(("http", _)) | Some(("https", _)) => {
+                if has_media_extension(url) {
+                    Ok(Self::Stream(url.to_owned()))
+                } else {
+                    Ok(Self::Ytdl(url.to_owned()))
+                }
+            }
+            Some(_) => Err(SourceError::Unsupported(url.to_owned())),
+            // bare names are library files, or whatever youtube-dl makes of them (like video IDs)
+            None => Ok(library_path(media, url)
+                .map(Self::Local)
+                .unwrap_or_else(|_| Self::Ytdl(url.to_owned()))),
+        }
+    }
+
+    pub fn kind(&self) -> SourceKind {
+        match self {
+            Self::Local(_) => SourceKind::Local,
+            Self::Stream(_) => SourceKind::Stream,
+            Self::Ytdl(_) => SourceKind::Ytdl,
+        }
+    }
+
+    /// The argument for mpv's `loadfile` command.
+    pub fn mpv_url(&self) -> String {
+        match self {
+            Self::Local(path) => path.to_string_lossy().into_owned(),
+            Self::Stream(url) => url.clone(),
+            Self::Ytdl(url) => format!("ytdl://{url}"),
+        }
+    }
+}
+
+fn collect_media(root: &Path, directory: &Path, media: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            collect_media(root, &path, media)?;
+        } else if let Some(name) = path
+            .strip_prefix(root)
+            .ok()
+            .and_then(|name| name.to_str())
+            .filter(|name| has_media_extension(name))
+        {
+            media.push(format!("{MEDIA_SCHEME}{name}"));
+        }
+    }
+    Ok(())
+}
+
+/// Lists all playable files in the media library, as URLs accepted by `Source::parse`.
+pub fn list_media(root: impl AsRef<Path>) -> io::Result<Vec<String>> {
+    let root = root.as_ref();
+    let mut media = Vec::new();
+    collect_media(root, root, &mut media)?;
+    media.sort();
+    Ok(media)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    /// A media library with a video, one in a subdirectory, and a file that isn't media.
+    fn library(directory: &TempDir) -> PathBuf {
+        let root = directory.join("media");
+        fs::create_dir_all(root.join("talks")).unwrap();
+        fs::write(root.join("loop.mp4"), b"").unwrap();
+        fs::write(root.join("talks/Opening.MKV"), b"").unwrap();
+        fs::write(root.join("notes.txt"), b"").unwrap();
+        root
+    }
+
+    fn kind(url: &str) -> Option<SourceKind> {
+        Source::parse(url, None).ok().map(|source| source.kind())
+    }
+
+    #[test]
+    fn urls_are_sorted_by_how_mpv_opens_them() {
+        assert_eq!(kind("rtsp://camera/stream"), Some(SourceKind::Stream));
+        assert_eq!(
+            kind("https://host/video.webm?t=1"),
+            Some(SourceKind::Stream)
+        );
+        assert_eq!(kind("http://host/live.M3U8"), Some(SourceKind::Stream));
+        assert_eq!(
+            kind("https://youtube.com/watch?v=x.mp4"),
+            Some(SourceKind::Ytdl)
+        );
+        assert_eq!(kind("https://vimeo.com/123"), Some(SourceKind::Ytdl));
+        assert_eq!(kind("ytdl://https://host/a.mp4"), Some(SourceKind::Ytdl));
+        // bare names without a library are left to youtube-dl
+        assert_eq!(kind("dQw4w9WgXcQ"), Some(SourceKind::Ytdl));
+        assert!(matches!(
+            Source::parse("file:///etc/passwd", None),
+            Err(SourceError::Unsupported(_))
+        ));
+        assert!(matches!(
+            Source::parse("media:loop.mp4", None),
+            Err(SourceError::NoMediaLibrary)
+        ));
+    }
+
+    #[test]
+    fn mpv_urls() {
+        let ytdl = Source::parse("ytdl://dQw4w9WgXcQ", None).unwrap();
+        assert_eq!(ytdl.mpv_url(), "ytdl://dQw4w9WgXcQ");
+        let stream = Source::parse("rtmp://host/live", None).unwrap();
+        assert_eq!(stream.mpv_url(), "rtmp://host/live");
+    }
+
+    #[test]
+    fn library_files_stay_in_the_library() {
+        let directory = TempDir::new("media");
+        let root = library(&directory);
+        let media = Some(root.as_path());
+        let root = root.canonicalize().unwrap();
+        match Source::parse("media:talks/Opening.MKV", media).unwrap() {
+            Source::Local(path) => assert_eq!(path, root.join("talks/Opening.MKV")),
+            _ => panic!("expected a library file"),
+        }
+        assert_eq!(
+            Source::parse("loop.mp4", media).unwrap().kind(),
+            SourceKind::Local
+        );
+        for name in &["media:missing.mp4", "media:talks", "media:../loop.mp4"] {
+            assert!(
+                matches!(
+                    Source::parse(name, media),
+                    Err(SourceError::NotInLibrary(_))
+                ),
+                "{}",
+                name
+            );
+        }
+        fs::write(directory.join("outside.mp4"), b"").unwrap();
+        assert!(Source::parse("media:../outside.mp4", media).is_err());
+    }
+
+    #[test]
+    fn media_is_listed() {
+        let directory = TempDir::new("media");
+        let root = library(&directory);
+        assert_eq!(
+            list_media(&root).unwrap(),
+            ["media:loop.mp4", "media:talks/Opening.MKV"]
+        );
+    }
+}
@@ -0,0 +1,782 @@
+use glium::{
+    backend::glutin::Display,
+    texture::{texture2d::Texture2d, RawImage2d},
+    Surface,
+};
+use log::{error, info};
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, Shutdown, SocketAddr, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use super::{
+    protocol_error, read_string, read_u16, read_u32, read_u8, skip, vnc_auth_response, PIXEL_FORMAT,
+};
+use crate::blit::{Blit, Placement};
+
+const DEFAULT_PORT: u16 = 5900;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest screen accepted, as its size comes from the server
+const MAX_SIZE: u16 = 8192;
+
+const ENCODING_RAW: i32 = 0;
+const ENCODING_COPY_RECT: i32 = 1;
+const ENCODING_DESKTOP_SIZE: i32 = -223;
+
+/// The remote screen as received so far.
+struct Framebuffer {
+    width: u16,
+    height: u16,
+    /// RGBA, top row first
+    pixels: Vec<u8>,
+    dirty: bool,
+    /// Why the connection ended
+    closed: Option<String>,
+}
+
+impl Framebuffer {
+    fn new(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * 4],
+            dirty: true,
+            closed: None,
+        }
+    }
+
+    /// A screen of the size announced by the server.
+    fn announced(width: u16, height: u16) -> io::Result<Self> {
+        if width > MAX_SIZE || height > MAX_SIZE {
+            return Err(protocol_error(format!(
+                "Screen of {}x{} is too large",
+                width, height
+            )));
+        }
+        Ok(Self::new(width, height))
+    }
+
+    fn contains(&self, x: u16, y: u16, width: u16, height: u16) -> bool {
+        x as u32 + width as u32 <= self.width as u32
+            && y as u32 + height as u32 <= self.height as u32
+    }
+
+    fn copy_rect(&mut self, from: (u16, u16), to: (u16, u16), width: u16, height: u16) {
+        let stride = self.width as usize * 4;
+        let row_length = width as usize * 4;
+        let rows: Vec<u16> = if from.1 < to.1 {
+            (0..height).rev().collect()
+        } else {
+            (0..height).collect()
+        };
+        for row in rows {
+            let source = (from.1 + row) as usize * stride + from.0 as usize * 4;
+            let destination = (to.1 + row) as usize * stride + to.0 as usize * 4;
+            self.pixels
+                .copy_within(source..source + row_length, destination);
+        }
+    }
+}
+
+fn read_failure(stream: &mut TcpStream) -> io::Error {
+    match read_string(stream) {
+        Ok(reason) => protocol_error(format!("Server refused connection: {}", reason)),
+        Err(err) => err,
+    }
+}
+
+/// Negotiates version and security, returns the framebuffer size and desktop name.
+fn handshake(
+    stream: &mut TcpStream,
+    password: Option<&str>,
+    shared: bool,
+) -> io::Result<(u16, u16, String)> {
+    let mut version = [0u8; 12];
+    stream.read_exact(&mut version)?;
+    let minor = std::str::from_utf8(&version)
+        .ok()
+        .and_then(|version| version.strip_prefix("RFB 003."))
+        .and_then(|minor| minor.trim_end().parse::<u32>().ok())
+        .ok_or_else(|| protocol_error("Not an RFB server".to_owned()))?;
+    let minor = if minor >= 8 {
+        8
+    } else if minor == 7 {
+        7
+    } else {
+        3
+    };
+    stream.write_all(format!("RFB 003.{:03}\n", minor).as_bytes())?;
+
+    let security = if minor >= 7 {
+        let count = read_u8(stream)?;
+        if count == 0 {
+            return Err(read_failure(stream));
+        }
+        let mut types = vec![0u8; count as usize];
+        stream.read_exact(&mut types)?;
+        let security = if types.contains(&1) {
+            1
+        } else if types.contains(&2) && password.is_some() {
+            2
+        } else {
+            return Err(protocol_error(format!(
+                "No supported security type in {:?}",
+                types
+            )));
+        };
+        stream.write_all(&[security])?;
+        security
+    } else {
+        match read_u32(stream)? {
+            0 => return Err(read_failure(stream)),
+            security => security as u8,
+        }
+    };
+    match security {
+        1 => {}
+        2 => {
+            let password =
+                password.ok_or_else(|| protocol_error("Server requires a password".to_owned()))?;
+            let mut challenge = [0u8; 16];
+            stream.read_exact(&mut challenge)?;
+            stream.write_all(&vnc_auth_response(password, &challenge))?;
+        }
+        security => {
+            return Err(protocol_error(format!(
+                "Unsupported security type {}",
+                security
+            )))
+        }
+    }
+    // 3.8 reports the result of every security type, older versions only of authentication
+    if (security != 1 || minor >= 8) && read_u32(stream)? != 0 {
+        return Err(if minor >= 8 {
+            read_failure(stream)
+        } else {
+            protocol_error("Authentication failed".to_owned())
+        });
+    }
+
+    stream.write_all(&[shared as u8])?;
+    let width = read_u16(stream)?;
+    let height = read_u16(stream)?;
+    skip(stream, 16)?;
+    let name = read_string(stream)?;
+
+    let mut set_pixel_format = vec![0u8, 0, 0, 0];
+    set_pixel_format.extend_from_slice(&PIXEL_FORMAT);
+    stream.write_all(&set_pixel_format)?;
+    let encodings = [ENCODING_COPY_RECT, ENCODING_RAW, ENCODING_DESKTOP_SIZE];
+    let mut set_encodings = vec![2u8, 0];
+    set_encodings.extend_from_slice(&(encodings.len() as u16).to_be_bytes());
+    for encoding in &encodings {
+        set_encodings.extend_from_slice(&encoding.to_be_bytes());
+    }
+    stream.write_all(&set_encodings)?;
+    Ok((width, height, name))
+}
+
+fn request_update(
+    connection: &Connection,
+    incremental: bool,
+    width: u16,
+    height: u16,
+) -> io::Result<()> {
+    let mut message = vec![3u8, incremental as u8, 0, 0, 0, 0];
+    message.extend_from_slice(&width.to_be_bytes());
+    message.extend_from_slice(&height.to_be_bytes());
+    connection.send(&message)
+}
+
+/// Reads one `FramebufferUpdate` into `framebuffer`.
+fn read_update(stream: &mut TcpStream, framebuffer: &Mutex<Framebuffer>) -> io::Result<()> {
+    skip(stream, 1)?;
+    let rectangles = read_u16(stream)?;
+    for _ in 0..rectangles {
+        let x = read_u16(stream)?;
+        let y = read_u16(stream)?;
+        let width = read_u16(stream)?;
+        let height = read_u16(stream)?;
+        let encoding = read_u32(stream)? as i32;
+        match encoding {
+            ENCODING_RAW => {
+                // checked before allocating, the size comes from the server
+                if !framebuffer.lock().unwrap().contains(x, y, width, height) {
+                    return Err(protocol_error("Rectangle outside the screen".to_owned()));
+                }
+                let mut data = vec![0u8; width as usize * height as usize * 4];
+                stream.read_exact(&mut data)?;
+                let mut framebuffer = framebuffer.lock().unwrap();
+                let stride = framebuffer.width as usize * 4;
+                for (row, line) in data.chunks_exact((width as usize * 4).max(4)).enumerate() {
+                    let start = (y as usize + row) * stride + x as usize * 4;
+                    let target = &mut framebuffer.pixels[start..start + line.len()];
+                    target.copy_from_slice(line);
+                    for pixel in target.chunks_exact_mut(4) {
+                        pixel[3] = 255;
+                    }
+                }
+            }
+            ENCODING_COPY_RECT => {
+                let source_x = read_u16(stream)?;
+                let source_y = read_u16(stream)?;
+                let mut framebuffer = framebuffer.lock().unwrap();
+                if !framebuffer.contains(x, y, width, height)
+                    || !framebuffer.contains(source_x, source_y, width, height)
+                {
+                    return Err(protocol_error("Rectangle outside the screen".to_owned()));
+                }
+                framebuffer.copy_rect((source_x, source_y), (x, y), width, height);
+            }
+            ENCODING_DESKTOP_SIZE => {
+                *framebuffer.lock().unwrap() = Framebuffer::announced(width, height)?;
+            }
+            encoding => {
+                return Err(protocol_error(format!("Unexpected encoding {}", encoding)));
+            }
+        }
+    }
+    framebuffer.lock().unwrap().dirty = true;
+    Ok(())
+}
+
+/// Handles server messages until the connection fails or is shut down.
+fn receive(mut stream: TcpStream, connection: &Connection) -> io::Result<()> {
+    loop {
+        match read_u8(&mut stream)? {
+            0 => {
+                read_update(&mut stream, &connection.framebuffer)?;
+                let (width, height) = {
+                    let framebuffer = connection.framebuffer.lock().unwrap();
+                    (framebuffer.width, framebuffer.height)
+                };
+                request_update(connection, true, width, height)?;
+            }
+            1 => {
+                // colour map entries, meaningless with true colour
+                skip(&mut stream, 3)?;
+                let count = read_u16(&mut stream)?;
+                skip(&mut stream, count as u64 * 6)?;
+            }
+            // bell
+            2 => {}
+            3 => {
+                // clipboard
+                skip(&mut stream, 3)?;
+                let length = read_u32(&mut stream)?;
+                skip(&mut stream, length as u64)?;
+            }
+            message => {
+                return Err(protocol_error(format!(
+                    "Unexpected message type {}",
+                    message
+                )))
+            }
+        }
+    }
+}
+
+/// Connects to `address` and negotiates the connection, returns the stream and the framebuffer
+/// size and desktop name.
+fn open(
+    address: &str,
+    password: Option<&str>,
+    shared: bool,
+) -> io::Result<(TcpStream, u16, u16, String)> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "Server not found");
+    let mut stream = None;
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(connected) => {
+                stream = Some(connected);
+                break;
+            }
+            Err(err) => last_error = err,
+        }
+    }
+    let mut stream = stream.ok_or(last_error)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    let (width, height, name) = handshake(&mut stream, password, shared)?;
+    stream.set_read_timeout(None)?;
+    Ok((stream, width, height, name))
+}
+
+/// Where input for the server goes, once connected.
+#[derive(Default)]
+struct Writer {
+    stream: Option<TcpStream>,
+    /// Set when the client is dropped, so a connection made afterwards is closed right away
+    dropped: bool,
+}
+
+/// State shared with the thread talking to the server.
+struct Connection {
+    writer: Mutex<Writer>,
+    framebuffer: Mutex<Framebuffer>,
+}
+
+impl Connection {
+    /// Sends `message` to the server.
+    fn send(&self, message: &[u8]) -> io::Result<()> {
+        match &mut self.writer.lock().unwrap().stream {
+            Some(stream) => stream.write_all(message),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Not connected to the VNC server yet",
+            )),
+        }
+    }
+
+    /// Connects and handles server messages until the connection fails or is shut down.
+    fn run(&self, address: &str, password: Option<&str>, shared: bool) -> io::Result<()> {
+        let (stream, width, height, name) = open(address, password, shared)?;
+        info!("Connected to VNC server {} ({}x{})", name, width, height);
+        {
+            let mut writer = self.writer.lock().unwrap();
+            if writer.dropped {
+                stream.shutdown(Shutdown::Both).ok();
+                return Ok(());
+            }
+            writer.stream = Some(stream.try_clone()?);
+        }
+        *self.framebuffer.lock().unwrap() = Framebuffer::announced(width, height)?;
+        request_update(self, false, width, height)?;
+        receive(stream, self)
+    }
+}
+
+/// `server` as `host:port`, with the default port if it's left out. IPv6 addresses may be given
+/// bare or in brackets.
+pub fn address(server: &str) -> io::Result<String> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid VNC server {}", server),
+        )
+    };
+    if let Ok(address) = server.parse::<SocketAddr>() {
+        return Ok(address.to_string());
+    }
+    let bare = server
+        .strip_prefix('[')
+        .and_then(|server| server.strip_suffix(']'))
+        .unwrap_or(server);
+    if let Ok(ip) = bare.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, DEFAULT_PORT).to_string());
+    }
+    let (host, port) = match server.split_once(':') {
+        Some((host, port)) => (host, port.parse::<u16>().map_err(|_| invalid())?),
+        None => (server, DEFAULT_PORT),
+    };
+    if host.is_empty() || host.contains(|c: char| c.is_whitespace() || "[]/@".contains(c)) {
+        return Err(invalid());
+    }
+    Ok(format!("{}:{}", host, port))
+}
+
+/// The address of the server to connect to, the `requested` one or else the `configured` one,
+/// and whether it's the configured one. Only the configured and the `allowed` servers may be
+/// connected to.
+pub fn choose(
+    requested: Option<&str>,
+    configured: Option<&str>,
+    allowed: &[String],
+) -> io::Result<(String, bool)> {
+    let configured = configured.map(address).transpose()?;
+    let server = match requested.or(configured.as_deref()) {
+        Some(server) => address(server)?,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No VNC server given",
+            ))
+        }
+    };
+    if configured.as_ref() == Some(&server) {
+        return Ok((server, true));
+    }
+    for other in allowed {
+        if address(other)? == server {
+            return Ok((server, false));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("VNC server {} is not allowed", server),
+    ))
+}
+
+/// Shows the screen of a VNC server and forwards input to it.
+pub struct Client {
+    pub server: String,
+    connection: Arc<Connection>,
+    texture: Option<Texture2d>,
+}
+
+impl Client {
+    /// Connects to `server` (see `address`) in the background and starts receiving updates once
+    /// connected.
+    pub fn connect(server: &str, password: Option<&str>, shared: bool) -> io::Result<Client> {
+        let address = address(server)?;
+        let connection = Arc::new(Connection {
+            writer: Mutex::new(Writer::default()),
+            framebuffer: Mutex::new(Framebuffer::new(0, 0)),
+        });
+        {
+            let connection = connection.clone();
+            let address = address.clone();
+            let password = password.map(str::to_owned);
+            thread::Builder::new()
+                .name("VNC client".to_owned())
+                .spawn(move || {
+                    let result = connection.run(&address, password.as_deref(), shared);
+                    connection.framebuffer.lock().unwrap().closed = Some(match result {
+                        Ok(()) => "Disconnected".to_owned(),
+                        Err(err) => err.to_string(),
+                    });
+                })?;
+        }
+        Ok(Client {
+            server: address,
+            connection,
+            texture: None,
+        })
+    }
+
+    /// Whether the handshake is done and the screen is being received.
+    pub fn is_connected(&self) -> bool {
+        self.connection.writer.lock().unwrap().stream.is_some() && self.closed().is_none()
+    }
+
+    /// Why the connection ended, `None` while it's up.
+    pub fn closed(&self) -> Option<String> {
+        self.connection.framebuffer.lock().unwrap().closed.clone()
+    }
+
+    /// Moves the remote pointer to `x`, `y` as fractions of the screen from the top left, with
+    /// `buttons` as a bit mask of the pressed ones.
+    pub fn pointer(&self, x: f32, y: f32, buttons: u8) -> io::Result<()> {
+        let (width, height) = {
+            let framebuffer = self.connection.framebuffer.lock().unwrap();
+            (framebuffer.width, framebuffer.height)
+        };
+        let x = (x.clamp(0.0, 1.0) * (width.max(1) - 1) as f32).round() as u16;
+        let y = (y.clamp(0.0, 1.0) * (height.max(1) - 1) as f32).round() as u16;
+        let mut message = vec![5u8, buttons];
+        message.extend_from_slice(&x.to_be_bytes());
+        message.extend_from_slice(&y.to_be_bytes());
+        self.connection.send(&message)
+    }
+
+    pub fn key(&self, keysym: u32, down: bool) -> io::Result<()> {
+        let mut message = vec![4u8, down as u8, 0, 0];
+        message.extend_from_slice(&keysym.to_be_bytes());
+        self.connection.send(&message)
+    }
+
+    /// Uploads the screen if it changed.
+    pub fn update(&mut self, display: &Display) {
+        let mut framebuffer = self.connection.framebuffer.lock().unwrap();
+        if !framebuffer.dirty || framebuffer.width == 0 || framebuffer.height == 0 {
+            return;
+        }
+        framebuffer.dirty = false;
+        let image = RawImage2d::from_raw_rgba_reversed(
+            &framebuffer.pixels,
+            (framebuffer.width as u32, framebuffer.height as u32),
+        );
+        match Texture2d::new(display, image) {
+            Ok(texture) => self.texture = Some(texture),
+            Err(err) => error!("Failed uploading VNC screen: {}", err),
+        }
+    }
+
+    /// Draws the screen as large as it fits, keeping its aspect ratio.
+    pub fn draw<S: Surface>(&self, target: &mut S, size: (u32, u32), blit: &Blit) {
+        if let Some(texture) = &self.texture {
+            let (width, height) = texture.dimensions();
+            let scale = (size.0 as f32 / width as f32).min(size.1 as f32 / height as f32);
+            let width = width as f32 * scale / size.0 as f32;
+            let height = height as f32 * scale / size.1 as f32;
+            blit.draw_with(
+                target,
+                texture,
+                &Placement {
+                    bounds: [(1.0 - width) / 2.0, (1.0 - height) / 2.0, width, height],
+                    ..Default::default()
+                },
+            );
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        // ends the receiving thread
+        let mut writer = self.connection.writer.lock().unwrap();
+        writer.dropped = true;
+        if let Some(stream) = &writer.stream {
+            stream.shutdown(Shutdown::Both).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, time::Instant};
+
+    /// Serves a single connection with `serve`, returns the address to connect to.
+    fn stand_in<F: FnOnce(TcpStream) + Send + 'static>(serve: F) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || serve(listener.accept().unwrap().0));
+        address
+    }
+
+    /// Sends the protocol `version` and checks that the client answers with it.
+    fn exchange_versions(stream: &mut TcpStream, version: &str) {
+        stream.write_all(version.as_bytes()).unwrap();
+        let mut answer = [0u8; 12];
+        stream.read_exact(&mut answer).unwrap();
+        assert_eq!(&answer, version.as_bytes());
+    }
+
+    /// Asks for the password and checks the answer, returns whether it was right.
+    fn authenticate(stream: &mut TcpStream, password: &str) -> bool {
+        let challenge = [0x5a; 16];
+        stream.write_all(&challenge).unwrap();
+        let mut response = [0u8; 16];
+        stream.read_exact(&mut response).unwrap();
+        response == vnc_auth_response(password, &challenge)
+    }
+
+    /// Sends a `width`×`height` screen and checks the messages the client sends in return.
+    fn initialize(stream: &mut TcpStream, width: u16, height: u16) {
+        assert_eq!(read_u8(stream).unwrap(), 1, "shared flag");
+        stream.write_all(&width.to_be_bytes()).unwrap();
+        stream.write_all(&height.to_be_bytes()).unwrap();
+        stream.write_all(&[0; 16]).unwrap();
+        stream.write_all(&4u32.to_be_bytes()).unwrap();
+        stream.write_all(b"wall").unwrap();
+        let mut set_pixel_format = [0u8; 20];
+        stream.read_exact(&mut set_pixel_format).unwrap();
+        assert_eq!(set_pixel_format[0], 0);
+        assert_eq!(set_pixel_format[4..], PIXEL_FORMAT);
+        let mut set_encodings = [0u8; 4 + 3 * 4];
+        stream.read_exact(&mut set_encodings).unwrap();
+        assert_eq!(set_encodings[..4], [2, 0, 0, 3]);
+    }
+
+    #[test]
+    fn handshake_3_3_with_password() {
+        let address = stand_in(|mut stream| {
+            exchange_versions(&mut stream, "RFB 003.003\n");
+            stream.write_all(&2u32.to_be_bytes()).unwrap();
+            assert!(authenticate(&mut stream, "secret"));
+            stream.write_all(&0u32.to_be_bytes()).unwrap();
+            initialize(&mut stream, 640, 480);
+        });
+        let (_, width, height, name) = open(&address, Some("secret"), true).unwrap();
+        assert_eq!((width, height, name.as_str()), (640, 480, "wall"));
+    }
+
+    #[test]
+    fn handshake_3_7_without_security() {
+        let address = stand_in(|mut stream| {
+            exchange_versions(&mut stream, "RFB 003.007\n");
+            stream.write_all(&[2, 2, 1]).unwrap();
+            assert_eq!(read_u8(&mut stream).unwrap(), 1);
+            // no security result before 3.8
+            initialize(&mut stream, 320, 200);
+        });
+        let (_, width, height, _) = open(&address, Some("secret"), true).unwrap();
+        assert_eq!((width, height), (320, 200));
+    }
+
+    #[test]
+    fn handshake_3_8_with_password() {
+        let address = stand_in(|mut stream| {
+            exchange_versions(&mut stream, "RFB 003.008\n");
+            stream.write_all(&[1, 2]).unwrap();
+            assert_eq!(read_u8(&mut stream).unwrap(), 2);
+            assert!(authenticate(&mut stream, "secret"));
+            stream.write_all(&0u32.to_be_bytes()).unwrap();
+            initialize(&mut stream, 1920, 1080);
+        });
+        let (_, width, height, _) = open(&address, Some("secret"), true).unwrap();
+        assert_eq!((width, height), (1920, 1080));
+    }
+
+    #[test]
+    fn handshake_newer_versions_speak_3_8() {
+        let address = stand_in(|mut stream| {
+            stream.write_all(b"RFB 003.889\n").unwrap();
+            let mut answer = [0u8; 12];
+            stream.read_exact(&mut answer).unwrap();
+            assert_eq!(&answer, b"RFB 003.008\n");
+            stream.write_all(&[1, 1]).unwrap();
+            assert_eq!(read_u8(&mut stream).unwrap(), 1);
+            stream.write_all(&0u32.to_be_bytes()).unwrap();
+            initialize(&mut stream, 8, 8);
+        });
+        assert!(open(&address, None, true).is_ok());
+    }
+
+    #[test]
+    fn handshake_3_8_reports_failed_authentication() {
+        let address = stand_in(|mut stream| {
+            exchange_versions(&mut stream, "RFB 003.008\n");
+            stream.write_all(&[1, 2]).unwrap();
+            assert_eq!(read_u8(&mut stream).unwrap(), 2);
+            assert!(!authenticate(&mut stream, "secret"));
+            stream.write_all(&1u32.to_be_bytes()).unwrap();
+            stream.write_all(&12u32.to_be_bytes()).unwrap();
+            stream.write_all(b"Bad password").unwrap();
+        });
+        let err = open(&address, Some("wrong"), true).unwrap_err();
+        assert!(err.to_string().contains("Bad password"), "{}", err);
+    }
+
+    #[test]
+    fn handshake_needs_password_for_vnc_auth() {
+        let address = stand_in(|mut stream| {
+            exchange_versions(&mut stream, "RFB 003.008\n");
+            stream.write_all(&[1, 2]).unwrap();
+        });
+        assert!(open(&address, None, true).is_err());
+    }
+
+    #[test]
+    fn raw_and_copy_rect_updates() {
+        let address = stand_in(|mut stream| {
+            exchange_versions(&mut stream, "RFB 003.008\n");
+            stream.write_all(&[1, 1]).unwrap();
+            assert_eq!(read_u8(&mut stream).unwrap(), 1);
+            stream.write_all(&0u32.to_be_bytes()).unwrap();
+            initialize(&mut stream, 2, 2);
+            let mut request = [0u8; 10];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(request, [3, 0, 0, 0, 0, 0, 0, 2, 0, 2]);
+
+            let mut update = vec![0u8, 0, 0, 2];
+            // raw top row
+            update.extend_from_slice(&[0, 0, 0, 0, 0, 2, 0, 1]);
+            update.extend_from_slice(&ENCODING_RAW.to_be_bytes());
+            update.extend_from_slice(&[1, 2, 3, 0, 4, 5, 6, 0]);
+            // copied to the bottom row
+            update.extend_from_slice(&[0, 0, 0, 1, 0, 2, 0, 1]);
+            update.extend_from_slice(&ENCODING_COPY_RECT.to_be_bytes());
+            update.extend_from_slice(&[0, 0, 0, 0]);
+            stream.write_all(&update).unwrap();
+            // the next request is incremental, then wait for the client to go away
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(request[1], 1);
+            while read_u8(&mut stream).is_ok() {}
+        });
+
+        let client = Client::connect(&address, None, true).unwrap();
+        let expected = [1, 2, 3, 255, 4, 5, 6, 255, 1, 2, 3, 255, 4, 5, 6, 255];
+        let started = Instant::now();
+        loop {
+            assert!(client.closed().is_none(), "{:?}", client.closed());
+            if client.connection.framebuffer.lock().unwrap().pixels == expected {
+                break;
+            }
+            assert!(started.elapsed() < Duration::from_secs(5), "No update");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(client.is_connected());
+    }
+
+    #[test]
+    fn rectangles_outside_the_screen_are_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut server = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut client = listener.accept().unwrap().0;
+        // a huge raw rectangle without its pixels, it must not be allocated
+        let mut update = vec![0u8, 0, 1];
+        update.extend_from_slice(&[0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+        update.extend_from_slice(&ENCODING_RAW.to_be_bytes());
+        server.write_all(&update).unwrap();
+        let framebuffer = Mutex::new(Framebuffer::new(4, 4));
+        let err = read_update(&mut client, &framebuffer).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn input_before_connecting_fails() {
+        // nothing listens on the discard port of localhost
+        let client = Client::connect("127.0.0.1:9", None, true).unwrap();
+        assert!(client.key(0x61, true).is_err());
+        assert!(!client.is_connected());
+    }
+
+    #[test]
+    fn server_addresses() {
+        assert_eq!(address("wall").unwrap(), "wall:5900");
+        assert_eq!(address("wall:5901").unwrap(), "wall:5901");
+        assert_eq!(address("10.0.0.2").unwrap(), "10.0.0.2:5900");
+        assert_eq!(address("::1").unwrap(), "[::1]:5900");
+        assert_eq!(address("[fd00::2]").unwrap(), "[fd00::2]:5900");
+        assert_eq!(address("[fd00::2]:5901").unwrap(), "[fd00::2]:5901");
+        for invalid in [
+            "",
+            ":5900",
+            "wall:",
+            "wall:65536",
+            "fd00::2:x",
+            "a b",
+            "user@wall",
+        ] {
+            assert_eq!(
+                address(invalid).unwrap_err().kind(),
+                io::ErrorKind::InvalidInput,
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn only_configured_and_allowed_servers() {
+        let allowed = ["[fd00::2]".to_owned(), "other:5901".to_owned()];
+        assert_eq!(
+            choose(None, Some("wall"), &allowed).unwrap(),
+            ("wall:5900".to_owned(), true)
+        );
+        assert_eq!(
+            choose(Some("wall:5900"), Some("wall"), &allowed).unwrap(),
+            ("wall:5900".to_owned(), true)
+        );
+        assert_eq!(
+            choose(Some("fd00::2"), Some("wall"), &allowed).unwrap(),
+            ("[fd00::2]:5900".to_owned(), false)
+        );
+        assert_eq!(
+            choose(Some("other:5901"), None, &allowed).unwrap(),
+            ("other:5901".to_owned(), false)
+        );
+        for refused in ["other", "wall:5901", "127.0.0.1", "metadata.internal:80"] {
+            assert_eq!(
+                choose(Some(refused), Some("wall"), &allowed)
+                    .unwrap_err()
+                    .kind(),
+                io::ErrorKind::PermissionDenied,
+                "{}",
+                refused
+            );
+        }
+        assert_eq!(
+            choose(None, None, &allowed).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use des::{
+    cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit},
+    Des,
+};
+use std::io::{self, Read};
+
+mod client;
+mod server;
+
+pub use self::client::{choose, Client};
+pub use self::server::{Pointer, Server};
+
+/// Pixel format requested from servers: 32 bit true colour, little endian with red in the
+/// lowest byte, so pixels arrive as RGBX.
+const PIXEL_FORMAT: [u8; 16] = [32, 24, 0, 1, 0, 255, 0, 255, 0, 255, 0, 8, 16, 0, 0, 0];
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buffer = [0u8; 1];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer[0])
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut buffer = [0u8; 2];
+    reader.read_exact(&mut buffer)?;
+    Ok(u16::from_be_bytes(buffer))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buffer = [0u8; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_be_bytes(buffer))
+}
+
+/// Reads a string prefixed with its length, like failure reasons and desktop names.
+fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let length = read_u32(reader)?;
+    let mut buffer = Vec::new();
+    reader.take(length as u64).read_to_end(&mut buffer)?;
+    if buffer.len() != length as usize {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+fn skip<R: Read>(reader: &mut R, length: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(length), &mut io::sink())?;
+    if skipped == length {
+        Ok(())
+    } else {
+        Err(io::ErrorKind::UnexpectedEof.into())
+    }
+}
+
+/// Encrypts the server's challenge with the password as key, its bits mirrored as the protocol
+/// demands.
+fn vnc_auth_response(password: &str, challenge: &[u8; 16]) -> [u8; 16] {
+    let mut key = [0u8; 8];
+    for (byte, ch) in key.iter_mut().zip(password.bytes()) {
+        *byte = ch.reverse_bits();
+    }
+    let cipher = Des::new_from_slice(&key).unwrap();
+    let mut response = [0u8; 16];
+    for (output, input) in response.chunks_mut(8).zip(challenge.chunks(8)) {
+        let mut block = GenericArray::clone_from_slice(input);
+        cipher.encrypt_block(&mut block);
+        output.copy_from_slice(&block);
+    }
+    response
+}
+
+fn protocol_error(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// X11 keysym of a `KeyboardEvent.key` value from a browser.
+pub fn keysym(key: &str) -> Option<u32> {
+    let mut chars = key.chars();
+    if let (Some(ch), None) = (chars.next(), chars.next()) {
+        let code = ch as u32;
+        return Some(if (0x20..0x100).contains(&code) {
+            code
+        } else {
+            0x0100_0000 + code
+        });
+    }
+    if let Some(number) = key.strip_prefix('F').and_then(|n| n.parse::<u32>().ok()) {
+        if (1..=35).contains(&number) {
+            return Some(0xffbd + number);
+        }
+    }
+    Some(match key {
+        "Backspace" => 0xff08,
+        "Tab" => 0xff09,
+        "Enter" => 0xff0d,
+        "Escape" => 0xff1b,
+        "Delete" => 0xffff,
+        "Home" => 0xff50,
+        "ArrowLeft" => 0xff51,
+        "ArrowUp" => 0xff52,
+        "ArrowRight" => 0xff53,
+        "ArrowDown" => 0xff54,
+        "PageUp" => 0xff55,
+        "PageDown" => 0xff56,
+        "End" => 0xff57,
+        "Insert" => 0xff63,
+        "Shift" => 0xffe1,
+        "Control" => 0xffe3,
+        "Meta" => 0xffe7,
+        "Alt" => 0xffe9,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auth_response_mirrors_key_bits() {
+        let mut challenge = [0u8; 16];
+        for (i, byte) in challenge.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        assert_eq!(
+            vnc_auth_response("password", &challenge),
+            [
+                0xb8, 0x66, 0x92, 0x41, 0x25, 0xc8, 0xee, 0xbb, 0x9d, 0xeb, 0xc1, 0xdb, 0x61, 0xc5,
+                0x38, 0xe2
+            ]
+        );
+    }
+
+    #[test]
+    fn auth_response_uses_first_eight_characters() {
+        let challenge = [7u8; 16];
+        assert_eq!(
+            vnc_auth_response("password", &challenge),
+            vnc_auth_response("password123", &challenge)
+        );
+    }
+
+    #[test]
+    fn keysyms() {
+        assert_eq!(keysym("a"), Some(0x61));
+        assert_eq!(keysym("€"), Some(0x0100_20ac));
+        assert_eq!(keysym("F1"), Some(0xffbe));
+        assert_eq!(keysym("Enter"), Some(0xff0d));
+        assert_eq!(keysym("F36"), None);
+        assert_eq!(keysym("Hyper"), None);
+    }
+}
@@ -0,0 +1,601 @@
+use glium::texture::{pixel_buffer::PixelBuffer, texture2d::Texture2d, RawImage2d};
+use log::{error, info};
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{protocol_error, read_u16, read_u32, read_u8, skip, vnc_auth_response, PIXEL_FORMAT};
+use crate::{capture::Frame, config};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Viewers served at once, each has two threads of its own
+const MAX_VIEWERS: usize = 8;
+const DESKTOP_NAME: &str = "Blinkenwall";
+
+/// Pointer input of an interactive viewer.
+#[derive(Clone, Copy, Debug)]
+pub struct Pointer {
+    /// Pixels from the top left of the frame
+    pub x: u16,
+    pub y: u16,
+    /// Bit mask of the pressed buttons, 1 is the left one
+    pub buttons: u8,
+}
+
+/// True colour pixel format a viewer asked for.
+#[derive(Clone, Copy)]
+struct PixelFormat {
+    bytes: usize,
+    big_endian: bool,
+    max: [u32; 3],
+    shift: [u8; 3],
+}
+
+impl PixelFormat {
+    fn parse(data: &[u8; 16]) -> io::Result<Self> {
+        if data[3] == 0 {
+            return Err(protocol_error("Colour maps are not supported".to_owned()));
+        }
+        let bytes = match data[0] {
+            8 | 16 | 32 => data[0] as usize / 8,
+            bits => return Err(protocol_error(format!("Unsupported pixel size {}", bits))),
+        };
+        let max = |index: usize| u16::from_be_bytes([data[index], data[index + 1]]) as u32;
+        let format = Self {
+            bytes,
+            big_endian: data[2] != 0,
+            max: [max(4), max(6), max(8)],
+            shift: [data[10], data[11], data[12]],
+        };
+        let bits = bytes as u32 * 8;
+        for (&max, &shift) in format.max.iter().zip(&format.shift) {
+            if shift as u32 >= bits || (max as u64) << shift >= 1 << bits {
+                return Err(protocol_error(format!(
+                    "Colour with maximum {} shifted by {} doesn't fit {} bits",
+                    max, shift, bits
+                )));
+            }
+        }
+        Ok(format)
+    }
+
+    /// Appends the RGB `frame` in this format to `out`.
+    fn encode(&self, frame: &Frame, out: &mut Vec<u8>) {
+        out.reserve(frame.pixels.len() / 3 * self.bytes);
+        for rgb in frame.pixels.chunks_exact(3) {
+            let mut value = 0u32;
+            for ((&component, max), shift) in rgb.iter().zip(&self.max).zip(&self.shift) {
+                value |= ((component as u32 * max + 127) / 255) << shift;
+            }
+            match (self.bytes, self.big_endian) {
+                (1, _) => out.push(value as u8),
+                (2, true) => out.extend_from_slice(&(value as u16).to_be_bytes()),
+                (2, false) => out.extend_from_slice(&(value as u16).to_le_bytes()),
+                (_, true) => out.extend_from_slice(&value.to_be_bytes()),
+                (_, false) => out.extend_from_slice(&value.to_le_bytes()),
+            }
+        }
+    }
+}
+
+/// What the receiving thread of a viewer passes on to the sending one.
+enum Event {
+    Format(PixelFormat),
+    Request { incremental: bool },
+}
+
+/// Latest frame for the viewers, which wait on `updated` for the next one.
+#[derive(Default)]
+struct Shared {
+    /// Frame number and frame
+    frame: Mutex<(u64, Option<Arc<Frame>>)>,
+    updated: Condvar,
+    clients: AtomicUsize,
+}
+
+impl Shared {
+    /// Waits for a frame newer than `number`, or until the viewer is `closed`.
+    fn next_frame(&self, number: u64, closed: &AtomicBool) -> Option<(u64, Arc<Frame>)> {
+        let mut frame = self.frame.lock().unwrap();
+        loop {
+            if closed.load(Ordering::SeqCst) {
+                return None;
+            }
+            if let (current, Some(next)) = &*frame {
+                if *current > number {
+                    return Some((*current, next.clone()));
+                }
+            }
+            frame = self.updated.wait(frame).unwrap();
+        }
+    }
+
+    /// Wakes every viewer waiting for a frame.
+    fn wake(&self) {
+        let _frame = self.frame.lock().unwrap();
+        self.updated.notify_all();
+    }
+}
+
+/// Counts a viewer as connected until it's dropped, even by a panic.
+struct Connected(Arc<Shared>);
+
+impl Connected {
+    /// `None` if `MAX_VIEWERS` are connected already.
+    fn admit(shared: &Arc<Shared>) -> Option<Self> {
+        shared
+            .clients
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                (count < MAX_VIEWERS).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| Self(shared.clone()))
+    }
+}
+
+impl Drop for Connected {
+    fn drop(&mut self) {
+        self.0.clients.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+struct Settings {
+    password: Option<String>,
+    interactive: bool,
+    size: (u16, u16),
+}
+
+/// Negotiates version and security with a viewer and sends the desktop's description.
+fn handshake(stream: &mut TcpStream, settings: &Settings) -> io::Result<()> {
+    stream.write_all(b"RFB 003.008\n")?;
+    let mut version = [0u8; 12];
+    stream.read_exact(&mut version)?;
+    let minor = std::str::from_utf8(&version)
+        .ok()
+        .and_then(|version| version.strip_prefix("RFB 003."))
+        .and_then(|minor| minor.trim_end().parse::<u32>().ok())
+        .ok_or_else(|| protocol_error("Not an RFB viewer".to_owned()))?;
+
+    let security = if settings.password.is_some() { 2 } else { 1 };
+    if minor >= 7 {
+        stream.write_all(&[1, security])?;
+        let chosen = read_u8(stream)?;
+        if chosen != security {
+            return Err(protocol_error(format!(
+                "Viewer chose security type {}",
+                chosen
+            )));
+        }
+    } else {
+        stream.write_all(&(security as u32).to_be_bytes())?;
+    }
+    let mut authenticated = true;
+    if let Some(password) = &settings.password {
+        let challenge: [u8; 16] = rand::random();
+        stream.write_all(&challenge)?;
+        let mut response = [0u8; 16];
+        stream.read_exact(&mut response)?;
+        authenticated = response == vnc_auth_response(password, &challenge);
+    }
+    // 3.8 reports the result of every security type, older versions only of authentication
+    if security == 2 || minor >= 8 {
+        stream.write_all(&(!authenticated as u32).to_be_bytes())?;
+    }
+    if !authenticated {
+        if minor >= 8 {
+            let reason = "Wrong password";
+            stream.write_all(&(reason.len() as u32).to_be_bytes())?;
+            stream.write_all(reason.as_bytes())?;
+        }
+        return Err(protocol_error("Wrong password".to_owned()));
+    }
+
+    // whether to share the desktop, which it always is
+    read_u8(stream)?;
+    let mut server_init = Vec::new();
+    server_init.extend_from_slice(&settings.size.0.to_be_bytes());
+    server_init.extend_from_slice(&settings.size.1.to_be_bytes());
+    server_init.extend_from_slice(&PIXEL_FORMAT);
+    server_init.extend_from_slice(&(DESKTOP_NAME.len() as u32).to_be_bytes());
+    server_init.extend_from_slice(DESKTOP_NAME.as_bytes());
+    stream.write_all(&server_init)
+}
+
+/// Handles viewer messages until the connection ends.
+fn receive(
+    mut stream: TcpStream,
+    events: Sender<Event>,
+    pointer: Option<Sender<Pointer>>,
+) -> io::Result<()> {
+    loop {
+        match read_u8(&mut stream)? {
+            0 => {
+                skip(&mut stream, 3)?;
+                let mut format = [0u8; 16];
+                stream.read_exact(&mut format)?;
+                events
+                    .send(Event::Format(PixelFormat::parse(&format)?))
+                    .ok();
+            }
+            2 => {
+                // only raw is sent, which every viewer supports
+                skip(&mut stream, 1)?;
+                let count = read_u16(&mut stream)?;
+                skip(&mut stream, count as u64 * 4)?;
+            }
+            3 => {
+                let incremental = read_u8(&mut stream)? != 0;
+                // the whole frame is sent either way
+                skip(&mut stream, 8)?;
+                events.send(Event::Request { incremental }).ok();
+            }
+            4 => {
+                // keys have nothing to go to
+                skip(&mut stream, 7)?;
+            }
+            5 => {
+                let buttons = read_u8(&mut stream)?;
+                let x = read_u16(&mut stream)?;
+                let y = read_u16(&mut stream)?;
+                if let Some(pointer) = &pointer {
+                    pointer.send(Pointer { x, y, buttons }).ok();
+                }
+            }
+            6 => {
+                skip(&mut stream, 3)?;
+                let length = read_u32(&mut stream)?;
+                skip(&mut stream, length as u64)?;
+            }
+            message => {
+                return Err(protocol_error(format!(
+                    "Unexpected message type {}",
+                    message
+                )))
+            }
+        }
+    }
+}
+
+fn serve(
+    mut stream: TcpStream,
+    shared: &Arc<Shared>,
+    settings: &Settings,
+    pointer: Sender<Pointer>,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    handshake(&mut stream, settings)?;
+    stream.set_read_timeout(None)?;
+
+    let (sender, events) = channel();
+    let reader = stream.try_clone()?;
+    let pointer = if settings.interactive {
+        Some(pointer)
+    } else {
+        None
+    };
+    // the input ending means the viewer is gone, also while waiting for a frame
+    let closed = Arc::new(AtomicBool::new(false));
+    let (input_closed, input_shared) = (closed.clone(), shared.clone());
+    thread::Builder::new()
+        .name("VNC viewer input".to_owned())
+        .spawn(move || {
+            if let Err(err) = receive(reader, sender, pointer) {
+                info!("VNC viewer input ended: {}", err);
+            }
+            input_closed.store(true, Ordering::SeqCst);
+            input_shared.wake();
+        })?;
+
+    let mut format = PixelFormat::parse(&PIXEL_FORMAT)?;
+    let mut sent = 0;
+    let mut message = Vec::new();
+    for event in events {
+        match event {
+            Event::Format(next) => format = next,
+            Event::Request { incremental } => {
+                let (number, frame) =
+                    match shared.next_frame(if incremental { sent } else { 0 }, &closed) {
+                        Some(next) => next,
+                        None => break,
+                    };
+                sent = number;
+                message.clear();
+                message.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0]);
+                message.extend_from_slice(&(frame.width as u16).to_be_bytes());
+                message.extend_from_slice(&(frame.height as u16).to_be_bytes());
+                // raw encoding
+                message.extend_from_slice(&0u32.to_be_bytes());
+                format.encode(&frame, &mut message);
+                stream.write_all(&message)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_server(
+    listener: TcpListener,
+    shared: Arc<Shared>,
+    settings: Arc<Settings>,
+    pointer: Sender<Pointer>,
+) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                error!("Failed accepting VNC viewer: {}", err);
+                continue;
+            }
+        };
+        let connected = match Connected::admit(&shared) {
+            Some(connected) => connected,
+            None => {
+                info!("Refusing VNC viewer, serving {} already", MAX_VIEWERS);
+                continue;
+            }
+        };
+        let (settings, pointer) = (settings.clone(), pointer.clone());
+        thread::Builder::new()
+            .name("VNC viewer".to_owned())
+            .spawn(move || {
+                if let Err(err) = serve(stream, &connected.0, &settings, pointer) {
+                    info!("VNC viewer disconnected: {}", err);
+                }
+            })
+            .ok();
+    }
+}
+
+/// Serves the rendered frames to VNC viewers, optionally taking their pointer input.
+pub struct Server {
+    shared: Option<Arc<Shared>>,
+    pointer: Receiver<Pointer>,
+    interval: Duration,
+    last_frame: Option<Instant>,
+    frames: u64,
+    /// Frame being read back, taken on the next update so reading doesn't wait for the GPU
+    pending: Option<PixelBuffer<(u8, u8, u8, u8)>>,
+}
+
+impl Server {
+    /// Listens on the configured port, for frames of `size`.
+    pub fn new(config: &config::VncServer, address: &str, size: (u32, u32)) -> Self {
+        let (sender, pointer) = channel();
+        let shared = config.port.and_then(|port| {
+            let listener = match TcpListener::bind((address, port)) {
+                Ok(listener) => listener,
+                Err(err) => {
+                    error!("Failed opening VNC server on port {}: {}", port, err);
+                    return None;
+                }
+            };
+            info!(
+                "Serving VNC on port {}{}",
+                port,
+                if config.interactive {
+                    ""
+                } else {
+                    ", view only"
+                }
+            );
+            let shared = Arc::new(Shared::default());
+            let settings = Arc::new(Settings {
+                password: config.password.clone(),
+                interactive: config.interactive,
+                size: (size.0 as u16, size.1 as u16),
+            });
+            let server_shared = shared.clone();
+            thread::Builder::new()
+                .name("VNC server".to_owned())
+                .spawn(move || run_server(listener, server_shared, settings, sender))
+                .ok()?;
+            Some(shared)
+        });
+        Self {
+            shared,
+            pointer,
+            interval: Duration::from_secs(1) / config.fps.max(1),
+            last_frame: None,
+            frames: 0,
+            pending: None,
+        }
+    }
+
+    /// Called with every finished frame, reads it back when viewers are connected. The read
+    /// back frame is passed on with the next update, a frame late.
+    pub fn update(&mut self, texture: &Texture2d) {
+        let shared = match &self.shared {
+            Some(shared) if shared.clients.load(Ordering::SeqCst) > 0 => shared,
+            _ => {
+                self.pending = None;
+                return;
+            }
+        };
+        if let Some(pending) = self.pending.take() {
+            match pending.read_as_texture_2d::<RawImage2d<u8>>() {
+                Ok(image) => {
+                    self.frames += 1;
+                    *shared.frame.lock().unwrap() =
+                        (self.frames, Some(Arc::new(Frame::from_image(image))));
+                    shared.updated.notify_all();
+                }
+                Err(err) => error!("Failed reading frame for VNC viewers: {:?}", err),
+            }
+        }
+        if self
+            .last_frame
+            .map_or(false, |last| last.elapsed() < self.interval)
+        {
+            return;
+        }
+        self.last_frame = Some(Instant::now());
+        self.pending = Some(texture.read_to_pixel_buffer());
+    }
+
+    /// Next pointer input of an interactive viewer.
+    pub fn pointer(&self) -> Option<Pointer> {
+        self.pointer.try_recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(bits: u8, max: u16, shift: [u8; 3]) -> [u8; 16] {
+        let [high, low] = max.to_be_bytes();
+        [
+            bits, 24, 0, 1, high, low, high, low, high, low, shift[0], shift[1], shift[2], 0, 0, 0,
+        ]
+    }
+
+    #[test]
+    fn pixel_formats_are_checked() {
+        assert!(PixelFormat::parse(&PIXEL_FORMAT).is_ok());
+        assert!(PixelFormat::parse(&format(16, 31, [11, 5, 0])).is_ok());
+        assert!(PixelFormat::parse(&format(32, 255, [40, 8, 0])).is_err());
+        assert!(PixelFormat::parse(&format(32, 255, [32, 8, 0])).is_err());
+        assert!(PixelFormat::parse(&format(16, 255, [11, 5, 0])).is_err());
+        assert!(PixelFormat::parse(&format(8, 7, [5, 2, 0])).is_ok());
+        assert!(PixelFormat::parse(&format(8, 7, [6, 2, 0])).is_err());
+        let mut colour_map = PIXEL_FORMAT;
+        colour_map[3] = 0;
+        assert!(PixelFormat::parse(&colour_map).is_err());
+    }
+
+    #[test]
+    fn frames_are_encoded_in_the_viewers_format() {
+        let frame = Frame {
+            width: 2,
+            height: 1,
+            pixels: vec![255, 0, 0, 0, 255, 255],
+        };
+        let mut out = Vec::new();
+        PixelFormat::parse(&PIXEL_FORMAT)
+            .unwrap()
+            .encode(&frame, &mut out);
+        assert_eq!(out, [255, 0, 0, 0, 0, 255, 255, 0]);
+
+        let mut rgb565 = format(16, 31, [11, 5, 0]);
+        // green has six bits
+        rgb565[6..8].copy_from_slice(&63u16.to_be_bytes());
+        rgb565[2] = 1;
+        out.clear();
+        PixelFormat::parse(&rgb565)
+            .unwrap()
+            .encode(&frame, &mut out);
+        assert_eq!(out, [0xf8, 0x00, 0x07, 0xff]);
+    }
+
+    /// Serves on a free port and returns it with the shared state.
+    fn start() -> (u16, Arc<Shared>) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let shared = Arc::new(Shared::default());
+        let settings = Arc::new(Settings {
+            password: None,
+            interactive: false,
+            size: (2, 1),
+        });
+        let (pointer, _) = channel();
+        let server_shared = shared.clone();
+        thread::spawn(move || run_server(listener, server_shared, settings, pointer));
+        (port, shared)
+    }
+
+    fn connect(port: u16) -> TcpStream {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut version = [0u8; 12];
+        stream.read_exact(&mut version).unwrap();
+        stream.write_all(b"RFB 003.008\n").unwrap();
+        let mut security = [0u8; 2];
+        stream.read_exact(&mut security).unwrap();
+        assert_eq!(security, [1, 1]);
+        stream.write_all(&[1]).unwrap();
+        assert_eq!(read_u32(&mut stream).unwrap(), 0);
+        stream.write_all(&[1]).unwrap();
+        let mut init = [0u8; 24];
+        stream.read_exact(&mut init).unwrap();
+        assert_eq!(init[..4], [0, 2, 0, 1]);
+        skip(
+            &mut stream,
+            read_u32(&mut init[20..].as_ref()).unwrap() as u64,
+        )
+        .unwrap();
+        stream
+    }
+
+    fn wait_for_clients(shared: &Shared, clients: usize) {
+        let start = Instant::now();
+        while shared.clients.load(Ordering::SeqCst) != clients {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "expected {} clients",
+                clients
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn viewers_waiting_for_a_frame_can_leave() {
+        let (port, shared) = start();
+        let mut stream = connect(port);
+        // nothing is drawn, so this waits for a frame that never comes
+        stream.write_all(&[3, 0, 0, 0, 0, 0, 0, 2, 0, 1]).unwrap();
+        wait_for_clients(&shared, 1);
+        thread::sleep(Duration::from_millis(50));
+        drop(stream);
+        wait_for_clients(&shared, 0);
+    }
+
+    #[test]
+    fn viewers_get_frames_and_bad_formats_end_them() {
+        let (port, shared) = start();
+        let mut stream = connect(port);
+        wait_for_clients(&shared, 1);
+        *shared.frame.lock().unwrap() = (
+            1,
+            Some(Arc::new(Frame {
+                width: 2,
+                height: 1,
+                pixels: vec![1, 2, 3, 4, 5, 6],
+            })),
+        );
+        shared.wake();
+        stream.write_all(&[3, 0, 0, 0, 0, 0, 0, 2, 0, 1]).unwrap();
+        let mut update = [0u8; 16 + 8];
+        stream.read_exact(&mut update).unwrap();
+        assert_eq!(update[..4], [0, 0, 0, 1]);
+        assert_eq!(update[16..], [1, 2, 3, 0, 4, 5, 6, 0]);
+
+        stream.write_all(&[0, 0, 0, 0]).unwrap();
+        stream.write_all(&format(32, 255, [40, 8, 0])).unwrap();
+        wait_for_clients(&shared, 0);
+    }
+
+    #[test]
+    fn viewers_are_limited() {
+        let (port, shared) = start();
+        let viewers: Vec<_> = (0..MAX_VIEWERS).map(|_| connect(port)).collect();
+        wait_for_clients(&shared, MAX_VIEWERS);
+        // refused before the handshake
+        let mut refused = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut version = Vec::new();
+        refused.read_to_end(&mut version).unwrap();
+        assert!(version.is_empty());
+        assert_eq!(shared.clients.load(Ordering::SeqCst), MAX_VIEWERS);
+        drop(viewers);
+        wait_for_clients(&shared, 0);
+        connect(port);
+    }
+}